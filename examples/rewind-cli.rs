@@ -0,0 +1,168 @@
+//! Companion command line tool for inspecting exported worldlines
+//!
+//! Usage:
+//!
+//!     rewind-cli <worldline> history <x> <y> <z>
+//!     rewind-cli <worldline> player <uuid>
+//!     rewind-cli <worldline> query <terms>...
+//!     rewind-cli <worldline> diff <x1> <y1> <z1> <x2> <y2> <z2> <from id> <to id>
+//!     rewind-cli <worldline> compact <output>
+//!     rewind-cli <worldline> export <output> [until id]
+//!
+//! Transaction ids are given as major ids, e.g. `12`. Query terms are the same as for chat
+//! commands, like `owner:<uuid> action:break time:1h`, see rewind::query::Query::parse.
+extern crate chrono;
+extern crate rewind;
+extern crate uuid;
+
+use chrono::Local;
+use rewind::data::*;
+use rewind::query::QueryContext;
+use rewind::Rewind;
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::process;
+use uuid::Uuid;
+
+const USAGE: &str = "usage:
+    rewind-cli <worldline> history <x> <y> <z>
+    rewind-cli <worldline> player <uuid>
+    rewind-cli <worldline> query <terms>...
+    rewind-cli <worldline> diff <x1> <y1> <z1> <x2> <y2> <z2> <from id> <to id>
+    rewind-cli <worldline> compact <output>
+    rewind-cli <worldline> export <output> [until id]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(message) = run(&args) {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    if args.len() < 2 {
+        return Err(USAGE.to_string());
+    }
+    let rewind = open(&args[0])?;
+    let rest = &args[2..];
+
+    match (args[1].as_str(), rest.len()) {
+        ("history", 3) => {
+            let (x, y, z) = (parse(&rest[0])?, parse(&rest[1])?, parse(&rest[2])?);
+            for (block, transaction) in rewind.get_block_history(x, y, z) {
+                println!("{}\t-> {}", format_transaction(&transaction), block);
+            }
+            Ok(())
+        }
+        ("player", 1) => {
+            let owner = Uuid::parse_str(&rest[0]).map_err(|e| e.to_string())?;
+            for transaction in rewind.get_transactions() {
+                if transaction.get_transaction().get_owner() == owner {
+                    println!("{}", format_transaction(&transaction));
+                }
+            }
+            Ok(())
+        }
+        ("query", n) if n > 0 => {
+            let context = QueryContext::new(Local::now());
+            let mut query = rewind.query();
+            query
+                .parse(&rest.join(" "), &context)
+                .map_err(|e| e.to_string())?;
+            for transaction in query.iter() {
+                println!("{}", format_transaction(&transaction));
+            }
+            Ok(())
+        }
+        ("diff", 8) => {
+            let min = (parse(&rest[0])?, parse(&rest[1])?, parse(&rest[2])?);
+            let max = (parse(&rest[3])?, parse(&rest[4])?, parse(&rest[5])?);
+            let from = last_of(parse(&rest[6])?);
+            let to = last_of(parse(&rest[7])?);
+            for ((x, y, z), before, after) in rewind.diff_region(Region::new(min, max), from, to) {
+                println!("{} {} {}\t{} -> {}", x, y, z, before, after);
+            }
+            Ok(())
+        }
+        ("compact", 1) => save(&rewind.compact(), &rest[0]),
+        ("export", 1) => save(&rewind, &rest[0]),
+        ("export", 2) => save(&until(&rewind, parse(&rest[1])?), &rest[0]),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// Opens an exported worldline
+fn open(path: &str) -> Result<Rewind, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    Rewind::import_worldline(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Exports a worldline to the given path
+fn save(rewind: &Rewind, path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    rewind
+        .export_worldline(BufWriter::new(file))
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Returns the worldline as it was just after the given transaction
+fn until(rewind: &Rewind, id: u32) -> Rewind {
+    let transactions = rewind
+        .get_transactions()
+        .into_iter()
+        .filter(|x| x.get_id().get_id() <= id);
+    Rewind::from_transactions(transactions, rewind.get_default_block())
+}
+
+/// Returns the last possible transaction id with the given major id
+fn last_of(id: u32) -> TransactionID {
+    TransactionID::new_from_parts(id, u32::MAX)
+}
+
+fn parse<T: std::str::FromStr>(arg: &str) -> Result<T, String> {
+    arg.parse().map_err(|_| format!("invalid number: {}", arg))
+}
+
+fn format_transaction(transaction: &Transaction) -> String {
+    let raw = transaction.get_transaction();
+    let id = transaction.get_id();
+    let time = raw
+        .get_local_time()
+        .map(|x| x.to_rfc3339())
+        .unwrap_or_else(|| "-".to_string());
+    let coords = raw
+        .get_coords()
+        .map(|(x, y, z)| format!("{} {} {}", x, y, z))
+        .unwrap_or_else(|| "-".to_string());
+    let action = match raw.get_transaction_type() {
+        TransactionType::Set { block_set } => format!("set {}", block_set),
+        TransactionType::Replace {
+            block_current,
+            block_set,
+        } => format!("replace {} with {}", block_current, block_set),
+        TransactionType::Undo { transaction } => {
+            format!("undo {}.{}", transaction.get_id(), transaction.get_sub_id())
+        }
+        TransactionType::SetBiome { biome } => format!("set biome {}", biome.get_id()),
+        TransactionType::SetDecoration { entity, decoration } => {
+            format!("set {:?} {}", decoration.get_kind(), entity)
+        }
+        TransactionType::RemoveDecoration { entity } => format!("remove decoration {}", entity),
+        TransactionType::Explode {
+            explosion,
+            block_set,
+        } => format!("explode {} blocks into {}", explosion.len(), block_set),
+        TransactionType::PistonMove { moves, .. } => format!("piston move {} blocks", moves.len()),
+    };
+    format!(
+        "{}.{}\t{}\t{}\t{}\t{}",
+        id.get_id(),
+        id.get_sub_id(),
+        time,
+        raw.get_owner(),
+        coords,
+        action
+    )
+}
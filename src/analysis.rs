@@ -0,0 +1,360 @@
+//! Provides analysis of the worldline for patterns of griefing
+//!
+//! The history of every block is replayed, and the changes each owner made are scanned for
+//! bursts of suspicious activity within a window of time:
+//!
+//! - Mass removals, many blocks replaced with the default block
+//! - Hazard spikes, many hazardous blocks such as lava or fire placed
+//! - Container drains, containers emptied of their items or broken while holding them
+//!
+//! Each burst of at least the configured number of events becomes an incident, scored by how far
+//! past that threshold it went, for moderators to review. Transactions without a timestamp can
+//! not be placed in a window, and are ignored.
+
+use chrono::{DateTime, Duration, Utc};
+use data::*;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use Rewind;
+
+/// The kind of pattern an incident matched
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IncidentKind {
+    /// Many blocks replaced with the default block
+    MassRemoval,
+    /// Many hazardous blocks placed
+    HazardSpike,
+    /// Many containers emptied of their items
+    ContainerDrain,
+}
+
+/// A burst of suspicious activity by one owner
+#[derive(Clone, Debug)]
+pub struct Incident {
+    kind: IncidentKind,
+    owner: Uuid,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    transactions: Vec<TransactionID>,
+    score: f64,
+}
+
+impl Incident {
+    /// Returns the kind of pattern the incident matched
+    pub fn get_kind(&self) -> IncidentKind {
+        self.kind
+    }
+
+    /// Returns the owner of the transactions involved
+    pub fn get_owner(&self) -> Uuid {
+        self.owner
+    }
+
+    /// Returns the time of the first transaction involved
+    pub fn get_start(&self) -> DateTime<Utc> {
+        self.start
+    }
+
+    /// Returns the time of the last transaction involved
+    pub fn get_end(&self) -> DateTime<Utc> {
+        self.end
+    }
+
+    /// Returns the transactions involved, oldest first
+    pub fn get_transactions(&self) -> &[TransactionID] {
+        &self.transactions
+    }
+
+    /// Returns how suspicious the incident is
+    ///
+    /// The number of events divided by the threshold for the kind, so always at least 1.0
+    pub fn get_score(&self) -> f64 {
+        self.score
+    }
+}
+
+/// What counts as suspicious when analyzing a worldline
+///
+/// Hazards and containers are empty by default, as which blocks they are depends on the
+/// dictonary, so only mass removals are found unless they are set.
+#[derive(Clone, Debug)]
+pub struct AnalysisConfig {
+    window: Duration,
+    removal_threshold: usize,
+    hazard_threshold: usize,
+    drain_threshold: usize,
+    hazards: HashSet<Block>,
+    containers: HashSet<Block>,
+    items_key: String,
+}
+
+impl AnalysisConfig {
+    /// Creates a config with the default thresholds
+    ///
+    /// Events are grouped into windows of one minute. It takes 50 removals, 5 hazards or 3
+    /// drained containers within a window to make an incident. Items are read from the
+    /// `minecraft:Items` NBT list of containers.
+    pub fn new() -> AnalysisConfig {
+        AnalysisConfig {
+            window: Duration::minutes(1),
+            removal_threshold: 50,
+            hazard_threshold: 5,
+            drain_threshold: 3,
+            hazards: HashSet::new(),
+            containers: HashSet::new(),
+            items_key: "minecraft:Items".to_string(),
+        }
+    }
+
+    /// Sets the longest time between the first and last event of an incident
+    pub fn set_window(&mut self, window: Duration) -> &mut Self {
+        self.window = window;
+        self
+    }
+
+    /// Sets the number of removals within a window that makes an incident
+    ///
+    /// Returns None, leaving the config untouched, if the threshold is zero
+    pub fn set_removal_threshold(&mut self, threshold: usize) -> Option<&mut Self> {
+        if threshold == 0 {
+            return None;
+        }
+        self.removal_threshold = threshold;
+        Some(self)
+    }
+
+    /// Sets the number of hazards placed within a window that makes an incident
+    ///
+    /// Returns None, leaving the config untouched, if the threshold is zero
+    pub fn set_hazard_threshold(&mut self, threshold: usize) -> Option<&mut Self> {
+        if threshold == 0 {
+            return None;
+        }
+        self.hazard_threshold = threshold;
+        Some(self)
+    }
+
+    /// Sets the number of containers drained within a window that makes an incident
+    ///
+    /// Returns None, leaving the config untouched, if the threshold is zero
+    pub fn set_drain_threshold(&mut self, threshold: usize) -> Option<&mut Self> {
+        if threshold == 0 {
+            return None;
+        }
+        self.drain_threshold = threshold;
+        Some(self)
+    }
+
+    /// Sets the blocks that count as hazards, such as lava and fire
+    ///
+    /// Metadata is ignored
+    pub fn set_hazards(&mut self, hazards: HashSet<Block>) -> &mut Self {
+        self.hazards = hazards;
+        self
+    }
+
+    /// Sets the blocks that count as containers, such as chests and barrels
+    ///
+    /// Metadata is ignored
+    pub fn set_containers(&mut self, containers: HashSet<Block>) -> &mut Self {
+        self.containers = containers;
+        self
+    }
+
+    /// Sets the NBT key holding the list of items in a container
+    pub fn set_items_key(&mut self, key: &str) -> &mut Self {
+        self.items_key = key.to_string();
+        self
+    }
+
+    /// Returns the number of items in a block, zero if it is not a container
+    fn count_items(&self, block: &MetaBlock) -> usize {
+        if !self.containers.contains(block.get_block()) {
+            return 0;
+        }
+        match block.get_meta_data().get_nbt(&self.items_key) {
+            Some(NbtValue::List(items)) => items.len(),
+            _ => 0,
+        }
+    }
+
+    /// Returns the kind of event a change to a block is, if it is suspicious at all
+    fn classify(
+        &self,
+        before: &MetaBlock,
+        after: &MetaBlock,
+        default_block: &MetaBlock,
+    ) -> Option<IncidentKind> {
+        if self.count_items(after) < self.count_items(before) {
+            Some(IncidentKind::ContainerDrain)
+        } else if self.hazards.contains(after.get_block())
+            && !self.hazards.contains(before.get_block())
+        {
+            Some(IncidentKind::HazardSpike)
+        } else if after == default_block && before != default_block {
+            Some(IncidentKind::MassRemoval)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the threshold for a kind of incident
+    fn get_threshold(&self, kind: IncidentKind) -> usize {
+        match kind {
+            IncidentKind::MassRemoval => self.removal_threshold,
+            IncidentKind::HazardSpike => self.hazard_threshold,
+            IncidentKind::ContainerDrain => self.drain_threshold,
+        }
+    }
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> AnalysisConfig {
+        AnalysisConfig::new()
+    }
+}
+
+/// Scans the worldline of a Rewind for suspicious patterns
+///
+/// Returns every incident found, most suspicious first.
+///
+/// This function aquires a readlock on the world line, and will block until it is available
+pub fn analyze(rewind: &Rewind, config: &AnalysisConfig) -> Vec<Incident> {
+    let default_block = rewind.get_default_block();
+
+    // The events of each owner, of each kind, in the order they were committed
+    type Events = Vec<(DateTime<Utc>, TransactionID)>;
+    let mut events: HashMap<(Uuid, IncidentKind), Events> = HashMap::new();
+    for (before, after, transaction) in rewind.get_block_changes() {
+        let raw = transaction.get_transaction();
+        let time = match raw.get_time() {
+            Some(time) => time,
+            None => continue,
+        };
+        if let Some(kind) = config.classify(&before, &after, &default_block) {
+            events
+                .entry((raw.get_owner(), kind))
+                .or_default()
+                .push((time, transaction.get_id()));
+        }
+    }
+
+    let mut incidents = Vec::new();
+    for ((owner, kind), mut events) in events {
+        // Ids follow causality rather than clocks, so sort by time for the windows
+        events.sort();
+        let threshold = config.get_threshold(kind);
+        let mut start = 0;
+        while start < events.len() {
+            let end = events[start..]
+                .iter()
+                .take_while(|x| x.0 - events[start].0 <= config.window)
+                .count()
+                + start;
+            if end - start >= threshold {
+                incidents.push(Incident {
+                    kind,
+                    owner,
+                    start: events[start].0,
+                    end: events[end - 1].0,
+                    transactions: events[start..end].iter().map(|x| x.1).collect(),
+                    score: (end - start) as f64 / threshold as f64,
+                });
+                start = end;
+            } else {
+                start += 1;
+            }
+        }
+    }
+
+    incidents.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.start.cmp(&b.start)));
+    incidents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone};
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn finds_bursts() {
+        let rewind = Rewind::new(block(0));
+        let (griefer, builder) = (Uuid::new_v4(), Uuid::new_v4());
+        let start = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 0, 0, 0)
+            .unwrap();
+        let apply = |owner, set: MetaBlock, x, seconds| {
+            let raw = RawTransactionBuilder::new(TransactionType::new_set(set))
+                .set_owner(owner)
+                .set_time(start + Duration::seconds(seconds))
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap();
+            rewind.apply_transaction(raw).unwrap()
+        };
+
+        let items = NbtValue::List(vec![NbtValue::Int(1), NbtValue::Int(2)]);
+        let chest = |items| {
+            let meta = MetaData::new().set_nbt("minecraft:Items", items).unwrap();
+            MetaBlock::fuse(Block::new_from_ids(0, 5), meta)
+        };
+        for x in 0..10 {
+            apply(builder, block(1), x, x as i64);
+        }
+        apply(builder, chest(items.clone()), 10, 10);
+        apply(builder, chest(items), 11, 10);
+
+        // Removing the builder's blocks in a burst, with one slow removal after it
+        for x in 0..6 {
+            apply(griefer, block(0), x, 100 + x as i64);
+        }
+        apply(griefer, block(0), 6, 1000);
+        // Fire placed slowly never makes a spike
+        for x in 20..25 {
+            apply(griefer, block(9), x, 100 * x as i64);
+        }
+        apply(griefer, chest(NbtValue::List(Vec::new())), 10, 200);
+        apply(griefer, block(0), 11, 201);
+
+        let mut config = AnalysisConfig::new();
+        config.set_removal_threshold(3).unwrap();
+        config.set_hazard_threshold(2).unwrap();
+        config.set_drain_threshold(2).unwrap();
+        config.set_hazards(vec![Block::new_from_ids(0, 9)].into_iter().collect());
+        config.set_containers(vec![Block::new_from_ids(0, 5)].into_iter().collect());
+        assert!(config.set_drain_threshold(0).is_none());
+
+        let incidents = analyze(&rewind, &config);
+        assert_eq!(incidents.len(), 2);
+        assert!(incidents.iter().all(|x| x.get_owner() == griefer));
+        assert_eq!(incidents[0].get_kind(), IncidentKind::MassRemoval);
+        assert_eq!(incidents[0].get_transactions().len(), 6);
+        assert_eq!(incidents[0].get_score(), 2.0);
+        assert!(incidents[0].get_end() - incidents[0].get_start() == Duration::seconds(5));
+        assert_eq!(incidents[1].get_kind(), IncidentKind::ContainerDrain);
+        assert_eq!(incidents[1].get_score(), 1.0);
+
+        // Undone griefing is no longer suspicious
+        let undo = |id| {
+            RawTransactionBuilder::new(TransactionType::new_undo(id))
+                .build_transaction()
+                .unwrap()
+        };
+        for id in incidents[1].get_transactions() {
+            rewind.apply_transaction(undo(*id)).unwrap();
+        }
+        config.set_window(Duration::hours(1));
+        let incidents = analyze(&rewind, &config);
+        assert_eq!(incidents.len(), 2);
+        assert_eq!(incidents[0].get_kind(), IncidentKind::HazardSpike);
+        assert_eq!(incidents[0].get_score(), 2.5);
+        assert_eq!(incidents[1].get_transactions().len(), 7);
+    }
+}
@@ -0,0 +1,186 @@
+//! Provides proptest strategies over the data model, for fuzzing what is built on top of it
+//!
+//! Blocks, transaction ids, regions and raw transactions implement Arbitrary, and the functions
+//! here build strategies for them with a bit more control, like keeping transactions inside a
+//! small region so they land on the same blocks often enough to interact. Only available with
+//! the proptest feature.
+//!
+//! Arbitrary raw transactions are only ever Sets and Replaces. Undos have to refer to a
+//! transaction that has been committed, so tests build those from the ids they get back.
+
+use data::*;
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use uuid::Uuid;
+
+/// Returns a strategy for blocks with ids below the given number, from the first two providers,
+/// sometimes with a data value
+pub fn meta_blocks(ids: u16) -> BoxedStrategy<MetaBlock> {
+    (0u16..2, 0..ids.max(1), option::of(0i32..16))
+        .prop_map(|(provider, id, data_value)| {
+            let meta = match data_value {
+                Some(data_value) => MetaData::new().set_data_value(data_value),
+                None => MetaData::new(),
+            };
+            MetaBlock::fuse(Block::new_from_ids(provider, id), meta)
+        })
+        .boxed()
+}
+
+/// Returns a strategy for the coordinates of blocks inside the region
+pub fn coords_in(region: Region) -> BoxedStrategy<(i32, i32, i32)> {
+    let (min, max) = (region.get_min(), region.get_max());
+    (min.0..=max.0, min.1..=max.1, min.2..=max.2).boxed()
+}
+
+/// Returns a strategy for uuids, from a pool of the given size, so several transactions often
+/// share an owner
+pub fn owners(pool: u8) -> BoxedStrategy<Uuid> {
+    (0..pool.max(1))
+        .prop_map(|x| Uuid::from_uuid_bytes([x; 16]))
+        .boxed()
+}
+
+/// Returns a strategy for Sets and Replaces of the given blocks at coordinates inside the region,
+/// by owners from a pool of four
+pub fn raw_transactions_in(
+    region: Region,
+    blocks: BoxedStrategy<MetaBlock>,
+) -> BoxedStrategy<RawTransaction> {
+    let kind = prop_oneof![
+        blocks.clone().prop_map(TransactionType::new_set),
+        (blocks.clone(), blocks)
+            .prop_map(|(current, set)| TransactionType::new_replace(current, set)),
+    ];
+    (kind, coords_in(region), owners(4))
+        .prop_map(|(kind, (x, y, z), owner)| {
+            RawTransactionBuilder::new(kind)
+                .set_owner(owner)
+                .set_x_coord(x)
+                .set_y_coord(y)
+                .set_z_coord(z)
+                .build_transaction()
+                .unwrap()
+        })
+        .boxed()
+}
+
+/// Returns a strategy for lists of up to len transactions inside the region, see
+/// raw_transactions_in
+pub fn histories_in(region: Region, len: usize) -> BoxedStrategy<Vec<RawTransaction>> {
+    vec(raw_transactions_in(region, meta_blocks(8)), 0..=len).boxed()
+}
+
+/// Blocks with ids below 64
+impl Arbitrary for MetaBlock {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<MetaBlock>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        meta_blocks(64)
+    }
+}
+
+impl Arbitrary for TransactionID {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<TransactionID>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (any::<u32>(), any::<u32>())
+            .prop_map(|(id, sub_id)| TransactionID::new_from_parts(id, sub_id))
+            .boxed()
+    }
+}
+
+/// Regions with both corners within 512 blocks of the origin, between heights 0 and 255
+impl Arbitrary for Region {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Region>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let corner = || (-512i32..512, 0i32..256, -512i32..512);
+        (corner(), corner())
+            .prop_map(|(a, b)| Region::new(a, b))
+            .boxed()
+    }
+}
+
+/// Sets and Replaces inside a 16 block cube at the origin, see raw_transactions_in
+impl Arbitrary for RawTransaction {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<RawTransaction>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        raw_transactions_in(Region::new((0, 0, 0), (15, 15, 15)), meta_blocks(8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use Rewind;
+
+    fn region() -> Region {
+        Region::new((0, 0, 0), (3, 3, 3))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn transactions_round_trip(transaction in any::<RawTransaction>()) {
+            let json = serde_json::to_string(&transaction).unwrap();
+            let read: RawTransaction = serde_json::from_str(&json).unwrap();
+            prop_assert!(read == transaction);
+        }
+
+        #[test]
+        fn replaying_matches_applying(history in histories_in(region(), 24)) {
+            let default_block = MetaBlock::fuse(Block::new_from_ids(0, 0), MetaData::new());
+            let rewind = Rewind::new(default_block.clone());
+            for transaction in history {
+                rewind.apply_transaction(transaction);
+            }
+            let replayed = Rewind::from_transactions(rewind.get_transactions(), default_block);
+            let (applied, replayed) = (rewind.get_world_state(), replayed.get_world_state());
+            for (x, y, z) in region_coords(region()) {
+                prop_assert!(applied.get_block_defaulting(x, y, z).unwrap() == replayed.get_block_defaulting(x, y, z).unwrap());
+            }
+        }
+
+        #[test]
+        fn undoing_everything_restores_the_default(history in histories_in(region(), 24)) {
+            let default_block = MetaBlock::fuse(Block::new_from_ids(0, 0), MetaData::new());
+            let rewind = Rewind::new(default_block.clone());
+            let committed: Vec<Transaction> = history
+                .into_iter()
+                .filter_map(|x| rewind.apply_transaction(x))
+                .collect();
+            for transaction in committed.iter().rev() {
+                let undo = RawTransactionBuilder::new(TransactionType::new_undo(transaction.get_id()))
+                    .build_transaction()
+                    .unwrap();
+                prop_assert!(rewind.apply_transaction(undo).is_some());
+            }
+            let world = rewind.get_world_state();
+            for (x, y, z) in region_coords(region()) {
+                prop_assert!(world.get_block_defaulting(x, y, z).unwrap() == default_block);
+            }
+        }
+    }
+
+    fn region_coords(region: Region) -> Vec<(i32, i32, i32)> {
+        let (min, max) = (region.get_min(), region.get_max());
+        let mut coords = Vec::new();
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    coords.push((x, y, z));
+                }
+            }
+        }
+        coords
+    }
+}
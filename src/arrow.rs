@@ -0,0 +1,371 @@
+//! Provides query results as Arrow RecordBatches, for analytical engines like DataFusion and
+//! polars
+//!
+//! Enabled with the `arrow` feature. Every transaction becomes a row with the same flat columns
+//! as the dicts of the python bindings:
+//!
+//! - `id` and `sub_id`, the two parts of the id of the transaction
+//! - `time`, a UTC timestamp in microseconds, and `utc_offset`, the offset the transaction was
+//!   submitted with in seconds
+//! - `owner`, the uuid of whoever did the transaction, and `world`, the numeric id of the world
+//! - `x`, `y` and `z`
+//! - `action`, one of `set`, `replace`, `undo`, `set_biome`, `set_decoration`,
+//!   `remove_decoration`, `explode` or `piston_move`, and `category`, like `burn`
+//! - `provider`, `block_id` and `data_value` of the block placed, and `replaced_provider`,
+//!   `replaced_block_id` and `replaced_data_value` of the block a Replace expected
+//! - `undone_id` and `undone_sub_id` of the transaction an Undo reverts
+//! - `biome`, and `entity`, the uuid of the decoration a transaction places or breaks
+//!
+//! Columns that do not apply to a transaction are null. The blocks destroyed by an explosion and
+//! the blocks moved by a piston are not included. Batches are built as they are read, so a
+//! query over the whole worldline never has to be held in memory at once, and once built they
+//! can be handed to an engine without further copies.
+
+use arrow_array::builder::{
+    Int32Builder, StringBuilder, TimestampMicrosecondBuilder, UInt16Builder, UInt32Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch, RecordBatchReader};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use data::*;
+use query::Query;
+use std::sync::Arc;
+
+/// Returns the schema of the batches, see the module documentation for the columns
+pub fn transaction_schema() -> SchemaRef {
+    let block = |prefix: &str| {
+        vec![
+            Field::new(format!("{}provider", prefix), DataType::UInt16, true),
+            Field::new(format!("{}block_id", prefix), DataType::UInt16, true),
+            Field::new(format!("{}data_value", prefix), DataType::Int32, true),
+        ]
+    };
+    let mut fields = vec![
+        Field::new("id", DataType::UInt32, false),
+        Field::new("sub_id", DataType::UInt32, false),
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            true,
+        ),
+        Field::new("utc_offset", DataType::Int32, true),
+        Field::new("owner", DataType::Utf8, false),
+        Field::new("world", DataType::Int32, false),
+        Field::new("x", DataType::Int32, true),
+        Field::new("y", DataType::Int32, true),
+        Field::new("z", DataType::Int32, true),
+        Field::new("action", DataType::Utf8, false),
+        Field::new("category", DataType::Utf8, true),
+    ];
+    fields.extend(block(""));
+    fields.extend(block("replaced_"));
+    fields.extend(vec![
+        Field::new("undone_id", DataType::UInt32, true),
+        Field::new("undone_sub_id", DataType::UInt32, true),
+        Field::new("biome", DataType::UInt16, true),
+        Field::new("entity", DataType::Utf8, true),
+    ]);
+    Arc::new(Schema::new(fields))
+}
+
+/// Builders for the three columns of a block
+#[derive(Default)]
+struct BlockColumns {
+    provider: UInt16Builder,
+    block_id: UInt16Builder,
+    data_value: Int32Builder,
+}
+
+impl BlockColumns {
+    fn append(&mut self, block: Option<MetaBlock>) {
+        self.provider
+            .append_option(block.as_ref().map(|x| x.get_block().get_provider()));
+        self.block_id
+            .append_option(block.as_ref().map(|x| x.get_block().get_id()));
+        self.data_value
+            .append_option(block.and_then(|x| x.get_meta_data().get_data_value()));
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.provider.finish()),
+            Arc::new(self.block_id.finish()),
+            Arc::new(self.data_value.finish()),
+        ]
+    }
+}
+
+/// Builds a RecordBatch out of transactions, one row at a time
+#[derive(Default)]
+pub struct TransactionBatchBuilder {
+    rows: usize,
+    id: UInt32Builder,
+    sub_id: UInt32Builder,
+    time: TimestampMicrosecondBuilder,
+    utc_offset: Int32Builder,
+    owner: StringBuilder,
+    world: Int32Builder,
+    x: Int32Builder,
+    y: Int32Builder,
+    z: Int32Builder,
+    action: StringBuilder,
+    category: StringBuilder,
+    block: BlockColumns,
+    replaced: BlockColumns,
+    undone_id: UInt32Builder,
+    undone_sub_id: UInt32Builder,
+    biome: UInt16Builder,
+    entity: StringBuilder,
+}
+
+impl TransactionBatchBuilder {
+    /// Creates a builder with no rows
+    pub fn new() -> TransactionBatchBuilder {
+        TransactionBatchBuilder::default()
+    }
+
+    /// Returns the number of rows added since the last batch was finished
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns true if no rows were added since the last batch was finished
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    /// Adds the row of a transaction
+    pub fn append(&mut self, transaction: &Transaction) {
+        let raw = transaction.get_transaction();
+        let coords = raw.get_coords();
+        self.rows += 1;
+        self.id.append_value(transaction.get_id().get_id());
+        self.sub_id.append_value(transaction.get_id().get_sub_id());
+        self.time
+            .append_option(raw.get_time().map(|x| x.timestamp_micros()));
+        self.utc_offset
+            .append_option(raw.get_offset().map(|x| x.local_minus_utc()));
+        self.owner.append_value(raw.get_owner().to_string());
+        self.world.append_value(raw.get_world().get_id());
+        self.x.append_option(coords.map(|c| c.0));
+        self.y.append_option(coords.map(|c| c.1));
+        self.z.append_option(coords.map(|c| c.2));
+        self.category
+            .append_option(raw.get_category().map(|x| x.get_name()));
+
+        let mut block = None;
+        let mut replaced = None;
+        let mut undone = None;
+        let mut biome = None;
+        let mut entity = None;
+        let action = match raw.get_transaction_type() {
+            TransactionType::Set { block_set } => {
+                block = Some(block_set.get());
+                "set"
+            }
+            TransactionType::Replace {
+                block_current,
+                block_set,
+            } => {
+                block = Some(block_set.get());
+                replaced = Some(block_current.get());
+                "replace"
+            }
+            TransactionType::Undo { transaction } => {
+                undone = Some(transaction);
+                "undo"
+            }
+            TransactionType::SetBiome { biome: set } => {
+                biome = Some(set.get_id());
+                "set_biome"
+            }
+            TransactionType::SetDecoration { entity: placed, .. } => {
+                entity = Some(placed);
+                "set_decoration"
+            }
+            TransactionType::RemoveDecoration { entity: broken } => {
+                entity = Some(broken);
+                "remove_decoration"
+            }
+            TransactionType::Explode { block_set, .. } => {
+                block = Some(block_set.get());
+                "explode"
+            }
+            TransactionType::PistonMove { block_set, .. } => {
+                block = Some(block_set.get());
+                "piston_move"
+            }
+        };
+        self.action.append_value(action);
+        self.block.append(block);
+        self.replaced.append(replaced);
+        self.undone_id.append_option(undone.map(|x| x.get_id()));
+        self.undone_sub_id
+            .append_option(undone.map(|x| x.get_sub_id()));
+        self.biome.append_option(biome);
+        self.entity.append_option(entity.map(|x| x.to_string()));
+    }
+
+    /// Returns a batch of the rows added so far, and starts over with no rows
+    pub fn finish(&mut self) -> RecordBatch {
+        self.rows = 0;
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(self.id.finish()),
+            Arc::new(self.sub_id.finish()),
+            Arc::new(self.time.finish().with_timezone("UTC")),
+            Arc::new(self.utc_offset.finish()),
+            Arc::new(self.owner.finish()),
+            Arc::new(self.world.finish()),
+            Arc::new(self.x.finish()),
+            Arc::new(self.y.finish()),
+            Arc::new(self.z.finish()),
+            Arc::new(self.action.finish()),
+            Arc::new(self.category.finish()),
+        ];
+        columns.extend(self.block.finish());
+        columns.extend(self.replaced.finish());
+        columns.push(Arc::new(self.undone_id.finish()));
+        columns.push(Arc::new(self.undone_sub_id.finish()));
+        columns.push(Arc::new(self.biome.finish()));
+        columns.push(Arc::new(self.entity.finish()));
+        RecordBatch::try_new(transaction_schema(), columns)
+            .expect("columns are built to match the schema")
+    }
+}
+
+/// Lazily builds the batches of a query, see Query::record_batches
+///
+/// Also a RecordBatchReader, so it can be handed to anything reading Arrow streams.
+pub struct TransactionBatches {
+    transactions: Box<dyn Iterator<Item = Transaction>>,
+    batch_size: usize,
+    builder: TransactionBatchBuilder,
+}
+
+impl Iterator for TransactionBatches {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Result<RecordBatch, ArrowError>> {
+        for transaction in self.transactions.by_ref().take(self.batch_size) {
+            self.builder.append(&transaction);
+        }
+        if self.builder.is_empty() {
+            None
+        } else {
+            Some(Ok(self.builder.finish()))
+        }
+    }
+}
+
+impl RecordBatchReader for TransactionBatches {
+    fn schema(&self) -> SchemaRef {
+        transaction_schema()
+    }
+}
+
+impl Query {
+    /// Returns the matching transactions as a single batch, in the order of the query
+    pub fn to_record_batch(&self) -> RecordBatch {
+        let mut builder = TransactionBatchBuilder::new();
+        for transaction in self.iter() {
+            builder.append(&transaction);
+        }
+        builder.finish()
+    }
+
+    /// Returns the matching transactions as batches of at most batch_size rows, in the order of
+    /// the query, built as they are read
+    ///
+    /// Returns None if batch_size is zero
+    pub fn record_batches(&self, batch_size: usize) -> Option<TransactionBatches> {
+        if batch_size == 0 {
+            return None;
+        }
+        Some(TransactionBatches {
+            transactions: Box::new(self.iter()),
+            batch_size,
+            builder: TransactionBatchBuilder::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Int32Type, TimestampMicrosecondType, UInt16Type, UInt32Type};
+    use arrow_array::Array;
+    use chrono::{FixedOffset, TimeZone};
+    use Rewind;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn queries_become_batches() {
+        let rewind = Rewind::with_node_id(block(0), 0);
+        let zone = FixedOffset::east_opt(3600).unwrap();
+        let time = zone.with_ymd_and_hms(2020, 1, 1, 12, 0, 0).unwrap();
+        for x in 0..5 {
+            let replace = RawTransactionBuilder::new(TransactionType::new_replace(
+                block(0),
+                block(x as u16 + 1),
+            ))
+            .set_time(time)
+            .set_x_coord(x)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+            rewind.apply_transaction(replace).unwrap();
+        }
+        let undo = RawTransactionBuilder::new(TransactionType::new_undo(
+            TransactionID::new_from_parts(0, 0),
+        ))
+        .build_transaction()
+        .unwrap();
+        rewind.apply_transaction(undo).unwrap();
+
+        let batch = rewind.query().to_record_batch();
+        assert_eq!(batch.num_rows(), 6);
+        assert!(batch.schema() == transaction_schema());
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let block_ids = column("block_id");
+        let block_ids = block_ids.as_primitive::<UInt16Type>();
+        assert_eq!(block_ids.value(4), 5);
+        assert!(block_ids.is_null(5));
+        assert_eq!(
+            column("replaced_block_id")
+                .as_primitive::<UInt16Type>()
+                .value(0),
+            0
+        );
+        assert_eq!(column("undone_id").as_primitive::<UInt32Type>().value(5), 0);
+        assert_eq!(column("action").as_string::<i32>().value(5), "undo");
+        assert_eq!(
+            column("utc_offset").as_primitive::<Int32Type>().value(0),
+            3600
+        );
+        assert_eq!(
+            column("time")
+                .as_primitive::<TimestampMicrosecondType>()
+                .value(0),
+            time.timestamp_micros()
+        );
+
+        let mut query = rewind.query();
+        query.set_newest_first();
+        assert!(query.record_batches(0).is_none());
+        let batches: Vec<RecordBatch> = query
+            .record_batches(4)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let sizes: Vec<usize> = batches.iter().map(|x| x.num_rows()).collect();
+        assert!(sizes == vec![4, 2]);
+        assert_eq!(
+            batches[0].column(0).as_primitive::<UInt32Type>().value(0),
+            5
+        );
+    }
+}
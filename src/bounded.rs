@@ -0,0 +1,423 @@
+//! Provides bounded streams of committed transactions, for consumers that may fall behind
+//!
+//! A plain subscription buffers every transaction its consumer has not read yet, without limit.
+//! A bounded subscription holds at most a fixed number, and its overflow policy decides what
+//! happens when a transaction is committed while it is full.
+//!
+//! Commits send their transactions to the bounded subscriptions while still holding the locks of
+//! the Rewind, so they arrive in commit order, and never wait on a consumer. Subscriptions with
+//! OverflowPolicy::Block are sent them from a delivery thread instead, through an outbox that
+//! holds as many transactions as the largest of them.
+
+use data::*;
+use std::cmp;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What a full bounded subscription does with a newly committed transaction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drops the oldest transaction not read yet, to make room for the new one
+    DropOldest,
+    /// Holds newer transactions back until the consumer has read one
+    ///
+    /// Commits never wait on the consumer. Transactions are delivered by a separate thread, and
+    /// wait in an outbox while the subscription is full, which holds as many as the largest
+    /// subscription with this policy and drops its oldest transaction once that is full too.
+    /// Delivery happens one transaction at a time, so a slow consumer holds up every subscription
+    /// with this policy.
+    Block,
+    /// Drops the transactions not read yet that changed the same block as the new one, as only
+    /// the latest state of a block matters, or the oldest transaction if there are none
+    Coalesce,
+}
+
+/// State shared between the two ends of a bounded subscription
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Signaled whenever a transaction is pushed or popped, or either end goes away
+    changed: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+struct Queue {
+    transactions: VecDeque<Transaction>,
+    dropped: u64,
+    sender_gone: bool,
+    receiver_gone: bool,
+}
+
+/// Receiving end of a bounded subscription, see Rewind::subscribe_bounded
+///
+/// Dropping it unsubscribes.
+pub struct BoundedReceiver {
+    shared: Arc<Shared>,
+}
+
+/// Sending end of a bounded subscription, held by the Rewind
+pub(crate) struct BoundedSender {
+    shared: Arc<Shared>,
+}
+
+/// Creates a bounded subscription holding at most capacity transactions
+///
+/// The capacity must not be zero
+pub(crate) fn bounded(capacity: usize, policy: OverflowPolicy) -> (BoundedSender, BoundedReceiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            transactions: VecDeque::with_capacity(capacity),
+            dropped: 0,
+            sender_gone: false,
+            receiver_gone: false,
+        }),
+        changed: Condvar::new(),
+        capacity,
+        policy,
+    });
+    let sender = BoundedSender {
+        shared: shared.clone(),
+    };
+    (sender, BoundedReceiver { shared })
+}
+
+impl BoundedSender {
+    /// Sends a transaction, applying the overflow policy if the subscription is full
+    ///
+    /// Returns false if the receiver has been dropped
+    pub(crate) fn send(&self, transaction: Transaction) -> bool {
+        let shared = &*self.shared;
+        let mut queue = shared.queue.lock().unwrap();
+        while shared.policy == OverflowPolicy::Block
+            && queue.transactions.len() >= shared.capacity
+            && !queue.receiver_gone
+        {
+            queue = shared.changed.wait(queue).unwrap();
+        }
+        if queue.receiver_gone {
+            return false;
+        }
+
+        if queue.transactions.len() >= shared.capacity {
+            let before = queue.transactions.len();
+            if shared.policy == OverflowPolicy::Coalesce {
+                let key = block_key(&transaction);
+                if key.is_some() {
+                    queue.transactions.retain(|x| block_key(x) != key);
+                }
+            }
+            if queue.transactions.len() >= shared.capacity {
+                queue.transactions.pop_front();
+            }
+            queue.dropped += (before - queue.transactions.len()) as u64;
+        }
+        queue.transactions.push_back(transaction);
+        shared.changed.notify_all();
+        true
+    }
+}
+
+impl BoundedSender {
+    /// Counts transactions dropped before they reached the subscription
+    fn add_dropped(&self, count: u64) {
+        if count > 0 {
+            self.shared.queue.lock().unwrap().dropped += count;
+        }
+    }
+}
+
+impl Drop for BoundedSender {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().sender_gone = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Every bounded subscription of a Rewind
+pub(crate) struct BoundedSenders {
+    /// Subscriptions sent transactions as they are committed, as sending to them never waits
+    direct: Mutex<Vec<BoundedSender>>,
+    /// Subscriptions with OverflowPolicy::Block, sent transactions from the delivery thread
+    blocking: Arc<Blocking>,
+}
+
+/// Subscriptions with OverflowPolicy::Block, shared with the delivery thread
+struct Blocking {
+    /// Subscriptions with the sequence number of the first transaction committed after them
+    senders: Mutex<Vec<(u64, BoundedSender)>>,
+    outbox: Mutex<Outbox>,
+    /// Signaled whenever a transaction is queued, or every Rewind sharing the subscriptions is
+    /// gone
+    queued: Condvar,
+}
+
+/// Transactions waiting to be delivered, numbered in commit order
+struct Outbox {
+    transactions: VecDeque<(u64, Transaction)>,
+    next: u64,
+    /// Largest capacity of the subscriptions, or zero if there are none, in which case nothing
+    /// is queued
+    capacity: usize,
+    started: bool,
+    closed: bool,
+}
+
+impl BoundedSenders {
+    /// Creates a set of bounded subscriptions with no one listening
+    pub(crate) fn new() -> BoundedSenders {
+        BoundedSenders {
+            direct: Mutex::new(Vec::new()),
+            blocking: Arc::new(Blocking {
+                senders: Mutex::new(Vec::new()),
+                outbox: Mutex::new(Outbox {
+                    transactions: VecDeque::new(),
+                    next: 0,
+                    capacity: 0,
+                    started: false,
+                    closed: false,
+                }),
+                queued: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Adds a subscription, which is sent every transaction committed from now on
+    ///
+    /// Starts the delivery thread with the first subscription with OverflowPolicy::Block
+    pub(crate) fn subscribe(&self, sender: BoundedSender) {
+        if sender.shared.policy != OverflowPolicy::Block {
+            self.direct.lock().unwrap().push(sender);
+            return;
+        }
+        let mut outbox = self.blocking.outbox.lock().unwrap();
+        outbox.capacity = cmp::max(outbox.capacity, sender.shared.capacity);
+        self.blocking
+            .senders
+            .lock()
+            .unwrap()
+            .push((outbox.next, sender));
+        if !outbox.started {
+            outbox.started = true;
+            let blocking = self.blocking.clone();
+            thread::spawn(move || blocking.run());
+        }
+    }
+
+    /// Returns true if there are no subscriptions, counting those whose receiver is gone until
+    /// the next transaction is sent to them
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.direct.lock().unwrap().is_empty() && self.blocking.senders.lock().unwrap().is_empty()
+    }
+
+    /// Returns the number of transactions waiting for the delivery thread
+    #[cfg(test)]
+    pub(crate) fn outbox_len(&self) -> usize {
+        self.blocking.outbox.lock().unwrap().transactions.len()
+    }
+
+    /// Sends a committed transaction to every subscription, which must happen while the commit
+    /// holds the locks of the Rewind, so they are sent in commit order
+    ///
+    /// Never waits on a consumer. Subscriptions with OverflowPolicy::Block get the transaction
+    /// through the outbox, which drops its oldest transaction once it is full.
+    pub(crate) fn push(&self, transaction: Transaction) {
+        self.direct
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(transaction.clone()));
+
+        let mut outbox = self.blocking.outbox.lock().unwrap();
+        if outbox.capacity == 0 {
+            return;
+        }
+        let sequence = outbox.next;
+        outbox.next += 1;
+        if outbox.transactions.len() >= outbox.capacity {
+            // Counted as dropped by the delivery thread, from the gap in the sequence numbers
+            outbox.transactions.pop_front();
+        }
+        outbox.transactions.push_back((sequence, transaction));
+        self.blocking.queued.notify_one();
+    }
+}
+
+impl Drop for BoundedSenders {
+    fn drop(&mut self) {
+        self.blocking.outbox.lock().unwrap().closed = true;
+        self.blocking.queued.notify_one();
+    }
+}
+
+impl Blocking {
+    /// Delivers the queued transactions one at a time, in order, until every Rewind sharing the
+    /// subscriptions is gone and the outbox is empty
+    fn run(&self) {
+        let mut expected = 0;
+        loop {
+            let (sequence, transaction) = {
+                let mut outbox = self.outbox.lock().unwrap();
+                loop {
+                    if let Some(next) = outbox.transactions.pop_front() {
+                        break next;
+                    }
+                    if outbox.closed {
+                        return;
+                    }
+                    // Nothing is queued again until someone subscribes
+                    if self.senders.lock().unwrap().is_empty() {
+                        outbox.capacity = 0;
+                    }
+                    outbox = self.queued.wait(outbox).unwrap();
+                }
+            };
+
+            // Taken out, so subscribing does not wait on a blocked delivery
+            let mut senders = mem::take(&mut *self.senders.lock().unwrap());
+            senders.retain(|&(from, ref sender)| {
+                if sequence < from {
+                    return true;
+                }
+                sender.add_dropped(sequence - cmp::max(expected, from));
+                sender.send(transaction.clone())
+            });
+            self.senders.lock().unwrap().append(&mut senders);
+            expected = sequence + 1;
+        }
+    }
+}
+
+/// Returns the block a transaction changes, if it changes one directly
+fn block_key(transaction: &Transaction) -> Option<(WorldId, (i32, i32, i32))> {
+    let raw = transaction.get_transaction();
+    if raw.is_set_biome() || raw.is_decoration() || raw.is_multi_block() {
+        return None;
+    }
+    raw.get_coords().map(|coords| (raw.get_world(), coords))
+}
+
+impl BoundedReceiver {
+    /// Returns the oldest transaction not read yet, waiting for one if there is none
+    ///
+    /// Returns None once every Rewind sharing the subscription is gone, and everything has been
+    /// read
+    pub fn recv(&self) -> Option<Transaction> {
+        let shared = &*self.shared;
+        let mut queue = shared.queue.lock().unwrap();
+        loop {
+            if let Some(transaction) = queue.transactions.pop_front() {
+                shared.changed.notify_all();
+                return Some(transaction);
+            }
+            if queue.sender_gone {
+                return None;
+            }
+            queue = shared.changed.wait(queue).unwrap();
+        }
+    }
+
+    /// Returns the oldest transaction not read yet, waiting up to timeout for one
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Transaction> {
+        let shared = &*self.shared;
+        let deadline = Instant::now() + timeout;
+        let mut queue = shared.queue.lock().unwrap();
+        loop {
+            if let Some(transaction) = queue.transactions.pop_front() {
+                shared.changed.notify_all();
+                return Some(transaction);
+            }
+            let now = Instant::now();
+            if queue.sender_gone || now >= deadline {
+                return None;
+            }
+            queue = shared
+                .changed
+                .wait_timeout(queue, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Returns the oldest transaction not read yet, without waiting
+    pub fn try_recv(&self) -> Option<Transaction> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let transaction = queue.transactions.pop_front();
+        if transaction.is_some() {
+            self.shared.changed.notify_all();
+        }
+        transaction
+    }
+
+    /// Returns the number of transactions dropped by the overflow policy so far
+    pub fn get_dropped(&self) -> u64 {
+        self.shared.queue.lock().unwrap().dropped
+    }
+}
+
+impl Drop for BoundedReceiver {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().receiver_gone = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn set_at(id: u16, x: i32) -> Transaction {
+        let block = MetaBlock::fuse(Block::new_from_ids(0, 1), MetaData::new());
+        let raw = RawTransactionBuilder::new(TransactionType::new_set(block))
+            .set_x_coord(x)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+        Transaction::new(raw, TransactionID::new_from_parts(id as u32, 0))
+    }
+
+    fn drain(receiver: &BoundedReceiver) -> Vec<Transaction> {
+        let mut output = Vec::new();
+        while let Some(transaction) = receiver.try_recv() {
+            output.push(transaction);
+        }
+        output
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let (sender, receiver) = bounded(2, OverflowPolicy::DropOldest);
+        for i in 0..4 {
+            assert!(sender.send(set_at(i, i as i32)));
+        }
+        assert!(drain(&receiver) == vec![set_at(2, 2), set_at(3, 3)]);
+        assert_eq!(receiver.get_dropped(), 2);
+
+        let (sender, receiver) = bounded(2, OverflowPolicy::Coalesce);
+        sender.send(set_at(0, 0));
+        sender.send(set_at(1, 1));
+        sender.send(set_at(2, 0));
+        sender.send(set_at(3, 5));
+        assert!(drain(&receiver) == vec![set_at(2, 0), set_at(3, 5)]);
+        assert_eq!(receiver.get_dropped(), 2);
+
+        // A blocked sender resumes once the receiver reads, or goes away
+        let (sender, receiver) = bounded(1, OverflowPolicy::Block);
+        let writer = thread::spawn(move || (0..3).all(|i| sender.send(set_at(i, 0))));
+        assert!(receiver.recv() == Some(set_at(0, 0)));
+        assert!(receiver.recv() == Some(set_at(1, 0)));
+        assert!(receiver.recv() == Some(set_at(2, 0)));
+        assert!(writer.join().unwrap());
+        assert!(receiver.recv().is_none());
+        assert!(receiver.recv_timeout(Duration::from_millis(1)).is_none());
+
+        let (sender, receiver) = bounded(1, OverflowPolicy::Block);
+        let writer = thread::spawn(move || (0..3).all(|i| sender.send(set_at(i, 0))));
+        drop(receiver);
+        assert!(!writer.join().unwrap());
+    }
+}
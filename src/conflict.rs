@@ -0,0 +1,321 @@
+//! Provides pluggable policies for resolving conflicting transactions
+//!
+//! A conflict happens when a Replace expects a block to be in one state, but finds it in
+//! another, either because the world changed between a client reading it and writing it, or
+//! because history was rewritten underneath it by an Undo or by merging in transactions from
+//! another node.
+//!
+//! The policy is consulted both when a transaction is first applied and whenever history is
+//! replayed, so it must be deterministic: given the same conflict it has to give the same
+//! resolution, or replicas and merged nodes will not converge.
+//!
+//! A transaction can carry its own resolution, set with RawTransactionBuilder::set_on_conflict,
+//! which is used in place of the policy.
+
+use data::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// What to do with a conflicting transaction
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Resolution {
+    /// The transaction fails, and leaves the block alone
+    Reject,
+    /// The transaction is applied anyway, as if its precondition had held
+    Apply,
+    /// The transaction is committed, but leaves the block alone, so it stays in history as a
+    /// failed attempt
+    ///
+    /// Like every Replace, it is resolved again whenever history is replayed, so it takes effect
+    /// if an Undo later makes its precondition hold.
+    Record,
+}
+
+/// Describes a Replace whose precondition did not hold
+pub struct Conflict {
+    transaction: RawTransaction,
+    current_block: MetaBlock,
+    last_writer: Option<Transaction>,
+}
+
+impl Conflict {
+    /// Creates a new conflict
+    pub fn new(
+        transaction: RawTransaction,
+        current_block: MetaBlock,
+        last_writer: Option<Transaction>,
+    ) -> Conflict {
+        Conflict {
+            transaction,
+            current_block,
+            last_writer,
+        }
+    }
+
+    /// Returns the conflicting transaction
+    pub fn get_transaction(&self) -> RawTransaction {
+        self.transaction.clone()
+    }
+
+    /// Returns the block the transaction actually found
+    pub fn get_current_block(&self) -> MetaBlock {
+        self.current_block.clone()
+    }
+
+    /// Returns the transaction responsible for the current state of the block
+    ///
+    /// This is None if the block has never been written to, and is still the default
+    pub fn get_last_writer(&self) -> Option<Transaction> {
+        self.last_writer.clone()
+    }
+}
+
+/// Decides what happens to conflicting transactions
+pub trait ConflictPolicy: Send + Sync {
+    /// Resolves a conflict, must be deterministic
+    fn resolve(&self, conflict: &Conflict) -> Resolution;
+}
+
+/// Custom policies can be written as closures
+impl<F> ConflictPolicy for F
+where
+    F: Fn(&Conflict) -> Resolution + Send + Sync,
+{
+    fn resolve(&self, conflict: &Conflict) -> Resolution {
+        self(conflict)
+    }
+}
+
+/// Resolves a conflict with the resolution the transaction carries, or with the policy if it
+/// does not carry one
+pub(crate) fn resolve(policy: &dyn ConflictPolicy, conflict: &Conflict) -> Resolution {
+    match conflict.get_transaction().get_on_conflict() {
+        Some(resolution) => resolution,
+        None => policy.resolve(conflict),
+    }
+}
+
+/// Rejects every conflicting transaction
+///
+/// This is the default policy.
+pub struct RejectConflicts;
+
+impl ConflictPolicy for RejectConflicts {
+    fn resolve(&self, _: &Conflict) -> Resolution {
+        Resolution::Reject
+    }
+}
+
+/// Applies every conflicting transaction, so the last writer always wins
+pub struct LastWriterWins;
+
+impl ConflictPolicy for LastWriterWins {
+    fn resolve(&self, _: &Conflict) -> Resolution {
+        Resolution::Apply
+    }
+}
+
+/// Commits every conflicting transaction without applying it, keeping it in history as a failed
+/// attempt
+pub struct RecordConflicts;
+
+impl ConflictPolicy for RecordConflicts {
+    fn resolve(&self, _: &Conflict) -> Resolution {
+        Resolution::Record
+    }
+}
+
+/// Lets owners with a higher priority overwrite the work of owners with a lower one
+///
+/// A conflicting transaction is applied if its owner's priority is at least that of the owner of
+/// the last writer of the block. Owners without an explicit priority, and blocks that have never
+/// been written to, use the default priority.
+pub struct OwnerPriority {
+    priorities: HashMap<Uuid, i32>,
+    default_priority: i32,
+}
+
+impl OwnerPriority {
+    /// Creates a new policy, where every owner has the given default priority
+    pub fn new(default_priority: i32) -> OwnerPriority {
+        OwnerPriority {
+            priorities: HashMap::new(),
+            default_priority,
+        }
+    }
+
+    /// Sets the priority of an owner
+    pub fn set_priority(&mut self, owner: Uuid, priority: i32) -> &mut Self {
+        self.priorities.insert(owner, priority);
+        self
+    }
+
+    /// Returns the priority of an owner
+    pub fn get_priority(&self, owner: Uuid) -> i32 {
+        *self
+            .priorities
+            .get(&owner)
+            .unwrap_or(&self.default_priority)
+    }
+}
+
+impl ConflictPolicy for OwnerPriority {
+    fn resolve(&self, conflict: &Conflict) -> Resolution {
+        let incoming = self.get_priority(conflict.get_transaction().get_owner());
+        let existing = match conflict.get_last_writer() {
+            Some(writer) => self.get_priority(writer.get_transaction().get_owner()),
+            None => self.default_priority,
+        };
+        if incoming >= existing {
+            Resolution::Apply
+        } else {
+            Resolution::Reject
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Rewind, RewindBuilder};
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    fn transaction(owner: Uuid, transaction_type: TransactionType) -> RawTransaction {
+        RawTransactionBuilder::new(transaction_type)
+            .set_owner(owner)
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap()
+    }
+
+    fn current(rewind: &Rewind) -> MetaBlock {
+        rewind
+            .get_world_state()
+            .get_block_defaulting(0, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn default_rejects_stale_replace() {
+        let player = Uuid::new_v4();
+        let rewind = Rewind::new(block(0));
+        rewind.apply_transaction(transaction(player, TransactionType::new_set(block(1))));
+        let stale = TransactionType::new_replace(block(0), block(2));
+        assert!(rewind
+            .apply_transaction(transaction(player, stale))
+            .is_none());
+        assert!(current(&rewind) == block(1));
+    }
+
+    #[test]
+    fn last_writer_wins() {
+        let player = Uuid::new_v4();
+        let rewind = RewindBuilder::new(block(0))
+            .set_conflict_policy(LastWriterWins)
+            .build();
+        rewind.apply_transaction(transaction(player, TransactionType::new_set(block(1))));
+        let stale = TransactionType::new_replace(block(0), block(2));
+        assert!(rewind
+            .apply_transaction(transaction(player, stale))
+            .is_some());
+        assert!(current(&rewind) == block(2));
+        // Replaying history agrees with the live world
+        assert!(rewind.get_block_history(0, 0, 0)[1].0 == block(2));
+    }
+
+    #[test]
+    fn owner_priority() {
+        let (player, admin) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut policy = OwnerPriority::new(0);
+        policy.set_priority(admin, 10);
+        let rewind = RewindBuilder::new(block(0))
+            .set_conflict_policy(policy)
+            .build();
+
+        rewind.apply_transaction(transaction(admin, TransactionType::new_set(block(1))));
+        let stale = TransactionType::new_replace(block(0), block(2));
+        assert!(rewind
+            .apply_transaction(transaction(player, stale.clone()))
+            .is_none());
+        assert!(rewind
+            .apply_transaction(transaction(admin, stale))
+            .is_some());
+        assert!(current(&rewind) == block(2));
+    }
+
+    #[test]
+    fn record_conflicts() {
+        let player = Uuid::new_v4();
+        let rewind = Rewind::new(block(0));
+        let set = rewind
+            .apply_transaction(transaction(player, TransactionType::new_set(block(1))))
+            .unwrap();
+        let stale = TransactionType::new_replace(block(0), block(2));
+        let recorded = RawTransactionBuilder::new(stale.clone())
+            .set_on_conflict(Resolution::Record)
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+        let recorded = rewind.apply_transaction(recorded).unwrap();
+        assert!(current(&rewind) == block(1));
+        assert_eq!(rewind.is_replace_applied(recorded.get_id()), Some(false));
+        assert_eq!(rewind.is_replace_applied(set.get_id()), None);
+        assert_eq!(rewind.get_block_history(0, 0, 0).len(), 2);
+
+        // Undoing the Set makes the precondition hold
+        let undo = RawTransactionBuilder::new(TransactionType::new_undo(set.get_id()))
+            .build_transaction()
+            .unwrap();
+        rewind.apply_transaction(undo).unwrap();
+        assert!(current(&rewind) == block(2));
+        assert_eq!(rewind.is_replace_applied(recorded.get_id()), Some(true));
+
+        // The resolution of the transaction wins over the policy
+        let rewind = RewindBuilder::new(block(0))
+            .set_conflict_policy(RecordConflicts)
+            .build();
+        rewind.apply_transaction(transaction(player, TransactionType::new_set(block(1))));
+        let recorded = rewind
+            .apply_transaction(transaction(player, stale.clone()))
+            .unwrap();
+        assert_eq!(rewind.is_replace_applied(recorded.get_id()), Some(false));
+        let forced = RawTransactionBuilder::new(stale)
+            .set_on_conflict(Resolution::Apply)
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+        assert!(rewind.apply_transaction(forced).is_some());
+        assert!(current(&rewind) == block(2));
+    }
+
+    #[test]
+    fn custom_policy() {
+        let rewind = RewindBuilder::new(block(0))
+            .set_conflict_policy(|conflict: &Conflict| {
+                if conflict.get_current_block() == block(1) {
+                    Resolution::Apply
+                } else {
+                    Resolution::Reject
+                }
+            })
+            .build();
+        let owner = Uuid::new_v4();
+        let stale = TransactionType::new_replace(block(5), block(2));
+        assert!(rewind
+            .apply_transaction(transaction(owner, stale.clone()))
+            .is_none());
+        rewind.apply_transaction(transaction(owner, TransactionType::new_set(block(1))));
+        assert!(rewind
+            .apply_transaction(transaction(owner, stale))
+            .is_some());
+    }
+}
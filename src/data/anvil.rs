@@ -0,0 +1,536 @@
+//! Provides a baseline read lazily from the region files of a vanilla world
+//!
+//! An AnvilWorld reads the `region` directory of a minecraft world, in the Anvil format used
+//! since 1.2, as a BaselineWorld. Rewind can then start tracking an existing server world as an
+//! overlay, without importing every block: a chunk column is only read from its region file the
+//! first time one of its blocks is needed, and only changes made after that are recorded.
+//!
+//! Block states are supported as stored by 1.13 and later, both in the older `Level` layout and
+//! the layout used since 1.18. Chunks compressed with gzip, zlib, or not at all are read
+//! natively through flate2, other compression schemes, like LZ4, need a decompressor to be set.
+
+use data::baseline::*;
+use data::block::*;
+use data::nbt::*;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Decompresses chunks stored with a compression scheme not supported natively
+///
+/// Called with the compression id from the region file, and the compressed bytes
+pub type Decompressor = dyn Fn(u8, &[u8]) -> io::Result<Vec<u8>> + Send + Sync;
+
+/// Size of the sectors region files are split into
+const SECTOR_SIZE: u64 = 4096;
+
+/// Number of blocks in a section of a chunk column
+const SECTION_VOLUME: usize = 16 * 16 * 16;
+
+/// The blocks of a vanilla world, read lazily from its region files
+pub struct AnvilWorld {
+    directory: PathBuf,
+    dictonary: Arc<BlockDictonary>,
+    capacity: usize,
+    decompressor: Option<Arc<Decompressor>>,
+    columns: Mutex<Columns>,
+}
+
+/// A read chunk column, None if it has not been generated, and the tick it was last read at
+type CachedColumn = (u64, Option<Arc<Column>>);
+
+/// Recently read chunk columns, by chunk coordinates, aged in ticks of a counter
+struct Columns {
+    tick: u64,
+    columns: HashMap<(i32, i32), CachedColumn>,
+}
+
+/// The blocks of one chunk column, by section
+struct Column {
+    sections: HashMap<i32, Section>,
+}
+
+/// The blocks of a 16 block tall section of a chunk column
+struct Section {
+    /// Blocks not in the dictionary are None
+    palette: Vec<Option<MetaBlock>>,
+    /// Index into the palette of every block, in y, z, x order, empty if there is only one
+    indices: Vec<u16>,
+}
+
+impl AnvilWorld {
+    /// Creates a new baseline reading the region files in the given directory, usually the
+    /// `region` directory of a world
+    ///
+    /// Block states are looked up in the dictionary, blocks that are not in it have no baseline.
+    /// Keeps up to 1024 chunk columns in memory by default.
+    pub fn new<P: AsRef<Path>>(directory: P, dictonary: Arc<BlockDictonary>) -> AnvilWorld {
+        AnvilWorld {
+            directory: directory.as_ref().to_path_buf(),
+            dictonary,
+            capacity: 1024,
+            decompressor: None,
+            columns: Mutex::new(Columns {
+                tick: 0,
+                columns: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Sets how many decoded chunk columns are kept in memory, least recently read first out
+    ///
+    /// Returns None, leaving the baseline untouched, if the capacity is zero
+    pub fn set_capacity(&mut self, capacity: usize) -> Option<&mut Self> {
+        if capacity == 0 {
+            return None;
+        }
+        self.capacity = capacity;
+        Some(self)
+    }
+
+    /// Sets the decompressor used for chunks with a compression scheme not supported natively
+    pub fn set_decompressor<F>(&mut self, decompressor: F) -> &mut Self
+    where
+        F: Fn(u8, &[u8]) -> io::Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.decompressor = Some(Arc::new(decompressor));
+        self
+    }
+
+    /// Returns the directory region files are read from
+    pub fn get_directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Reads the block at the specified location
+    ///
+    /// Returns None if the chunk has not been generated, the location is above or below every
+    /// section of it, or the block is not in the dictionary. Fails if the region file can not be
+    /// read, or the chunk in it is corrupt.
+    pub fn read_block(&self, x: i32, y: i32, z: i32) -> io::Result<Option<MetaBlock>> {
+        let column = match self.get_column(x >> 4, z >> 4)? {
+            Some(column) => column,
+            None => return Ok(None),
+        };
+        let section = match column.sections.get(&(y >> 4)) {
+            Some(section) => section,
+            None => return Ok(None),
+        };
+        let index = match section.indices.len() {
+            0 => 0,
+            _ => section.indices[(((y & 15) << 8) | ((z & 15) << 4) | (x & 15)) as usize] as usize,
+        };
+        Ok(section.palette[index].clone())
+    }
+
+    /// Returns the chunk column with the given chunk coordinates, reading it if it is not in
+    /// memory
+    fn get_column(&self, x: i32, z: i32) -> io::Result<Option<Arc<Column>>> {
+        {
+            let mut columns = self.columns.lock().unwrap();
+            columns.tick += 1;
+            let tick = columns.tick;
+            if let Some(entry) = columns.columns.get_mut(&(x, z)) {
+                entry.0 = tick;
+                return Ok(entry.1.clone());
+            }
+        }
+
+        // Read without holding the lock, so columns are read in parallel
+        let column = match self.read_chunk(x, z)? {
+            Some(bytes) => {
+                let (_, nbt) =
+                    NbtValue::from_bytes(&bytes).ok_or_else(|| invalid("corrupt chunk nbt"))?;
+                Some(Arc::new(self.decode_column(&nbt)?))
+            }
+            None => None,
+        };
+
+        let mut columns = self.columns.lock().unwrap();
+        if columns.columns.len() >= self.capacity {
+            let oldest = columns
+                .columns
+                .iter()
+                .min_by_key(|(_, entry)| entry.0)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                columns.columns.remove(&oldest);
+            }
+        }
+        let tick = columns.tick;
+        columns.columns.insert((x, z), (tick, column.clone()));
+        Ok(column)
+    }
+
+    /// Reads the uncompressed NBT of the chunk with the given chunk coordinates
+    ///
+    /// None if its region file does not exist, or the chunk is not in it
+    fn read_chunk(&self, x: i32, z: i32) -> io::Result<Option<Vec<u8>>> {
+        let path = self.directory.join(format!("r.{}.{}.mca", x >> 5, z >> 5));
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        // The header starts with the location of every chunk in the region
+        let mut location = [0u8; 4];
+        file.seek(SeekFrom::Start((((x & 31) + (z & 31) * 32) * 4) as u64))?;
+        file.read_exact(&mut location)?;
+        let sector = u32::from_be_bytes([0, location[0], location[1], location[2]]);
+        if sector == 0 {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; 5];
+        file.seek(SeekFrom::Start(u64::from(sector) * SECTOR_SIZE))?;
+        file.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let compression = header[4];
+        if length == 0 {
+            return Err(invalid("empty chunk in region file"));
+        }
+        // The length counts itself, so it has to fit in the sectors after its own four bytes
+        if length as u64 + 4 > u64::from(location[3]) * SECTOR_SIZE {
+            return Err(invalid("chunk longer than its sectors in region file"));
+        }
+        // Chunks too large for the region file are stored in a file of their own
+        let data = if compression & 0x80 != 0 {
+            fs::read(self.directory.join(format!("c.{}.{}.mcc", x, z)))?
+        } else {
+            let mut data = vec![0; length - 1];
+            file.read_exact(&mut data)?;
+            data
+        };
+        self.decompress(compression & 0x7f, &data).map(Some)
+    }
+
+    /// Decompresses a chunk with the given compression id
+    fn decompress(&self, compression: u8, data: &[u8]) -> io::Result<Vec<u8>> {
+        match compression {
+            1 => read_all(GzDecoder::new(data)),
+            2 => read_all(ZlibDecoder::new(data)),
+            3 => Ok(data.to_vec()),
+            _ => match self.decompressor {
+                Some(ref decompressor) => decompressor(compression, data),
+                None => Err(invalid("unsupported chunk compression")),
+            },
+        }
+    }
+
+    /// Decodes the block states of a chunk column from its NBT
+    fn decode_column(&self, nbt: &NbtValue) -> io::Result<Column> {
+        if !matches!(nbt, NbtValue::Compound(_)) {
+            return Err(invalid("chunk nbt is not a compound"));
+        }
+        // Before 1.18 everything was nested under Level, with differently named keys
+        let (sections, states_key, palette_key, data_key) = match get(nbt, "Level") {
+            Some(level) => (get(level, "Sections"), None, "Palette", "BlockStates"),
+            None => (
+                get(nbt, "sections"),
+                Some("block_states"),
+                "palette",
+                "data",
+            ),
+        };
+        let sections = match sections {
+            Some(NbtValue::List(sections)) => sections.as_slice(),
+            Some(_) => return Err(invalid("chunk sections are not a list")),
+            None => &[],
+        };
+
+        let mut column = Column {
+            sections: HashMap::new(),
+        };
+        for section in sections {
+            let y = match get(section, "Y") {
+                Some(&NbtValue::Byte(y)) => i32::from(y),
+                Some(&NbtValue::Int(y)) => y,
+                _ => return Err(invalid("chunk section without a height")),
+            };
+            let states = match states_key {
+                Some(key) => match get(section, key) {
+                    Some(states) => states,
+                    None => continue,
+                },
+                None => section,
+            };
+            let palette = match get(states, palette_key) {
+                Some(NbtValue::List(palette)) if !palette.is_empty() => palette,
+                // Sections with only light data have no blocks
+                _ => continue,
+            };
+            let palette: Vec<Option<MetaBlock>> = palette
+                .iter()
+                .map(|entry| self.decode_state(entry))
+                .collect::<io::Result<_>>()?;
+            let indices = match get(states, data_key) {
+                Some(NbtValue::LongArray(data)) => unpack(data, palette.len())?,
+                _ if palette.len() == 1 => Vec::new(),
+                _ => return Err(invalid("chunk section without block states")),
+            };
+            column.sections.insert(y, Section { palette, indices });
+        }
+        Ok(column)
+    }
+
+    /// Looks up a block state from a palette entry in the dictionary
+    fn decode_state(&self, entry: &NbtValue) -> io::Result<Option<MetaBlock>> {
+        let name = match get(entry, "Name") {
+            Some(NbtValue::String(name)) => name,
+            _ => return Err(invalid("palette entry without a name")),
+        };
+        let mut state = name.clone();
+        if let Some(NbtValue::Compound(properties)) = get(entry, "Properties") {
+            let properties: Vec<String> = properties
+                .iter()
+                .filter_map(|(key, value)| match value {
+                    NbtValue::String(value) => Some(format!("{}={}", key, value)),
+                    _ => None,
+                })
+                .collect();
+            if !properties.is_empty() {
+                state = format!("{}[{}]", state, properties.join(","));
+            }
+        }
+        Ok(self.dictonary.encode_state(&state))
+    }
+}
+
+impl BaselineWorld for AnvilWorld {
+    /// Chunks that fail to read are treated as not generated
+    fn get_block(&self, x: i32, y: i32, z: i32) -> Option<MetaBlock> {
+        self.read_block(x, y, z).ok().and_then(|x| x)
+    }
+}
+
+/// Returns an entry of a compound, None if the value is not a compound or has no such entry
+fn get<'a>(value: &'a NbtValue, key: &str) -> Option<&'a NbtValue> {
+    match value {
+        NbtValue::Compound(entries) => entries.get(key),
+        _ => None,
+    }
+}
+
+/// Unpacks the palette index of every block of a section
+///
+/// Indices take as many bits as the largest one needs, but at least four. Since 1.16 indices
+/// never straddle two longs, before that they were packed back to back, both are recognized by
+/// the number of longs.
+fn unpack(data: &[i64], palette_len: usize) -> io::Result<Vec<u16>> {
+    let needed = (usize::BITS - (palette_len - 1).leading_zeros()) as usize;
+    let bits = needed.max(4);
+    let per_long = 64 / bits;
+    let mask = (1u64 << bits) - 1;
+    let straddles = if data.len() == SECTION_VOLUME.div_ceil(per_long) {
+        false
+    } else if data.len() == SECTION_VOLUME * bits / 64 {
+        true
+    } else {
+        return Err(invalid(
+            "chunk section has the wrong number of block states",
+        ));
+    };
+
+    let mut indices = Vec::with_capacity(SECTION_VOLUME);
+    for i in 0..SECTION_VOLUME {
+        let index = if straddles {
+            let bit = i * bits;
+            let (long, offset) = (bit / 64, bit % 64);
+            let mut value = data[long] as u64 >> offset;
+            if offset + bits > 64 {
+                value |= (data[long + 1] as u64) << (64 - offset);
+            }
+            value & mask
+        } else {
+            (data[i / per_long] as u64 >> ((i % per_long) * bits)) & mask
+        };
+        if index as usize >= palette_len {
+            return Err(invalid("block state outside of the palette"));
+        }
+        indices.push(index as u16);
+    }
+    Ok(indices)
+}
+
+/// Reads a decompressed chunk to its end
+fn read_all<R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::collections::BTreeMap;
+    use std::io::Write;
+
+    fn compound(entries: Vec<(&str, NbtValue)>) -> NbtValue {
+        NbtValue::Compound(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+
+    fn state(name: &str) -> NbtValue {
+        compound(vec![("Name", NbtValue::String(name.to_string()))])
+    }
+
+    /// A region file holding the given chunks, by their index in the region, uncompressed
+    fn region(chunks: &[(usize, NbtValue)]) -> Vec<u8> {
+        let mut file = vec![0u8; 2 * SECTOR_SIZE as usize];
+        for (index, nbt) in chunks {
+            let sector = file.len() / SECTOR_SIZE as usize;
+            let mut bytes = nbt.to_bytes("");
+            let length = bytes.len() as u32 + 1;
+            file[index * 4..index * 4 + 3].copy_from_slice(&(sector as u32).to_be_bytes()[1..]);
+            file.extend_from_slice(&length.to_be_bytes());
+            file.push(3);
+            file.append(&mut bytes);
+            let padded = file.len().div_ceil(SECTOR_SIZE as usize) * SECTOR_SIZE as usize;
+            file[index * 4 + 3] = (padded / SECTOR_SIZE as usize - sector) as u8;
+            file.resize(padded, 0);
+        }
+        file
+    }
+
+    #[test]
+    fn reads_region_files() {
+        let blocks = r#"{"minecraft:block": {"entries": {
+            "minecraft:air": {"protocol_id": 0},
+            "minecraft:stone": {"protocol_id": 1},
+            "minecraft:oak_log": {"protocol_id": 2}
+        }}}"#;
+        let dictonary = Arc::new(BlockDictonary::from_registry_json(blocks.as_bytes()).unwrap());
+        let stone = dictonary.encode_state("minecraft:stone").unwrap();
+        let log = dictonary.encode_state("minecraft:oak_log[axis=x]").unwrap();
+
+        // Stone with a log at the origin of the section, and a block not in the dictionary
+        let mut data = vec![0x1111_1111_1111_1111i64; 256];
+        data[0] = 0x1111_1111_1111_1112;
+        let palette = NbtValue::List(vec![
+            state("minecraft:air"),
+            state("minecraft:stone"),
+            compound(vec![
+                ("Name", NbtValue::String("minecraft:oak_log".to_string())),
+                (
+                    "Properties",
+                    compound(vec![("axis", NbtValue::String("x".to_string()))]),
+                ),
+            ]),
+        ]);
+        let section = compound(vec![
+            ("Y", NbtValue::Byte(-1)),
+            (
+                "block_states",
+                compound(vec![
+                    ("palette", palette),
+                    ("data", NbtValue::LongArray(data)),
+                ]),
+            ),
+        ]);
+        let uniform = compound(vec![
+            ("Y", NbtValue::Byte(0)),
+            (
+                "block_states",
+                compound(vec![(
+                    "palette",
+                    NbtValue::List(vec![state("mymod:unknown")]),
+                )]),
+            ),
+        ]);
+        let chunk = compound(vec![("sections", NbtValue::List(vec![section, uniform]))]);
+
+        let directory = std::env::temp_dir().join(format!("rewind-anvil-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        // The chunk at chunk coordinates (-1, 1), in the region at (-1, 0)
+        fs::write(directory.join("r.-1.0.mca"), region(&[(31 + 32, chunk)])).unwrap();
+        let mut anvil = AnvilWorld::new(&directory, dictonary);
+        assert!(anvil.set_capacity(0).is_none());
+        anvil.set_capacity(1).unwrap();
+
+        assert!(anvil.read_block(-16, -16, 16).unwrap() == Some(log.clone()));
+        assert!(anvil.read_block(-15, -16, 16).unwrap() == Some(stone.clone()));
+        assert!(anvil.read_block(-1, -1, 31).unwrap() == Some(stone));
+        assert!(anvil.read_block(-1, 0, 31).unwrap().is_none());
+        assert!(anvil.read_block(-1, 16, 31).unwrap().is_none());
+        assert!(anvil.read_block(0, 0, 0).unwrap().is_none());
+        assert!(anvil.read_block(-1, -1, 0).unwrap().is_none());
+        assert!(anvil.get_block(-16, -16, 16) == Some(log));
+
+        // Corrupt chunks fail to read, rather than reading as empty
+        fs::write(
+            directory.join("r.1.0.mca"),
+            region(&[(0, NbtValue::Int(0))]),
+        )
+        .unwrap();
+        assert!(anvil.read_block(512, 0, 0).is_err());
+        assert!(anvil.get_block(512, 0, 0).is_none());
+
+        // So do chunks claiming to be longer than their sectors, without allocating that much
+        let mut huge = region(&[(0, state("minecraft:stone"))]);
+        huge[2 * SECTOR_SIZE as usize..][..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        fs::write(directory.join("r.2.0.mca"), huge).unwrap();
+        assert!(anvil.read_block(1024, 0, 0).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn decompresses_chunks() {
+        let mut anvil = AnvilWorld::new(std::env::temp_dir(), Arc::new(BlockDictonary::new()));
+        let chunk = region(&[]);
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&chunk).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&chunk).unwrap();
+        let mut zlib = zlib.finish().unwrap();
+
+        assert!(anvil.decompress(1, &gzip).unwrap() == chunk);
+        assert!(anvil.decompress(2, &zlib).unwrap() == chunk);
+        assert!(anvil.decompress(3, &chunk).unwrap() == chunk);
+        zlib.truncate(zlib.len() / 2);
+        assert!(anvil.decompress(2, &zlib).is_err());
+
+        // Anything else, like LZ4, needs a decompressor
+        assert!(anvil.decompress(4, &chunk).is_err());
+        anvil.set_decompressor(|compression, data| {
+            assert_eq!(compression, 4);
+            Ok(data.to_vec())
+        });
+        assert!(anvil.decompress(4, &chunk).unwrap() == chunk);
+    }
+
+    #[test]
+    fn unpacks_both_layouts() {
+        // Five bits per index, 12 per long since 1.16, back to back before
+        let palette_len = 17;
+        let expected: Vec<u16> = (0..SECTION_VOLUME).map(|i| (i % 17) as u16).collect();
+        let mut padded = vec![0i64; SECTION_VOLUME.div_ceil(12)];
+        let mut packed = vec![0i64; SECTION_VOLUME * 5 / 64];
+        for (i, &index) in expected.iter().enumerate() {
+            padded[i / 12] |= (i64::from(index)) << ((i % 12) * 5);
+            let bit = i * 5;
+            packed[bit / 64] |= (i64::from(index)) << (bit % 64);
+            if bit % 64 + 5 > 64 {
+                packed[bit / 64 + 1] |= i64::from(index) >> (64 - bit % 64);
+            }
+        }
+        assert!(unpack(&padded, palette_len).unwrap() == expected);
+        assert!(unpack(&packed, palette_len).unwrap() == expected);
+        assert!(unpack(&packed[1..], palette_len).is_err());
+        assert!(unpack(&padded, 3).is_err());
+    }
+}
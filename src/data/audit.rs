@@ -0,0 +1,231 @@
+//! Provides flat audit records of transactions, for compliance archives and spreadsheets
+//!
+//! Every transaction becomes one record with the columns `id`, `time`, `owner`, `world`, `x`,
+//! `y`, `z`, `action`, `block`, `expected`, `biome`, `cause` and `entity`. Columns that do not
+//! apply to a transaction are left empty in CSV, and null in JSON Lines.
+//!
+//! `action` is one of `set`, `replace`, `undo`, `set_biome`, `set_decoration`,
+//! `remove_decoration`, `explode` or `piston_move`. `block` is the block a Set, Replace or Explode placed, or
+//! the item a SetDecoration leaves in an item frame, or the variant of a painting, and `expected`
+//! the block a Replace expected to find, both written like `0:1:2[facing=east]`. `cause` is the
+//! id of the transaction that led to this one, such as the transaction an Undo reverts, or the
+//! bucket placement a liquid flowed from. `entity` is the uuid of the decoration a transaction
+//! places, updates or breaks.
+
+use data::decoration::*;
+use data::transaction::*;
+use serde_json;
+use std::io::{self, Write};
+use uuid::Uuid;
+
+/// Format audit records are written in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AuditFormat {
+    /// Comma separated values, with a header row
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+/// A transaction, flattened into the columns of an audit
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Written as `id.sub_id`
+    pub id: String,
+    /// RFC 3339 timestamp
+    pub time: Option<String>,
+    pub owner: Uuid,
+    pub world: i32,
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub z: Option<i32>,
+    pub action: String,
+    pub block: Option<String>,
+    pub expected: Option<String>,
+    pub biome: Option<u16>,
+    pub cause: Option<String>,
+    #[serde(default)]
+    pub entity: Option<Uuid>,
+}
+
+/// Names of the CSV columns, in order
+const CSV_HEADER: &str = "id,time,owner,world,x,y,z,action,block,expected,biome,cause,entity";
+
+/// Formats a transaction id as `id.sub_id`
+fn format_id(id: TransactionID) -> String {
+    format!("{}.{}", id.get_id(), id.get_sub_id())
+}
+
+impl AuditRecord {
+    /// Flattens a transaction into a record
+    pub fn new(transaction: &Transaction) -> AuditRecord {
+        let raw = transaction.get_transaction();
+        let coords = raw.get_coords();
+        let mut record = AuditRecord {
+            id: format_id(transaction.get_id()),
+            time: raw.get_time().map(|x| x.to_rfc3339()),
+            owner: raw.get_owner(),
+            world: raw.get_world().get_id(),
+            x: coords.map(|x| x.0),
+            y: coords.map(|x| x.1),
+            z: coords.map(|x| x.2),
+            action: String::new(),
+            block: None,
+            expected: None,
+            biome: None,
+            cause: raw.get_cause().map(format_id),
+            entity: raw.get_decoration_entity(),
+        };
+        match raw.get_transaction_type() {
+            TransactionType::Set { block_set } => {
+                record.action = "set".to_string();
+                record.block = Some(block_set.to_string());
+            }
+            TransactionType::Replace {
+                block_current,
+                block_set,
+            } => {
+                record.action = "replace".to_string();
+                record.block = Some(block_set.to_string());
+                record.expected = Some(block_current.to_string());
+            }
+            TransactionType::Undo { transaction } => {
+                record.action = "undo".to_string();
+                record.cause = Some(format_id(*transaction));
+            }
+            TransactionType::SetBiome { biome } => {
+                record.action = "set_biome".to_string();
+                record.biome = Some(biome.get_id());
+            }
+            TransactionType::SetDecoration { decoration, .. } => {
+                record.action = "set_decoration".to_string();
+                let shown = decoration
+                    .get_item(EquipmentSlot::MainHand)
+                    .or(decoration.get_variant());
+                record.block = shown.map(|x| x.to_string());
+            }
+            TransactionType::RemoveDecoration { .. } => {
+                record.action = "remove_decoration".to_string();
+            }
+            TransactionType::Explode { block_set, .. } => {
+                record.action = "explode".to_string();
+                record.block = Some(block_set.to_string());
+            }
+            TransactionType::PistonMove { .. } => {
+                record.action = "piston_move".to_string();
+            }
+        }
+        record
+    }
+
+    /// Returns the record as a CSV row, without the line ending
+    pub fn to_csv(&self) -> String {
+        fn column<T: ToString>(value: &Option<T>) -> String {
+            value.as_ref().map(|x| x.to_string()).unwrap_or_default()
+        }
+        let columns = [
+            self.id.clone(),
+            column(&self.time),
+            self.owner.to_string(),
+            self.world.to_string(),
+            column(&self.x),
+            column(&self.y),
+            column(&self.z),
+            self.action.clone(),
+            column(&self.block),
+            column(&self.expected),
+            column(&self.biome),
+            column(&self.cause),
+            column(&self.entity),
+        ];
+        let columns: Vec<String> = columns.iter().map(|x| escape_csv(x)).collect();
+        columns.join(",")
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Streams audit records out in a given format
+pub struct AuditWriter<W: Write> {
+    writer: W,
+    format: AuditFormat,
+}
+
+impl<W: Write> AuditWriter<W> {
+    /// Starts an audit, writing the CSV header if needed
+    pub fn new(mut writer: W, format: AuditFormat) -> io::Result<AuditWriter<W>> {
+        if format == AuditFormat::Csv {
+            writeln!(writer, "{}", CSV_HEADER)?;
+        }
+        Ok(AuditWriter { writer, format })
+    }
+
+    /// Writes the record of a transaction
+    pub fn write(&mut self, transaction: &Transaction) -> io::Result<()> {
+        let record = AuditRecord::new(transaction);
+        match self.format {
+            AuditFormat::Csv => writeln!(self.writer, "{}", record.to_csv()),
+            AuditFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, &record)?;
+                writeln!(self.writer)
+            }
+        }
+    }
+
+    /// Flushes the records written so far, returning the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::*;
+
+    #[test]
+    fn csv_escapes_block_states() {
+        let meta_data = MetaData::new()
+            .set_property("facing", "east")
+            .set_property("half", "top");
+        let stairs = MetaBlock::fuse(Block::new_from_ids(0, 3), meta_data);
+        let raw = RawTransactionBuilder::new(TransactionType::new_set(stairs.clone()))
+            .set_x_coord(1)
+            .set_y_coord(-2)
+            .set_z_coord(3)
+            .build_transaction()
+            .unwrap();
+        let set = Transaction::new(raw, TransactionID::new_from_parts(4, 0));
+        let undo = RawTransactionBuilder::new(TransactionType::new_undo(set.get_id()))
+            .build_transaction()
+            .unwrap();
+        let undo = Transaction::new(undo, TransactionID::new_from_parts(5, 0));
+
+        let mut audit = AuditWriter::new(Vec::new(), AuditFormat::Csv).unwrap();
+        audit.write(&set).unwrap();
+        audit.write(&undo).unwrap();
+        let csv = String::from_utf8(audit.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            format!("4.0,,{},0,1,-2,3,set,\"{}\",,,,", Uuid::nil(), stairs)
+        );
+        assert!(lines[1].contains("[facing=east,half=top]"));
+        assert_eq!(lines[2], format!("5.0,,{},0,,,,undo,,,,4.0,", Uuid::nil()));
+
+        let mut audit = AuditWriter::new(Vec::new(), AuditFormat::JsonLines).unwrap();
+        audit.write(&undo).unwrap();
+        let json = audit.finish().unwrap();
+        let record: AuditRecord = serde_json::from_slice(&json).unwrap();
+        assert!(record == AuditRecord::new(&undo));
+    }
+}
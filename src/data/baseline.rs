@@ -0,0 +1,27 @@
+//! Provides baselines, the original blocks of a world that existed before it was tracked
+//!
+//! With a baseline, such as a map read from disk or a terrain generator, a world starts out as
+//! that map rather than as its default blocks, and only the changes made on top of it are
+//! recorded as transactions. Locations the baseline has nothing for fall back to the default
+//! layers.
+
+use data::block::*;
+
+/// Supplies the original block at any location of a world
+///
+/// Baselines are read whenever a block that has never been set is read, one block at a time,
+/// so they should always return the same block for the same location.
+pub trait BaselineWorld: Send + Sync {
+    /// Returns the original block at the location, or None if the baseline has nothing there
+    fn get_block(&self, x: i32, y: i32, z: i32) -> Option<MetaBlock>;
+}
+
+/// Custom baselines can be written as closures
+impl<F> BaselineWorld for F
+where
+    F: Fn(i32, i32, i32) -> Option<MetaBlock> + Send + Sync,
+{
+    fn get_block(&self, x: i32, y: i32, z: i32) -> Option<MetaBlock> {
+        self(x, y, z)
+    }
+}
@@ -0,0 +1,31 @@
+//! Provides biomes
+//!
+//! Like in minecraft, biomes are not stored per block, but per cell of BIOME_CELL_SIZE blocks
+//! along each axis. Cells are aligned to the origin.
+
+/// Size of the cells biomes are stored for, along each axis
+pub const BIOME_CELL_SIZE: usize = 4;
+
+/// A biome, identified by its numerical id
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Biome {
+    id: u16,
+}
+
+impl Biome {
+    pub fn new(id: u16) -> Biome {
+        Biome { id }
+    }
+
+    /// Returns the numerical id of this biome
+    pub fn get_id(&self) -> u16 {
+        self.id
+    }
+}
+
+/// Biome 0, the biome of cells that have never been set
+impl Default for Biome {
+    fn default() -> Biome {
+        Biome::new(0)
+    }
+}
@@ -2,11 +2,18 @@
 //!
 //! Uses minecraft stile "provider":"name" format.
 
-use std::collections::HashMap;
+use data::nbt::*;
+use data::properties::*;
+use serde_json::{self, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Read};
 
 /// Structure that stores a single Block
 /// Needs to be paired with a BlockDictonary to get useful values
-#[derive(Clone, Copy, PartialEq, Eq)]
+///
+/// Displayed by its numerical ids, as `provider:id`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Block {
     provider: u16,
     id: u16,
@@ -14,23 +21,43 @@ pub struct Block {
 
 impl Block {
     pub fn new_from_ids(provider: u16, id: u16) -> Block {
-        Block {
-            provider: provider,
-            id: id,
-        }
+        Block { provider, id }
+    }
+
+    /// Returns the numerical id of the provider of this block
+    pub fn get_provider(&self) -> u16 {
+        self.provider
+    }
+
+    /// Returns the numerical id of this block within its provider
+    pub fn get_id(&self) -> u16 {
+        self.id
     }
 }
 
-/// Stores metadata about a block (i.e. damagevalue)
-#[derive(Clone, Copy, PartialEq, Eq)]
+/// Stores metadata about a block (i.e. damagevalue, block state properties, and modded NBT entries)
+///
+/// Two metadata are only equal, and only hash the same, if every part of them is equal, so a
+/// block without a data value differs from the same block with a data value of 0.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MetaData {
     data_value: Option<i32>,
+    /// Block state properties, such as facing or waterlogged
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    properties: Properties,
+    /// Arbitrary namespaced NBT entries, for modded blocks
+    #[serde(default, skip_serializing_if = "NbtData::is_empty")]
+    nbt: NbtData,
 }
 
 impl MetaData {
     /// Creates a new MetaData with nothing in it
     pub fn new() -> MetaData {
-        MetaData { data_value: None }
+        MetaData {
+            data_value: None,
+            properties: Properties::new(),
+            nbt: NbtData::new(),
+        }
     }
 
     /// Sets the data_value of the meta data
@@ -44,10 +71,115 @@ impl MetaData {
     pub fn get_data_value(&self) -> Option<i32> {
         self.data_value
     }
+
+    /// Sets a block state property
+    ///
+    /// Values can be given as bools, ints or strings, e.g.
+    /// `MetaData::new().set_property("facing", "east").set_property("waterlogged", true)`
+    pub fn set_property<V: Into<PropertyValue>>(&self, name: &str, value: V) -> MetaData {
+        let mut new_meta = self.clone();
+        new_meta.properties = self.properties.set(name, value.into());
+        new_meta
+    }
+
+    /// Removes a block state property
+    pub fn remove_property(&self, name: &str) -> MetaData {
+        let mut new_meta = self.clone();
+        new_meta.properties = self.properties.remove(name);
+        new_meta
+    }
+
+    /// Gets the value of a block state property, if it is set
+    pub fn get_property(&self, name: &str) -> Option<PropertyValue> {
+        self.properties.get(name)
+    }
+
+    /// Returns the block state properties
+    pub fn get_properties(&self) -> Properties {
+        self.properties
+    }
+
+    /// Replaces every block state property
+    pub fn set_properties(&self, properties: Properties) -> MetaData {
+        let mut new_meta = self.clone();
+        new_meta.properties = properties;
+        new_meta
+    }
+
+    /// Sets an NBT entry, e.g. `MetaData::new().set_nbt("mymod:charge", NbtValue::Int(3))`
+    ///
+    /// Returns None if the key is not namespaced
+    pub fn set_nbt(&self, key: &str, value: NbtValue) -> Option<MetaData> {
+        let mut new_meta = self.clone();
+        new_meta.nbt = self.nbt.set(key, value)?;
+        Some(new_meta)
+    }
+
+    /// Removes an NBT entry
+    pub fn remove_nbt(&self, key: &str) -> MetaData {
+        let mut new_meta = self.clone();
+        new_meta.nbt = self.nbt.remove(key);
+        new_meta
+    }
+
+    /// Gets the value of an NBT entry, if it is set
+    pub fn get_nbt(&self, key: &str) -> Option<NbtValue> {
+        self.nbt.get(key)
+    }
+
+    /// Returns the NBT entries
+    pub fn get_nbt_data(&self) -> &NbtData {
+        &self.nbt
+    }
+
+    /// Replaces every NBT entry
+    pub fn set_nbt_data(&self, nbt: NbtData) -> MetaData {
+        let mut new_meta = self.clone();
+        new_meta.nbt = nbt;
+        new_meta
+    }
+}
+
+impl Default for MetaData {
+    fn default() -> MetaData {
+        MetaData::new()
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.provider, self.id)
+    }
+}
+
+/// Written as `:data_value`, `[properties]` and `{nbt}`, leaving out the parts that are unset
+impl fmt::Display for MetaData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(data_value) = self.data_value {
+            write!(f, ":{}", data_value)?;
+        }
+        if !self.properties.is_empty() {
+            write!(f, "{}", self.properties)?;
+        }
+        if !self.nbt.is_empty() {
+            write!(f, "{}", self.nbt)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for MetaBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.block, self.meta_data)
+    }
 }
 
 /// Pairs a block with its metadata, if it has any
-#[derive(Clone, Copy, PartialEq, Eq)]
+///
+/// Metablocks are equal if both their blocks and metadata are, so the same block with different
+/// properties is a different metablock. Displayed as `provider:id`, followed by the metadata,
+/// e.g. `2:7:3[facing=east]{mymod:charge:1.5f}`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MetaBlock {
     block: Block,
     meta_data: MetaData,
@@ -57,7 +189,7 @@ impl MetaBlock {
     /// Combines a block and a metadata into a metablock
     pub fn fuse(block: Block, meta: MetaData) -> MetaBlock {
         MetaBlock {
-            block: block,
+            block,
             meta_data: meta,
         }
     }
@@ -74,6 +206,7 @@ impl MetaBlock {
 }
 
 /// Provides a dictonary from provider:blockname values to u16:u16 values
+#[derive(Clone)]
 pub struct BlockDictonary {
     provider_id_to_blocktable: HashMap<u16, BlockTable>,
     provider_name_to_id: HashMap<String, u16>,
@@ -90,6 +223,61 @@ impl BlockDictonary {
         }
     }
 
+    /// Creates a BlockDictonary holding every block in a vanilla data generator report
+    ///
+    /// Accepts either the blocks report (blocks.json), whose blocks are numbered in order of
+    /// their names within each provider, or the registries report (registries.json), whose
+    /// blocks keep their protocol ids. Providers are numbered in order of their names. As the
+    /// reports are generated per game version, so is the dictionary.
+    pub fn from_registry_json<R: Read>(reader: R) -> io::Result<BlockDictonary> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let report: BTreeMap<String, Value> = serde_json::from_reader(reader)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // The registries report nests blocks under the block registry
+        let entries = report
+            .get("minecraft:block")
+            .and_then(|x| x.get("entries"))
+            .and_then(|x| x.as_object());
+        let blocks: Vec<(String, Option<u16>)> = match entries {
+            Some(entries) => entries
+                .iter()
+                .map(|(name, entry)| {
+                    let id = entry.get("protocol_id").and_then(|x| x.as_u64());
+                    match id {
+                        Some(id) if id <= u64::from(u16::MAX) => {
+                            Ok((name.clone(), Some(id as u16)))
+                        }
+                        _ => Err(invalid("block without a valid protocol_id")),
+                    }
+                })
+                .collect::<io::Result<_>>()?,
+            None => report.keys().map(|name| (name.clone(), None)).collect(),
+        };
+
+        let mut tables: BTreeMap<&str, BlockTable> = BTreeMap::new();
+        for (name, id) in &blocks {
+            let (provider, name) = name
+                .split_once(':')
+                .ok_or_else(|| invalid("block name without a provider"))?;
+            let table = tables
+                .entry(provider)
+                .or_insert_with(|| BlockTable::new(provider));
+            match *id {
+                Some(id) => table.add_pair(name, id),
+                None => {
+                    table.add_name(name);
+                }
+            }
+        }
+
+        let mut dictonary = BlockDictonary::new();
+        for (_, table) in tables {
+            dictonary.add_table(table);
+        }
+        Ok(dictonary)
+    }
+
     /// Returns an avaible provider id
     fn new_id(&self) -> u16 {
         let new_val = self.provider_id_to_name.keys().max();
@@ -134,12 +322,207 @@ impl BlockDictonary {
         let block_id = blocktable.lookup_value(id);
         Block::new_from_ids(*provider_id, block_id)
     }
+
+    /// Provides a ("provider","id") from a block
+    ///
+    /// Returns None if the block is not in the dictionary
+    pub fn try_decode_block(&self, block: Block) -> Option<(&str, &str)> {
+        let table = self.provider_id_to_blocktable.get(&block.provider)?;
+        let id = table.val_to_name.get(&block.id)?;
+        Some((table.get_provider(), id))
+    }
+
+    /// Provides a block from a ("provider","id")
+    ///
+    /// Returns None if the name is not in the dictionary
+    pub fn try_encode_block(&self, (provider, id): (&str, &str)) -> Option<Block> {
+        let provider_id = self.provider_name_to_id.get(provider)?;
+        let blocktable = self.provider_id_to_blocktable.get(provider_id)?;
+        let block_id = blocktable.name_to_val.get(id)?;
+        Some(Block::new_from_ids(*provider_id, *block_id))
+    }
+
+    /// Merges another dictionary into this one
+    ///
+    /// Every provider and name of this dictionary keeps its id, those only found in the other
+    /// one are given new ids. Returns the merged dictionary, along with a table converting
+    /// blocks encoded with the other dictionary to the merged one.
+    pub fn merge(&self, other: &BlockDictonary) -> (BlockDictonary, RemapTable) {
+        let mut merged = self.clone();
+        let mut remap = RemapTable::new();
+
+        let mut other_ids: Vec<&u16> = other.provider_id_to_blocktable.keys().collect();
+        other_ids.sort();
+        for &other_id in other_ids {
+            let other_table = &other.provider_id_to_blocktable[&other_id];
+            let provider_id = match merged.provider_name_to_id.get(&other_table.provider) {
+                Some(&id) => id,
+                None => merged.add_table(BlockTable::new(&other_table.provider)),
+            };
+            let table = merged
+                .provider_id_to_blocktable
+                .get_mut(&provider_id)
+                .unwrap();
+
+            let mut values: Vec<(&u16, &String)> = other_table.val_to_name.iter().collect();
+            values.sort();
+            for (&other_value, name) in values {
+                let value = match table.name_to_val.get(name) {
+                    Some(&value) => value,
+                    None => table.add_name(name),
+                };
+                remap.add_pair(
+                    Block::new_from_ids(other_id, other_value),
+                    Block::new_from_ids(provider_id, value),
+                );
+            }
+        }
+        (merged, remap)
+    }
+
+    /// Provides a block from a flattened block state, such as
+    /// `minecraft:oak_stairs[facing=east,half=top]`
+    ///
+    /// Properties may be given in any order. Returns None if the state is malformed, or the
+    /// block is not in the dictionary
+    pub fn encode_state(&self, state: &str) -> Option<MetaBlock> {
+        let (provider, id, properties) = parse_block_state(state)?;
+        let block = self.try_encode_block((&provider, &id))?;
+        let meta_data = MetaData::new().set_properties(Properties::intern(properties));
+        Some(MetaBlock::fuse(block, meta_data))
+    }
+
+    /// Provides the canonical flattened block state of a block, with its properties ordered by
+    /// name
+    ///
+    /// The data value is not part of a flattened block state and is ignored. Returns None if the
+    /// block is not in the dictionary
+    pub fn decode_state(&self, block: MetaBlock) -> Option<String> {
+        let (provider, id) = self.try_decode_block(*block.get_block())?;
+        let properties = block.get_meta_data().get_properties().get_map();
+        Some(format_block_state(provider, id, &properties))
+    }
+}
+
+/// Splits a flattened block state into its provider, name and properties
+///
+/// Property values of true and false are read as bools, and integers as ints, anything else is
+/// kept as a string. Returns None if the state is malformed, or sets a property twice
+pub fn parse_block_state(state: &str) -> Option<(String, String, PropertyMap)> {
+    let (name, properties) = match state.find('[') {
+        Some(start) => {
+            if !state.ends_with(']') {
+                return None;
+            }
+            (&state[..start], Some(&state[start + 1..state.len() - 1]))
+        }
+        None => (state, None),
+    };
+    let (provider, id) = name.split_once(':')?;
+    if provider.is_empty() || id.is_empty() {
+        return None;
+    }
+
+    let mut map = PropertyMap::new();
+    let properties = properties.filter(|x| !x.trim().is_empty());
+    for property in properties.iter().flat_map(|x| x.split(',')) {
+        let (key, value) = property.split_once('=')?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            return None;
+        }
+        let value = match value {
+            "true" => PropertyValue::Bool(true),
+            "false" => PropertyValue::Bool(false),
+            _ => value
+                .parse()
+                .map(PropertyValue::Int)
+                .unwrap_or_else(|_| PropertyValue::from(value)),
+        };
+        if map.insert(key.to_string(), value).is_some() {
+            return None;
+        }
+    }
+    Some((provider.to_string(), id.to_string(), map))
+}
+
+/// Formats a flattened block state, with its properties ordered by name
+pub fn format_block_state(provider: &str, id: &str, properties: &PropertyMap) -> String {
+    if properties.is_empty() {
+        return format!("{}:{}", provider, id);
+    }
+    let properties: Vec<String> = properties
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    format!("{}:{}[{}]", provider, id, properties.join(","))
+}
+
+/// Returns the canonical form of a flattened block state, with its properties ordered by name
+///
+/// Returns None if the state is malformed
+pub fn canonicalize_block_state(state: &str) -> Option<String> {
+    let (provider, id, properties) = parse_block_state(state)?;
+    Some(format_block_state(&provider, &id, &properties))
+}
+
+impl Default for BlockDictonary {
+    fn default() -> BlockDictonary {
+        BlockDictonary::new()
+    }
+}
+
+/// Converts blocks encoded with one dictionary to another
+#[derive(Clone, Default)]
+pub struct RemapTable {
+    blocks: HashMap<Block, Block>,
+}
+
+impl RemapTable {
+    /// Creates a new, empty remap table, which leaves every block alone
+    pub fn new() -> RemapTable {
+        RemapTable {
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Converts the from block to the to block
+    pub fn add_pair(&mut self, from: Block, to: Block) -> &mut Self {
+        self.blocks.insert(from, to);
+        self
+    }
+
+    /// Returns what a block is converted to, if it is in the table
+    pub fn get(&self, block: Block) -> Option<Block> {
+        self.blocks.get(&block).cloned()
+    }
+
+    /// Converts a block, leaving blocks that are not in the table alone
+    pub fn remap_block(&self, block: Block) -> Block {
+        self.get(block).unwrap_or(block)
+    }
+
+    /// Converts the block of a MetaBlock, keeping its metadata
+    pub fn remap_meta_block(&self, block: MetaBlock) -> MetaBlock {
+        MetaBlock::fuse(self.remap_block(block.block), block.meta_data)
+    }
+
+    /// Returns the number of blocks in the table
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns true if the table converts no blocks
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
 }
 
 /// Provides the table for a single block provider
 ///
 /// In the minecraft blockname "minecraft:air", "minecraft" would be the
 /// provider, and "air" would be the name".
+#[derive(Clone)]
 pub struct BlockTable {
     provider: String,
     name_to_val: HashMap<String, u16>,
@@ -185,7 +568,7 @@ impl BlockTable {
     ///
     /// Dangerous, will crash if you give it an invalid name
     pub fn lookup_value(&self, name: &str) -> u16 {
-        self.name_to_val.get(name).unwrap().clone()
+        *self.name_to_val.get(name).unwrap()
     }
 
     /// Looks up the name of a block, given the value
@@ -200,3 +583,122 @@ impl BlockTable {
         &self.provider
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn metadata_participates_in_equality() {
+        let block = Block::new_from_ids(2, 7);
+        let plain = MetaBlock::fuse(block, MetaData::new());
+        let zero = MetaBlock::fuse(block, MetaData::new().set_data_value(0));
+        let meta_data = MetaData::new()
+            .set_data_value(3)
+            .set_property("facing", "east")
+            .set_nbt("mymod:charge", NbtValue::Float(1.5))
+            .unwrap();
+        let full = MetaBlock::fuse(block, meta_data);
+
+        let set: HashSet<MetaBlock> = vec![plain.clone(), zero.clone(), full.clone(), full.clone()]
+            .into_iter()
+            .collect();
+        assert_eq!(set.len(), 3);
+        assert_eq!(plain.to_string(), "2:7");
+        assert_eq!(zero.to_string(), "2:7:0");
+        assert_eq!(full.to_string(), "2:7:3[facing=east]{mymod:charge:1.5f}");
+        assert!(format!("{:?}", full).contains("\"facing\": Str(\"east\")"));
+    }
+
+    #[test]
+    fn merge_dictionaries() {
+        let dictonary = |tables: &[(&str, &[&str])]| {
+            let mut dictonary = BlockDictonary::new();
+            for &(provider, names) in tables {
+                let mut table = BlockTable::new(provider);
+                for name in names {
+                    table.add_name(name);
+                }
+                dictonary.add_table(table);
+            }
+            dictonary
+        };
+        let ours = dictonary(&[("minecraft", &["air", "stone"])]);
+        let theirs = dictonary(&[("mymod", &["ore"]), ("minecraft", &["dirt", "stone"])]);
+
+        let (merged, remap) = ours.merge(&theirs);
+        assert_eq!(remap.len(), 3);
+        // Our blocks keep their ids
+        assert!(merged.try_encode_block(("minecraft", "stone")) == Some(Block::new_from_ids(0, 1)));
+        for &(provider, name) in &[
+            ("mymod", "ore"),
+            ("minecraft", "dirt"),
+            ("minecraft", "stone"),
+        ] {
+            let block = theirs.try_encode_block((provider, name)).unwrap();
+            let converted = remap.remap_block(block);
+            assert_eq!(merged.try_decode_block(converted), Some((provider, name)));
+        }
+    }
+
+    #[test]
+    fn registry_reports() {
+        let blocks = r#"{
+            "minecraft:stone": {"states": [{"id": 1, "default": true}]},
+            "minecraft:air": {"states": [{"id": 0, "default": true}]},
+            "mymod:ore": {"states": [{"id": 2, "default": true}]}
+        }"#;
+        let dictonary = BlockDictonary::from_registry_json(blocks.as_bytes()).unwrap();
+        let air = dictonary.try_encode_block(("minecraft", "air")).unwrap();
+        let stone = dictonary.try_encode_block(("minecraft", "stone")).unwrap();
+        let ore = dictonary.try_encode_block(("mymod", "ore")).unwrap();
+        assert!(air == Block::new_from_ids(0, 0));
+        assert!(stone == Block::new_from_ids(0, 1));
+        assert!(ore == Block::new_from_ids(1, 0));
+
+        let registries = r#"{
+            "minecraft:block": {"entries": {
+                "minecraft:air": {"protocol_id": 0},
+                "minecraft:stone": {"protocol_id": 7}
+            }},
+            "minecraft:item": {"entries": {}}
+        }"#;
+        let dictonary = BlockDictonary::from_registry_json(registries.as_bytes()).unwrap();
+        let stone = dictonary.try_encode_block(("minecraft", "stone")).unwrap();
+        assert!(stone == Block::new_from_ids(0, 7));
+
+        assert!(BlockDictonary::from_registry_json(&b"{\"stone\": {}}"[..]).is_err());
+        assert!(BlockDictonary::from_registry_json(&b"[]"[..]).is_err());
+    }
+
+    #[test]
+    fn flattened_states() {
+        let mut table = BlockTable::new("minecraft");
+        table.add_name("air");
+        table.add_name("oak_stairs");
+        let mut dictonary = BlockDictonary::new();
+        dictonary.add_table(table);
+
+        let block = dictonary
+            .encode_state("minecraft:oak_stairs[half=top,facing=east,waterlogged=false]")
+            .unwrap();
+        assert!(*block.get_block() == Block::new_from_ids(0, 1));
+        assert!(block.get_meta_data().get_property("waterlogged") == Some(false.into()));
+        let state = dictonary.decode_state(block.clone()).unwrap();
+        assert_eq!(
+            state,
+            "minecraft:oak_stairs[facing=east,half=top,waterlogged=false]"
+        );
+        assert!(dictonary.encode_state(&state) == Some(block));
+
+        assert!(dictonary.encode_state("minecraft:dirt").is_none());
+        assert!(parse_block_state("oak_stairs").is_none());
+        assert!(parse_block_state("minecraft:oak_stairs[facing=east").is_none());
+        assert!(parse_block_state("minecraft:oak_stairs[age=1,age=2]").is_none());
+        assert_eq!(
+            canonicalize_block_state("minecraft:wheat[ age = 7 ]").unwrap(),
+            "minecraft:wheat[age=7]"
+        );
+    }
+}
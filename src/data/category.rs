@@ -0,0 +1,87 @@
+//! Provides action categories, a coarse description of what kind of change a transaction is
+//!
+//! A category says what happened to a block, like it burning or a crop growing on it, where the
+//! cause of a transaction says which other transaction led to it. Categories let admins pick out
+//! one kind of change, for example rolling back fire damage while keeping what players built.
+
+use std::fmt;
+
+/// What kind of change a transaction is
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ActionCategory {
+    /// A player placing a block
+    Build,
+    /// A player, mob or explosion breaking a block
+    Break,
+    /// Fire spreading or burning a block away
+    Burn,
+    /// Leaves decaying, ice melting and the like
+    Decay,
+    /// Crops, trees and grass growing
+    Growth,
+    /// Liquids flowing
+    Flow,
+    /// Pistons, doors and other redstone components changing state
+    Redstone,
+}
+
+impl ActionCategory {
+    /// Every category
+    pub const ALL: [ActionCategory; 7] = [
+        ActionCategory::Build,
+        ActionCategory::Break,
+        ActionCategory::Burn,
+        ActionCategory::Decay,
+        ActionCategory::Growth,
+        ActionCategory::Flow,
+        ActionCategory::Redstone,
+    ];
+
+    /// Returns the name of the category, in lowercase, like `burn`
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            ActionCategory::Build => "build",
+            ActionCategory::Break => "break",
+            ActionCategory::Burn => "burn",
+            ActionCategory::Decay => "decay",
+            ActionCategory::Growth => "growth",
+            ActionCategory::Flow => "flow",
+            ActionCategory::Redstone => "redstone",
+        }
+    }
+
+    /// Returns the category with the given name, ignoring case
+    pub fn from_name(name: &str) -> Option<ActionCategory> {
+        ActionCategory::ALL
+            .iter()
+            .find(|x| x.get_name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+}
+
+impl fmt::Display for ActionCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.get_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for category in ActionCategory::ALL.iter() {
+            assert_eq!(
+                ActionCategory::from_name(category.get_name()),
+                Some(*category)
+            );
+        }
+        assert_eq!(
+            ActionCategory::from_name("Burn"),
+            Some(ActionCategory::Burn)
+        );
+        assert_eq!(ActionCategory::from_name("arson"), None);
+        assert_eq!(ActionCategory::Redstone.to_string(), "redstone");
+    }
+}
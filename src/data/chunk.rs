@@ -1,14 +1,37 @@
 //! Provides an efficent, immutable repusentation of a chunk in memory
 
-use storage::cuboid::*;
+use data::biome::*;
 use data::block::*;
+use data::decoration::*;
+use data::entity::*;
+use data::handle::*;
+use data::light::*;
+use data::nbt::*;
+use data::properties::*;
+use im::OrdMap;
+use std::collections::{BTreeSet, HashMap};
+use std::mem::size_of;
 use std::sync::Arc;
+use storage::cuboid::*;
+use storage::purse::*;
+use storage::slice::*;
+use storage::voxel::*;
+use uuid::Uuid;
 
 /// Persistent chunk
 ///
 /// Chunks can have any x,y,z
+///
+/// Internally, chunks start out sparse, only storing the blocks that have been set. Once more
+/// than SPARSE_CHUNK_LIMIT blocks have been set, or light levels are, they are split into vertical
+/// sections SECTION_HEIGHT blocks tall, which are only allocated once a block inside of them is
+/// set. Setting a block then only copies the section it is in.
+///
+/// Sections store their blocks in any VoxelStore, a palette packed one by default.
+///
+/// Chunks also hold the entities inside of them, keyed by their uuid.
 #[derive(Clone)]
-pub struct Chunk {
+pub struct Chunk<S = PackedCuboid<BlockHandle>> {
     /// Dictonary provided to this chunk by the world
     ///
     /// Use Arc for thread saftey. Uses Arc instead of mutex due to immutability.
@@ -16,10 +39,14 @@ pub struct Chunk {
     /// dictonary, if you only care about numerical IDs and not the
     /// minecraft style provider:id names.
     dictonary: Option<Arc<BlockDictonary>>,
-    /// Collection of blocks making up this chunk
-    blocks: Cuboid<Block>,
-    /// MetaData belonging to those Blocks
-    meta_data: Cuboid<MetaData>,
+    /// Blocks making up this chunk
+    blocks: Blocks<S>,
+    /// Biome of each biome cell of this chunk
+    biomes: Cuboid<Biome>,
+    /// Height of the highest block in each column that differs from the default, if any
+    heightmap: Slice<Option<usize>>,
+    /// Entities inside of this chunk
+    entities: OrdMap<Uuid, Entity>,
     /// Default block for this cunk
     default_block: Block,
     /// x size of this chunk
@@ -30,50 +57,1324 @@ pub struct Chunk {
     z_size: usize,
 }
 
+/// How the blocks of a chunk are stored
+#[derive(Clone)]
+enum Blocks<S> {
+    /// Only the blocks that differ from the default, keyed by location
+    Sparse(OrdMap<(usize, usize, usize), BlockHandle>),
+    /// Sections making up the chunk, from the bottom up
+    ///
+    /// None for sections that have never been written to
+    Sectioned(Purse<Option<Section<S>>>),
+}
+
+/// A horizontal slab of a chunk
+#[derive(Clone)]
+struct Section<S> {
+    /// Blocks making up this section, along with their MetaData
+    blocks: S,
+    /// Block light levels, if known
+    block_light: Option<NibbleArray>,
+    /// Sky light levels, if known
+    sky_light: Option<NibbleArray>,
+}
+
+impl<S> Section<S> {
+    /// Returns the light levels of the given type, if known
+    fn get_light(&self, light: LightType) -> Option<&NibbleArray> {
+        match light {
+            LightType::Block => self.block_light.as_ref(),
+            LightType::Sky => self.sky_light.as_ref(),
+        }
+    }
+
+    /// Sets the light levels of the given type
+    fn set_light(&mut self, light: LightType, levels: NibbleArray) {
+        match light {
+            LightType::Block => self.block_light = Some(levels),
+            LightType::Sky => self.sky_light = Some(levels),
+        }
+    }
+}
+
+/// The blocks that changed between two versions of a chunk
+///
+/// Produced by Chunk::diff, and applied with Chunk::apply_patch
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkPatch {
+    /// The new block of every changed cell, ordered by location
+    changes: Vec<((usize, usize, usize), MetaBlock)>,
+}
+
+impl ChunkPatch {
+    /// Returns the changed cells, along with their new blocks
+    pub fn get_changes(&self) -> &[((usize, usize, usize), MetaBlock)] {
+        &self.changes
+    }
+
+    /// Returns the number of changed cells
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Returns true if nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
 /// Default size of a chunk (chunks default to cubes).
 pub const CHUNK_SIZE: usize = 256;
 
+/// Height of the sections chunks are split into, matching minecraft
+pub const SECTION_HEIGHT: usize = 16;
+
+/// Number of blocks a chunk can have set before it is split into sections
+pub const SPARSE_CHUNK_LIMIT: usize = 512;
+
 impl Chunk {
     /// Creates a new chunk with the specificed default block
     ///
     /// Defaults to a chunk size of 256x256x256.
     /// Defaults to no dictionary.
     pub fn new(default_block: Block) -> Chunk {
-        let blank_meta = MetaData::new();
+        Chunk::with_size(default_block, CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE)
+    }
+
+    /// Creates a new chunk with the specified default block and dimensions
+    ///
+    /// Defaults to no dictionary.
+    pub fn with_size(default_block: Block, x_size: usize, y_size: usize, z_size: usize) -> Chunk {
+        Chunk::with_store(default_block, x_size, y_size, z_size)
+    }
+
+    /// Deserializes a chunk serialized with to_bytes
+    ///
+    /// Returns None if the bytes are not a valid serialized chunk
+    pub fn from_bytes(bytes: &[u8]) -> Option<Chunk> {
+        Chunk::from_bytes_with_store(bytes)
+    }
+}
+
+impl<S: VoxelStore<BlockHandle>> Chunk<S> {
+    /// Creates a new chunk with the specified default block and dimensions, storing its sections
+    /// in S
+    ///
+    /// Defaults to no dictionary.
+    pub fn with_store(
+        default_block: Block,
+        x_size: usize,
+        y_size: usize,
+        z_size: usize,
+    ) -> Chunk<S> {
+        let cells = |size: usize| size.div_ceil(BIOME_CELL_SIZE);
         Chunk {
             dictonary: None,
-            blocks: Cuboid::new(CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE, &default_block),
-            meta_data: Cuboid::new(CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE, &blank_meta),
-            default_block: default_block,
-            x_size: CHUNK_SIZE,
-            y_size: CHUNK_SIZE,
-            z_size: CHUNK_SIZE,
+            blocks: Blocks::Sparse(OrdMap::new()),
+            biomes: Cuboid::new(
+                cells(x_size),
+                cells(y_size),
+                cells(z_size),
+                &Biome::default(),
+            ),
+            heightmap: Slice::new(x_size, z_size, None),
+            entities: OrdMap::new(),
+            default_block,
+            x_size,
+            y_size,
+            z_size,
         }
     }
 
     /// Sets the dictionary to be used by this chunk
-    pub fn set_dict(&self, dictonary: &Arc<BlockDictonary>) -> Chunk {
+    pub fn set_dict(&self, dictonary: &Arc<BlockDictonary>) -> Chunk<S> {
         let mut new_chunk = self.clone();
         new_chunk.dictonary = Some(dictonary.clone());
         new_chunk
     }
 
+    /// Returns the dictionary used by this chunk, if it has one
+    pub fn get_dict(&self) -> Option<Arc<BlockDictonary>> {
+        self.dictonary.clone()
+    }
+
+    /// Returns the default block of this chunk
+    pub fn get_default_block(&self) -> Block {
+        self.default_block
+    }
+
+    /// Returns the (x, y, z) dimensions of this chunk
+    pub fn get_size(&self) -> (usize, usize, usize) {
+        (self.x_size, self.y_size, self.z_size)
+    }
+
+    /// Returns the number of sections that have been allocated
+    ///
+    /// Sparse chunks have no sections
+    pub fn get_allocated_sections(&self) -> usize {
+        match self.blocks {
+            Blocks::Sparse(_) => 0,
+            Blocks::Sectioned(ref sections) => sections.into_iter().filter(|x| x.is_some()).count(),
+        }
+    }
+
+    /// Returns true if this chunk only stores the blocks that have been set
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.blocks, Blocks::Sparse(_))
+    }
+
+    /// Returns roughly how many bytes the blocks of this chunk take up
+    ///
+    /// Structure shared with other chunks is counted in full
+    pub fn memory_estimate(&self) -> usize {
+        match self.blocks {
+            Blocks::Sparse(ref blocks) => {
+                blocks.len() * size_of::<((usize, usize, usize), BlockHandle)>()
+            }
+            Blocks::Sectioned(ref sections) => sections
+                .into_iter()
+                .flatten()
+                .map(|x| x.blocks.memory_estimate())
+                .sum(),
+        }
+    }
+
     /// Gets the block at a specificed location, by value
     pub fn get_block(&self, x: usize, y: usize, z: usize) -> MetaBlock {
-        let block = *self.blocks.get(x, y, z);
-        let meta = *self.meta_data.get(x, y, z);
-        MetaBlock::fuse(block, meta)
+        let default = MetaBlock::fuse(self.default_block, MetaData::new());
+        if y >= self.y_size {
+            return default;
+        }
+        if let Blocks::Sparse(ref blocks) = self.blocks {
+            return blocks.get(&(x, y, z)).map(|x| x.get()).unwrap_or(default);
+        }
+        match self.get_section(y / SECTION_HEIGHT) {
+            Some(section) => section.blocks.get(x, y % SECTION_HEIGHT, z).get(),
+            None => default,
+        }
     }
 
-    /// Sets the block at a specified location, by value
-    pub fn set_block(&self, x: usize, y: usize, z: usize, block: MetaBlock) -> Chunk {
+    /// Returns every block that differs from the default, along with its location
+    ///
+    /// Ordered by location, only visits the blocks that have been set
+    pub fn modified_blocks(&self) -> Vec<((usize, usize, usize), MetaBlock)> {
+        let sections = match self.blocks {
+            Blocks::Sparse(ref blocks) => {
+                return blocks.iter().map(|(k, v)| (*k, v.get())).collect();
+            }
+            Blocks::Sectioned(ref sections) => sections,
+        };
+        let mut blocks = Vec::new();
+        for (i, section) in sections.into_iter().enumerate() {
+            if let Some(ref section) = *section {
+                let base = i * SECTION_HEIGHT;
+                let modified = section.blocks.iter_non_default();
+                blocks.extend(modified.map(|((x, y, z), block)| ((x, y + base, z), block.get())));
+            }
+        }
+        blocks.sort_by_key(|x| x.0);
+        blocks
+    }
+
+    /// Returns the cells that would have to change to turn this chunk into the other one
+    ///
+    /// Only cells inside of this chunk are compared. If both chunks have the same default block,
+    /// only the blocks that have been set in either of them are visited, and sections both chunks
+    /// share are skipped entirely.
+    pub fn diff(&self, other: &Chunk<S>) -> ChunkPatch {
+        if let (Blocks::Sectioned(ref ours), Blocks::Sectioned(ref theirs)) =
+            (&self.blocks, &other.blocks)
+        {
+            if self.default_block == other.default_block && self.get_size() == other.get_size() {
+                return self.diff_sections(ours, theirs);
+            }
+        }
+        let mut locations = BTreeSet::new();
+        if self.default_block == other.default_block {
+            locations.extend(self.modified_blocks().into_iter().map(|x| x.0));
+            locations.extend(other.modified_blocks().into_iter().map(|x| x.0));
+        } else {
+            for x in 0..self.x_size {
+                for y in 0..self.y_size {
+                    for z in 0..self.z_size {
+                        locations.insert((x, y, z));
+                    }
+                }
+            }
+        }
+        let changes = locations
+            .into_iter()
+            .filter(|&(x, y, z)| x < self.x_size && y < self.y_size && z < self.z_size)
+            .filter_map(|(x, y, z)| {
+                let block = other.get_block(x, y, z);
+                if self.get_block(x, y, z) != block {
+                    Some(((x, y, z), block))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        ChunkPatch { changes }
+    }
+
+    /// Diffs two sectioned chunks of the same size and default block, section by section
+    fn diff_sections(
+        &self,
+        ours: &Purse<Option<Section<S>>>,
+        theirs: &Purse<Option<Section<S>>>,
+    ) -> ChunkPatch {
+        let mut changes = Vec::new();
+        for index in 0..self.get_section_count() {
+            if ours.ptr_eq_at(theirs, index) {
+                continue;
+            }
+            let height = self.get_section_height(index);
+            let default = BlockHandle::intern(MetaBlock::fuse(self.default_block, MetaData::new()));
+            let empty = || S::new(self.x_size, height, self.z_size, &default);
+            let section_changes = match (&ours[index], &theirs[index]) {
+                (None, None) => continue,
+                (Some(a), Some(b)) => a.blocks.diff(&b.blocks),
+                (Some(a), None) => a.blocks.diff(&empty()),
+                (None, Some(b)) => empty().diff(&b.blocks),
+            };
+            let base = index * SECTION_HEIGHT;
+            changes.extend(
+                section_changes
+                    .into_iter()
+                    .map(|((x, y, z), block)| ((x, y + base, z), block.get())),
+            );
+        }
+        changes.sort_by_key(|x| x.0);
+        ChunkPatch { changes }
+    }
+
+    /// Applies a patch produced by diff, setting every changed cell to its new block
+    pub fn apply_patch(&self, patch: &ChunkPatch) -> Chunk<S> {
+        patch
+            .changes
+            .iter()
+            .fold(self.clone(), |chunk, &((x, y, z), ref block)| {
+                chunk.set_block(x, y, z, block.clone())
+            })
+    }
+
+    /// Returns a copy of this chunk with every block passed through f
+    ///
+    /// The default block is mapped as well, though only its block is kept, as chunks have no
+    /// default metadata. Biomes, light levels, entities and the dictionary are kept.
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> Chunk<S> {
+        let default = MetaBlock::fuse(self.default_block, MetaData::new());
+        let new_default = *f(default).get_block();
+        let start = if new_default == self.default_block {
+            self.clone()
+        } else {
+            // Allocated sections remember the old default, so the chunk has to be rebuilt
+            let mut chunk = Chunk::with_store(new_default, self.x_size, self.y_size, self.z_size);
+            chunk.dictonary = self.dictonary.clone();
+            chunk.biomes = self.biomes.clone();
+            chunk.entities = self.entities.clone();
+            for index in 0..self.get_section_count() {
+                for &light in &[LightType::Block, LightType::Sky] {
+                    if let Some(levels) = self.get_light_section(light, index) {
+                        chunk = chunk.set_light_section(light, index, levels).unwrap();
+                    }
+                }
+            }
+            chunk
+        };
+        self.modified_blocks()
+            .into_iter()
+            .fold(start, |chunk, ((x, y, z), block)| {
+                chunk.set_block(x, y, z, f(block))
+            })
+    }
+
+    /// Sets every block of the horizontal layers from min_y to max_y, inclusive
+    ///
+    /// Fills the sections the layers span as a whole, rather than setting one block at a time.
+    /// Layers outside of the chunk are ignored.
+    pub fn fill_layers(&self, min_y: usize, max_y: usize, block: MetaBlock) -> Chunk<S> {
+        let max_y = max_y.min(self.y_size.saturating_sub(1));
+        if min_y > max_y {
+            return self.clone();
+        }
+        let handle = BlockHandle::intern(block.clone());
+        let mut new_chunk = self.to_sectioned();
+        for index in min_y / SECTION_HEIGHT..=max_y / SECTION_HEIGHT {
+            let base = index * SECTION_HEIGHT;
+            let low = min_y.max(base) - base;
+            let high = max_y.min(base + self.get_section_height(index) - 1) - base;
+            let section = new_chunk.get_section_or_new(index);
+            let new_section = Section {
+                blocks: section.blocks.fill(
+                    (0, low, 0),
+                    (self.x_size - 1, high, self.z_size - 1),
+                    handle.clone(),
+                ),
+                ..section
+            };
+            new_chunk = new_chunk.with_section(index, new_section);
+        }
+
+        // Keep the heightmap up to date
+        let default = MetaBlock::fuse(self.default_block, MetaData::new());
+        let mut heights = Vec::new();
+        for x in 0..self.x_size {
+            for z in 0..self.z_size {
+                let height = self.get_height(x, z);
+                if block != default {
+                    if height.is_none_or(|height| height < max_y) {
+                        heights.push(((x, z), Some(max_y)));
+                    }
+                } else if height.is_some_and(|height| height >= min_y && height <= max_y) {
+                    let below = (0..min_y)
+                        .rev()
+                        .find(|&y| new_chunk.get_block(x, y, z) != default);
+                    heights.push(((x, z), below));
+                }
+            }
+        }
+        new_chunk.heightmap = self.heightmap.set_many(heights);
+        new_chunk
+    }
+
+    /// Returns the height of the highest block in the column that differs from the default
+    ///
+    /// None if every block in the column is the default
+    pub fn get_height(&self, x: usize, z: usize) -> Option<usize> {
+        *self.heightmap.get(x, z)
+    }
+
+    /// Gets the biome of the cell containing the specified location
+    pub fn get_biome(&self, x: usize, y: usize, z: usize) -> Biome {
+        let cell = BIOME_CELL_SIZE;
+        *self.biomes.get(x / cell, y / cell, z / cell)
+    }
+
+    /// Sets the biome of the cell containing the specified location
+    pub fn set_biome(&self, x: usize, y: usize, z: usize, biome: Biome) -> Chunk<S> {
+        let cell = BIOME_CELL_SIZE;
+        let mut new_chunk = self.clone();
+        if let Some(biomes) = self.biomes.set(x / cell, y / cell, z / cell, biome) {
+            new_chunk.biomes = biomes;
+        }
+        new_chunk
+    }
+
+    /// Returns the entity with the given uuid, if it is in this chunk
+    pub fn get_entity(&self, id: Uuid) -> Option<Entity> {
+        self.entities.get(&id).map(|x| (*x).clone())
+    }
+
+    /// Returns every entity in this chunk, ordered by uuid
+    pub fn get_entities(&self) -> Vec<Entity> {
+        self.entities.values().map(|x| (*x).clone()).collect()
+    }
+
+    /// Returns the number of entities in this chunk
+    pub fn get_entity_count(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Adds an entity to this chunk, replacing the entity with the same uuid if there is one
+    ///
+    /// Entities keep their world coordinates, the chunk does not check that they are inside of
+    /// it, that is up to the world.
+    pub fn set_entity(&self, entity: Entity) -> Chunk<S> {
         let mut new_chunk = self.clone();
-        new_chunk.blocks = self.blocks
-            .set(x, y, z, *block.get_block())
-            .unwrap_or(self.blocks.clone());
-        new_chunk.meta_data = self.meta_data
-            .set(x, y, z, *block.get_meta_data())
-            .unwrap_or(self.meta_data.clone());
+        new_chunk.entities = self.entities.insert(entity.get_id(), entity);
         new_chunk
     }
+
+    /// Removes the entity with the given uuid from this chunk, if it is in it
+    pub fn remove_entity(&self, id: Uuid) -> Chunk<S> {
+        let mut new_chunk = self.clone();
+        new_chunk.entities = self.entities.remove(&id);
+        new_chunk
+    }
+
+    /// Returns the height of the section with the given index
+    ///
+    /// The topmost section is cut short by the height of the chunk
+    fn get_section_height(&self, index: usize) -> usize {
+        SECTION_HEIGHT.min(self.y_size - index * SECTION_HEIGHT)
+    }
+
+    /// Returns the number of sections this chunk is split into, once it is no longer sparse
+    fn get_section_count(&self) -> usize {
+        self.y_size.div_ceil(SECTION_HEIGHT)
+    }
+
+    /// Returns the section with the given index, if it has been allocated
+    fn get_section(&self, index: usize) -> Option<&Section<S>> {
+        match self.blocks {
+            Blocks::Sparse(_) => None,
+            Blocks::Sectioned(ref sections) => sections[index].as_ref(),
+        }
+    }
+
+    /// Returns a copy of this chunk split into sections
+    fn to_sectioned(&self) -> Chunk<S> {
+        let blocks = match self.blocks {
+            Blocks::Sparse(ref blocks) => blocks,
+            Blocks::Sectioned(_) => return self.clone(),
+        };
+        let sections = Purse::new_filled(self.get_section_count(), None);
+        let chunk = Chunk {
+            blocks: Blocks::Sectioned(sections),
+            ..self.clone()
+        };
+        blocks.iter().fold(chunk, |chunk, (location, block)| {
+            let (x, y, z) = *location;
+            chunk.set_section_block(x, y, z, block.get())
+        })
+    }
+
+    /// Returns a copy of this chunk with the section at the given index replaced
+    fn with_section(&self, index: usize, section: Section<S>) -> Chunk<S> {
+        let mut new_chunk = self.to_sectioned();
+        if let Blocks::Sectioned(ref mut sections) = new_chunk.blocks {
+            *sections = sections.set(index, Some(section));
+        }
+        new_chunk
+    }
+
+    /// Returns a copy of the section with the given index, allocating a new one if needed
+    ///
+    /// Sparse chunks have no sections, so they should be split with to_sectioned first
+    fn get_section_or_new(&self, index: usize) -> Section<S> {
+        match self.get_section(index) {
+            Some(section) => section.clone(),
+            None => {
+                let height = self.get_section_height(index);
+                let default =
+                    BlockHandle::intern(MetaBlock::fuse(self.default_block, MetaData::new()));
+                Section {
+                    blocks: S::new(self.x_size, height, self.z_size, &default),
+                    block_light: None,
+                    sky_light: None,
+                }
+            }
+        }
+    }
+
+    /// Returns the light level of the given type at a specified location
+    ///
+    /// None if the light levels of the section the location is in are not known
+    pub fn get_light(&self, light: LightType, x: usize, y: usize, z: usize) -> Option<u8> {
+        if x >= self.x_size || y >= self.y_size || z >= self.z_size {
+            return None;
+        }
+        let index = y / SECTION_HEIGHT;
+        let section = self.get_section(index)?;
+        let levels = section.get_light(light)?;
+        Some(levels.get(self.get_light_index(x, y % SECTION_HEIGHT, z)))
+    }
+
+    /// Sets the light level of the given type at a specified location
+    ///
+    /// If the light levels of the section were not known, the rest of the section is set to 0
+    pub fn set_light(&self, light: LightType, x: usize, y: usize, z: usize, level: u8) -> Chunk<S> {
+        if x >= self.x_size || y >= self.y_size || z >= self.z_size {
+            return self.clone();
+        }
+        let index = y / SECTION_HEIGHT;
+        let chunk = self.to_sectioned();
+        let mut section = chunk.get_section_or_new(index);
+        let levels = match section.get_light(light) {
+            Some(levels) => levels.clone(),
+            None => NibbleArray::new(self.get_light_len(index)),
+        };
+        let levels = levels.set(self.get_light_index(x, y % SECTION_HEIGHT, z), level);
+        section.set_light(light, levels);
+        chunk.with_section(index, section)
+    }
+
+    /// Returns the light levels of the given type for an entire section, if they are known
+    ///
+    /// Levels are ordered by y, then z, then x, like in minecraft's Anvil format
+    pub fn get_light_section(&self, light: LightType, index: usize) -> Option<NibbleArray> {
+        if index >= self.get_section_count() {
+            return None;
+        }
+        let section = self.get_section(index)?;
+        section.get_light(light).cloned()
+    }
+
+    /// Sets the light levels of the given type for an entire section
+    ///
+    /// Returns None if there is no such section, or the number of levels does not match its
+    /// size
+    pub fn set_light_section(
+        &self,
+        light: LightType,
+        index: usize,
+        levels: NibbleArray,
+    ) -> Option<Chunk<S>> {
+        if index >= self.get_section_count() || levels.len() != self.get_light_len(index) {
+            return None;
+        }
+        let chunk = self.to_sectioned();
+        let mut section = chunk.get_section_or_new(index);
+        section.set_light(light, levels);
+        Some(chunk.with_section(index, section))
+    }
+
+    /// Returns the number of light levels in the section with the given index
+    fn get_light_len(&self, index: usize) -> usize {
+        self.x_size * self.get_section_height(index) * self.z_size
+    }
+
+    /// Returns the index of a location, relative to its section, in the section's light levels
+    fn get_light_index(&self, x: usize, section_y: usize, z: usize) -> usize {
+        (section_y * self.z_size + z) * self.x_size + x
+    }
+
+    /// Sets the block at a specified location, by value
+    pub fn set_block(&self, x: usize, y: usize, z: usize, block: MetaBlock) -> Chunk<S> {
+        if y >= self.y_size {
+            return self.clone();
+        }
+        let default = MetaBlock::fuse(self.default_block, MetaData::new());
+        let is_default = block == default;
+
+        let mut new_chunk = match self.blocks {
+            Blocks::Sparse(ref blocks) => {
+                if x >= self.x_size || z >= self.z_size {
+                    return self.clone();
+                }
+                let blocks = if is_default {
+                    blocks.remove(&(x, y, z))
+                } else {
+                    blocks.insert((x, y, z), BlockHandle::intern(block))
+                };
+                let mut new_chunk = self.clone();
+                new_chunk.blocks = Blocks::Sparse(blocks);
+                new_chunk
+            }
+            Blocks::Sectioned(_) => self.set_section_block(x, y, z, block),
+        };
+
+        // Keep the heightmap up to date
+        let height = self.get_height(x, z);
+        if !is_default {
+            if height.is_none_or(|height| height < y) {
+                new_chunk.heightmap = self.heightmap.set(x, z, Some(y));
+            }
+        } else if height == Some(y) {
+            let below = (0..y)
+                .rev()
+                .find(|&y| new_chunk.get_block(x, y, z) != default);
+            new_chunk.heightmap = self.heightmap.set(x, z, below);
+        }
+
+        match new_chunk.blocks {
+            Blocks::Sparse(ref blocks) if blocks.len() > SPARSE_CHUNK_LIMIT => {
+                new_chunk.to_sectioned()
+            }
+            _ => new_chunk,
+        }
+    }
+
+    /// Sets the block at a specified location in the section holding it
+    fn set_section_block(&self, x: usize, y: usize, z: usize, block: MetaBlock) -> Chunk<S> {
+        let index = y / SECTION_HEIGHT;
+        let section = self.get_section_or_new(index);
+
+        let section_y = y % SECTION_HEIGHT;
+        let new_section = Section {
+            blocks: section
+                .blocks
+                .set(x, section_y, z, BlockHandle::intern(block))
+                .unwrap_or(section.blocks.clone()),
+            ..section
+        };
+        self.with_section(index, new_section)
+    }
+}
+
+/// Version of the chunk serialization format
+///
+/// NBT is written as little endian binary NBT. Earlier versions wrote it in an encoding of their
+/// own, and are no longer read, chunks are only ever read back by the provider that wrote them.
+const CHUNK_FORMAT_VERSION: u8 = 4;
+
+impl<S: VoxelStore<BlockHandle>> Chunk<S> {
+    /// Serializes the chunk, so it can be persisted or sent elsewhere
+    ///
+    /// Blocks are stored as indices into a palette of the distinct blocks in the chunk, run
+    /// length encoded one section at a time, so empty and uniform sections take almost no space.
+    /// Biomes, entities and known light levels are stored as well, the dictionary is not.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let sections_in = match self.blocks {
+            Blocks::Sparse(_) => return self.to_sectioned().to_bytes(),
+            Blocks::Sectioned(ref sections) => sections,
+        };
+        let default = MetaBlock::fuse(self.default_block, MetaData::new());
+        let mut bytes = vec![CHUNK_FORMAT_VERSION];
+        for size in &[self.x_size, self.y_size, self.z_size] {
+            put_u32(&mut bytes, *size as u32);
+        }
+        put_block(&mut bytes, &default);
+
+        // Index 0 of the palette is always the default block
+        let mut palette = vec![default.clone()];
+        let mut indices = HashMap::new();
+        indices.insert(default, 0);
+        let mut sections = Vec::new();
+        for (index, section) in sections_in.into_iter().enumerate() {
+            let section = match *section {
+                Some(ref section) => section,
+                None => {
+                    sections.push(None);
+                    continue;
+                }
+            };
+
+            // Blocks are run length encoded in y, z, x order
+            let mut modified: Vec<(usize, MetaBlock)> = self
+                .modified_blocks_in(section)
+                .into_iter()
+                .map(|((x, y, z), block)| (self.get_light_index(x, y, z), block))
+                .collect();
+            modified.sort_by_key(|x| x.0);
+
+            let mut runs = Vec::new();
+            let mut next = 0;
+            for (cell, block) in modified {
+                if cell > next {
+                    push_run(&mut runs, cell - next, 0);
+                }
+                let entry = *indices.entry(block.clone()).or_insert_with(|| {
+                    palette.push(block);
+                    palette.len() as u32 - 1
+                });
+                push_run(&mut runs, 1, entry);
+                next = cell + 1;
+            }
+            let cells = self.get_light_len(index);
+            if cells > next {
+                push_run(&mut runs, cells - next, 0);
+            }
+            sections.push(Some((section, runs)));
+        }
+
+        put_u32(&mut bytes, palette.len() as u32);
+        for block in &palette {
+            put_block(&mut bytes, block);
+        }
+
+        put_u32(&mut bytes, sections.len() as u32);
+        for section in sections {
+            let (section, runs) = match section {
+                Some(section) => section,
+                None => {
+                    bytes.push(0);
+                    continue;
+                }
+            };
+            let mut flags = 1;
+            if section.block_light.is_some() {
+                flags |= 2;
+            }
+            if section.sky_light.is_some() {
+                flags |= 4;
+            }
+            bytes.push(flags);
+            put_u32(&mut bytes, runs.len() as u32);
+            for (length, block) in runs {
+                put_u32(&mut bytes, length);
+                put_u32(&mut bytes, block);
+            }
+            for levels in section.block_light.iter().chain(section.sky_light.iter()) {
+                bytes.extend_from_slice(levels.as_bytes());
+            }
+        }
+
+        let biomes: Vec<_> = self.biomes.iter_non_default().collect();
+        put_u32(&mut bytes, biomes.len() as u32);
+        for ((x, y, z), biome) in biomes {
+            for coord in &[x, y, z] {
+                put_u32(&mut bytes, *coord as u32);
+            }
+            put_u16(&mut bytes, biome.get_id());
+        }
+
+        put_u32(&mut bytes, self.entities.len() as u32);
+        for entity in self.entities.values() {
+            bytes.extend_from_slice(entity.get_id().as_bytes());
+            put_str(&mut bytes, entity.get_type());
+            let (x, y, z) = entity.get_position();
+            for coord in &[x, y, z] {
+                bytes.extend_from_slice(&coord.to_bits().to_le_bytes());
+            }
+            entity
+                .get_nbt()
+                .write_unnamed(&mut bytes, ByteOrder::Little);
+            match entity.get_decoration() {
+                Some(decoration) => {
+                    bytes.push(1);
+                    put_decoration(&mut bytes, &decoration);
+                }
+                None => bytes.push(0),
+            }
+        }
+
+        bytes
+    }
+
+    /// Deserializes a chunk serialized with to_bytes, storing its sections in S
+    ///
+    /// Returns None if the bytes are not a valid serialized chunk
+    pub fn from_bytes_with_store(bytes: &[u8]) -> Option<Chunk<S>> {
+        let mut reader = ByteReader { bytes, position: 0 };
+        let version = reader.u8()?;
+        if version != CHUNK_FORMAT_VERSION {
+            return None;
+        }
+        let (x_size, y_size, z_size) = (reader.u32()?, reader.u32()?, reader.u32()?);
+        let default = reader.block()?;
+        let mut chunk: Chunk<S> = Chunk::with_store(
+            *default.get_block(),
+            x_size as usize,
+            y_size as usize,
+            z_size as usize,
+        );
+
+        let palette_len = reader.u32()? as usize;
+        let mut palette = Vec::new();
+        for _ in 0..palette_len {
+            palette.push(reader.block()?);
+        }
+
+        let section_count = reader.u32()? as usize;
+        if section_count != chunk.get_section_count() {
+            return None;
+        }
+        for index in 0..section_count {
+            let flags = reader.u8()?;
+            if flags & 1 == 0 {
+                continue;
+            }
+            let cells = chunk.get_light_len(index);
+            let mut section = chunk.get_section_or_new(index);
+
+            let mut cell = 0;
+            for _ in 0..reader.u32()? {
+                let (length, entry) = (reader.u32()? as usize, reader.u32()? as usize);
+                let block = BlockHandle::intern(palette.get(entry)?.clone());
+                if cell + length > cells {
+                    return None;
+                }
+                if entry != 0 {
+                    for i in cell..cell + length {
+                        let x = i % chunk.x_size;
+                        let z = (i / chunk.x_size) % chunk.z_size;
+                        let y = i / (chunk.x_size * chunk.z_size);
+                        section.blocks = section.blocks.set(x, y, z, block.clone())?;
+                    }
+                }
+                cell += length;
+            }
+            if cell != cells {
+                return None;
+            }
+
+            let light_bytes = cells.div_ceil(2);
+            if flags & 2 != 0 {
+                let levels = reader.take(light_bytes)?.to_vec();
+                section.block_light = Some(NibbleArray::from_bytes(levels, cells)?);
+            }
+            if flags & 4 != 0 {
+                let levels = reader.take(light_bytes)?.to_vec();
+                section.sky_light = Some(NibbleArray::from_bytes(levels, cells)?);
+            }
+            chunk = chunk.with_section(index, section);
+        }
+
+        for _ in 0..reader.u32()? {
+            let (x, y, z) = (reader.u32()?, reader.u32()?, reader.u32()?);
+            let biome = Biome::new(reader.u16()?);
+            chunk.biomes = chunk
+                .biomes
+                .set(x as usize, y as usize, z as usize, biome)?;
+        }
+        for _ in 0..reader.length(16)? {
+            let id = Uuid::from_bytes(reader.take(16)?).ok()?;
+            let entity_type = reader.str()?;
+            let position = (reader.f64()?, reader.f64()?, reader.f64()?);
+            let mut entity = Entity::new(id, &entity_type, position).set_nbt(reader.nbt()?);
+            if reader.u8()? != 0 {
+                entity = entity.set_decoration(Some(reader.decoration()?));
+            }
+            chunk.entities = chunk.entities.insert(id, entity);
+        }
+        if reader.position != bytes.len() {
+            return None;
+        }
+
+        // The heightmap is rebuilt rather than stored
+        for ((x, y, z), _) in chunk.modified_blocks() {
+            if chunk.get_height(x, z).is_none_or(|height| height < y) {
+                chunk.heightmap = chunk.heightmap.set(x, z, Some(y));
+            }
+        }
+        Some(chunk)
+    }
+
+    /// Returns every block of a section that differs from the default, along with its location
+    /// relative to the section
+    fn modified_blocks_in(&self, section: &Section<S>) -> Vec<((usize, usize, usize), MetaBlock)> {
+        section
+            .blocks
+            .iter_non_default()
+            .map(|(location, block)| (location, block.get()))
+            .collect()
+    }
+}
+
+/// Appends a run of the given palette entry, extending the last run if it has the same entry
+fn push_run(runs: &mut Vec<(u32, u32)>, length: usize, entry: u32) {
+    match runs.last_mut() {
+        Some(last) if last.1 == entry => last.0 += length as u32,
+        _ => runs.push((length as u32, entry)),
+    }
+}
+
+fn put_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_str(bytes: &mut Vec<u8>, value: &str) {
+    put_u16(bytes, value.len() as u16);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+/// Writes a block, followed by a byte flagging which parts of its metadata are present
+fn put_block(bytes: &mut Vec<u8>, block: &MetaBlock) {
+    put_u16(bytes, block.get_block().get_provider());
+    put_u16(bytes, block.get_block().get_id());
+    let meta_data = block.get_meta_data();
+    let properties = meta_data.get_properties();
+    let data_flag = meta_data.get_data_value().is_some() as u8;
+    let properties_flag = (!properties.is_empty() as u8) << 1;
+    let nbt = meta_data.get_nbt_data();
+    let nbt_flag = (!nbt.is_empty() as u8) << 2;
+    bytes.push(data_flag | properties_flag | nbt_flag);
+    if let Some(data_value) = meta_data.get_data_value() {
+        bytes.extend_from_slice(&data_value.to_le_bytes());
+    }
+    if !properties.is_empty() {
+        let properties = properties.get_map();
+        put_u16(bytes, properties.len() as u16);
+        for (name, value) in properties.iter() {
+            put_str(bytes, name);
+            match *value {
+                PropertyValue::Bool(value) => bytes.extend_from_slice(&[0, value as u8]),
+                PropertyValue::Int(value) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+                PropertyValue::Str(ref value) => {
+                    bytes.push(2);
+                    put_str(bytes, value);
+                }
+            }
+        }
+    }
+    if !nbt.is_empty() {
+        let nbt = nbt.get_map();
+        put_u16(bytes, nbt.len() as u16);
+        for (key, value) in nbt.iter() {
+            put_str(bytes, key);
+            value.write_unnamed(bytes, ByteOrder::Little);
+        }
+    }
+}
+
+/// The kinds of decorations, in the order their ids are written
+const DECORATION_KINDS: [DecorationKind; 4] = [
+    DecorationKind::ItemFrame,
+    DecorationKind::GlowItemFrame,
+    DecorationKind::Painting,
+    DecorationKind::ArmorStand,
+];
+
+/// Writes a decoration, followed by its variant and the item in each slot, if there is one
+fn put_decoration(bytes: &mut Vec<u8>, decoration: &Decoration) {
+    let kind = DECORATION_KINDS
+        .iter()
+        .position(|x| *x == decoration.get_kind())
+        .unwrap();
+    bytes.extend_from_slice(&[
+        kind as u8,
+        decoration.get_facing(),
+        decoration.get_rotation(),
+    ]);
+    let items = EquipmentSlot::ALL.iter().map(|&x| decoration.get_item(x));
+    for block in Some(decoration.get_variant()).into_iter().chain(items) {
+        match block {
+            Some(block) => {
+                bytes.push(1);
+                put_block(bytes, &block);
+            }
+            None => bytes.push(0),
+        }
+    }
+}
+
+/// Reads little endian values out of a byte slice
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(length)?;
+        let taken = self.bytes.get(self.position..end)?;
+        self.position = end;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let mut buffer = [0; 2];
+        buffer.copy_from_slice(self.take(2)?);
+        Some(u16::from_le_bytes(buffer))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut buffer = [0; 4];
+        buffer.copy_from_slice(self.take(4)?);
+        Some(u32::from_le_bytes(buffer))
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        Some(self.u32()? as i32)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut buffer = [0; 8];
+        buffer.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(buffer))
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        Some(f64::from_bits(self.u64()?))
+    }
+
+    /// Reads a u32 length, making sure there are at least that many elements of the given size
+    /// left, so corrupt lengths can't cause huge allocations
+    fn length(&mut self, element_size: usize) -> Option<usize> {
+        let length = self.u32()? as usize;
+        let remaining = self.bytes.len() - self.position;
+        if length.checked_mul(element_size)? > remaining {
+            return None;
+        }
+        Some(length)
+    }
+
+    fn nbt(&mut self) -> Option<NbtValue> {
+        let (value, length) =
+            NbtValue::read_unnamed(&self.bytes[self.position..], ByteOrder::Little)?;
+        self.position += length;
+        Some(value)
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let length = self.u16()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).ok()
+    }
+
+    fn decoration(&mut self) -> Option<Decoration> {
+        let kind = *DECORATION_KINDS.get(self.u8()? as usize)?;
+        let decoration = Decoration::new(kind, self.u8()?)?.set_rotation(self.u8()?)?;
+        let variant = self.optional_block()?;
+        let mut decoration = match variant {
+            Some(_) => decoration.set_variant(variant)?,
+            None => decoration,
+        };
+        for &slot in &EquipmentSlot::ALL {
+            if let Some(item) = self.optional_block()? {
+                decoration = decoration.set_item(slot, Some(item))?;
+            }
+        }
+        Some(decoration)
+    }
+
+    /// Reads a flag byte, followed by a block if it is set
+    fn optional_block(&mut self) -> Option<Option<MetaBlock>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => self.block().map(Some),
+            _ => None,
+        }
+    }
+
+    fn block(&mut self) -> Option<MetaBlock> {
+        let block = Block::new_from_ids(self.u16()?, self.u16()?);
+        let flags = self.u8()?;
+        if flags > 7 {
+            return None;
+        }
+        let mut meta_data = MetaData::new();
+        if flags & 1 != 0 {
+            meta_data = meta_data.set_data_value(self.i32()?);
+        }
+        if flags & 2 != 0 {
+            let mut properties = PropertyMap::new();
+            for _ in 0..self.u16()? {
+                let name = self.str()?;
+                let value = match self.u8()? {
+                    0 => PropertyValue::Bool(self.u8()? != 0),
+                    1 => PropertyValue::Int(self.i32()?),
+                    2 => PropertyValue::Str(self.str()?),
+                    _ => return None,
+                };
+                properties.insert(name, value);
+            }
+            meta_data = meta_data.set_properties(Properties::intern(properties));
+        }
+        if flags & 4 != 0 {
+            let mut nbt = NbtMap::new();
+            for _ in 0..self.u16()? {
+                let key = self.str()?;
+                nbt.insert(key, self.nbt()?);
+            }
+            meta_data = meta_data.set_nbt_data(NbtData::from_map(nbt));
+        }
+        Some(MetaBlock::fuse(block, meta_data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn sections_allocated_on_write() {
+        let chunk = Chunk::with_size(Block::new_from_ids(0, 0), 16, 40, 16);
+        assert_eq!(chunk.get_allocated_sections(), 0);
+
+        let sparse = chunk
+            .set_block(3, 39, 5, block(1))
+            .set_block(0, 0, 0, block(2));
+        assert!(sparse.is_sparse());
+        assert_eq!(sparse.get_allocated_sections(), 0);
+        assert!(sparse.get_block(3, 39, 5) == block(1));
+        assert_eq!(sparse.modified_blocks().len(), 2);
+
+        // Setting light levels splits the chunk into sections
+        let chunk = sparse.set_light(LightType::Block, 0, 0, 0, 0);
+        assert!(!chunk.is_sparse());
+        assert_eq!(chunk.get_allocated_sections(), 2);
+        assert!(chunk.modified_blocks() == sparse.modified_blocks());
+        let chunk = sparse.to_sectioned();
+        assert!(chunk.get_block(3, 39, 5) == block(1));
+        assert!(chunk.get_block(0, 0, 0) == block(2));
+        assert!(chunk.get_block(3, 20, 5) == block(0));
+        assert_eq!(chunk.modified_blocks().len(), 2);
+
+        assert_eq!(chunk.get_height(3, 5), Some(39));
+        assert_eq!(chunk.get_height(0, 0), Some(0));
+        assert_eq!(chunk.get_height(1, 1), None);
+        let lowered = chunk
+            .set_block(3, 10, 5, block(4))
+            .set_block(3, 39, 5, block(0));
+        assert_eq!(lowered.get_height(3, 5), Some(10));
+
+        assert!(chunk.get_light(LightType::Sky, 3, 39, 5).is_none());
+        let lit = chunk.set_light(LightType::Sky, 3, 39, 5, 15);
+        assert_eq!(lit.get_light(LightType::Sky, 3, 39, 5), Some(15));
+        assert_eq!(lit.get_light(LightType::Sky, 4, 39, 5), Some(0));
+        assert!(lit.get_light(LightType::Block, 3, 39, 5).is_none());
+        // The top section is only 8 blocks tall
+        let levels = lit.get_light_section(LightType::Sky, 2).unwrap();
+        assert_eq!(levels.len(), 16 * 8 * 16);
+        assert!(lit
+            .set_light_section(LightType::Block, 1, levels.clone())
+            .is_none());
+        assert!(lit.set_light_section(LightType::Block, 2, levels).is_some());
+
+        // Writes outside of the chunk are ignored
+        let chunk = chunk.set_block(0, 40, 0, block(3));
+        assert!(chunk.get_block(0, 40, 0) == block(0));
+        let sparse = sparse.set_block(16, 0, 0, block(3));
+        assert_eq!(sparse.modified_blocks().len(), 2);
+    }
+
+    #[test]
+    fn sparse_until_limit() {
+        let mut chunk = Chunk::with_size(Block::new_from_ids(0, 0), 32, 32, 32);
+        for i in 0..SPARSE_CHUNK_LIMIT {
+            chunk = chunk.set_block(i % 32, i / 32, 0, block(1));
+        }
+        assert!(chunk.is_sparse());
+        // Clearing a block keeps the chunk sparse
+        let cleared = chunk.set_block(0, 0, 0, block(0));
+        assert_eq!(cleared.modified_blocks().len(), SPARSE_CHUNK_LIMIT - 1);
+
+        let chunk = chunk.set_block(0, 0, 1, block(2));
+        assert!(!chunk.is_sparse());
+        assert_eq!(chunk.modified_blocks().len(), SPARSE_CHUNK_LIMIT + 1);
+        assert!(chunk.get_block(0, 0, 1) == block(2));
+        assert_eq!(chunk.get_height(31, 0), Some(SPARSE_CHUNK_LIMIT / 32 - 1));
+    }
+
+    #[test]
+    fn fill_layers() {
+        let chunk = Chunk::with_size(Block::new_from_ids(0, 0), 4, 40, 4)
+            .set_block(1, 30, 1, block(2))
+            .fill_layers(10, 20, block(1));
+        assert_eq!(chunk.get_allocated_sections(), 2);
+        assert!(chunk.get_block(3, 10, 3) == block(1));
+        assert!(chunk.get_block(3, 20, 0) == block(1));
+        assert!(chunk.get_block(3, 21, 0) == block(0));
+        assert_eq!(chunk.get_height(0, 0), Some(20));
+        assert_eq!(chunk.get_height(1, 1), Some(30));
+
+        let chunk = chunk.fill_layers(15, 100, block(0));
+        assert!(chunk.get_block(1, 30, 1) == block(0));
+        assert_eq!(chunk.get_height(0, 0), Some(14));
+        assert_eq!(chunk.get_height(1, 1), Some(14));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let with_meta = MetaBlock::fuse(
+            Block::new_from_ids(2, 7),
+            MetaData::new()
+                .set_data_value(-3)
+                .set_property("facing", "east")
+                .set_property("waterlogged", true)
+                .set_property("age", 4)
+                .set_nbt(
+                    "mymod:state",
+                    NbtValue::Compound(
+                        vec![
+                            ("charge".to_string(), NbtValue::Float(-0.5)),
+                            (
+                                "owners".to_string(),
+                                NbtValue::List(vec![NbtValue::LongArray(vec![1, -2])]),
+                            ),
+                        ]
+                        .into_iter()
+                        .collect(),
+                    ),
+                )
+                .unwrap(),
+        );
+        let chunk = Chunk::with_size(Block::new_from_ids(0, 0), 8, 40, 4)
+            .set_block(1, 2, 3, block(1))
+            .set_block(2, 2, 3, block(1))
+            .set_block(7, 39, 0, with_meta.clone())
+            .set_block(
+                0,
+                0,
+                0,
+                MetaBlock::fuse(Block::new_from_ids(0, 0), MetaData::new().set_data_value(1)),
+            )
+            .set_light(LightType::Block, 7, 39, 0, 12)
+            .set_biome(4, 36, 0, Biome::new(5));
+        let frame = Entity::new(Uuid::new_v4(), "minecraft:item_frame", (1.5, 2.0, -0.25)).set_nbt(
+            NbtValue::Compound(
+                vec![("ItemRotation".to_string(), NbtValue::Byte(3))]
+                    .into_iter()
+                    .collect(),
+            ),
+        );
+        let framed = Decoration::new(DecorationKind::ItemFrame, 2)
+            .unwrap()
+            .set_item(EquipmentSlot::MainHand, Some(with_meta));
+        let frame = frame.set_decoration(framed);
+        let chunk = chunk.set_entity(frame.clone());
+
+        let decoded = Chunk::from_bytes(&chunk.to_bytes()).unwrap();
+        assert_eq!(decoded.get_size(), (8, 40, 4));
+        assert_eq!(decoded.get_allocated_sections(), 2);
+        assert!(decoded.modified_blocks() == chunk.modified_blocks());
+        assert_eq!(decoded.get_light(LightType::Block, 7, 39, 0), Some(12));
+        assert!(decoded.get_light(LightType::Sky, 7, 39, 0).is_none());
+        assert!(decoded.get_biome(4, 36, 0) == Biome::new(5));
+        assert!(decoded.get_biome(0, 36, 0) == Biome::default());
+        assert_eq!(decoded.get_height(7, 0), Some(39));
+        assert_eq!(decoded.get_height(0, 0), Some(0));
+        assert!(chunk.diff(&decoded).is_empty());
+        assert!(decoded.get_entities() == vec![frame.clone()]);
+
+        // Chunks from before NBT was written as binary NBT are rejected
+        let mut old = chunk.to_bytes();
+        old[0] = 3;
+        assert!(Chunk::from_bytes(&old).is_none());
+
+        // Truncated or trailing bytes are rejected
+        let bytes = chunk.to_bytes();
+        assert!(Chunk::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(Chunk::from_bytes(&extended).is_none());
+    }
+
+    #[test]
+    fn any_store() {
+        let packed = Chunk::with_size(Block::new_from_ids(0, 0), 16, 32, 16);
+        let plain: Chunk<Cuboid<BlockHandle>> =
+            Chunk::with_store(Block::new_from_ids(0, 0), 16, 32, 16);
+        let changes = (0..SPARSE_CHUNK_LIMIT + 100)
+            .map(|i| ((i % 16, i / 256, (i / 16) % 16), block(i as u16 % 3 + 1)))
+            .collect();
+        let patch = ChunkPatch { changes };
+        let packed = packed.apply_patch(&patch);
+        let plain = plain.apply_patch(&patch);
+        assert!(!plain.is_sparse());
+        assert!(packed.modified_blocks() == plain.modified_blocks());
+        assert!(packed.memory_estimate() < plain.memory_estimate());
+
+        // Sectioned chunks are diffed one section at a time
+        let changed = packed
+            .set_block(3, 20, 3, block(5))
+            .set_block(0, 0, 0, block(0));
+        let expected = [((0, 0, 0), block(0)), ((3, 20, 3), block(5))];
+        assert_eq!(packed.diff(&changed).get_changes(), &expected);
+        assert!(packed.diff(&packed).is_empty());
+
+        let decoded: Chunk<Cuboid<BlockHandle>> =
+            Chunk::from_bytes_with_store(&packed.to_bytes()).unwrap();
+        assert!(decoded.modified_blocks() == plain.modified_blocks());
+    }
+
+    #[test]
+    fn diff_and_patch() {
+        let base = Chunk::with_size(Block::new_from_ids(0, 0), 8, 20, 8)
+            .set_block(1, 1, 1, block(1))
+            .set_block(2, 2, 2, block(2));
+        let changed = base
+            .set_block(1, 1, 1, block(0))
+            .set_block(2, 2, 2, block(3))
+            .set_block(3, 18, 3, block(4));
+
+        let patch = base.diff(&changed);
+        assert_eq!(patch.len(), 3);
+        assert!(patch.get_changes()[0] == ((1, 1, 1), block(0)));
+        assert!(base.apply_patch(&patch).diff(&changed).is_empty());
+        assert!(base.diff(&base).is_empty());
+
+        // Chunks with different defaults still diff correctly
+        let other = Chunk::with_size(Block::new_from_ids(0, 5), 8, 20, 8);
+        let patch = base.diff(&other);
+        assert_eq!(patch.len(), 8 * 20 * 8);
+        assert!(base.apply_patch(&patch).diff(&other).is_empty());
+    }
 }
@@ -0,0 +1,176 @@
+//! Provides the mapping between block coordinates and chunks
+//!
+//! Chunks tile the world starting from the origin, so the chunk holding a block is found by
+//! flooring division, and the block's offset inside of it by the euclidean remainder. Truncating
+//! division would fold the chunks on either side of zero onto each other.
+//!
+//! Chunk dimensions are given as (x, y, z) sizes in blocks, as returned by Chunk::get_size.
+
+use data::biome::BIOME_CELL_SIZE;
+
+/// The position of a block in the world
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct BlockPos {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+impl BlockPos {
+    /// Creates a new BlockPos
+    pub fn new(x: i32, y: i32, z: i32) -> BlockPos {
+        BlockPos { x, y, z }
+    }
+
+    pub fn get_x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn get_y(&self) -> i32 {
+        self.y
+    }
+
+    pub fn get_z(&self) -> i32 {
+        self.z
+    }
+
+    /// Returns the position as an (x, y, z) tuple
+    pub fn get_coords(&self) -> (i32, i32, i32) {
+        (self.x, self.y, self.z)
+    }
+
+    /// Returns the position of the biome cell containing this block, in cells
+    pub fn get_biome_cell(&self) -> (i32, i32, i32) {
+        let size = BIOME_CELL_SIZE as i32;
+        (
+            self.x.div_euclid(size),
+            self.y.div_euclid(size),
+            self.z.div_euclid(size),
+        )
+    }
+
+    /// Returns the position of the chunk containing this block
+    pub fn get_chunk_pos(&self, dims: (usize, usize, usize)) -> ChunkPos {
+        ChunkPos {
+            x: self.x.div_euclid(dims.0 as i32),
+            y: self.y.div_euclid(dims.1 as i32),
+            z: self.z.div_euclid(dims.2 as i32),
+        }
+    }
+
+    /// Returns the offset of this block inside of its chunk
+    pub fn get_offset(&self, dims: (usize, usize, usize)) -> (usize, usize, usize) {
+        (
+            self.x.rem_euclid(dims.0 as i32) as usize,
+            self.y.rem_euclid(dims.1 as i32) as usize,
+            self.z.rem_euclid(dims.2 as i32) as usize,
+        )
+    }
+}
+
+/// The position of a chunk in the grid of chunks
+///
+/// The chunk at (0, 0, 0) starts at the origin, the one at (-1, 0, 0) ends just before it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ChunkPos {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+impl ChunkPos {
+    /// Creates a new ChunkPos
+    pub fn new(x: i32, y: i32, z: i32) -> ChunkPos {
+        ChunkPos { x, y, z }
+    }
+
+    pub fn get_x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn get_y(&self) -> i32 {
+        self.y
+    }
+
+    pub fn get_z(&self) -> i32 {
+        self.z
+    }
+
+    /// Returns the position of the block in the lowest corner of this chunk
+    pub fn get_origin(&self, dims: (usize, usize, usize)) -> BlockPos {
+        BlockPos {
+            x: self.x * dims.0 as i32,
+            y: self.y * dims.1 as i32,
+            z: self.z * dims.2 as i32,
+        }
+    }
+
+    /// Returns the position of the block at the given offset inside of this chunk
+    pub fn get_block_pos(
+        &self,
+        offset: (usize, usize, usize),
+        dims: (usize, usize, usize),
+    ) -> BlockPos {
+        let origin = self.get_origin(dims);
+        BlockPos {
+            x: origin.x + offset.0 as i32,
+            y: origin.y + offset.1 as i32,
+            z: origin.z + offset.2 as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::*;
+
+    const DIMS: (usize, usize, usize) = (4, 3, 5);
+
+    #[test]
+    fn mapping_round_trips() {
+        for x in -13..13 {
+            for y in -10..10 {
+                for z in -16..16 {
+                    let pos = BlockPos::new(x, y, z);
+                    let chunk = pos.get_chunk_pos(DIMS);
+                    let offset = pos.get_offset(DIMS);
+                    assert!(offset.0 < DIMS.0 && offset.1 < DIMS.1 && offset.2 < DIMS.2);
+                    assert!(chunk.get_block_pos(offset, DIMS) == pos);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chunk_borders() {
+        for border in -3..4 {
+            // The last block of one chunk, and the first of the next
+            let first = border * DIMS.0 as i32;
+            let before = BlockPos::new(first - 1, 0, 0);
+            let after = BlockPos::new(first, 0, 0);
+            assert_eq!(before.get_chunk_pos(DIMS).get_x(), border - 1);
+            assert_eq!(after.get_chunk_pos(DIMS).get_x(), border);
+            assert_eq!(before.get_offset(DIMS).0, DIMS.0 - 1);
+            assert_eq!(after.get_offset(DIMS).0, 0);
+            assert!(ChunkPos::new(border, 0, 0).get_origin(DIMS) == after);
+        }
+    }
+
+    #[test]
+    fn negative_blocks_do_not_alias() {
+        let block = |id| MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new());
+        let size = CHUNK_SIZE as i32;
+        let xs = [-size - 1, -size, -size + 1, -1, 0, 1, size - 1, size];
+
+        let mut world = World::new(block(0));
+        for (i, &x) in xs.iter().enumerate() {
+            world = world
+                .set_block_defaulting(x, -x, x, block(i as u16 + 1))
+                .unwrap();
+        }
+        for (i, &x) in xs.iter().enumerate() {
+            assert!(world.get_block_defaulting(x, -x, x).unwrap() == block(i as u16 + 1));
+        }
+    }
+}
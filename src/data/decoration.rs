@@ -0,0 +1,254 @@
+//! Provides decorations, the entities players hang on walls or stand up as part of their builds
+//!
+//! Item frames, paintings and armor stands are entities rather than blocks, so a log of block
+//! changes misses them being broken, turned or robbed. Decorations describe what one looks like
+//! and what it holds, so they can have a history of their own alongside the blocks.
+//!
+//! Items and painting variants are stored as MetaBlocks, looked up in the same dictionary as
+//! blocks, with any item NBT attached as namespaced NBT. Like blocks, they are interned.
+
+use data::block::*;
+use data::entity::*;
+use data::handle::*;
+use uuid::Uuid;
+
+/// The kinds of decorations that are tracked
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DecorationKind {
+    ItemFrame,
+    GlowItemFrame,
+    Painting,
+    ArmorStand,
+}
+
+impl DecorationKind {
+    /// Returns the entity type of this kind of decoration
+    pub fn get_entity_type(&self) -> &'static str {
+        match *self {
+            DecorationKind::ItemFrame => "minecraft:item_frame",
+            DecorationKind::GlowItemFrame => "minecraft:glow_item_frame",
+            DecorationKind::Painting => "minecraft:painting",
+            DecorationKind::ArmorStand => "minecraft:armor_stand",
+        }
+    }
+
+    /// Returns the number of distinct rotations this kind of decoration has
+    ///
+    /// Item frames turn their item in eighths of a turn, armor stands turn in sixteenths, like
+    /// signs. Paintings do not turn.
+    pub fn get_rotations(&self) -> u8 {
+        match *self {
+            DecorationKind::ItemFrame | DecorationKind::GlowItemFrame => 8,
+            DecorationKind::Painting => 1,
+            DecorationKind::ArmorStand => 16,
+        }
+    }
+
+    /// Returns true if this kind of decoration can hold an item in the slot
+    ///
+    /// Item frames hold their item in the main hand, armor stands can hold one in every slot.
+    pub fn holds(&self, slot: EquipmentSlot) -> bool {
+        match *self {
+            DecorationKind::ItemFrame | DecorationKind::GlowItemFrame => {
+                slot == EquipmentSlot::MainHand
+            }
+            DecorationKind::Painting => false,
+            DecorationKind::ArmorStand => true,
+        }
+    }
+}
+
+/// The slots a decoration can hold items in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EquipmentSlot {
+    MainHand,
+    OffHand,
+    Feet,
+    Legs,
+    Chest,
+    Head,
+}
+
+impl EquipmentSlot {
+    /// Every slot, in the order they are stored
+    pub const ALL: [EquipmentSlot; 6] = [
+        EquipmentSlot::MainHand,
+        EquipmentSlot::OffHand,
+        EquipmentSlot::Feet,
+        EquipmentSlot::Legs,
+        EquipmentSlot::Chest,
+        EquipmentSlot::Head,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// What a decoration looks like and holds
+///
+/// Facing is numbered like minecraft does, 0 down, 1 up, 2 north, 3 south, 4 west and 5 east.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decoration {
+    kind: DecorationKind,
+    facing: u8,
+    rotation: u8,
+    /// Only paintings have a variant
+    variant: Option<BlockHandle>,
+    /// Indexed by EquipmentSlot
+    items: [Option<BlockHandle>; 6],
+}
+
+impl Decoration {
+    /// Creates a new, empty decoration of the given kind, facing the given direction
+    ///
+    /// Returns None if the facing is not one of the six directions
+    pub fn new(kind: DecorationKind, facing: u8) -> Option<Decoration> {
+        if facing > 5 {
+            return None;
+        }
+        Some(Decoration {
+            kind,
+            facing,
+            rotation: 0,
+            variant: None,
+            items: Default::default(),
+        })
+    }
+
+    /// Returns the kind of this decoration
+    pub fn get_kind(&self) -> DecorationKind {
+        self.kind
+    }
+
+    /// Returns the direction this decoration faces
+    pub fn get_facing(&self) -> u8 {
+        self.facing
+    }
+
+    /// Returns the rotation of this decoration, see DecorationKind::get_rotations
+    pub fn get_rotation(&self) -> u8 {
+        self.rotation
+    }
+
+    /// Returns the variant of a painting
+    pub fn get_variant(&self) -> Option<MetaBlock> {
+        self.variant.as_ref().map(|x| x.get())
+    }
+
+    /// Returns the item held in the slot
+    pub fn get_item(&self, slot: EquipmentSlot) -> Option<MetaBlock> {
+        self.items[slot.index()].as_ref().map(|x| x.get())
+    }
+
+    /// Returns every item this decoration holds, along with its slot
+    pub fn get_items(&self) -> Vec<(EquipmentSlot, MetaBlock)> {
+        EquipmentSlot::ALL
+            .iter()
+            .filter_map(|&slot| self.get_item(slot).map(|item| (slot, item)))
+            .collect()
+    }
+
+    /// Returns a copy of this decoration with the given rotation
+    ///
+    /// Returns None if this kind of decoration does not have that many rotations
+    pub fn set_rotation(&self, rotation: u8) -> Option<Decoration> {
+        if rotation >= self.kind.get_rotations() {
+            return None;
+        }
+        Some(Decoration {
+            rotation,
+            ..self.clone()
+        })
+    }
+
+    /// Returns a copy of this painting with the given variant
+    ///
+    /// Returns None if this decoration is not a painting
+    pub fn set_variant(&self, variant: Option<MetaBlock>) -> Option<Decoration> {
+        if self.kind != DecorationKind::Painting {
+            return None;
+        }
+        Some(Decoration {
+            variant: variant.map(BlockHandle::intern),
+            ..self.clone()
+        })
+    }
+
+    /// Returns a copy of this decoration holding the item in the slot, or nothing for None
+    ///
+    /// Returns None if this kind of decoration can not hold items in that slot
+    pub fn set_item(&self, slot: EquipmentSlot, item: Option<MetaBlock>) -> Option<Decoration> {
+        if !self.kind.holds(slot) {
+            return None;
+        }
+        let mut decoration = self.clone();
+        decoration.items[slot.index()] = item.map(BlockHandle::intern);
+        Some(decoration)
+    }
+
+    /// Returns a copy of this decoration with its variant and every item passed through f
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> Decoration {
+        let map = |x: &Option<BlockHandle>| x.as_ref().map(|x| BlockHandle::intern(f(x.get())));
+        let mut decoration = self.clone();
+        decoration.variant = map(&self.variant);
+        for item in decoration.items.iter_mut() {
+            *item = map(item);
+        }
+        decoration
+    }
+
+    /// Returns the entity with the given uuid this decoration is, in the given block
+    ///
+    /// The entity stands in the middle of the bottom of the block, and carries the decoration.
+    pub fn to_entity(&self, id: Uuid, (x, y, z): (i32, i32, i32)) -> Entity {
+        let position = (f64::from(x) + 0.5, f64::from(y), f64::from(z) + 0.5);
+        Entity::new(id, self.kind.get_entity_type(), position).set_decoration(Some(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn decorations_hold_what_they_can() {
+        assert!(Decoration::new(DecorationKind::Painting, 6).is_none());
+        let frame = Decoration::new(DecorationKind::ItemFrame, 2).unwrap();
+        let framed = frame
+            .set_item(EquipmentSlot::MainHand, Some(block(1)))
+            .unwrap()
+            .set_rotation(7)
+            .unwrap();
+        assert!(framed.get_items() == vec![(EquipmentSlot::MainHand, block(1))]);
+        assert!(frame
+            .set_item(EquipmentSlot::Head, Some(block(1)))
+            .is_none());
+        assert!(frame.set_rotation(8).is_none());
+        assert!(frame.set_variant(Some(block(2))).is_none());
+
+        let painting = Decoration::new(DecorationKind::Painting, 3).unwrap();
+        let painting = painting.set_variant(Some(block(2))).unwrap();
+        assert!(painting.get_variant() == Some(block(2)));
+        assert!(painting.set_rotation(1).is_none());
+
+        let stand = Decoration::new(DecorationKind::ArmorStand, 1)
+            .unwrap()
+            .set_item(EquipmentSlot::Head, Some(block(3)))
+            .unwrap()
+            .set_rotation(15)
+            .unwrap();
+        let mapped = stand.map_blocks(|_| block(4));
+        assert!(mapped.get_item(EquipmentSlot::Head) == Some(block(4)));
+        assert_eq!(mapped.get_rotation(), 15);
+
+        let entity = stand.to_entity(Uuid::new_v4(), (-1, 64, 3));
+        assert_eq!(entity.get_type(), "minecraft:armor_stand");
+        assert_eq!(entity.get_block_coords(), (-1, 64, 3));
+        assert!(entity.get_decoration() == Some(stand));
+    }
+}
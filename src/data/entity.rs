@@ -0,0 +1,94 @@
+//! Provides entities, the things in a world that are not blocks
+//!
+//! Mobs, item frames, dropped items and the like are stored in the chunk containing them, keyed
+//! by their uuid, alongside the blocks. Like chunks, entities are immutable, updating one returns
+//! a new copy.
+
+use data::decoration::*;
+use data::nbt::*;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// A single entity, and everything known about it
+///
+/// The NBT blob holds any state beyond the entity's type and position, such as its health, or
+/// the item in an item frame, in whatever shape the game stores it. Decorations placed by
+/// transactions also carry what they look like and hold, as a Decoration.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entity {
+    id: Uuid,
+    entity_type: String,
+    position: (f64, f64, f64),
+    nbt: NbtValue,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    decoration: Option<Decoration>,
+}
+
+impl Entity {
+    /// Creates a new entity of the given type, such as `minecraft:item_frame`, at the given
+    /// position, with an empty compound as its NBT
+    pub fn new(id: Uuid, entity_type: &str, position: (f64, f64, f64)) -> Entity {
+        Entity {
+            id,
+            entity_type: entity_type.to_string(),
+            position,
+            nbt: NbtValue::Compound(BTreeMap::new()),
+            decoration: None,
+        }
+    }
+
+    /// Returns the uuid of this entity
+    pub fn get_id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Returns the type of this entity
+    pub fn get_type(&self) -> &str {
+        &self.entity_type
+    }
+
+    /// Returns the position of this entity, in world coordinates
+    pub fn get_position(&self) -> (f64, f64, f64) {
+        self.position
+    }
+
+    /// Returns the coordinates of the block this entity is in
+    pub fn get_block_coords(&self) -> (i32, i32, i32) {
+        let (x, y, z) = self.position;
+        (x.floor() as i32, y.floor() as i32, z.floor() as i32)
+    }
+
+    /// Returns the NBT blob of this entity
+    pub fn get_nbt(&self) -> &NbtValue {
+        &self.nbt
+    }
+
+    /// Returns the decoration this entity is, if it is one
+    pub fn get_decoration(&self) -> Option<Decoration> {
+        self.decoration.clone()
+    }
+
+    /// Returns a copy of this entity moved to the given position
+    pub fn set_position(&self, position: (f64, f64, f64)) -> Entity {
+        Entity {
+            position,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this entity with the given NBT blob
+    pub fn set_nbt(&self, nbt: NbtValue) -> Entity {
+        Entity {
+            nbt,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this entity that is the given decoration, or no decoration for None
+    pub fn set_decoration(&self, decoration: Option<Decoration>) -> Entity {
+        Entity {
+            decoration,
+            ..self.clone()
+        }
+    }
+}
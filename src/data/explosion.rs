@@ -0,0 +1,131 @@
+//! Provides explosions, the blocks a creeper or TNT destroyed all at once
+//!
+//! An explosion can destroy hundreds of blocks, which should show up, and be undone, as one entry
+//! in history rather than hundreds, so an Explode transaction carries every block it destroyed
+//! along with what was there before. The list of blocks is shared between the copies of the
+//! transaction, so cloning a transaction does not clone hundreds of blocks.
+
+use data::block::*;
+use data::handle::*;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::sync::Arc;
+
+/// A destroyed block, with its coordinates
+type Destroyed = ((i32, i32, i32), BlockHandle);
+
+/// The blocks destroyed by an explosion, and the block each one was before it was destroyed
+///
+/// Blocks are kept ordered by coordinates, so comparing explosions compares their contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Explosion {
+    blocks: Arc<[Destroyed]>,
+}
+
+impl Explosion {
+    /// Creates an explosion destroying the given blocks, each given with its state beforehand
+    ///
+    /// If the same coordinates are given more than once, the first state given is kept
+    pub fn new<I>(blocks: I) -> Explosion
+    where
+        I: IntoIterator<Item = ((i32, i32, i32), MetaBlock)>,
+    {
+        let mut blocks: Vec<Destroyed> = blocks
+            .into_iter()
+            .map(|(coords, block)| (coords, BlockHandle::intern(block)))
+            .collect();
+        blocks.sort_by_key(|x| x.0);
+        blocks.dedup_by_key(|x| x.0);
+        Explosion {
+            blocks: blocks.into(),
+        }
+    }
+
+    /// Returns every destroyed block, along with its state beforehand, ordered by coordinates
+    pub fn get_blocks(&self) -> Vec<((i32, i32, i32), MetaBlock)> {
+        self.blocks
+            .iter()
+            .map(|(coords, block)| (*coords, block.get()))
+            .collect()
+    }
+
+    /// Returns the coordinates of every destroyed block, in order
+    pub fn get_coords(&self) -> Vec<(i32, i32, i32)> {
+        self.blocks.iter().map(|x| x.0).collect()
+    }
+
+    /// Returns the state of the block at the coordinates before the explosion, if the explosion
+    /// destroyed it
+    pub fn get_destroyed(&self, coords: (i32, i32, i32)) -> Option<MetaBlock> {
+        self.blocks
+            .binary_search_by_key(&coords, |x| x.0)
+            .ok()
+            .map(|i| self.blocks[i].1.get())
+    }
+
+    /// Returns true if the explosion destroyed the block at the coordinates
+    pub fn contains(&self, coords: (i32, i32, i32)) -> bool {
+        self.blocks.binary_search_by_key(&coords, |x| x.0).is_ok()
+    }
+
+    /// Returns the number of blocks destroyed
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns true if the explosion did not destroy any blocks
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Returns a copy of this explosion with the prior state of every block passed through f
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> Explosion {
+        Explosion::new(
+            self.blocks
+                .iter()
+                .map(|(coords, block)| (*coords, f(block.get()))),
+        )
+    }
+}
+
+/// Written as the list of destroyed blocks
+impl Serialize for Explosion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.blocks.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Explosion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Explosion, D::Error> {
+        Vec::<((i32, i32, i32), MetaBlock)>::deserialize(deserializer).map(Explosion::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn explosions_are_ordered_sets() {
+        let explosion = Explosion::new(vec![
+            ((1, 0, 0), block(1)),
+            ((-1, 0, 0), block(2)),
+            ((1, 0, 0), block(3)),
+        ]);
+        assert_eq!(explosion.len(), 2);
+        assert!(explosion.get_coords() == vec![(-1, 0, 0), (1, 0, 0)]);
+        assert!(explosion.get_destroyed((1, 0, 0)) == Some(block(1)));
+        assert!(explosion.get_destroyed((0, 0, 0)).is_none());
+        assert!(explosion == Explosion::new(explosion.get_blocks()));
+        assert!(explosion.map_blocks(|_| block(4)).get_destroyed((-1, 0, 0)) == Some(block(4)));
+
+        let json = serde_json::to_string(&explosion).unwrap();
+        let read: Explosion = serde_json::from_str(&json).unwrap();
+        assert!(read == explosion);
+    }
+}
@@ -0,0 +1,286 @@
+//! Provides filters for picking transactions out of a worldline
+
+use chrono::prelude::*;
+use data::block::*;
+use data::category::*;
+use data::region::*;
+use data::tags::*;
+use data::transaction::*;
+use data::world::WorldId;
+use std::collections::HashSet;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A span of wall-clock time, from start up to but not including end
+///
+/// Times are kept in UTC, so ranges given in any time zone compare correctly with transactions
+/// submitted from any other.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimeRange {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+impl TimeRange {
+    /// Creates a new range, from start up to but not including end
+    ///
+    /// Returns None if end comes before start
+    pub fn new<Tz: TimeZone>(start: DateTime<Tz>, end: DateTime<Tz>) -> Option<TimeRange> {
+        let (start, end) = (start.with_timezone(&Utc), end.with_timezone(&Utc));
+        if end < start {
+            return None;
+        }
+        Some(TimeRange { start, end })
+    }
+
+    /// Returns the start of the range
+    pub fn get_start(&self) -> DateTime<Utc> {
+        self.start
+    }
+
+    /// Returns the end of the range, which is not part of it
+    pub fn get_end(&self) -> DateTime<Utc> {
+        self.end
+    }
+
+    /// Returns true if the time is within the range
+    pub fn contains<Tz: TimeZone>(&self, time: DateTime<Tz>) -> bool {
+        let time = time.with_timezone(&Utc);
+        self.start <= time && time < self.end
+    }
+
+    /// Returns true if the transaction occured within the range
+    ///
+    /// Transactions without a time are never within a range
+    pub fn contains_transaction(&self, transaction: &Transaction) -> bool {
+        transaction
+            .get_transaction()
+            .get_time()
+            .is_some_and(|time| self.contains(time))
+    }
+}
+
+/// Describes which transactions a query is interested in
+///
+/// An empty filter matches every transaction. Each criteria that is set narrows the match down
+/// further, so a transaction has to satisfy all of them.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TransactionFilter {
+    owner: Option<Uuid>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    region: Option<Region>,
+    world: Option<WorldId>,
+    blocks: Option<Arc<HashSet<Block>>>,
+    categories: Option<HashSet<ActionCategory>>,
+}
+
+impl TransactionFilter {
+    /// Creates a new filter that matches every transaction
+    pub fn new() -> TransactionFilter {
+        TransactionFilter {
+            owner: None,
+            start: None,
+            end: None,
+            region: None,
+            world: None,
+            blocks: None,
+            categories: None,
+        }
+    }
+
+    /// Only match transactions performed by the given owner
+    pub fn set_owner(&mut self, owner: Uuid) -> &mut Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// Only match transactions that occured at or after the given time
+    ///
+    /// Transactions without a time will no longer match
+    pub fn set_start<Tz: TimeZone>(&mut self, start: DateTime<Tz>) -> &mut Self {
+        self.start = Some(start.with_timezone(&Utc));
+        self
+    }
+
+    /// Only match transactions that occured before the given time
+    ///
+    /// Transactions without a time will no longer match
+    pub fn set_end<Tz: TimeZone>(&mut self, end: DateTime<Tz>) -> &mut Self {
+        self.end = Some(end.with_timezone(&Utc));
+        self
+    }
+
+    /// Only match transactions that occured within the range, like set_start and set_end
+    pub fn set_time_range(&mut self, range: TimeRange) -> &mut Self {
+        self.set_start(range.start).set_end(range.end)
+    }
+
+    /// Only match transactions affecting a block inside the region
+    ///
+    /// Transactions without coordinates, like Undos, will no longer match
+    pub fn set_region(&mut self, region: Region) -> &mut Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// Only match transactions taking place in the given world
+    ///
+    /// Undos take place in the world they were built for, which defaults to the overworld
+    pub fn set_world(&mut self, world: WorldId) -> &mut Self {
+        self.world = Some(world);
+        self
+    }
+
+    /// Only match transactions placing or replacing one of the given blocks
+    ///
+    /// Metadata is ignored. Transactions that do not involve a block, like Undos and SetBiomes,
+    /// will no longer match
+    pub fn set_blocks(&mut self, blocks: HashSet<Block>) -> &mut Self {
+        self.blocks = Some(Arc::new(blocks));
+        self
+    }
+
+    /// Only match transactions placing or replacing one of the blocks in a tag, see set_blocks
+    ///
+    /// Returns None, leaving the filter untouched, if the tag is not in the registry
+    pub fn set_block_tag(
+        &mut self,
+        tags: &BlockTagRegistry,
+        tag: &str,
+        dictonary: &BlockDictonary,
+    ) -> Option<&mut Self> {
+        let blocks = tags.resolve(tag, dictonary)?;
+        Some(self.set_blocks(blocks))
+    }
+
+    /// Only match transactions in one of the given categories
+    ///
+    /// Transactions without a category will no longer match
+    pub fn set_categories(&mut self, categories: HashSet<ActionCategory>) -> &mut Self {
+        self.categories = Some(categories);
+        self
+    }
+
+    /// Returns the owner being filtered on, if any
+    pub fn get_owner(&self) -> Option<Uuid> {
+        self.owner
+    }
+
+    /// Returns the start of the time range being filtered on, if any
+    pub fn get_start(&self) -> Option<DateTime<Utc>> {
+        self.start
+    }
+
+    /// Returns the end of the time range being filtered on, if any
+    pub fn get_end(&self) -> Option<DateTime<Utc>> {
+        self.end
+    }
+
+    /// Returns the region being filtered on, if any
+    pub fn get_region(&self) -> Option<Region> {
+        self.region
+    }
+
+    /// Returns the world being filtered on, if any
+    pub fn get_world(&self) -> Option<WorldId> {
+        self.world
+    }
+
+    /// Returns the blocks being filtered on, if any
+    pub fn get_blocks(&self) -> Option<&HashSet<Block>> {
+        self.blocks.as_deref()
+    }
+
+    /// Returns the categories being filtered on, if any
+    pub fn get_categories(&self) -> Option<&HashSet<ActionCategory>> {
+        self.categories.as_ref()
+    }
+
+    /// Returns true if the transaction satisfies every criteria of the filter
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        let raw = transaction.get_transaction();
+
+        if let Some(owner) = self.owner {
+            if raw.get_owner() != owner {
+                return false;
+            }
+        }
+
+        if self.start.is_some() || self.end.is_some() {
+            let time = match raw.get_time() {
+                Some(time) => time,
+                None => return false,
+            };
+            if self.start.is_some_and(|start| time < start) {
+                return false;
+            }
+            if self.end.is_some_and(|end| time >= end) {
+                return false;
+            }
+        }
+
+        if self.world.is_some_and(|world| raw.get_world() != world) {
+            return false;
+        }
+
+        if let Some(ref categories) = self.categories {
+            match raw.get_category() {
+                Some(category) if categories.contains(&category) => (),
+                _ => return false,
+            }
+        }
+
+        if let Some(ref blocks) = self.blocks {
+            let matched = match raw.get_transaction_type() {
+                TransactionType::Set { block_set } => blocks.contains(block_set.get().get_block()),
+                TransactionType::Replace {
+                    block_current,
+                    block_set,
+                } => {
+                    blocks.contains(block_current.get().get_block())
+                        || blocks.contains(block_set.get().get_block())
+                }
+                TransactionType::Explode {
+                    explosion,
+                    block_set,
+                } => {
+                    blocks.contains(block_set.get().get_block())
+                        || explosion
+                            .get_blocks()
+                            .iter()
+                            .any(|(_, block)| blocks.contains(block.get_block()))
+                }
+                TransactionType::PistonMove { moves, .. } => moves
+                    .get_moves()
+                    .iter()
+                    .any(|(_, _, block)| blocks.contains(block.get_block())),
+                TransactionType::Undo { .. }
+                | TransactionType::SetBiome { .. }
+                | TransactionType::SetDecoration { .. }
+                | TransactionType::RemoveDecoration { .. } => false,
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        if let Some(region) = self.region {
+            let inside = raw
+                .get_affected_coords()
+                .into_iter()
+                .any(|(x, y, z)| region.contains(x, y, z));
+            if !inside {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl Default for TransactionFilter {
+    fn default() -> TransactionFilter {
+        TransactionFilter::new()
+    }
+}
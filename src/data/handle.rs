@@ -0,0 +1,170 @@
+//! Provides interned handles to blocks
+//!
+//! The same few blocks show up millions of times across chunks and transactions, so every
+//! distinct MetaBlock is stored once, and referred to by a pointer sized handle. The palette
+//! finding the stored block only holds weak references, so a block is freed once no handle
+//! refers to it, and its entry is pruned the next time its shard grows. The palette is split
+//! into shards by hash, so threads interning blocks at the same time rarely wait on each other,
+//! and reading a block through a handle never locks at all.
+
+use data::block::*;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+/// Number of shards the palette is split into
+const SHARDS: usize = 64;
+
+/// A handle to an interned MetaBlock
+///
+/// Equal blocks always share the same handle, so comparing handles is a pointer compare.
+#[derive(Clone)]
+pub struct BlockHandle {
+    block: Arc<MetaBlock>,
+}
+
+/// One shard of the palette
+#[derive(Default)]
+struct Shard {
+    blocks: HashMap<MetaBlock, Weak<MetaBlock>>,
+    /// Number of entries left after the last time dead ones were pruned
+    pruned_len: usize,
+}
+
+impl Shard {
+    /// Drops the entries of blocks no handle refers to anymore, once the shard has doubled in
+    /// size since it was last pruned
+    fn prune(&mut self) {
+        if self.blocks.len() >= (2 * self.pruned_len).max(64) {
+            self.blocks.retain(|_, block| block.strong_count() > 0);
+            self.pruned_len = self.blocks.len();
+        }
+    }
+}
+
+fn palette() -> &'static [Mutex<Shard>] {
+    static PALETTE: OnceLock<Vec<Mutex<Shard>>> = OnceLock::new();
+    PALETTE.get_or_init(|| (0..SHARDS).map(|_| Mutex::default()).collect())
+}
+
+/// Returns the shard of the palette a block belongs in
+fn shard(block: &MetaBlock) -> &'static Mutex<Shard> {
+    let mut hasher = DefaultHasher::new();
+    block.hash(&mut hasher);
+    &palette()[hasher.finish() as usize % SHARDS]
+}
+
+impl BlockHandle {
+    /// Returns the handle of a block, adding it to the palette if needed
+    pub fn intern(block: MetaBlock) -> BlockHandle {
+        let mut shard = shard(&block).lock().unwrap();
+        if let Some(block) = shard.blocks.get(&block).and_then(Weak::upgrade) {
+            return BlockHandle { block };
+        }
+        let handle = BlockHandle {
+            block: Arc::new(block.clone()),
+        };
+        shard.blocks.insert(block, Arc::downgrade(&handle.block));
+        shard.prune();
+        handle
+    }
+
+    /// Returns the block this handle refers to
+    pub fn get(&self) -> MetaBlock {
+        (*self.block).clone()
+    }
+
+    /// Returns the number of distinct blocks that currently have a handle
+    pub fn palette_len() -> usize {
+        palette()
+            .iter()
+            .map(|shard| {
+                let shard = shard.lock().unwrap();
+                shard
+                    .blocks
+                    .values()
+                    .filter(|x| x.strong_count() > 0)
+                    .count()
+            })
+            .sum()
+    }
+}
+
+impl From<MetaBlock> for BlockHandle {
+    fn from(block: MetaBlock) -> BlockHandle {
+        BlockHandle::intern(block)
+    }
+}
+
+impl PartialEq for BlockHandle {
+    fn eq(&self, other: &BlockHandle) -> bool {
+        Arc::ptr_eq(&self.block, &other.block)
+    }
+}
+
+impl Eq for BlockHandle {}
+
+impl Hash for BlockHandle {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.block).hash(state)
+    }
+}
+
+impl fmt::Debug for BlockHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.block, f)
+    }
+}
+
+impl fmt::Display for BlockHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.block, f)
+    }
+}
+
+/// Handles are only meaningful inside of one process, so the block itself is written
+impl Serialize for BlockHandle {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.block.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHandle {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<BlockHandle, D::Error> {
+        MetaBlock::deserialize(deserializer).map(BlockHandle::intern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_blocks_share_a_handle() {
+        let block = |id, data_value| {
+            MetaBlock::fuse(
+                Block::new_from_ids(9, id),
+                MetaData::new().set_data_value(data_value),
+            )
+        };
+        let a = BlockHandle::intern(block(1, 2));
+        assert!(a == BlockHandle::intern(block(1, 2)));
+        assert!(a != BlockHandle::intern(block(1, 3)));
+        assert!(a.get() == block(1, 2));
+    }
+
+    #[test]
+    fn unused_blocks_are_freed() {
+        let block = MetaBlock::fuse(Block::new_from_ids(9, 4), MetaData::new().set_data_value(1));
+        let handle = BlockHandle::intern(block.clone());
+        let freed = Arc::downgrade(&handle.block);
+        assert!(handle.clone() == BlockHandle::intern(block.clone()));
+        drop(handle);
+        assert!(freed.upgrade().is_none());
+        assert!(BlockHandle::intern(block.clone()).get() == block);
+    }
+}
@@ -0,0 +1,137 @@
+//! Provides heatmaps, counts of changes over a grid of columns of a region
+//!
+//! Web maps are seen from above, so a heatmap splits the region into square cells along x and z,
+//! each spanning the full height of the region, and counts the block changes within each one.
+//! That is small enough to send to a browser, no matter how many transactions it counts.
+
+use data::region::*;
+
+/// Counts of changes in each cell of a region, see the module documentation
+///
+/// Cells are cell_size blocks wide, starting from the minimum corner of the region, and the cells
+/// along the far edges are cut short by the region.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heatmap {
+    region: Region,
+    cell_size: u32,
+    width: usize,
+    depth: usize,
+    /// Row by row, x first
+    counts: Vec<usize>,
+}
+
+impl Heatmap {
+    /// Creates an empty heatmap of the region, with cells cell_size blocks wide
+    ///
+    /// Returns None if the cell size is zero
+    pub fn new(region: Region, cell_size: u32) -> Option<Heatmap> {
+        if cell_size == 0 {
+            return None;
+        }
+        let cells = |min: i32, max: i32| {
+            let span = i64::from(max) - i64::from(min) + 1;
+            ((span + i64::from(cell_size) - 1) / i64::from(cell_size)) as usize
+        };
+        let (min, max) = (region.get_min(), region.get_max());
+        let (width, depth) = (cells(min.0, max.0), cells(min.2, max.2));
+        Some(Heatmap {
+            region,
+            cell_size,
+            width,
+            depth,
+            counts: vec![0; width * depth],
+        })
+    }
+
+    /// Returns the region the heatmap covers
+    pub fn get_region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns the width of a cell, in blocks
+    pub fn get_cell_size(&self) -> u32 {
+        self.cell_size
+    }
+
+    /// Returns the number of cells along x
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the number of cells along z
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the cell the block at the coordinates is in, as (x, z), if it is in the region
+    pub fn get_cell(&self, (x, y, z): (i32, i32, i32)) -> Option<(usize, usize)> {
+        if !self.region.contains(x, y, z) {
+            return None;
+        }
+        let min = self.region.get_min();
+        let cell = |coord: i32, min: i32| {
+            ((i64::from(coord) - i64::from(min)) / i64::from(self.cell_size)) as usize
+        };
+        Some((cell(x, min.0), cell(z, min.2)))
+    }
+
+    /// Counts a change to the block at the coordinates, returning false if it is not in the
+    /// region
+    pub fn add(&mut self, coords: (i32, i32, i32)) -> bool {
+        match self.get_cell(coords) {
+            Some((x, z)) => {
+                self.counts[z * self.width + x] += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the number of changes in a cell, zero for cells outside the grid
+    pub fn get_count(&self, x: usize, z: usize) -> usize {
+        if x >= self.width || z >= self.depth {
+            return 0;
+        }
+        self.counts[z * self.width + x]
+    }
+
+    /// Returns the number of changes in every cell, row by row along x, one row per cell along z
+    pub fn get_counts(&self) -> &[usize] {
+        &self.counts
+    }
+
+    /// Returns the number of changes in the busiest cell
+    pub fn get_max(&self) -> usize {
+        self.counts.iter().cloned().max().unwrap_or(0)
+    }
+
+    /// Returns the number of changes in every cell together
+    pub fn get_total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_cover_the_region() {
+        assert!(Heatmap::new(Region::new((0, 0, 0), (1, 1, 1)), 0).is_none());
+
+        let mut heatmap = Heatmap::new(Region::new((-5, 0, 0), (4, 10, 4)), 4).unwrap();
+        assert_eq!((heatmap.get_width(), heatmap.get_depth()), (3, 2));
+        assert!(heatmap.add((-5, 3, 0)));
+        assert!(heatmap.add((-2, 10, 3)));
+        assert!(heatmap.add((4, 0, 4)));
+        assert!(!heatmap.add((5, 0, 0)));
+        assert!(!heatmap.add((0, 11, 0)));
+
+        assert_eq!(heatmap.get_cell((-1, 0, 0)), Some((1, 0)));
+        assert_eq!(heatmap.get_count(0, 0), 2);
+        assert_eq!(heatmap.get_count(2, 1), 1);
+        assert_eq!(heatmap.get_count(3, 0), 0);
+        assert!(heatmap.get_counts() == [2, 0, 0, 0, 0, 1]);
+        assert_eq!((heatmap.get_max(), heatmap.get_total()), (2, 3));
+    }
+}
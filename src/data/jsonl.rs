@@ -0,0 +1,287 @@
+//! Provides JSON Lines interchange of transactions, for scripts and for diffing logs between
+//! servers
+//!
+//! Where a worldline export writes transactions the way they are stored, here every transaction
+//! is one flat JSON object on its own line, oldest first, that reads back into the same
+//! transaction. The keys are:
+//!
+//! - `id` and `sub_id`, the two parts of the id of the transaction
+//! - `time`, an RFC 3339 timestamp in the offset the transaction was submitted with, or null
+//! - `owner`, the uuid of whoever did the transaction
+//! - `world`, the numeric id of the world
+//! - `x`, `y` and `z`, all null for transactions without coordinates, like an Undo
+//! - `request_id`, `cause`, `category` and `on_conflict`, left out when not set. `cause` is an
+//!   object with an `id` and a `sub_id`, and `category` the name of an action category, like `burn`
+//! - `action`, one of `set`, `replace`, `undo`, `set_biome`, `set_decoration`,
+//!   `remove_decoration`, `explode` or `piston_move`
+//! - the fields of the action, named as in TransactionType: `block_set` for the block placed,
+//!   `block_current` for the block a Replace expects, `transaction` for the id an Undo reverts,
+//!   `biome`, `entity` and `decoration`, `explosion` and `moves`
+//!
+//! Blocks are written like `{"block":{"provider":0,"id":1},"meta_data":{...}}`, the same as in
+//! a worldline export. Keys are written in alphabetical order, so exporting the same transactions
+//! always gives the same bytes, and the logs of two servers can be compared line by line.
+//!
+//! When reading, `owner` defaults to the null Uuid, `world` to the overworld and `time` to none,
+//! so scripts only have to write what they know.
+
+use data::category::ActionCategory;
+use data::transaction::*;
+use serde_json::{self, Map, Value};
+use std::io::{self, BufRead, Lines, Write};
+use uuid::Uuid;
+
+/// Names of the actions, with the TransactionType variants they stand for
+const ACTIONS: [(&str, &str); 8] = [
+    ("set", "Set"),
+    ("replace", "Replace"),
+    ("undo", "Undo"),
+    ("set_biome", "SetBiome"),
+    ("set_decoration", "SetDecoration"),
+    ("remove_decoration", "RemoveDecoration"),
+    ("explode", "Explode"),
+    ("piston_move", "PistonMove"),
+];
+
+/// Returns an error for a line that does not follow the schema
+fn schema_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Flattens a transaction into the object of its line
+pub fn to_jsonl_value(transaction: &Transaction) -> Value {
+    let raw = transaction.get_transaction();
+    let mut object = match serde_json::to_value(raw) {
+        Ok(Value::Object(object)) => object,
+        _ => unreachable!("transactions serialize as objects"),
+    };
+
+    let coords = object.remove("coords").unwrap_or(Value::Null);
+    for (i, axis) in ["x", "y", "z"].iter().enumerate() {
+        object.insert(
+            axis.to_string(),
+            coords.get(i).cloned().unwrap_or(Value::Null),
+        );
+    }
+    if let Some(category) = raw.get_category() {
+        object.insert("category".to_string(), category.get_name().into());
+    }
+
+    // Externally tagged, so the only key is the name of the variant
+    if let Some(Value::Object(tagged)) = object.remove("transaction_type") {
+        for (variant, fields) in tagged {
+            let action = ACTIONS.iter().find(|x| x.1 == variant).map(|x| x.0);
+            object.insert("action".to_string(), action.unwrap_or_default().into());
+            if let Value::Object(fields) = fields {
+                object.extend(fields);
+            }
+        }
+    }
+
+    let id = transaction.get_id();
+    object.insert("id".to_string(), id.get_id().into());
+    object.insert("sub_id".to_string(), id.get_sub_id().into());
+    Value::Object(object)
+}
+
+/// Reads a transaction back from the object of its line
+pub fn from_jsonl_value(value: Value) -> io::Result<Transaction> {
+    let mut object = match value {
+        Value::Object(object) => object,
+        _ => return Err(schema_error("expected an object")),
+    };
+    let id: TransactionID = serde_json::from_value(json_take(&mut object, &["id", "sub_id"]))?;
+
+    let action = match object.remove("action") {
+        Some(Value::String(action)) => action,
+        _ => return Err(schema_error("missing action")),
+    };
+    let variant = ACTIONS
+        .iter()
+        .find(|x| x.0 == action)
+        .map(|x| x.1)
+        .ok_or_else(|| schema_error(&format!("unknown action {}", action)))?;
+
+    let axes: Vec<Value> = ["x", "y", "z"]
+        .iter()
+        .map(|x| object.remove(*x).unwrap_or(Value::Null))
+        .collect();
+    let coords = match axes.iter().filter(|x| x.is_null()).count() {
+        0 => Value::Array(axes),
+        3 => Value::Null,
+        _ => return Err(schema_error("x, y and z must all be set, or none of them")),
+    };
+    if let Some(category) = object.remove("category") {
+        let category = category
+            .as_str()
+            .and_then(ActionCategory::from_name)
+            .ok_or_else(|| schema_error("unknown category"))?;
+        object.insert("category".to_string(), serde_json::to_value(category)?);
+    }
+
+    let mut raw = json_take(
+        &mut object,
+        &[
+            "owner",
+            "time",
+            "world",
+            "request_id",
+            "cause",
+            "category",
+            "on_conflict",
+        ],
+    );
+    if let Value::Object(ref mut raw) = raw {
+        raw.entry("owner")
+            .or_insert_with(|| Value::String(Uuid::nil().to_string()));
+        raw.entry("time").or_insert(Value::Null);
+        raw.insert("coords".to_string(), coords);
+        // Whatever is left are the fields of the action
+        let mut tagged = Map::new();
+        tagged.insert(variant.to_string(), Value::Object(object));
+        raw.insert("transaction_type".to_string(), Value::Object(tagged));
+    }
+    let raw: RawTransaction = serde_json::from_value(raw)?;
+    Ok(Transaction::new(raw, id))
+}
+
+/// Moves the given keys, where present, out of an object and into a new one
+fn json_take(object: &mut Map<String, Value>, keys: &[&str]) -> Value {
+    let mut taken = Map::new();
+    for &key in keys {
+        if let Some(value) = object.remove(key) {
+            taken.insert(key.to_string(), value);
+        }
+    }
+    Value::Object(taken)
+}
+
+/// Streams transactions out as JSON Lines
+pub struct JsonlWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonlWriter<W> {
+    /// Starts writing transactions
+    pub fn new(writer: W) -> JsonlWriter<W> {
+        JsonlWriter { writer }
+    }
+
+    /// Writes the line of a transaction
+    pub fn write(&mut self, transaction: &Transaction) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &to_jsonl_value(transaction))?;
+        writeln!(self.writer)
+    }
+
+    /// Flushes the lines written so far, returning the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Streams transactions in from JSON Lines, skipping blank lines
+pub struct JsonlReader<R: BufRead> {
+    lines: Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> JsonlReader<R> {
+    /// Starts reading transactions
+    pub fn new(reader: R) -> JsonlReader<R> {
+        JsonlReader {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlReader<R> {
+    type Item = io::Result<Transaction>;
+
+    fn next(&mut self) -> Option<io::Result<Transaction>> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            self.line += 1;
+            if !line.trim().is_empty() {
+                return Some(parse_jsonl(&line).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: {}", self.line, e),
+                    )
+                }));
+            }
+        }
+    }
+}
+
+/// Reads a transaction from a single line
+pub fn parse_jsonl(line: &str) -> io::Result<Transaction> {
+    from_jsonl_value(serde_json::from_str(line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone};
+    use data::*;
+
+    #[test]
+    fn transactions_round_trip() {
+        let stairs = MetaBlock::fuse(
+            Block::new_from_ids(0, 3),
+            MetaData::new().set_property("facing", "east"),
+        );
+        let air = MetaBlock::fuse(Block::new_from_ids(0, 0), MetaData::new());
+        let zone = FixedOffset::east_opt(-5 * 3600).unwrap();
+        let replace = RawTransactionBuilder::new(TransactionType::new_replace(air, stairs))
+            .set_time(zone.with_ymd_and_hms(2020, 1, 1, 12, 0, 0).unwrap())
+            .set_owner(Uuid::new_v4())
+            .set_x_coord(1)
+            .set_y_coord(-2)
+            .set_z_coord(3)
+            .set_category(ActionCategory::Burn)
+            .build_transaction()
+            .unwrap();
+        let replace = Transaction::new(replace, TransactionID::new_from_parts(4, 1));
+        let undo = RawTransactionBuilder::new(TransactionType::new_undo(replace.get_id()))
+            .build_transaction()
+            .unwrap();
+        let undo = Transaction::new(undo, TransactionID::new_from_parts(5, 0));
+
+        let mut writer = JsonlWriter::new(Vec::new());
+        writer.write(&replace).unwrap();
+        writer.write(&undo).unwrap();
+        let written = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert!(lines[0].starts_with(r#"{"action":"replace","block_current":"#));
+        assert!(lines[0].contains(r#""category":"burn""#));
+        assert!(lines[0].contains(r#""time":"2020-01-01T12:00:00-05:00""#));
+        assert!(lines[0].contains(r#""x":1,"y":-2,"z":3"#));
+        assert!(lines[1].contains(r#""transaction":{"id":4,"sub_id":1}"#));
+        assert!(lines[1].contains(r#""x":null"#));
+
+        let read: Vec<Transaction> = JsonlReader::new(format!("\n{}", written).as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert!(read == vec![replace, undo]);
+        assert_eq!(read[0].get_transaction().get_offset(), Some(zone));
+
+        // Scripts can leave out what they do not know
+        let minimal = parse_jsonl(
+            r#"{"id":9,"sub_id":0,"action":"set_biome","biome":{"id":3},"x":0,"y":0,"z":0}"#,
+        )
+        .unwrap();
+        assert_eq!(minimal.get_transaction().get_owner(), Uuid::nil());
+        assert_eq!(minimal.get_transaction().get_time(), None);
+
+        let error = JsonlReader::new(&br#"{"id":1,"sub_id":0,"action":"set","x":0}"#[..])
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(error.to_string().starts_with("line 1:"));
+    }
+}
@@ -0,0 +1,105 @@
+//! Provides default blocks that vary with height
+//!
+//! A world with a single default block is one material all the way through. Default layers
+//! describe a profile instead, such as bedrock at y=0, stone up to y=60 and air above it, which
+//! is what every block that has never been set is made of.
+
+use data::block::*;
+
+/// The default block at every height of a world
+///
+/// Each layer starts at its min_y, and runs up to the start of the next one. Below the lowest
+/// layer, the base block is used.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefaultLayers {
+    base: MetaBlock,
+    /// Sorted by min_y, without duplicates
+    layers: Vec<(i32, MetaBlock)>,
+}
+
+impl DefaultLayers {
+    /// Creates a new profile using the base block at every height
+    pub fn new(base: MetaBlock) -> DefaultLayers {
+        DefaultLayers {
+            base,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Adds a layer of the block, from min_y up to the next layer
+    ///
+    /// Replaces the layer starting at min_y, if there already is one
+    pub fn add_layer(&mut self, min_y: i32, block: MetaBlock) -> &mut Self {
+        match self.layers.binary_search_by_key(&min_y, |x| x.0) {
+            Ok(i) => self.layers[i].1 = block,
+            Err(i) => self.layers.insert(i, (min_y, block)),
+        }
+        self
+    }
+
+    /// Returns the default block at the given height
+    pub fn get_block(&self, y: i32) -> MetaBlock {
+        match self.layers.binary_search_by_key(&y, |x| x.0) {
+            Ok(i) => self.layers[i].1.clone(),
+            Err(0) => self.base.clone(),
+            Err(i) => self.layers[i - 1].1.clone(),
+        }
+    }
+
+    /// Returns the block used below the lowest layer
+    pub fn get_base(&self) -> MetaBlock {
+        self.base.clone()
+    }
+
+    /// Returns the start and block of every layer, from the bottom up
+    pub fn get_layers(&self) -> &[(i32, MetaBlock)] {
+        &self.layers
+    }
+
+    /// Returns true if the default block is the same at every height
+    pub fn is_uniform(&self) -> bool {
+        self.layers.iter().all(|x| x.1 == self.base)
+    }
+
+    /// Returns a copy of this profile with every block passed through f
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> DefaultLayers {
+        DefaultLayers {
+            base: f(self.base.clone()),
+            layers: self
+                .layers
+                .iter()
+                .map(|&(y, ref block)| (y, f(block.clone())))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn layers_by_height() {
+        let mut layers = DefaultLayers::new(block(0));
+        assert!(layers.is_uniform());
+        layers
+            .add_layer(60, block(0))
+            .add_layer(0, block(7))
+            .add_layer(1, block(1));
+        assert!(!layers.is_uniform());
+        assert!(layers.get_block(-5) == block(0));
+        assert!(layers.get_block(0) == block(7));
+        assert!(layers.get_block(1) == block(1));
+        assert!(layers.get_block(59) == block(1));
+        assert!(layers.get_block(60) == block(0));
+        assert!(layers.get_block(1000) == block(0));
+
+        layers.add_layer(1, block(3));
+        assert_eq!(layers.get_layers().len(), 3);
+        assert!(layers.get_block(30) == block(3));
+    }
+}
@@ -0,0 +1,95 @@
+//! Provides storage for light levels
+//!
+//! Light is stored the way minecraft's Anvil format stores it: four bits per block, two blocks
+//! to a byte with the first block in the low nibble, and blocks ordered by y, then z, then x.
+
+use std::sync::Arc;
+
+/// The kinds of light minecraft tracks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LightType {
+    /// Light emitted by blocks, like torches
+    Block,
+    /// Light coming from the sky
+    Sky,
+}
+
+/// Persistent array of four bit values
+#[derive(Clone, PartialEq, Eq)]
+pub struct NibbleArray {
+    data: Arc<Vec<u8>>,
+    len: usize,
+}
+
+impl NibbleArray {
+    /// Creates an array of len zeros
+    pub fn new(len: usize) -> NibbleArray {
+        NibbleArray {
+            data: Arc::new(vec![0; len.div_ceil(2)]),
+            len,
+        }
+    }
+
+    /// Creates an array of len values from their packed bytes
+    ///
+    /// Returns None if the number of bytes does not match len
+    pub fn from_bytes(bytes: Vec<u8>, len: usize) -> Option<NibbleArray> {
+        if bytes.len() != len.div_ceil(2) {
+            return None;
+        }
+        Some(NibbleArray {
+            data: Arc::new(bytes),
+            len,
+        })
+    }
+
+    /// Returns the packed bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the number of values in the array
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the array holds no values
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the value at the given index
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given index is out of bounds
+    pub fn get(&self, index: usize) -> u8 {
+        assert!(index < self.len, "nibble index out of bounds");
+        let byte = self.data[index / 2];
+        if index.is_multiple_of(2) {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        }
+    }
+
+    /// "Sets" the value at the given index, only the low four bits of the value are kept
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given index is out of bounds
+    pub fn set(&self, index: usize, value: u8) -> NibbleArray {
+        assert!(index < self.len, "nibble index out of bounds");
+        let mut data = (*self.data).clone();
+        let byte = &mut data[index / 2];
+        if index.is_multiple_of(2) {
+            *byte = (*byte & 0xF0) | (value & 0x0F);
+        } else {
+            *byte = (*byte & 0x0F) | (value << 4);
+        }
+        NibbleArray {
+            data: Arc::new(data),
+            len: self.len,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Provides migrations of blocks between game versions
+//!
+//! A migration converts blocks encoded with the dictionary of one version to the dictionary of
+//! another, following rules supplied by the user, such as renaming `minecraft:grass` to
+//! `minecraft:grass_block`, or turning the data value 1 of `minecraft:stone` into
+//! `minecraft:granite`. Blocks without a rule keep their name, if the new dictionary has it.
+
+use data::block::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A set of rules converting blocks from one dictionary to another
+#[derive(Clone)]
+pub struct BlockMigration {
+    from: Arc<BlockDictonary>,
+    to: Arc<BlockDictonary>,
+    /// Blocks converted regardless of their data value
+    renames: HashMap<Block, Block>,
+    /// Blocks converted to a block state when they have a given data value
+    data_values: HashMap<(Block, i32), MetaBlock>,
+}
+
+impl BlockMigration {
+    /// Creates a migration from one dictionary to another, without any rules
+    pub fn new(from: Arc<BlockDictonary>, to: Arc<BlockDictonary>) -> BlockMigration {
+        BlockMigration {
+            from,
+            to,
+            renames: HashMap::new(),
+            data_values: HashMap::new(),
+        }
+    }
+
+    /// Returns the dictionary migrated blocks are encoded with
+    pub fn get_to(&self) -> Arc<BlockDictonary> {
+        self.to.clone()
+    }
+
+    /// Renames a block, given as `provider:name` in the old and new dictionaries
+    ///
+    /// The block keeps its metadata. Returns None, leaving the migration untouched, if either
+    /// name is not in its dictionary
+    pub fn add_rename(&mut self, old: &str, new: &str) -> Option<&mut Self> {
+        let old = self.from.try_encode_block(old.split_once(':')?)?;
+        let new = self.to.try_encode_block(new.split_once(':')?)?;
+        self.renames.insert(old, new);
+        Some(self)
+    }
+
+    /// Converts a block with the given data value to a flattened block state, such as
+    /// `minecraft:oak_stairs[facing=east,half=top]`
+    ///
+    /// The data value is dropped. Takes priority over renames of the same block. Returns None,
+    /// leaving the migration untouched, if the block or the state is not in its dictionary
+    pub fn add_data_value(&mut self, old: &str, data_value: i32, state: &str) -> Option<&mut Self> {
+        let old = self.from.try_encode_block(old.split_once(':')?)?;
+        let new = self.to.encode_state(state)?;
+        self.data_values.insert((old, data_value), new);
+        Some(self)
+    }
+
+    /// Converts a single block
+    ///
+    /// Blocks without a rule are looked up by name in the new dictionary, and left alone if it
+    /// does not have them
+    pub fn migrate_block(&self, block: MetaBlock) -> MetaBlock {
+        let inner = *block.get_block();
+        let meta_data = block.get_meta_data();
+        if let Some(data_value) = meta_data.get_data_value() {
+            if let Some(migrated) = self.data_values.get(&(inner, data_value)) {
+                return migrated.clone();
+            }
+        }
+        let renamed = match self.renames.get(&inner) {
+            Some(&renamed) => renamed,
+            None => self
+                .from
+                .try_decode_block(inner)
+                .and_then(|name| self.to.try_encode_block(name))
+                .unwrap_or(inner),
+        };
+        MetaBlock::fuse(renamed, meta_data.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::*;
+
+    fn dictonary(names: &[&str]) -> Arc<BlockDictonary> {
+        let mut table = BlockTable::new("minecraft");
+        for name in names {
+            table.add_name(name);
+        }
+        let mut dictonary = BlockDictonary::new();
+        dictonary.add_table(table);
+        Arc::new(dictonary)
+    }
+
+    #[test]
+    fn migrate_world() {
+        let old = dictonary(&["air", "stone", "grass"]);
+        let new = dictonary(&["granite", "air", "grass_block", "stone"]);
+        let mut migration = BlockMigration::new(old.clone(), new.clone());
+        migration
+            .add_rename("minecraft:grass", "minecraft:grass_block")
+            .unwrap()
+            .add_data_value("minecraft:stone", 1, "minecraft:granite")
+            .unwrap();
+        assert!(migration
+            .add_rename("minecraft:dirt", "minecraft:stone")
+            .is_none());
+
+        let at = |id, data_value: Option<i32>| {
+            let meta_data = match data_value {
+                Some(data_value) => MetaData::new().set_data_value(data_value),
+                None => MetaData::new(),
+            };
+            MetaBlock::fuse(Block::new_from_ids(0, id), meta_data)
+        };
+        let world = World::with_chunk_size(at(0, None), 4, 4)
+            .unwrap()
+            .set_block_defaulting(0, 0, 0, at(1, None))
+            .unwrap()
+            .set_block_defaulting(1, 0, 0, at(1, Some(1)))
+            .unwrap()
+            .set_block_defaulting(2, 0, 0, at(2, None))
+            .unwrap();
+
+        let world = world.map_blocks(|x| migration.migrate_block(x)).unwrap();
+        let name = |x, y, z| new.decode_state(world.get_block_defaulting(x, y, z).unwrap());
+        assert_eq!(name(0, 0, 0).unwrap(), "minecraft:stone");
+        assert_eq!(name(1, 0, 0).unwrap(), "minecraft:granite");
+        assert_eq!(name(2, 0, 0).unwrap(), "minecraft:grass_block");
+        // The default block moved too, including in chunks that already exist
+        assert_eq!(name(3, 0, 0).unwrap(), "minecraft:air");
+        assert_eq!(name(100, 0, 0).unwrap(), "minecraft:air");
+    }
+}
@@ -1,15 +1,81 @@
+pub mod anvil;
+pub mod audit;
+pub mod baseline;
+pub mod biome;
 pub mod block;
-pub mod transaction;
+pub mod category;
 pub mod chunk;
+pub mod coords;
+pub mod decoration;
+pub mod entity;
+pub mod explosion;
+pub mod filter;
+pub mod handle;
+pub mod heatmap;
+pub mod jsonl;
+pub mod layers;
+pub mod light;
+pub mod migration;
+pub mod nbt;
+pub mod piston;
+pub mod properties;
+pub mod provider;
+pub mod region;
+pub mod rejection;
+pub mod rollback;
+pub mod stats;
+pub mod tags;
+pub mod text;
+pub mod timelapse;
+pub mod transaction;
 pub mod world;
 
+pub use anvil::*;
+pub use audit::*;
+pub use baseline::*;
+pub use biome::*;
 pub use block::*;
-pub use transaction::*;
+pub use category::*;
 pub use chunk::*;
+pub use coords::*;
+pub use decoration::*;
+pub use entity::*;
+pub use explosion::*;
+pub use filter::*;
+pub use handle::*;
+pub use heatmap::*;
+pub use jsonl::*;
+pub use layers::*;
+pub use light::*;
+pub use migration::*;
+pub use nbt::*;
+pub use piston::*;
+pub use properties::*;
+pub use provider::*;
+pub use region::*;
+pub use rejection::*;
+pub use rollback::*;
+pub use stats::*;
+pub use tags::*;
+pub use text::*;
+pub use timelapse::*;
+pub use transaction::*;
 pub use world::*;
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use uuid::Uuid;
+
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn owner_defaults_to_nil() {
+        let undo = TransactionType::new_undo(TransactionID::new_from_parts(0, 0));
+        let transaction = RawTransactionBuilder::new(undo)
+            .build_transaction()
+            .unwrap();
+        assert_eq!(transaction.get_owner(), Uuid::nil());
+    }
 }
@@ -0,0 +1,547 @@
+//! Provides arbitrary NBT values attached to blocks
+//!
+//! Modded blocks often carry state that does not fit in block state properties. It can be
+//! attached to a block as namespaced keys, such as `mymod:charge`, mapping to NBT values. Unlike
+//! properties, NBT often holds values that keep changing, like counters and charges, so entries
+//! are not interned. Blocks share them behind a reference count instead, and they are freed once
+//! no block carries them.
+//!
+//! Floating point values are compared and hashed by their bits, so `NaN` equals itself and `0.0`
+//! differs from `-0.0`, the same way two NBT files holding them would differ.
+
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// A single NBT value
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum NbtValue {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    ByteArray(Vec<i8>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+    List(Vec<NbtValue>),
+    Compound(BTreeMap<String, NbtValue>),
+}
+
+impl NbtValue {
+    /// Returns the NBT tag id of this value
+    pub fn get_tag_id(&self) -> u8 {
+        match *self {
+            NbtValue::Byte(_) => 1,
+            NbtValue::Short(_) => 2,
+            NbtValue::Int(_) => 3,
+            NbtValue::Long(_) => 4,
+            NbtValue::Float(_) => 5,
+            NbtValue::Double(_) => 6,
+            NbtValue::ByteArray(_) => 7,
+            NbtValue::String(_) => 8,
+            NbtValue::List(_) => 9,
+            NbtValue::Compound(_) => 10,
+            NbtValue::IntArray(_) => 11,
+            NbtValue::LongArray(_) => 12,
+        }
+    }
+
+    /// Reads a value from the binary NBT format minecraft stores chunks in, uncompressed
+    ///
+    /// Returns the name of the root tag along with the value. Returns None if the input is not
+    /// valid NBT, has trailing bytes, or nests deeper than MAX_NBT_DEPTH.
+    pub fn from_bytes(bytes: &[u8]) -> Option<(String, NbtValue)> {
+        let mut reader = NbtReader {
+            bytes,
+            pos: 0,
+            order: ByteOrder::Big,
+        };
+        let tag = reader.u8()?;
+        let name = reader.string()?;
+        let value = reader.value(tag, 0)?;
+        if reader.pos != bytes.len() {
+            return None;
+        }
+        Some((name, value))
+    }
+
+    /// Writes this value in the binary NBT format, as the root tag with the given name
+    pub fn to_bytes(&self, name: &str) -> Vec<u8> {
+        let mut bytes = vec![self.get_tag_id()];
+        write_string(&mut bytes, name, ByteOrder::Big);
+        self.write_payload(&mut bytes, ByteOrder::Big);
+        bytes
+    }
+
+    /// Reads a value written by write_unnamed from the start of the bytes
+    ///
+    /// Returns the value along with the number of bytes it took up, or None if the bytes do not
+    /// start with valid NBT in the given byte order.
+    pub fn read_unnamed(bytes: &[u8], order: ByteOrder) -> Option<(NbtValue, usize)> {
+        let mut reader = NbtReader {
+            bytes,
+            pos: 0,
+            order,
+        };
+        let tag = reader.u8()?;
+        let value = reader.value(tag, 0)?;
+        Some((value, reader.pos))
+    }
+
+    /// Appends this value in binary NBT, as its tag id followed by its payload without a name,
+    /// for embedding NBT in other formats
+    pub fn write_unnamed(&self, bytes: &mut Vec<u8>, order: ByteOrder) {
+        bytes.push(self.get_tag_id());
+        self.write_payload(bytes, order);
+    }
+
+    /// Writes the payload of this value, without its tag id or name
+    fn write_payload(&self, bytes: &mut Vec<u8>, order: ByteOrder) {
+        match *self {
+            NbtValue::Byte(x) => bytes.push(x as u8),
+            NbtValue::Short(x) => bytes.extend_from_slice(&order.of(x.to_be_bytes())),
+            NbtValue::Int(x) => bytes.extend_from_slice(&order.of(x.to_be_bytes())),
+            NbtValue::Long(x) => bytes.extend_from_slice(&order.of(x.to_be_bytes())),
+            NbtValue::Float(x) => bytes.extend_from_slice(&order.of(x.to_be_bytes())),
+            NbtValue::Double(x) => bytes.extend_from_slice(&order.of(x.to_be_bytes())),
+            NbtValue::String(ref x) => write_string(bytes, x, order),
+            NbtValue::ByteArray(ref x) => {
+                bytes.extend_from_slice(&order.of((x.len() as i32).to_be_bytes()));
+                bytes.extend(x.iter().map(|&x| x as u8));
+            }
+            NbtValue::IntArray(ref x) => {
+                bytes.extend_from_slice(&order.of((x.len() as i32).to_be_bytes()));
+                for x in x {
+                    bytes.extend_from_slice(&order.of(x.to_be_bytes()));
+                }
+            }
+            NbtValue::LongArray(ref x) => {
+                bytes.extend_from_slice(&order.of((x.len() as i32).to_be_bytes()));
+                for x in x {
+                    bytes.extend_from_slice(&order.of(x.to_be_bytes()));
+                }
+            }
+            NbtValue::List(ref x) => {
+                // Empty lists are written as lists of End tags, like minecraft does
+                bytes.push(x.first().map_or(0, |x| x.get_tag_id()));
+                bytes.extend_from_slice(&order.of((x.len() as i32).to_be_bytes()));
+                for value in x {
+                    value.write_payload(bytes, order);
+                }
+            }
+            NbtValue::Compound(ref x) => {
+                for (name, value) in x {
+                    bytes.push(value.get_tag_id());
+                    write_string(bytes, name, order);
+                    value.write_payload(bytes, order);
+                }
+                bytes.push(0);
+            }
+        }
+    }
+}
+
+/// How deeply lists and compounds can be nested in binary NBT, matching minecraft
+pub const MAX_NBT_DEPTH: usize = 512;
+
+/// The order numbers are written in, in binary NBT
+///
+/// Java edition writes big endian NBT, bedrock edition little endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    /// Converts big endian bytes into this order, or back
+    fn of<const N: usize>(self, mut bytes: [u8; N]) -> [u8; N] {
+        if self == ByteOrder::Little {
+            bytes.reverse();
+        }
+        bytes
+    }
+}
+
+/// Writes a string the way binary NBT stores them, prefixed by its length
+///
+/// Strings are written as plain UTF-8 rather than minecraft's modified UTF-8, which only differs
+/// for nul characters and characters outside of the basic multilingual plane.
+fn write_string(bytes: &mut Vec<u8>, string: &str, order: ByteOrder) {
+    bytes.extend_from_slice(&order.of((string.len() as u16).to_be_bytes()));
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+/// Reads binary NBT from a byte slice
+struct NbtReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    order: ByteOrder,
+}
+
+impl<'a> NbtReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|x| x[0])
+    }
+
+    /// Reads N bytes, in big endian order whatever order they were written in
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.take(N)?.try_into().ok()?;
+        Some(self.order.of(bytes))
+    }
+
+    /// Reads the length of an array or list, which can not be negative
+    fn len(&mut self) -> Option<usize> {
+        let len = i32::from_be_bytes(self.array()?);
+        usize::try_from(len).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = u16::from_be_bytes(self.array()?) as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    /// Reads the payload of a tag with the given id
+    fn value(&mut self, tag: u8, depth: usize) -> Option<NbtValue> {
+        if depth > MAX_NBT_DEPTH {
+            return None;
+        }
+        Some(match tag {
+            1 => NbtValue::Byte(self.u8()? as i8),
+            2 => NbtValue::Short(i16::from_be_bytes(self.array()?)),
+            3 => NbtValue::Int(i32::from_be_bytes(self.array()?)),
+            4 => NbtValue::Long(i64::from_be_bytes(self.array()?)),
+            5 => NbtValue::Float(f32::from_be_bytes(self.array()?)),
+            6 => NbtValue::Double(f64::from_be_bytes(self.array()?)),
+            7 => {
+                let len = self.len()?;
+                NbtValue::ByteArray(self.take(len)?.iter().map(|&x| x as i8).collect())
+            }
+            8 => NbtValue::String(self.string()?),
+            9 => {
+                let tag = self.u8()?;
+                let len = self.len()?;
+                if tag == 0 && len > 0 {
+                    return None;
+                }
+                // Each element takes at least a byte, so the length can be checked up front
+                if len > self.bytes.len() - self.pos {
+                    return None;
+                }
+                let values = (0..len)
+                    .map(|_| self.value(tag, depth + 1))
+                    .collect::<Option<_>>()?;
+                NbtValue::List(values)
+            }
+            10 => {
+                let mut entries = NbtMap::new();
+                loop {
+                    let tag = self.u8()?;
+                    if tag == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    let value = self.value(tag, depth + 1)?;
+                    entries.insert(name, value);
+                }
+                NbtValue::Compound(entries)
+            }
+            11 => {
+                let len = self.len()?;
+                let values = self.take(len.checked_mul(4)?)?;
+                NbtValue::IntArray(
+                    values
+                        .chunks_exact(4)
+                        .map(|x| i32::from_be_bytes(self.order.of(x.try_into().unwrap())))
+                        .collect(),
+                )
+            }
+            12 => {
+                let len = self.len()?;
+                let values = self.take(len.checked_mul(8)?)?;
+                NbtValue::LongArray(
+                    values
+                        .chunks_exact(8)
+                        .map(|x| i64::from_be_bytes(self.order.of(x.try_into().unwrap())))
+                        .collect(),
+                )
+            }
+            _ => return None,
+        })
+    }
+}
+
+impl PartialEq for NbtValue {
+    fn eq(&self, other: &NbtValue) -> bool {
+        use self::NbtValue::*;
+        match (self, other) {
+            (Byte(a), Byte(b)) => a == b,
+            (Short(a), Short(b)) => a == b,
+            (Int(a), Int(b)) => a == b,
+            (Long(a), Long(b)) => a == b,
+            (Float(a), Float(b)) => a.to_bits() == b.to_bits(),
+            (Double(a), Double(b)) => a.to_bits() == b.to_bits(),
+            (String(a), String(b)) => a == b,
+            (ByteArray(a), ByteArray(b)) => a == b,
+            (IntArray(a), IntArray(b)) => a == b,
+            (LongArray(a), LongArray(b)) => a == b,
+            (List(a), List(b)) => a == b,
+            (Compound(a), Compound(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for NbtValue {}
+
+impl Hash for NbtValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.get_tag_id().hash(state);
+        match *self {
+            NbtValue::Byte(x) => x.hash(state),
+            NbtValue::Short(x) => x.hash(state),
+            NbtValue::Int(x) => x.hash(state),
+            NbtValue::Long(x) => x.hash(state),
+            NbtValue::Float(x) => x.to_bits().hash(state),
+            NbtValue::Double(x) => x.to_bits().hash(state),
+            NbtValue::String(ref x) => x.hash(state),
+            NbtValue::ByteArray(ref x) => x.hash(state),
+            NbtValue::IntArray(ref x) => x.hash(state),
+            NbtValue::LongArray(ref x) => x.hash(state),
+            NbtValue::List(ref x) => x.hash(state),
+            NbtValue::Compound(ref x) => x.hash(state),
+        }
+    }
+}
+
+/// Written as SNBT, the way minecraft commands write NBT, e.g. `{charge:1.5f,owners:[L;1L,2L]}`
+impl fmt::Display for NbtValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NbtValue::Byte(x) => write!(f, "{}b", x),
+            NbtValue::Short(x) => write!(f, "{}s", x),
+            NbtValue::Int(x) => write!(f, "{}", x),
+            NbtValue::Long(x) => write!(f, "{}L", x),
+            NbtValue::Float(x) => write!(f, "{}f", x),
+            NbtValue::Double(x) => write!(f, "{}d", x),
+            NbtValue::String(ref x) => write!(f, "{:?}", x),
+            NbtValue::ByteArray(ref x) => write_list(f, "B;", x.iter().map(|x| format!("{}B", x))),
+            NbtValue::IntArray(ref x) => write_list(f, "I;", x.iter().map(|x| x.to_string())),
+            NbtValue::LongArray(ref x) => write_list(f, "L;", x.iter().map(|x| format!("{}L", x))),
+            NbtValue::List(ref x) => write_list(f, "", x.iter().map(|x| x.to_string())),
+            NbtValue::Compound(ref x) => write_compound(f, x),
+        }
+    }
+}
+
+fn write_list<I: Iterator<Item = String>>(
+    f: &mut fmt::Formatter,
+    prefix: &str,
+    values: I,
+) -> fmt::Result {
+    write!(
+        f,
+        "[{}{}]",
+        prefix,
+        values.collect::<Vec<String>>().join(",")
+    )
+}
+
+fn write_compound(f: &mut fmt::Formatter, entries: &NbtMap) -> fmt::Result {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|(key, value)| format!("{}:{}", key, value))
+        .collect();
+    write!(f, "{{{}}}", entries.join(","))
+}
+
+/// A set of NBT entries, ordered by key
+pub type NbtMap = BTreeMap<String, NbtValue>;
+
+/// A set of NBT entries, shared by every block carrying it
+///
+/// Compared and hashed by value. Cloning only bumps a reference count, and the entries are freed
+/// once no block carries them anymore.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct NbtData {
+    /// None when no entries are set, so blocks without NBT do not allocate
+    entries: Option<Arc<NbtMap>>,
+}
+
+/// The entries of an NbtData without any
+static EMPTY: NbtMap = BTreeMap::new();
+
+/// Returns true if a key is namespaced, like `mymod:charge`
+pub fn is_namespaced(key: &str) -> bool {
+    match key.split_once(':') {
+        Some((namespace, name)) => !namespace.is_empty() && !name.is_empty(),
+        None => false,
+    }
+}
+
+impl NbtData {
+    /// Returns the empty set of entries
+    pub fn new() -> NbtData {
+        NbtData { entries: None }
+    }
+
+    /// Returns a set of the given entries
+    pub fn from_map(entries: NbtMap) -> NbtData {
+        if entries.is_empty() {
+            return NbtData::new();
+        }
+        NbtData {
+            entries: Some(Arc::new(entries)),
+        }
+    }
+
+    /// Returns the entries
+    pub fn get_map(&self) -> &NbtMap {
+        self.entries.as_deref().unwrap_or(&EMPTY)
+    }
+
+    /// Returns the value of an entry, if it is set
+    pub fn get(&self, key: &str) -> Option<NbtValue> {
+        self.get_map().get(key).cloned()
+    }
+
+    /// Returns this set with an entry set to the given value
+    ///
+    /// Returns None if the key is not namespaced
+    pub fn set(&self, key: &str, value: NbtValue) -> Option<NbtData> {
+        if !is_namespaced(key) {
+            return None;
+        }
+        let mut entries = self.get_map().clone();
+        entries.insert(key.to_string(), value);
+        Some(NbtData::from_map(entries))
+    }
+
+    /// Returns this set without the given entry
+    pub fn remove(&self, key: &str) -> NbtData {
+        if self.get(key).is_none() {
+            return self.clone();
+        }
+        let mut entries = self.get_map().clone();
+        entries.remove(key);
+        NbtData::from_map(entries)
+    }
+
+    /// Returns true if no entries are set
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_none()
+    }
+}
+
+impl fmt::Debug for NbtData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.get_map().iter()).finish()
+    }
+}
+
+/// Written as an SNBT compound of the entries
+impl fmt::Display for NbtData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_compound(f, self.get_map())
+    }
+}
+
+impl Serialize for NbtData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get_map().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NbtData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<NbtData, D::Error> {
+        NbtMap::deserialize(deserializer).map(NbtData::from_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floats_compare_by_bits() {
+        assert!(NbtValue::Float(f32::NAN) == NbtValue::Float(f32::NAN));
+        assert!(NbtValue::Double(0.0) != NbtValue::Double(-0.0));
+        assert!(NbtValue::Int(1) != NbtValue::Long(1));
+
+        let a = NbtData::new()
+            .set("mymod:charge", NbtValue::Double(f64::NAN))
+            .unwrap();
+        let b = NbtData::new()
+            .set("mymod:charge", NbtValue::Double(f64::NAN))
+            .unwrap();
+        assert!(a == b);
+        assert!(NbtData::new().set("charge", NbtValue::Int(1)).is_none());
+        assert!(a.remove("mymod:charge") == NbtData::new());
+    }
+
+    #[test]
+    fn entries_are_freed_with_their_blocks() {
+        let data = NbtData::new().set("mymod:count", NbtValue::Int(1)).unwrap();
+        let entries = Arc::downgrade(data.entries.as_ref().unwrap());
+        let copy = data.clone();
+        assert!(Arc::ptr_eq(
+            data.entries.as_ref().unwrap(),
+            copy.entries.as_ref().unwrap()
+        ));
+        drop(data);
+        assert!(entries.upgrade().is_some());
+        drop(copy);
+        assert!(entries.upgrade().is_none());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut entries = NbtMap::new();
+        entries.insert("name".to_string(), NbtValue::String("stone".to_string()));
+        entries.insert("states".to_string(), NbtValue::LongArray(vec![1, -2]));
+        entries.insert(
+            "list".to_string(),
+            NbtValue::List(vec![NbtValue::Short(3), NbtValue::Short(-4)]),
+        );
+        entries.insert("empty".to_string(), NbtValue::List(Vec::new()));
+        entries.insert("bytes".to_string(), NbtValue::ByteArray(vec![-1, 2]));
+        let value = NbtValue::Compound(entries);
+
+        let bytes = value.to_bytes("root");
+        assert!(NbtValue::from_bytes(&bytes) == Some(("root".to_string(), value.clone())));
+        assert!(NbtValue::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(NbtValue::from_bytes(&trailing).is_none());
+
+        // Embedded little endian, the value reads back and says where it ends
+        let mut embedded = Vec::new();
+        value.write_unnamed(&mut embedded, ByteOrder::Little);
+        let length = embedded.len();
+        embedded.push(7);
+        assert!(
+            NbtValue::read_unnamed(&embedded, ByteOrder::Little) == Some((value.clone(), length))
+        );
+        assert!(NbtValue::read_unnamed(&embedded, ByteOrder::Big).is_none());
+
+        // Nesting is limited, rather than overflowing the stack
+        let mut deep = NbtValue::Int(0);
+        for _ in 0..MAX_NBT_DEPTH + 1 {
+            deep = NbtValue::List(vec![deep]);
+        }
+        assert!(NbtValue::from_bytes(&deep.to_bytes("")).is_none());
+    }
+}
@@ -0,0 +1,140 @@
+//! Provides piston moves, the blocks a piston pushed or pulled all at once
+//!
+//! A piston moves up to a dozen blocks in one go, each leaving the block it was in and arriving
+//! in the next one over, so a push or pull is recorded as a single PistonMove transaction listing
+//! every move in order. Undoing it puts both ends of every move back together. Like explosions,
+//! the list of moves is shared between the copies of the transaction.
+
+use data::block::*;
+use data::handle::*;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::sync::Arc;
+
+/// A single block moved by a piston, as (from, to, block)
+pub type BlockMove = ((i32, i32, i32), (i32, i32, i32), MetaBlock);
+
+/// A single move, with the block interned
+type Move = ((i32, i32, i32), (i32, i32, i32), BlockHandle);
+
+/// The blocks moved by a piston, in the order they were moved
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PistonMove {
+    moves: Arc<[Move]>,
+}
+
+impl PistonMove {
+    /// Creates a piston move out of (from, to, block) moves, in order
+    pub fn new<I: IntoIterator<Item = BlockMove>>(moves: I) -> PistonMove {
+        let moves: Vec<Move> = moves
+            .into_iter()
+            .map(|(from, to, block)| (from, to, BlockHandle::intern(block)))
+            .collect();
+        PistonMove {
+            moves: moves.into(),
+        }
+    }
+
+    /// Returns every move, as (from, to, block), in order
+    pub fn get_moves(&self) -> Vec<BlockMove> {
+        self.moves
+            .iter()
+            .map(|(from, to, block)| (*from, *to, block.get()))
+            .collect()
+    }
+
+    /// Returns the coordinates of every block moved out of or into, ordered by coordinates
+    pub fn get_coords(&self) -> Vec<(i32, i32, i32)> {
+        let mut coords: Vec<(i32, i32, i32)> = self
+            .moves
+            .iter()
+            .flat_map(|&(from, to, _)| vec![from, to])
+            .collect();
+        coords.sort();
+        coords.dedup();
+        coords
+    }
+
+    /// Returns true if a block was moved out of or into the coordinates
+    pub fn contains(&self, coords: (i32, i32, i32)) -> bool {
+        self.moves
+            .iter()
+            .any(|&(from, to, _)| from == coords || to == coords)
+    }
+
+    /// Returns the block the piston leaves at the coordinates, if it changes them
+    ///
+    /// Coordinates a block was moved into hold that block, or the last one moved in if there were
+    /// several. Coordinates a block was only moved out of are left as vacated.
+    pub fn get_block_at(&self, coords: (i32, i32, i32), vacated: MetaBlock) -> Option<MetaBlock> {
+        let arrived = self.moves.iter().rev().find(|x| x.1 == coords);
+        match arrived {
+            Some((_, _, block)) => Some(block.get()),
+            None if self.moves.iter().any(|x| x.0 == coords) => Some(vacated),
+            None => None,
+        }
+    }
+
+    /// Returns the number of blocks moved
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    /// Returns true if the piston did not move any blocks
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// Returns a copy of this piston move with every moved block passed through f
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> PistonMove {
+        PistonMove::new(
+            self.moves
+                .iter()
+                .map(|(from, to, block)| (*from, *to, f(block.get()))),
+        )
+    }
+}
+
+/// Written as the list of moves
+impl Serialize for PistonMove {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.moves.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PistonMove {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PistonMove, D::Error> {
+        Vec::<BlockMove>::deserialize(deserializer).map(PistonMove::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn pushes_leave_the_start_vacated() {
+        // Two blocks pushed one along x, the first into where the second was
+        let push = PistonMove::new(vec![
+            ((2, 0, 0), (3, 0, 0), block(2)),
+            ((1, 0, 0), (2, 0, 0), block(1)),
+        ]);
+        assert!(push.get_coords() == vec![(1, 0, 0), (2, 0, 0), (3, 0, 0)]);
+        assert!(push.get_block_at((1, 0, 0), block(0)) == Some(block(0)));
+        assert!(push.get_block_at((2, 0, 0), block(0)) == Some(block(1)));
+        assert!(push.get_block_at((3, 0, 0), block(0)) == Some(block(2)));
+        assert!(push.get_block_at((4, 0, 0), block(0)).is_none());
+        assert!(!push.contains((0, 0, 0)));
+
+        let json = serde_json::to_string(&push).unwrap();
+        let read: PistonMove = serde_json::from_str(&json).unwrap();
+        assert!(read == push);
+        let mapped = push.map_blocks(|_| block(5));
+        assert!(mapped.get_block_at((3, 0, 0), block(0)) == Some(block(5)));
+    }
+}
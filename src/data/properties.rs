@@ -0,0 +1,197 @@
+//! Provides block state properties, such as facing, half, waterlogged, power or age
+//!
+//! Blocks are copied around constantly, in chunks, transactions and history, so a set of
+//! properties is not stored inline. Every distinct set is interned once for the lifetime of the
+//! process, and blocks only carry a small handle to it. Equal sets always get the same handle, so
+//! comparing and hashing handles is the same as comparing and hashing the sets.
+
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The value of a single property
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i32),
+    Str(String),
+}
+
+impl From<bool> for PropertyValue {
+    fn from(value: bool) -> PropertyValue {
+        PropertyValue::Bool(value)
+    }
+}
+
+impl From<i32> for PropertyValue {
+    fn from(value: i32) -> PropertyValue {
+        PropertyValue::Int(value)
+    }
+}
+
+impl<'a> From<&'a str> for PropertyValue {
+    fn from(value: &'a str) -> PropertyValue {
+        PropertyValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for PropertyValue {
+    fn from(value: String) -> PropertyValue {
+        PropertyValue::Str(value)
+    }
+}
+
+impl fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PropertyValue::Bool(value) => write!(f, "{}", value),
+            PropertyValue::Int(value) => write!(f, "{}", value),
+            PropertyValue::Str(ref value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A set of properties, ordered by name
+pub type PropertyMap = BTreeMap<String, PropertyValue>;
+
+/// Handle to an interned set of properties
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Properties {
+    id: u32,
+}
+
+/// Every set of properties seen so far, the empty set is always id 0
+struct Interner {
+    sets: Vec<Arc<PropertyMap>>,
+    ids: HashMap<Arc<PropertyMap>, u32>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| {
+        let empty = Arc::new(PropertyMap::new());
+        let mut ids = HashMap::new();
+        ids.insert(empty.clone(), 0);
+        Mutex::new(Interner {
+            sets: vec![empty],
+            ids,
+        })
+    })
+}
+
+impl Properties {
+    /// Returns the handle of the empty set of properties
+    pub fn new() -> Properties {
+        Properties { id: 0 }
+    }
+
+    /// Returns the handle of the given set of properties, interning it if needed
+    pub fn intern(properties: PropertyMap) -> Properties {
+        if properties.is_empty() {
+            return Properties::new();
+        }
+        let mut interner = interner().lock().unwrap();
+        if let Some(&id) = interner.ids.get(&properties) {
+            return Properties { id };
+        }
+        let id = interner.sets.len() as u32;
+        let properties = Arc::new(properties);
+        interner.sets.push(properties.clone());
+        interner.ids.insert(properties, id);
+        Properties { id }
+    }
+
+    /// Returns the set of properties this handle refers to
+    pub fn get_map(&self) -> Arc<PropertyMap> {
+        interner().lock().unwrap().sets[self.id as usize].clone()
+    }
+
+    /// Returns the value of a property, if it is set
+    pub fn get(&self, name: &str) -> Option<PropertyValue> {
+        if self.is_empty() {
+            return None;
+        }
+        self.get_map().get(name).cloned()
+    }
+
+    /// Returns the handle of this set with a property set to the given value
+    pub fn set(&self, name: &str, value: PropertyValue) -> Properties {
+        let mut properties = (*self.get_map()).clone();
+        properties.insert(name.to_string(), value);
+        Properties::intern(properties)
+    }
+
+    /// Returns the handle of this set without the given property
+    pub fn remove(&self, name: &str) -> Properties {
+        if self.get(name).is_none() {
+            return *self;
+        }
+        let mut properties = (*self.get_map()).clone();
+        properties.remove(name);
+        Properties::intern(properties)
+    }
+
+    /// Returns true if no properties are set
+    pub fn is_empty(&self) -> bool {
+        self.id == 0
+    }
+}
+
+impl Default for Properties {
+    fn default() -> Properties {
+        Properties::new()
+    }
+}
+
+impl fmt::Debug for Properties {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.get_map().iter()).finish()
+    }
+}
+
+/// Written the way block states write them, e.g. `[facing=east,waterlogged=true]`
+impl fmt::Display for Properties {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let properties: Vec<String> = self
+            .get_map()
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        write!(f, "[{}]", properties.join(","))
+    }
+}
+
+/// Handles are only meaningful inside of one process, so the properties themselves are written
+impl Serialize for Properties {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get_map().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Properties {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Properties, D::Error> {
+        PropertyMap::deserialize(deserializer).map(Properties::intern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_sets_share_a_handle() {
+        let a = Properties::new()
+            .set("facing", "east".into())
+            .set("waterlogged", true.into());
+        let b = Properties::new()
+            .set("waterlogged", true.into())
+            .set("facing", "east".into());
+        assert!(a == b);
+        assert!(a.get("facing") == Some(PropertyValue::from("east")));
+        assert!(a.remove("facing") != b);
+        assert!(a.remove("facing").remove("waterlogged") == Properties::new());
+        assert!(a.remove("power") == a);
+    }
+}
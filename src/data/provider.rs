@@ -0,0 +1,318 @@
+//! Provides storage for chunks evicted from memory
+//!
+//! A World with a chunk provider can evict chunks, handing them to the provider and keeping only
+//! the key it returned. Evicted chunks are reloaded from the provider whenever they are read, and
+//! reading or writing one fails if the provider can not load it back.
+//!
+//! As worlds are immutable, views taken before an eviction keep the chunk in memory, and views
+//! taken after it keep the key, so a stored chunk is never changed or replaced. It is removed
+//! from the provider once no view of the world refers to it anymore.
+
+use data::chunk::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Stores chunks evicted from a World
+pub trait ChunkProvider: Send + Sync {
+    /// Stores a chunk, returning the key it can be loaded back with
+    fn store(&self, chunk: &Chunk) -> io::Result<u64>;
+
+    /// Loads a chunk previously stored under the given key
+    fn load(&self, key: u64) -> io::Result<Chunk>;
+
+    /// Removes the chunk stored under the given key, it will not be loaded again
+    fn remove(&self, key: u64);
+}
+
+/// Keeps evicted chunks in memory
+///
+/// Mostly useful for testing, and as the simplest example of a provider.
+pub struct MemoryChunkProvider {
+    chunks: Mutex<(u64, HashMap<u64, Chunk>)>,
+}
+
+impl MemoryChunkProvider {
+    /// Creates a new, empty provider
+    pub fn new() -> MemoryChunkProvider {
+        MemoryChunkProvider {
+            chunks: Mutex::new((0, HashMap::new())),
+        }
+    }
+
+    /// Returns the number of chunks currently stored
+    pub fn len(&self) -> usize {
+        self.chunks.lock().unwrap().1.len()
+    }
+
+    /// Returns true if no chunks are currently stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for MemoryChunkProvider {
+    fn default() -> MemoryChunkProvider {
+        MemoryChunkProvider::new()
+    }
+}
+
+impl ChunkProvider for MemoryChunkProvider {
+    fn store(&self, chunk: &Chunk) -> io::Result<u64> {
+        let mut chunks = self.chunks.lock().unwrap();
+        let key = chunks.0;
+        chunks.0 += 1;
+        chunks.1.insert(key, chunk.clone());
+        Ok(key)
+    }
+
+    fn load(&self, key: u64) -> io::Result<Chunk> {
+        let chunks = self.chunks.lock().unwrap();
+        chunks
+            .1
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no chunk with that key"))
+    }
+
+    fn remove(&self, key: u64) {
+        self.chunks.lock().unwrap().1.remove(&key);
+    }
+}
+
+/// Keeps evicted chunks as files in a directory
+///
+/// Each chunk is serialized with Chunk::to_bytes into its own file. The directory is scratch
+/// space for the provider: files are only meaningful to the provider that wrote them, and are
+/// deleted once the chunk is no longer needed.
+pub struct DirectoryChunkProvider {
+    directory: PathBuf,
+    next_key: AtomicU64,
+}
+
+impl DirectoryChunkProvider {
+    /// Creates a new provider storing chunks in the given directory, creating it if needed
+    pub fn new<P: AsRef<Path>>(directory: P) -> io::Result<DirectoryChunkProvider> {
+        fs::create_dir_all(directory.as_ref())?;
+        Ok(DirectoryChunkProvider {
+            directory: directory.as_ref().to_path_buf(),
+            next_key: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the directory chunks are stored in
+    pub fn get_directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn get_path(&self, key: u64) -> PathBuf {
+        self.directory.join(format!("{}.chunk", key))
+    }
+}
+
+impl ChunkProvider for DirectoryChunkProvider {
+    fn store(&self, chunk: &Chunk) -> io::Result<u64> {
+        let key = self.next_key.fetch_add(1, Ordering::SeqCst);
+        fs::write(self.get_path(key), chunk.to_bytes())?;
+        Ok(key)
+    }
+
+    fn load(&self, key: u64) -> io::Result<Chunk> {
+        let bytes = fs::read(self.get_path(key))?;
+        Chunk::from_bytes(&bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupt chunk file"))
+    }
+
+    fn remove(&self, key: u64) {
+        // The chunk will never be loaded again, so failing to delete it only wastes space
+        let _ = fs::remove_file(self.get_path(key));
+    }
+}
+
+/// Statistics of a CachedChunkProvider
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    hits: u64,
+    misses: u64,
+}
+
+impl CacheStats {
+    /// Returns the number of loads served from the cache
+    pub fn get_hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of loads that had to go to the provider
+    pub fn get_misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Returns the fraction of loads served from the cache, zero if there were none
+    pub fn get_hit_rate(&self) -> f64 {
+        let loads = self.hits + self.misses;
+        if loads == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / loads as f64
+    }
+}
+
+/// Caches the chunks most recently loaded from another provider
+///
+/// Every read of an evicted chunk loads it from the provider, which for providers like
+/// DirectoryChunkProvider means reading and deserializing it again. Wrapping the provider in a
+/// cache keeps the chunks read most recently in memory, up to a fixed number of them, so chunks
+/// read over and over are only loaded once.
+pub struct CachedChunkProvider {
+    provider: Arc<dyn ChunkProvider>,
+    capacity: usize,
+    cache: Mutex<Cache>,
+}
+
+struct Cache {
+    /// Each cached chunk, along with the tick it was last loaded at
+    chunks: HashMap<u64, (Chunk, u64)>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl CachedChunkProvider {
+    /// Creates a new cache of up to capacity chunks in front of the provider
+    ///
+    /// Returns None if the capacity is zero
+    pub fn new(provider: Arc<dyn ChunkProvider>, capacity: usize) -> Option<CachedChunkProvider> {
+        if capacity == 0 {
+            return None;
+        }
+        Some(CachedChunkProvider {
+            provider,
+            capacity,
+            cache: Mutex::new(Cache {
+                chunks: HashMap::new(),
+                tick: 0,
+                stats: CacheStats::default(),
+            }),
+        })
+    }
+
+    /// Returns the number of chunks currently cached
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().chunks.len()
+    }
+
+    /// Returns true if no chunks are currently cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many loads were served from the cache so far, and how many were not
+    pub fn get_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats
+    }
+}
+
+impl ChunkProvider for CachedChunkProvider {
+    fn store(&self, chunk: &Chunk) -> io::Result<u64> {
+        // Chunks are evicted because they went cold, so they are only cached once read again
+        self.provider.store(chunk)
+    }
+
+    fn load(&self, key: u64) -> io::Result<Chunk> {
+        {
+            let mut cache = self.cache.lock().unwrap();
+            cache.tick += 1;
+            let tick = cache.tick;
+            if let Some(cached) = cache.chunks.get_mut(&key) {
+                cached.1 = tick;
+                let chunk = cached.0.clone();
+                cache.stats.hits += 1;
+                return Ok(chunk);
+            }
+            cache.stats.misses += 1;
+        }
+
+        // Loaded without holding the lock, so slow loads do not hold up cache hits
+        let chunk = self.provider.load(key)?;
+        let mut cache = self.cache.lock().unwrap();
+        if cache.chunks.len() >= self.capacity && !cache.chunks.contains_key(&key) {
+            let oldest = cache.chunks.iter().min_by_key(|x| (x.1).1).map(|x| *x.0);
+            if let Some(oldest) = oldest {
+                cache.chunks.remove(&oldest);
+            }
+        }
+        let tick = cache.tick;
+        cache.chunks.insert(key, (chunk.clone(), tick));
+        Ok(chunk)
+    }
+
+    fn remove(&self, key: u64) {
+        self.cache.lock().unwrap().chunks.remove(&key);
+        self.provider.remove(key);
+    }
+}
+
+/// A chunk that has been handed to a provider
+///
+/// Removes the chunk from the provider when dropped
+pub(crate) struct EvictedChunk {
+    key: u64,
+    provider: Arc<dyn ChunkProvider>,
+}
+
+impl EvictedChunk {
+    /// Hands the chunk to the provider
+    pub(crate) fn evict(chunk: &Chunk, provider: &Arc<dyn ChunkProvider>) -> io::Result<Self> {
+        let key = provider.store(chunk)?;
+        Ok(EvictedChunk {
+            key,
+            provider: provider.clone(),
+        })
+    }
+
+    /// Loads the chunk back from the provider
+    pub(crate) fn load(&self) -> io::Result<Chunk> {
+        self.provider.load(self.key)
+    }
+}
+
+impl Drop for EvictedChunk {
+    fn drop(&mut self) {
+        self.provider.remove(self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::block::*;
+
+    #[test]
+    fn cache_keeps_recent_chunks() {
+        let block = |id| MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new());
+        let chunk = |id| Chunk::new(Block::new_from_ids(0, id)).set_block(0, 0, 0, block(id + 1));
+        let inner = Arc::new(MemoryChunkProvider::new());
+        assert!(CachedChunkProvider::new(inner.clone(), 0).is_none());
+        let cache = CachedChunkProvider::new(inner.clone(), 2).unwrap();
+
+        let keys: Vec<u64> = (0..3).map(|id| cache.store(&chunk(id)).unwrap()).collect();
+        assert!(cache.is_empty());
+        cache.load(keys[0]).unwrap();
+        cache.load(keys[1]).unwrap();
+        assert!(cache.load(keys[0]).unwrap().get_block(0, 0, 0) == block(1));
+        // Loading a third chunk pushes out the least recently loaded one
+        cache.load(keys[2]).unwrap();
+        assert!(cache.load(keys[0]).unwrap().get_block(0, 0, 0) == block(1));
+        assert!(cache.load(keys[1]).unwrap().get_block(0, 0, 0) == block(2));
+
+        let stats = cache.get_stats();
+        assert_eq!((stats.get_hits(), stats.get_misses()), (2, 4));
+        assert!((stats.get_hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+        cache.remove(keys[1]);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(inner.len(), 2);
+        assert!(cache.load(keys[1]).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+//! Provides an axis aligned box of blocks
+
+/// An axis aligned box of blocks, inclusive on both ends
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    min: (i32, i32, i32),
+    max: (i32, i32, i32),
+}
+
+impl Region {
+    /// Creates the smallest region containing both corners
+    ///
+    /// The corners can be given in any order.
+    pub fn new(a: (i32, i32, i32), b: (i32, i32, i32)) -> Region {
+        Region {
+            min: (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+            max: (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+        }
+    }
+
+    /// Returns the corner with the smallest coordinates
+    pub fn get_min(&self) -> (i32, i32, i32) {
+        self.min
+    }
+
+    /// Returns the corner with the largest coordinates
+    pub fn get_max(&self) -> (i32, i32, i32) {
+        self.max
+    }
+
+    /// Returns true if the block at the given coordinates is inside the region
+    pub fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        x >= self.min.0
+            && x <= self.max.0
+            && y >= self.min.1
+            && y <= self.max.1
+            && z >= self.min.2
+            && z <= self.max.2
+    }
+}
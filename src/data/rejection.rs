@@ -0,0 +1,171 @@
+//! Provides records of transactions that failed to apply
+
+use chrono::{DateTime, Utc};
+use data::block::*;
+use data::transaction::*;
+use std::io;
+
+/// Why a transaction was rejected
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectionReason {
+    /// A validator rejected the transaction
+    Invalid,
+    /// A guard vetoed the transaction
+    Vetoed,
+    /// A Replace found a different block than it expected, and the conflict policy rejected it,
+    /// or a transaction merged from another node has the id of a different one
+    Conflict,
+    /// An Undo referred to a transaction that is not in the worldline
+    NotFound,
+    /// The transaction was missing something it needs, like the coordinates of a Set
+    Incomplete,
+    /// A committed transaction did not come after everything already in the worldline
+    OutOfOrder,
+    /// The world changed since the version the transaction was conditioned on
+    Stale,
+    /// The transaction was earlier than the latest time already committed, beyond the tolerance
+    /// of the time ordering
+    Backdated,
+    /// A chunk the transaction touches was evicted, and the chunk provider could not load it back
+    Unavailable,
+}
+
+/// Report of why a transaction failed to apply, detailed enough to show to a player
+///
+/// Besides the reason, it records where the transaction failed, and for a conflicting Replace
+/// what it expected to find, what it found instead, and which transaction put it there.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rejection {
+    reason: RejectionReason,
+    coords: Option<(i32, i32, i32)>,
+    expected: Option<MetaBlock>,
+    actual: Option<MetaBlock>,
+    conflicting: Option<TransactionID>,
+}
+
+impl Rejection {
+    /// Creates a new report of a rejection, at the given coordinates if they are known
+    pub fn new(reason: RejectionReason, coords: Option<(i32, i32, i32)>) -> Rejection {
+        Rejection {
+            reason,
+            coords,
+            expected: None,
+            actual: None,
+            conflicting: None,
+        }
+    }
+
+    /// Creates a new report of a Replace that expected one block at the coordinates, but found
+    /// another, last written by the conflicting transaction
+    ///
+    /// The conflicting transaction is None if the block had never been written to.
+    pub fn new_conflict(
+        coords: (i32, i32, i32),
+        expected: MetaBlock,
+        actual: MetaBlock,
+        conflicting: Option<TransactionID>,
+    ) -> Rejection {
+        Rejection {
+            reason: RejectionReason::Conflict,
+            coords: Some(coords),
+            expected: Some(expected),
+            actual: Some(actual),
+            conflicting,
+        }
+    }
+
+    /// Creates a new report of a transaction merged from another node, whose id is already
+    /// taken by a different transaction
+    pub fn new_taken_id(id: TransactionID, coords: Option<(i32, i32, i32)>) -> Rejection {
+        Rejection {
+            conflicting: Some(id),
+            ..Rejection::new(RejectionReason::Conflict, coords)
+        }
+    }
+
+    /// Returns a copy of this report, at the given coordinates if it does not have any yet
+    pub fn or_coords(self, coords: Option<(i32, i32, i32)>) -> Rejection {
+        Rejection {
+            coords: self.coords.or(coords),
+            ..self
+        }
+    }
+
+    /// Returns why the transaction was rejected
+    pub fn get_reason(&self) -> RejectionReason {
+        self.reason
+    }
+
+    /// Returns the coordinates of the block the transaction failed on, if it has any
+    ///
+    /// For an Undo, these are the coordinates of the transaction it undoes.
+    pub fn get_coords(&self) -> Option<(i32, i32, i32)> {
+        self.coords
+    }
+
+    /// Returns the block a conflicting Replace expected to find
+    pub fn get_expected(&self) -> Option<MetaBlock> {
+        self.expected.clone()
+    }
+
+    /// Returns the block a conflicting Replace actually found
+    pub fn get_actual(&self) -> Option<MetaBlock> {
+        self.actual.clone()
+    }
+
+    /// Returns the id of the transaction that last wrote the block a Replace conflicted on
+    pub fn get_conflicting(&self) -> Option<TransactionID> {
+        self.conflicting
+    }
+}
+
+impl From<RejectionReason> for Rejection {
+    fn from(reason: RejectionReason) -> Rejection {
+        Rejection::new(reason, None)
+    }
+}
+
+impl From<io::Error> for Rejection {
+    fn from(_: io::Error) -> Rejection {
+        Rejection::new(RejectionReason::Unavailable, None)
+    }
+}
+
+/// A transaction that was rejected, why, and when
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectedTransaction {
+    transaction: RawTransaction,
+    rejection: Rejection,
+    time: DateTime<Utc>,
+}
+
+impl RejectedTransaction {
+    /// Creates a new record of a transaction rejected just now
+    pub fn new(transaction: RawTransaction, rejection: Rejection) -> RejectedTransaction {
+        RejectedTransaction {
+            transaction,
+            rejection,
+            time: Utc::now(),
+        }
+    }
+
+    /// Returns when the transaction was rejected
+    pub fn get_time(&self) -> DateTime<Utc> {
+        self.time
+    }
+
+    /// Returns the transaction that was rejected
+    pub fn get_transaction(&self) -> RawTransaction {
+        self.transaction.clone()
+    }
+
+    /// Returns why the transaction was rejected
+    pub fn get_reason(&self) -> RejectionReason {
+        self.rejection.get_reason()
+    }
+
+    /// Returns the full report of why the transaction was rejected
+    pub fn get_rejection(&self) -> Rejection {
+        self.rejection.clone()
+    }
+}
@@ -0,0 +1,133 @@
+//! Provides plans for rolling back transactions, which can be reviewed before being applied
+
+use data::block::*;
+use data::transaction::*;
+use data::world::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// A block that a rollback would change
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockChange {
+    world: WorldId,
+    coords: (i32, i32, i32),
+    before: MetaBlock,
+    after: MetaBlock,
+}
+
+impl BlockChange {
+    /// Creates a new change of the block at coords, from before to after
+    pub fn new(
+        world: WorldId,
+        coords: (i32, i32, i32),
+        before: MetaBlock,
+        after: MetaBlock,
+    ) -> BlockChange {
+        BlockChange {
+            world,
+            coords,
+            before,
+            after,
+        }
+    }
+
+    /// Returns the world the block is in
+    pub fn get_world(&self) -> WorldId {
+        self.world
+    }
+
+    /// Returns the coordinates of the block
+    pub fn get_coords(&self) -> (i32, i32, i32) {
+        self.coords
+    }
+
+    /// Returns the block as it currently is
+    pub fn get_before(&self) -> MetaBlock {
+        self.before.clone()
+    }
+
+    /// Returns the block as it would be after the rollback
+    pub fn get_after(&self) -> MetaBlock {
+        self.after.clone()
+    }
+}
+
+/// Everything a rollback would do, obtained with Rewind::preview_rollback
+///
+/// A plan is only a preview, nothing is changed until it is applied with Rewind::apply_plan.
+#[derive(Clone, Debug)]
+pub struct RollbackPlan {
+    transactions: Vec<Transaction>,
+    changes: Vec<BlockChange>,
+    owners: HashMap<Uuid, usize>,
+    removed: HashMap<MetaBlock, usize>,
+    restored: HashMap<MetaBlock, usize>,
+    as_of: Option<TransactionID>,
+}
+
+impl RollbackPlan {
+    /// Creates a plan undoing the transactions, with the given effect on the world
+    ///
+    /// as_of is the last transaction in the worldline when the plan was made, if any
+    pub fn new(
+        transactions: Vec<Transaction>,
+        changes: Vec<BlockChange>,
+        as_of: Option<TransactionID>,
+    ) -> RollbackPlan {
+        let mut owners = HashMap::new();
+        for transaction in &transactions {
+            *owners
+                .entry(transaction.get_transaction().get_owner())
+                .or_insert(0) += 1;
+        }
+        let mut removed = HashMap::new();
+        let mut restored = HashMap::new();
+        for change in &changes {
+            *removed.entry(change.before.clone()).or_insert(0) += 1;
+            *restored.entry(change.after.clone()).or_insert(0) += 1;
+        }
+        RollbackPlan {
+            transactions,
+            changes,
+            owners,
+            removed,
+            restored,
+            as_of,
+        }
+    }
+
+    /// Returns the transactions the rollback would undo, oldest first
+    pub fn get_transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Returns every block the rollback would change, ordered by world and coordinates
+    pub fn get_changes(&self) -> &[BlockChange] {
+        &self.changes
+    }
+
+    /// Returns the number of transactions of each player the rollback would undo
+    pub fn get_owner_counts(&self) -> &HashMap<Uuid, usize> {
+        &self.owners
+    }
+
+    /// Returns the number of each block the rollback would remove from the world
+    pub fn get_removed_counts(&self) -> &HashMap<MetaBlock, usize> {
+        &self.removed
+    }
+
+    /// Returns the number of each block the rollback would put back into the world
+    pub fn get_restored_counts(&self) -> &HashMap<MetaBlock, usize> {
+        &self.restored
+    }
+
+    /// Returns the last transaction in the worldline when the plan was made, if any
+    pub fn get_as_of(&self) -> Option<TransactionID> {
+        self.as_of
+    }
+
+    /// Returns true if the rollback would do nothing at all
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+}
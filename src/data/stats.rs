@@ -0,0 +1,65 @@
+//! Provides statistics about the makeup of a world
+
+use data::block::*;
+use data::coords::*;
+use std::collections::HashMap;
+
+/// How many of each block a world is made of, in total and per chunk
+///
+/// Only chunks that exist are counted, so the default block is only counted for the unset blocks
+/// of chunks that have been written to.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BlockCounts {
+    totals: HashMap<MetaBlock, usize>,
+    chunks: HashMap<ChunkPos, HashMap<MetaBlock, usize>>,
+}
+
+impl BlockCounts {
+    /// Creates a new, empty set of counts
+    pub fn new() -> BlockCounts {
+        BlockCounts {
+            totals: HashMap::new(),
+            chunks: HashMap::new(),
+        }
+    }
+
+    /// Adds some number of a block in the given chunk to the counts
+    pub fn add(&mut self, chunk: ChunkPos, block: MetaBlock, count: usize) {
+        if count == 0 {
+            return;
+        }
+        *self.totals.entry(block.clone()).or_insert(0) += count;
+        *self
+            .chunks
+            .entry(chunk)
+            .or_default()
+            .entry(block)
+            .or_insert(0) += count;
+    }
+
+    /// Returns the number of a block in the world
+    pub fn get_count(&self, block: MetaBlock) -> usize {
+        self.totals.get(&block).cloned().unwrap_or(0)
+    }
+
+    /// Returns the number of each distinct block in the world
+    pub fn get_totals(&self) -> &HashMap<MetaBlock, usize> {
+        &self.totals
+    }
+
+    /// Returns the number of each distinct block in a chunk, if the chunk exists
+    pub fn get_chunk_counts(&self, chunk: ChunkPos) -> Option<&HashMap<MetaBlock, usize>> {
+        self.chunks.get(&chunk)
+    }
+
+    /// Returns the index of every counted chunk
+    pub fn get_chunks(&self) -> Vec<ChunkPos> {
+        self.chunks.keys().cloned().collect()
+    }
+}
+
+impl Default for BlockCounts {
+    fn default() -> BlockCounts {
+        BlockCounts::new()
+    }
+}
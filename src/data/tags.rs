@@ -0,0 +1,225 @@
+//! Provides block tags, named groups of blocks such as `#minecraft:logs`
+//!
+//! Tags are stored by name, the way data packs define them, and only turned into sets of blocks
+//! when resolved against a dictionary. A tag can include other tags by prefixing their name with
+//! `#`.
+
+use data::block::*;
+use serde_json::{self, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// A registry of block tags
+pub struct BlockTagRegistry {
+    /// The values of each tag, keyed by the tag's name without the leading #
+    tags: HashMap<String, Vec<String>>,
+}
+
+impl BlockTagRegistry {
+    /// Creates a new, empty registry
+    pub fn new() -> BlockTagRegistry {
+        BlockTagRegistry {
+            tags: HashMap::new(),
+        }
+    }
+
+    /// Adds values to a tag, creating it if needed
+    ///
+    /// Values are block names like `minecraft:oak_log`, or other tags like `#minecraft:logs`
+    pub fn add_tag<I, S>(&mut self, tag: &str, values: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags
+            .entry(strip_hash(tag).to_string())
+            .or_default()
+            .extend(values.into_iter().map(|x| x.into()));
+        self
+    }
+
+    /// Adds a tag from a data pack tag file
+    ///
+    /// Like in minecraft, the values are added to any existing ones unless the file sets replace
+    /// to true
+    pub fn add_tag_json<R: Read>(&mut self, tag: &str, reader: R) -> io::Result<&mut Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let file: Value = serde_json::from_reader(reader)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let values = file
+            .get("values")
+            .and_then(|x| x.as_array())
+            .ok_or_else(|| invalid("tag without values"))?;
+
+        // Values are either names, or objects naming an optional entry
+        let values = values
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .or_else(|| value.get("id").and_then(|x| x.as_str()))
+                    .map(|x| x.to_string())
+                    .ok_or_else(|| invalid("tag value without an id"))
+            })
+            .collect::<io::Result<Vec<String>>>()?;
+
+        if file.get("replace").and_then(|x| x.as_bool()) == Some(true) {
+            self.tags.remove(strip_hash(tag));
+        }
+        Ok(self.add_tag(tag, values))
+    }
+
+    /// Adds every block tag in a data pack
+    ///
+    /// Tags are read from `data/<namespace>/tags/blocks`, or `tags/block` in newer data packs,
+    /// and named after their namespace and path, e.g. `minecraft:logs`.
+    pub fn load_data_pack<P: AsRef<Path>>(&mut self, data_pack: P) -> io::Result<&mut Self> {
+        let mut files = Vec::new();
+        for namespace in fs::read_dir(data_pack.as_ref().join("data"))? {
+            let namespace = namespace?;
+            let name = namespace.file_name().to_string_lossy().into_owned();
+            for folder in &["blocks", "block"] {
+                let root = namespace.path().join("tags").join(folder);
+                if root.is_dir() {
+                    find_tag_files(&root, &name, "", &mut files)?;
+                }
+            }
+        }
+        // Sorted, so tags defined twice are combined in a predictable order
+        files.sort();
+        for (tag, path) in files {
+            self.add_tag_json(&tag, fs::File::open(path)?)?;
+        }
+        Ok(self)
+    }
+
+    /// Returns the values of a tag, as they were added
+    pub fn get_values(&self, tag: &str) -> Option<&[String]> {
+        self.tags.get(strip_hash(tag)).map(|x| x.as_slice())
+    }
+
+    /// Returns the names of every tag in the registry
+    pub fn get_tags(&self) -> Vec<&str> {
+        let mut tags: Vec<&str> = self.tags.keys().map(|x| x.as_str()).collect();
+        tags.sort();
+        tags
+    }
+
+    /// Returns every block in a tag, including those of the tags it includes
+    ///
+    /// Blocks missing from the dictionary, and included tags missing from the registry, are
+    /// skipped. Returns None if the tag itself is not in the registry
+    pub fn resolve(&self, tag: &str, dictonary: &BlockDictonary) -> Option<HashSet<Block>> {
+        let tag = strip_hash(tag);
+        self.tags.get(tag)?;
+        let mut blocks = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![tag];
+        while let Some(tag) = pending.pop() {
+            if !visited.insert(tag) {
+                continue;
+            }
+            for value in self.tags.get(tag).into_iter().flatten() {
+                if value.starts_with('#') {
+                    pending.push(strip_hash(value));
+                } else if let Some(block) = value
+                    .split_once(':')
+                    .and_then(|name| dictonary.try_encode_block(name))
+                {
+                    blocks.insert(block);
+                }
+            }
+        }
+        Some(blocks)
+    }
+}
+
+impl Default for BlockTagRegistry {
+    fn default() -> BlockTagRegistry {
+        BlockTagRegistry::new()
+    }
+}
+
+fn strip_hash(tag: &str) -> &str {
+    tag.strip_prefix('#').unwrap_or(tag)
+}
+
+/// Collects the tag files below a directory, along with the names of their tags
+fn find_tag_files(
+    directory: &Path,
+    namespace: &str,
+    prefix: &str,
+    files: &mut Vec<(String, PathBuf)>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let name = match path.file_stem() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        if path.is_dir() {
+            find_tag_files(&path, namespace, &format!("{}{}/", prefix, name), files)?;
+        } else if path.extension().is_some_and(|x| x == "json") {
+            files.push((format!("{}:{}{}", namespace, prefix, name), path));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::*;
+
+    #[test]
+    fn nested_tags() {
+        let mut table = BlockTable::new("minecraft");
+        for name in &["air", "oak_log", "birch_log", "stone"] {
+            table.add_name(name);
+        }
+        let mut dictonary = BlockDictonary::new();
+        dictonary.add_table(table);
+
+        let mut tags = BlockTagRegistry::new();
+        tags.add_tag("minecraft:oak_logs", vec!["minecraft:oak_log"])
+            .add_tag("#minecraft:logs", vec!["#minecraft:oak_logs", "#minecraft:logs"])
+            .add_tag_json(
+                "minecraft:logs",
+                &br#"{"values": ["minecraft:birch_log", {"id": "mod:log", "required": false}]}"#[..],
+            )
+            .unwrap();
+
+        let logs = tags.resolve("#minecraft:logs", &dictonary).unwrap();
+        assert_eq!(logs.len(), 2);
+        assert!(logs.contains(&Block::new_from_ids(0, 1)));
+        assert!(logs.contains(&Block::new_from_ids(0, 2)));
+        assert!(tags.resolve("minecraft:leaves", &dictonary).is_none());
+
+        // Filters can pick out transactions involving a tag
+        let set = |id| {
+            let block = MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new());
+            let raw = RawTransactionBuilder::new(TransactionType::new_set(block))
+                .set_x_coord(0)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap();
+            Transaction::new(raw, TransactionID::new())
+        };
+        let mut filter = TransactionFilter::new();
+        filter
+            .set_block_tag(&tags, "#minecraft:logs", &dictonary)
+            .unwrap();
+        assert!(filter.matches(&set(2)));
+        assert!(!filter.matches(&set(3)));
+
+        tags.add_tag_json("minecraft:logs", &br#"{"replace": true, "values": []}"#[..])
+            .unwrap();
+        assert!(tags
+            .resolve("minecraft:logs", &dictonary)
+            .unwrap()
+            .is_empty());
+    }
+}
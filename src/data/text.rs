@@ -0,0 +1,101 @@
+//! Provides the text written on blocks, like the lines of a sign, broken up into words for search
+//!
+//! Signs, lecterns and named containers keep their text in block entity data, which is attached
+//! to blocks as NBT. Every string in it counts as text written on the block. Minecraft stores
+//! sign lines as JSON text components, like `{"text":"hello"}`, so those are reduced to the text
+//! they show rather than searched as JSON.
+
+use data::block::*;
+use data::nbt::*;
+use serde_json::Value;
+
+/// Returns every piece of text written on the block, in the order of its NBT keys
+pub fn get_block_text(block: &MetaBlock) -> Vec<String> {
+    let mut text = Vec::new();
+    for value in block.get_meta_data().get_nbt_data().get_map().values() {
+        collect_nbt_text(value, &mut text);
+    }
+    text
+}
+
+/// Splits text into lowercase words, made of letters and digits, for indexing and searching
+pub fn get_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect()
+}
+
+fn collect_nbt_text(value: &NbtValue, text: &mut Vec<String>) {
+    match *value {
+        NbtValue::String(ref string) => match serde_json::from_str::<Value>(string) {
+            Ok(ref component @ Value::Object(_)) | Ok(ref component @ Value::Array(_)) => {
+                collect_component_text(component, text)
+            }
+            _ => text.push(string.clone()),
+        },
+        NbtValue::List(ref values) => {
+            for value in values {
+                collect_nbt_text(value, text);
+            }
+        }
+        NbtValue::Compound(ref values) => {
+            for value in values.values() {
+                collect_nbt_text(value, text);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Collects the text shown by a JSON text component, and every component nested in it
+fn collect_component_text(component: &Value, text: &mut Vec<String>) {
+    match *component {
+        Value::String(ref string) => text.push(string.clone()),
+        Value::Array(ref components) => {
+            for component in components {
+                collect_component_text(component, text);
+            }
+        }
+        Value::Object(ref fields) => {
+            if let Some(Value::String(ref string)) = fields.get("text") {
+                text.push(string.clone());
+            }
+            if let Some(extra) = fields.get("extra") {
+                collect_component_text(extra, text);
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_text_is_found() {
+        let lines = NbtValue::List(vec![
+            NbtValue::String(r#"{"text":"Hello","extra":[{"text":" World"}]}"#.to_string()),
+            NbtValue::String("plain, Text".to_string()),
+            NbtValue::String("42".to_string()),
+        ]);
+        let meta = MetaData::new()
+            .set_nbt(
+                "minecraft:front_text",
+                NbtValue::Compound(vec![("messages".to_string(), lines)].into_iter().collect()),
+            )
+            .unwrap()
+            .set_nbt("mymod:charge", NbtValue::Int(3))
+            .unwrap();
+        let sign = MetaBlock::fuse(Block::new_from_ids(0, 1), meta);
+
+        let text = get_block_text(&sign);
+        assert!(text == vec!["Hello", " World", "plain, Text", "42"]);
+        let words: Vec<String> = text.iter().flat_map(|x| get_words(x)).collect();
+        assert!(words == vec!["hello", "world", "plain", "text", "42"]);
+        assert!(
+            get_block_text(&MetaBlock::fuse(Block::new_from_ids(0, 1), MetaData::new())).is_empty()
+        );
+    }
+}
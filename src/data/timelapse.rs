@@ -0,0 +1,117 @@
+//! Provides timelapses of a region, sampled from the worldline at fixed intervals
+//!
+//! A timelapse export is JSON Lines. The first line is a TimelapseHeader, and every line after
+//! it a TimelapseFrame, in order. Keyframes list every block of the region that differs from the
+//! default block, while the frames between them only list the blocks that changed since the
+//! frame before, so a renderer can start from any keyframe and apply deltas from there.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use data::block::*;
+use data::region::*;
+
+/// Version of the timelapse export format
+pub const TIMELAPSE_VERSION: u32 = 1;
+
+/// Describes which part of the worldline a timelapse samples, and how often
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Timelapse {
+    region: Region,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    interval: Duration,
+    keyframe_every: usize,
+}
+
+impl Timelapse {
+    /// Creates a timelapse of the region, with a frame every interval from start until end
+    ///
+    /// The first frame is at start, and the last one at or before end. Every tenth frame is a
+    /// keyframe. Returns None if the interval is not positive, or end comes before start.
+    pub fn new<Tz: TimeZone>(
+        region: Region,
+        start: DateTime<Tz>,
+        end: DateTime<Tz>,
+        interval: Duration,
+    ) -> Option<Timelapse> {
+        let (start, end) = (start.with_timezone(&Utc), end.with_timezone(&Utc));
+        if interval <= Duration::zero() || end < start {
+            return None;
+        }
+        Some(Timelapse {
+            region,
+            start,
+            end,
+            interval,
+            keyframe_every: 10,
+        })
+    }
+
+    /// Sets how many frames apart keyframes are, the first frame is always one
+    ///
+    /// Returns None, leaving the timelapse untouched, if keyframe_every is zero
+    pub fn set_keyframe_every(&mut self, keyframe_every: usize) -> Option<&mut Self> {
+        if keyframe_every == 0 {
+            return None;
+        }
+        self.keyframe_every = keyframe_every;
+        Some(self)
+    }
+
+    /// Returns the region being sampled
+    pub fn get_region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns the time of every frame, in order
+    pub fn get_frame_times(&self) -> Vec<DateTime<Utc>> {
+        let mut times = Vec::new();
+        let mut time = self.start;
+        while time <= self.end {
+            times.push(time);
+            time += self.interval;
+        }
+        times
+    }
+
+    /// Returns true if the frame with the given index is a keyframe
+    pub fn is_keyframe(&self, frame: usize) -> bool {
+        frame.is_multiple_of(self.keyframe_every)
+    }
+
+    /// Returns the header a timelapse export starts with
+    pub fn get_header(&self) -> TimelapseHeader {
+        TimelapseHeader {
+            version: TIMELAPSE_VERSION,
+            region: self.region,
+            start: self.start.to_rfc3339(),
+            interval_seconds: self.interval.num_seconds(),
+            frames: self.get_frame_times().len(),
+            keyframe_every: self.keyframe_every,
+        }
+    }
+}
+
+/// First line of a timelapse export
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelapseHeader {
+    pub version: u32,
+    pub region: Region,
+    /// RFC 3339 time of the first frame
+    pub start: String,
+    pub interval_seconds: i64,
+    pub frames: usize,
+    pub keyframe_every: usize,
+}
+
+/// One frame of a timelapse export
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelapseFrame {
+    /// Index of the frame, starting at zero
+    pub frame: usize,
+    /// RFC 3339 time the frame was sampled at
+    pub time: String,
+    /// True if blocks lists the whole region, false if only the changes since the last frame
+    pub keyframe: bool,
+    /// Coordinates and state of each block, ordered by coordinates
+    pub blocks: Vec<((i32, i32, i32), MetaBlock)>,
+}
@@ -1,14 +1,29 @@
 //! This module contains datastructures describing transactions
 
 use chrono::prelude::*;
+use conflict::Resolution;
+use data::biome::*;
 use data::block::*;
+use data::category::*;
+use data::decoration::*;
+use data::explosion::*;
+use data::handle::*;
+use data::piston::*;
+use data::world::WorldId;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
 use std::cmp::*;
 use uuid::Uuid;
 
 /// Repusents a Transaction ID
 ///
 /// Id is the major time, sub_id is the minor time used for resolving conflicts
-#[derive(Copy, Clone, PartialEq, Eq)]
+///
+/// The major time is a logical clock rather than a wall clock, so it can be trusted to respect
+/// causality. In multi-primary deployments the sub_id is the id of the node that committed the
+/// transaction, which keeps ids unique across nodes and deterministically orders transactions
+/// committed at the same logical time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TransactionID {
     id: u32,
     sub_id: u32,
@@ -48,16 +63,19 @@ impl TransactionID {
     }
 }
 
+impl Default for TransactionID {
+    fn default() -> TransactionID {
+        TransactionID::new()
+    }
+}
+
 impl Ord for TransactionID {
     fn cmp(&self, other: &TransactionID) -> Ordering {
-        let order: Ordering;
         if self.id == other.id {
-            order = self.sub_id.cmp(&other.sub_id);
+            self.sub_id.cmp(&other.sub_id)
         } else {
-            order = self.id.cmp(&other.id);
+            self.id.cmp(&other.id)
         }
-
-        order
     }
 }
 
@@ -78,18 +96,54 @@ impl PartialOrd for TransactionID {
 /// 3. Undo
 ///    * Undoes the transaction with the given transaction id.
 ///      Will make the world appear as if that transaction had never existed.
-#[derive(Copy, Clone, PartialEq, Eq)]
+/// 4. SetBiome
+///    * Sets the biome of the biome cell containing the specified location. Biomes have their
+///      own history, separate from the history of the blocks in the cell.
+/// 5. SetDecoration
+///    * Places the decoration with the given uuid in the specified block, or updates it, like
+///      turning the item in an item frame. Decorations have their own history, separate from the
+///      history of the blocks.
+/// 6. RemoveDecoration
+///    * Breaks the decoration with the given uuid, which was in the specified block.
+/// 7. Explode
+///    * Sets every block destroyed by an explosion, such as air, all at once. The explosion
+///      records what each block was beforehand, but, like a Set, does not check it. Undoing it
+///      restores every block together.
+/// 8. PistonMove
+///    * Moves blocks pushed or pulled by a piston, all at once, leaving where they moved out of
+///      as the given block, such as air. Undoing it puts both ends of every move back.
+///
+/// Blocks are stored as interned handles, use BlockHandle::get to get at the block itself.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransactionType {
     Set {
-        block_set: MetaBlock,
+        block_set: BlockHandle,
     },
     Replace {
-        block_current: MetaBlock,
-        block_set: MetaBlock,
+        block_current: BlockHandle,
+        block_set: BlockHandle,
     },
     Undo {
         transaction: TransactionID,
     },
+    SetBiome {
+        biome: Biome,
+    },
+    SetDecoration {
+        entity: Uuid,
+        decoration: Decoration,
+    },
+    RemoveDecoration {
+        entity: Uuid,
+    },
+    Explode {
+        explosion: Explosion,
+        block_set: BlockHandle,
+    },
+    PistonMove {
+        moves: PistonMove,
+        block_set: BlockHandle,
+    },
 }
 
 impl TransactionType {
@@ -97,7 +151,9 @@ impl TransactionType {
     ///
     /// Takes the block to set to
     pub fn new_set(block: MetaBlock) -> TransactionType {
-        TransactionType::Set { block_set: block }
+        TransactionType::Set {
+            block_set: BlockHandle::intern(block),
+        }
     }
 
     /// Creates a new Replace transaction
@@ -108,8 +164,8 @@ impl TransactionType {
     /// in the orignal state when the transaction is being processed
     pub fn new_replace(original: MetaBlock, replacement: MetaBlock) -> TransactionType {
         TransactionType::Replace {
-            block_current: original,
-            block_set: replacement,
+            block_current: BlockHandle::intern(original),
+            block_set: BlockHandle::intern(replacement),
         }
     }
 
@@ -122,6 +178,105 @@ impl TransactionType {
     pub fn new_undo(transaction: TransactionID) -> TransactionType {
         TransactionType::Undo { transaction }
     }
+
+    /// Creates a new SetBiome transaction
+    ///
+    /// Takes the biome to set the cell to
+    pub fn new_set_biome(biome: Biome) -> TransactionType {
+        TransactionType::SetBiome { biome }
+    }
+
+    /// Creates a new SetDecoration transaction
+    ///
+    /// Takes the uuid of the decoration's entity, and what it looks like and holds afterwards
+    pub fn new_set_decoration(entity: Uuid, decoration: Decoration) -> TransactionType {
+        TransactionType::SetDecoration { entity, decoration }
+    }
+
+    /// Creates a new RemoveDecoration transaction
+    ///
+    /// Takes the uuid of the decoration's entity
+    pub fn new_remove_decoration(entity: Uuid) -> TransactionType {
+        TransactionType::RemoveDecoration { entity }
+    }
+
+    /// Creates a new Explode transaction
+    ///
+    /// Takes the blocks the explosion destroyed, and the block they are left as
+    pub fn new_explode(explosion: Explosion, block_set: MetaBlock) -> TransactionType {
+        TransactionType::Explode {
+            explosion,
+            block_set: BlockHandle::intern(block_set),
+        }
+    }
+
+    /// Creates a new PistonMove transaction
+    ///
+    /// Takes the blocks the piston moved, and the block left where they moved out of
+    pub fn new_piston_move(moves: PistonMove, block_set: MetaBlock) -> TransactionType {
+        TransactionType::PistonMove {
+            moves,
+            block_set: BlockHandle::intern(block_set),
+        }
+    }
+
+    /// Returns a copy of this transaction type with every block it refers to passed through f
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> TransactionType {
+        match self {
+            TransactionType::Set { block_set } => TransactionType::new_set(f(block_set.get())),
+            TransactionType::Replace {
+                block_current,
+                block_set,
+            } => TransactionType::new_replace(f(block_current.get()), f(block_set.get())),
+            TransactionType::SetDecoration { entity, decoration } => {
+                TransactionType::new_set_decoration(*entity, decoration.map_blocks(f))
+            }
+            TransactionType::Explode {
+                explosion,
+                block_set,
+            } => TransactionType::new_explode(explosion.map_blocks(&f), f(block_set.get())),
+            TransactionType::PistonMove { moves, block_set } => {
+                TransactionType::new_piston_move(moves.map_blocks(&f), f(block_set.get()))
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+/// A wall-clock time, stored in UTC so times from clients in different time zones compare
+/// correctly, along with the offset the submitter's clock had
+///
+/// Written as an RFC 3339 time in the submitter's offset, the same as transactions have always
+/// been written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Timestamp {
+    utc: DateTime<Utc>,
+    offset: FixedOffset,
+}
+
+impl Timestamp {
+    fn new<Tz: TimeZone>(time: DateTime<Tz>) -> Timestamp {
+        Timestamp {
+            utc: time.with_timezone(&Utc),
+            offset: time.offset().fix(),
+        }
+    }
+
+    fn get_local(&self) -> DateTime<FixedOffset> {
+        self.utc.with_timezone(&self.offset)
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get_local().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Timestamp, D::Error> {
+        DateTime::<FixedOffset>::deserialize(deserializer).map(Timestamp::new)
+    }
 }
 
 /// A transaction that has not yet been processed
@@ -130,7 +285,7 @@ impl TransactionType {
 /// associated with it, and has not yet been processed.
 ///
 /// This has several optional or defaulting behavior fields, so it the builder should be used
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RawTransaction {
     /// What this transaction is actually doing
     transaction_type: TransactionType,
@@ -141,7 +296,7 @@ pub struct RawTransaction {
     /// it would be wise to use the Uuid associated with that entitiy.
     owner: Uuid,
     /// When they did the transaction
-    time: Option<DateTime<FixedOffset>>,
+    time: Option<Timestamp>,
     /// Where the transaction takes place
     ///
     /// Stored in (x,y,z) format
@@ -149,12 +304,30 @@ pub struct RawTransaction {
     /// This is optional because some transactions don't explicitly refrence a block, like an
     /// undo, and others may refrence large sections of blocks, like a SetCuboid
     coords: Option<(i32, i32, i32)>,
+    /// Which world the transaction takes place in
+    ///
+    /// Defaults to the overworld, including when reading transactions exported before worlds
+    /// were tracked
+    #[serde(default)]
+    world: WorldId,
+    /// Id the client submitting the transaction gave it, so retries can be recognized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<Uuid>,
+    /// The transaction that caused this one, such as the bucket placement a liquid flowed from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cause: Option<TransactionID>,
+    /// What kind of change the transaction is, such as a block burning
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<ActionCategory>,
+    /// What to do if the precondition of a Replace does not hold, in place of the conflict policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    on_conflict: Option<Resolution>,
 }
 
 impl RawTransaction {
     /// Returns the TransactionType
-    pub fn get_transaction_type(&self) -> TransactionType {
-        self.transaction_type
+    pub fn get_transaction_type(&self) -> &TransactionType {
+        &self.transaction_type
     }
 
     /// Returns the owner of the transaction
@@ -162,26 +335,171 @@ impl RawTransaction {
         self.owner
     }
 
-    /// Returns the wall-clock time the transaction occured at
-    pub fn get_time(&self) -> Option<DateTime<FixedOffset>> {
-        self.time
+    /// Returns the wall-clock time the transaction occured at, in UTC
+    pub fn get_time(&self) -> Option<DateTime<Utc>> {
+        self.time.map(|x| x.utc)
+    }
+
+    /// Returns the offset from UTC of the clock the transaction was submitted with
+    pub fn get_offset(&self) -> Option<FixedOffset> {
+        self.time.map(|x| x.offset)
+    }
+
+    /// Returns the wall-clock time the transaction occured at, as the submitter saw it
+    pub fn get_local_time(&self) -> Option<DateTime<FixedOffset>> {
+        self.time.map(|x| x.get_local())
     }
 
     /// Returns the coordinantes of the block this transaction effects
     pub fn get_coords(&self) -> Option<(i32, i32, i32)> {
         self.coords
     }
+
+    /// Returns the world the transaction takes place in
+    pub fn get_world(&self) -> WorldId {
+        self.world
+    }
+
+    /// Returns the coordinates of every block this transaction takes place at
+    ///
+    /// This is every block destroyed by an Explode, or moved out of or into by a PistonMove,
+    /// whose own coordinates, if any, are only where the explosion went off or the piston is, and
+    /// the coordinates of any other transaction.
+    pub fn get_affected_coords(&self) -> Vec<(i32, i32, i32)> {
+        match &self.transaction_type {
+            TransactionType::Explode { explosion, .. } => explosion.get_coords(),
+            TransactionType::PistonMove { moves, .. } => moves.get_coords(),
+            _ => self.coords.into_iter().collect(),
+        }
+    }
+
+    /// Returns true if the transaction takes place at the given coordinates, see
+    /// get_affected_coords
+    pub fn affects_coords(&self, coords: (i32, i32, i32)) -> bool {
+        match &self.transaction_type {
+            TransactionType::Explode { explosion, .. } => explosion.contains(coords),
+            TransactionType::PistonMove { moves, .. } => moves.contains(coords),
+            _ => self.coords == Some(coords),
+        }
+    }
+
+    /// Returns the block this transaction leaves at the given coordinates, if it sets one there
+    ///
+    /// Whether a Replace actually applies is not checked, only what it would set.
+    pub fn get_block_set_at(&self, coords: (i32, i32, i32)) -> Option<MetaBlock> {
+        match &self.transaction_type {
+            TransactionType::Set { block_set } | TransactionType::Replace { block_set, .. }
+                if self.coords == Some(coords) =>
+            {
+                Some(block_set.get())
+            }
+            TransactionType::Explode {
+                explosion,
+                block_set,
+            } if explosion.contains(coords) => Some(block_set.get()),
+            TransactionType::PistonMove { moves, block_set } => {
+                moves.get_block_at(coords, block_set.get())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if this transaction can change more than one block, an Explode or a
+    /// PistonMove
+    pub fn is_multi_block(&self) -> bool {
+        matches!(
+            self.transaction_type,
+            TransactionType::Explode { .. } | TransactionType::PistonMove { .. }
+        )
+    }
+
+    /// Returns the id the client gave the transaction, if it gave one
+    pub fn get_request_id(&self) -> Option<Uuid> {
+        self.request_id
+    }
+
+    /// Returns the transaction that caused this one, if it was caused by another
+    pub fn get_cause(&self) -> Option<TransactionID> {
+        self.cause
+    }
+
+    /// Returns what kind of change the transaction is, if it was given a category
+    pub fn get_category(&self) -> Option<ActionCategory> {
+        self.category
+    }
+
+    /// Returns how a conflict is resolved, if the transaction overrides the conflict policy
+    pub fn get_on_conflict(&self) -> Option<Resolution> {
+        self.on_conflict
+    }
+
+    /// Returns true if this transaction is a SetBiome
+    pub fn is_set_biome(&self) -> bool {
+        matches!(self.transaction_type, TransactionType::SetBiome { .. })
+    }
+
+    /// Returns true if this transaction is an Explode
+    pub fn is_explosion(&self) -> bool {
+        matches!(self.transaction_type, TransactionType::Explode { .. })
+    }
+
+    /// Returns true if this transaction is a SetDecoration or RemoveDecoration
+    pub fn is_decoration(&self) -> bool {
+        self.get_decoration_entity().is_some()
+    }
+
+    /// Returns the uuid of the decoration this transaction places, updates or breaks, if it is a
+    /// SetDecoration or RemoveDecoration
+    pub fn get_decoration_entity(&self) -> Option<Uuid> {
+        match self.transaction_type {
+            TransactionType::SetDecoration { entity, .. }
+            | TransactionType::RemoveDecoration { entity } => Some(entity),
+            _ => None,
+        }
+    }
+
+    /// Returns a copy of this transaction with every block it refers to passed through f
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> RawTransaction {
+        RawTransaction {
+            transaction_type: self.transaction_type.map_blocks(f),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this transaction that took place at the given time
+    pub fn with_time<Tz: TimeZone>(&self, time: DateTime<Tz>) -> RawTransaction {
+        RawTransaction {
+            time: Some(Timestamp::new(time)),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this transaction that takes place at the given coordinates
+    ///
+    /// Returns None for transactions without coordinates, like Undos
+    pub fn with_coords(&self, coords: (i32, i32, i32)) -> Option<RawTransaction> {
+        self.coords?;
+        Some(RawTransaction {
+            coords: Some(coords),
+            ..self.clone()
+        })
+    }
 }
 
 /// A builder for transactions
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RawTransactionBuilder {
     transaction_type: TransactionType,
     owner: Option<Uuid>,
-    time: Option<DateTime<FixedOffset>>,
+    time: Option<Timestamp>,
     coord_x: Option<i32>,
     coord_y: Option<i32>,
     coord_z: Option<i32>,
+    world: WorldId,
+    request_id: Option<Uuid>,
+    cause: Option<TransactionID>,
+    category: Option<ActionCategory>,
+    on_conflict: Option<Resolution>,
 }
 
 impl RawTransactionBuilder {
@@ -196,6 +514,11 @@ impl RawTransactionBuilder {
             coord_x: None,
             coord_y: None,
             coord_z: None,
+            world: WorldId::default(),
+            request_id: None,
+            cause: None,
+            category: None,
+            on_conflict: None,
         }
     }
 
@@ -203,10 +526,8 @@ impl RawTransactionBuilder {
     ///
     /// Does not consume
     pub fn build_transaction(&self) -> Option<RawTransaction> {
-        let transaction_type = self.transaction_type;
         // If an owner was not provided, we are forced to default to the null Uuid
-        let owner = self.owner
-            .unwrap_or(Uuid::parse_str("0000000000000000000000000000000").unwrap());
+        let owner = self.owner.unwrap_or_else(Uuid::nil);
         let time = self.time;
         let coords: Option<(i32, i32, i32)> = match (self.coord_x, self.coord_y, self.coord_z) {
             (Some(x), Some(y), Some(z)) => Some((x, y, z)),
@@ -214,25 +535,47 @@ impl RawTransactionBuilder {
         };
 
         let transaction = RawTransaction {
-            transaction_type,
+            transaction_type: self.transaction_type.clone(),
             owner,
             time,
             coords,
+            world: self.world,
+            request_id: self.request_id,
+            cause: self.cause,
+            category: self.category,
+            on_conflict: self.on_conflict,
         };
 
         // Fail the build if the transaction requires coordinates, but does not have them
-        match transaction_type {
-            TransactionType::Set { .. } => if coords.is_some() {
-                Some(transaction)
-            } else {
-                None
-            },
-            TransactionType::Replace { .. } => if coords.is_some() {
-                Some(transaction)
-            } else {
-                None
-            },
-            TransactionType::Undo { .. } => Some(transaction),
+        match self.transaction_type {
+            TransactionType::Set { .. } => {
+                if coords.is_some() {
+                    Some(transaction)
+                } else {
+                    None
+                }
+            }
+            TransactionType::Replace { .. } => {
+                if coords.is_some() {
+                    Some(transaction)
+                } else {
+                    None
+                }
+            }
+            // Explosions and piston moves say which blocks they change, and do not need to say
+            // where they took place
+            TransactionType::Undo { .. }
+            | TransactionType::Explode { .. }
+            | TransactionType::PistonMove { .. } => Some(transaction),
+            TransactionType::SetBiome { .. }
+            | TransactionType::SetDecoration { .. }
+            | TransactionType::RemoveDecoration { .. } => {
+                if coords.is_some() {
+                    Some(transaction)
+                } else {
+                    None
+                }
+            }
         }
     }
 
@@ -243,15 +586,16 @@ impl RawTransactionBuilder {
     }
 
     /// Sets the time wall-clock time the transaction took place at
-    pub fn set_time(&mut self, time: DateTime<FixedOffset>) -> &mut Self {
-        self.time = Some(time);
+    ///
+    /// The time is stored in UTC, keeping the offset of the time zone it was given in.
+    pub fn set_time<Tz: TimeZone>(&mut self, time: DateTime<Tz>) -> &mut Self {
+        self.time = Some(Timestamp::new(time));
         self
     }
 
     /// Sets the wall-clock time of the transaction to now
     pub fn set_time_now(&mut self) -> &mut Self {
-        let local_time = Local::now();
-        self.time = Some(local_time.with_timezone(local_time.offset()));
+        self.time = Some(Timestamp::new(Local::now()));
         self
     }
 
@@ -272,10 +616,54 @@ impl RawTransactionBuilder {
         self.coord_z = Some(z);
         self
     }
+
+    /// Sets the world the transaction takes place in
+    ///
+    /// Defaults to the overworld
+    pub fn set_world(&mut self, world: WorldId) -> &mut Self {
+        self.world = world;
+        self
+    }
+
+    /// Sets the id the client gives the transaction
+    ///
+    /// Once a transaction with a request id has been committed, applying another one with the
+    /// same request id returns the committed transaction instead of applying it again, so
+    /// clients can safely retry submissions they never heard back about.
+    pub fn set_request_id(&mut self, request_id: Uuid) -> &mut Self {
+        self.request_id = Some(request_id);
+        self
+    }
+
+    /// Sets the transaction that caused this one
+    ///
+    /// Used for changes the game makes on its own as a result of a player's edit, such as liquid
+    /// flowing from a placed bucket, so rolling back the edit can also roll back what it caused.
+    pub fn set_cause(&mut self, cause: TransactionID) -> &mut Self {
+        self.cause = Some(cause);
+        self
+    }
+
+    /// Sets what kind of change the transaction is
+    ///
+    /// Transactions without a category never match a filter on categories
+    pub fn set_category(&mut self, category: ActionCategory) -> &mut Self {
+        self.category = Some(category);
+        self
+    }
+
+    /// Sets how a conflict is resolved if this is a Replace whose precondition does not hold,
+    /// overriding the conflict policy of the Rewind
+    ///
+    /// The resolution is part of the transaction, so it is used whenever history is replayed too.
+    pub fn set_on_conflict(&mut self, resolution: Resolution) -> &mut Self {
+        self.on_conflict = Some(resolution);
+        self
+    }
 }
 
 /// A transaction that has been commited to the world and has been assigned a transaction ID
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Transaction {
     /// The details about the transaction are stored in the corrosponding RawTransaction
     transaction: RawTransaction,
@@ -290,8 +678,8 @@ impl Transaction {
     }
 
     /// Returns the RawTransaction
-    pub fn get_transaction(&self) -> RawTransaction {
-        self.transaction
+    pub fn get_transaction(&self) -> &RawTransaction {
+        &self.transaction
     }
 
     /// Returns the transaction id
@@ -301,9 +689,30 @@ impl Transaction {
 
     /// Returns true if this transaction is an Undo
     pub fn is_undo(&self) -> bool {
-        match self.get_transaction().get_transaction_type() {
-            TransactionType::Undo { .. } => true,
-            _ => false,
-        }
+        matches!(
+            self.get_transaction().get_transaction_type(),
+            TransactionType::Undo { .. }
+        )
+    }
+
+    /// Returns true if this transaction is a SetBiome
+    pub fn is_set_biome(&self) -> bool {
+        self.get_transaction().is_set_biome()
+    }
+
+    /// Returns true if this transaction is an Explode
+    pub fn is_explosion(&self) -> bool {
+        self.get_transaction().is_explosion()
+    }
+
+    /// Returns true if this transaction is a SetDecoration or RemoveDecoration
+    pub fn is_decoration(&self) -> bool {
+        self.get_transaction().is_decoration()
+    }
+
+    /// Returns a copy of this transaction, with the same id, with every block it refers to
+    /// passed through f
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> Transaction {
+        Transaction::new(self.transaction.map_blocks(f), self.id)
     }
 }
@@ -1,26 +1,108 @@
-//! Provides a world stored as a concpetutally infinte 2D array of chunks
+//! Provides a world stored as a concpetutally infinte 3D array of chunks
 //!
 //! Worlds are persistent and immutable
 
+use data::provider::EvictedChunk;
 use data::*;
 use im::*;
+use std::io;
+use std::iter;
 use std::sync::Arc;
+use uuid::Uuid;
+
+/// Identifies one of the worlds sharing a worldline, such as a dimension
+///
+/// The ids of the vanilla dimensions match the ones minecraft uses, any other id can be used for
+/// custom worlds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct WorldId(i32);
+
+impl WorldId {
+    /// The overworld, which is the default world
+    pub const OVERWORLD: WorldId = WorldId(0);
+    /// The nether
+    pub const NETHER: WorldId = WorldId(-1);
+    /// The end
+    pub const END: WorldId = WorldId(1);
+
+    /// Creates a WorldId from a raw id
+    pub fn new(id: i32) -> WorldId {
+        WorldId(id)
+    }
+
+    /// Returns the raw id
+    pub fn get_id(&self) -> i32 {
+        self.0
+    }
+}
+
+impl Default for WorldId {
+    fn default() -> WorldId {
+        WorldId::OVERWORLD
+    }
+}
 
 /// Persistent World
 ///
-/// Stores the world as a conceptually infinite 2D array of chunks.
+/// Stores the world as a conceptually infinite 3D array of chunks.
+///
+/// For simplicity, all chunks in a world must be the same size, by default CHUNK_SIZE in every
+/// direction. Their width and their vertical extent (along y) can be configured with
+/// with_chunk_size, e.g. to match the 16x16x16 sections minecraft uses.
 ///
-/// For simplicity, all chunks must currently be the same size. (By default
-/// CHUNK_SIZE).
+/// Chunks are indexed by their ChunkPos, e.g. with a chunk size of 10, the chunk with corners
+/// (10,0,0) and (19,9,9) would be indexed with (1,0,0), and the one with corners (-10,0,0) and
+/// (-1,9,9) with (-1,0,0)
 ///
-/// Chunks are indexed by the offset applied to each individual block.
-/// E.g. with a chunk size of 10, the chunk with corners (10,0) and (20,10)
-/// would be indexed with (10,0)
+/// With a chunk provider, chunks can be evicted from memory, and are reloaded from the provider
+/// when read.
 #[derive(Clone)]
 pub struct World {
-    chunks: HashMap<(i32, i32), Chunk>,
-    default_block: MetaBlock,
+    chunks: HashMap<ChunkPos, ChunkSlot>,
+    /// The y index of every chunk in each column of chunks, keyed by the chunks' x and z index
+    columns: HashMap<(i32, i32), OrdSet<i32>>,
+    /// Incremented every time a chunk is modified
+    generation: u64,
+    /// The generation at which each chunk was last modified
+    modified: HashMap<ChunkPos, u64>,
+    /// The chunk every entity is in, keyed by its uuid
+    entities: HashMap<Uuid, ChunkPos>,
+    /// What blocks that have never been set are made of, at each height
+    defaults: Arc<DefaultLayers>,
+    /// Original blocks of the world, used before the default layers if there is one
+    baseline: Option<Arc<dyn BaselineWorld>>,
     chunk_size: usize,
+    chunk_height: usize,
+    provider: Option<Arc<dyn ChunkProvider>>,
+    /// Dictionary attached to every chunk created in this world
+    dictonary: Option<Arc<BlockDictonary>>,
+}
+
+/// The blocks of one chunk visited by get_blocks_in_region and modified_blocks
+type BlockIter = Box<dyn Iterator<Item = io::Result<((i32, i32, i32), MetaBlock)>>>;
+
+/// A chunk of the world, either in memory or evicted to the provider
+#[derive(Clone)]
+enum ChunkSlot {
+    Loaded(Box<Chunk>),
+    /// The chunk's dictionary is not stored by the provider, so it is kept alongside it
+    Evicted(Arc<EvictedChunk>, Option<Arc<BlockDictonary>>),
+}
+
+impl ChunkSlot {
+    /// Returns the chunk, loading it back from the provider if it was evicted
+    fn load(&self) -> io::Result<Chunk> {
+        match self {
+            ChunkSlot::Loaded(chunk) => Ok((**chunk).clone()),
+            ChunkSlot::Evicted(evicted, dictonary) => {
+                let chunk = evicted.load()?;
+                Ok(match dictonary {
+                    Some(dictonary) => chunk.set_dict(dictonary),
+                    None => chunk,
+                })
+            }
+        }
+    }
 }
 
 impl World {
@@ -28,86 +110,1346 @@ impl World {
     ///
     /// Defaults to CHUNK_SIZE chunks
     pub fn new(default_block: MetaBlock) -> World {
-        World {
+        World::with_chunk_size(default_block, CHUNK_SIZE, CHUNK_SIZE)
+            .expect("CHUNK_SIZE is a valid chunk size")
+    }
+
+    /// Creates a new world with the provided default block, whose chunks are the given number
+    /// of blocks tall
+    ///
+    /// Returns None if the height is not a valid chunk size, see with_chunk_size
+    pub fn with_chunk_height(default_block: MetaBlock, chunk_height: usize) -> Option<World> {
+        World::with_chunk_size(default_block, CHUNK_SIZE, chunk_height)
+    }
+
+    /// Creates a new world with the provided default block, whose chunks are chunk_size blocks
+    /// wide along x and z, and chunk_height blocks tall
+    ///
+    /// Returns None if either size is zero, too large to address with i32 coordinates, or not a
+    /// multiple of BIOME_CELL_SIZE, so biome cells never straddle chunks
+    pub fn with_chunk_size(
+        default_block: MetaBlock,
+        chunk_size: usize,
+        chunk_height: usize,
+    ) -> Option<World> {
+        let valid = |size: usize| {
+            size > 0 && size <= i32::MAX as usize && size.is_multiple_of(BIOME_CELL_SIZE)
+        };
+        if !valid(chunk_size) || !valid(chunk_height) {
+            return None;
+        }
+        Some(World {
             chunks: HashMap::new(),
-            default_block: default_block,
-            chunk_size: CHUNK_SIZE,
+            columns: HashMap::new(),
+            generation: 0,
+            modified: HashMap::new(),
+            entities: HashMap::new(),
+            defaults: Arc::new(DefaultLayers::new(default_block)),
+            baseline: None,
+            chunk_size,
+            chunk_height,
+            provider: None,
+            dictonary: None,
+        })
+    }
+
+    /// Returns an independent copy of this world, for trying out edits
+    ///
+    /// Worlds are persistent, so the fork shares every chunk with this world until one of them
+    /// modifies it, and edits to either one are never seen by the other. Forking is cheap no
+    /// matter the size of the world.
+    pub fn fork(&self) -> World {
+        self.clone()
+    }
+
+    /// Sets the provider evicted chunks are handed to
+    pub fn set_chunk_provider(&self, provider: Arc<dyn ChunkProvider>) -> World {
+        World {
+            provider: Some(provider),
+            ..self.clone()
         }
     }
 
-    /// Gets the index of the provided corrdinate
-    pub fn get_chunk_index(&self, x: i32, y: i32) -> (i32, i32) {
-        let chunk_size = self.chunk_size as i32;
-        (x - (x % chunk_size), y - (y % chunk_size))
+    /// Returns the provider evicted chunks are handed to, if any
+    pub fn get_chunk_provider(&self) -> Option<Arc<dyn ChunkProvider>> {
+        self.provider.clone()
     }
 
-    /// Gets the chunk at a specified index
-    pub fn get_chunk_at(&self, x: i32, y: i32) -> Option<Chunk> {
-        let index = self.get_chunk_index(x, y);
-        let result = self.chunks.get(&index);
-        match result {
-            Some(x) => Some((*x).clone()),
+    /// Sets the dictionary attached to chunks created from now on
+    ///
+    /// Chunks that already exist keep the dictionary they were created with.
+    pub fn set_dict(&self, dictonary: &Arc<BlockDictonary>) -> World {
+        World {
+            dictonary: Some(dictonary.clone()),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the dictionary of this world, if it has one
+    pub fn get_dict(&self) -> Option<Arc<BlockDictonary>> {
+        self.dictonary.clone()
+    }
+
+    /// Sets the default block at each height, in place of the single default block
+    ///
+    /// Chunks that already exist keep the defaults they were created with, so this should be
+    /// set before any blocks are.
+    pub fn set_default_layers(&self, layers: DefaultLayers) -> World {
+        World {
+            defaults: Arc::new(layers),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the default block at each height
+    pub fn get_default_layers(&self) -> &DefaultLayers {
+        &self.defaults
+    }
+
+    /// Sets the baseline supplying the original blocks of this world
+    ///
+    /// Like the default layers, chunks that already exist keep the blocks they were created
+    /// with, so this should be set before any blocks are.
+    pub fn set_baseline(&self, baseline: Arc<dyn BaselineWorld>) -> World {
+        World {
+            baseline: Some(baseline),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the baseline supplying the original blocks of this world, if it has one
+    pub fn get_baseline(&self) -> Option<Arc<dyn BaselineWorld>> {
+        self.baseline.clone()
+    }
+
+    /// Returns the block a location that has never been set is made of
+    ///
+    /// That is the block of the baseline, or the default block at that height where there is
+    /// none.
+    pub fn get_default_block_at(&self, x: i32, y: i32, z: i32) -> MetaBlock {
+        original_block(&self.defaults, &self.baseline, x, y, z)
+    }
+
+    /// Returns the ("provider","id") name of the block at the specified location
+    ///
+    /// Returns None if the world has no dictionary, or the block is not in it. Fails like
+    /// get_block_defaulting.
+    pub fn get_block_name(&self, x: i32, y: i32, z: i32) -> io::Result<Option<(String, String)>> {
+        let dictonary = match self.dictonary {
+            Some(ref dictonary) => dictonary,
+            None => return Ok(None),
+        };
+        let block = self.get_block_defaulting(x, y, z)?;
+        Ok(dictonary
+            .try_decode_block(*block.get_block())
+            .map(|(provider, id)| (provider.to_string(), id.to_string())))
+    }
+
+    /// Sets the block at the specified location by its ("provider","id") name, without metadata
+    ///
+    /// Returns None if the world has no dictionary, or the name is not in it. Fails like
+    /// set_block_defaulting.
+    pub fn set_block_by_name(
+        &self,
+        x: i32,
+        y: i32,
+        z: i32,
+        name: (&str, &str),
+    ) -> io::Result<Option<World>> {
+        let block = match self.dictonary {
+            Some(ref dictonary) => dictonary.try_encode_block(name),
             None => None,
+        };
+        match block {
+            Some(block) => {
+                let block = MetaBlock::fuse(block, MetaData::new());
+                self.set_block_defaulting(x, y, z, block).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the number of chunks in the world, whether in memory or evicted
+    pub fn get_chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Returns the index of every chunk currently held in memory
+    pub fn get_loaded_chunks(&self) -> Vec<ChunkPos> {
+        self.chunks
+            .iter()
+            .filter(|(_, slot)| matches!(**slot, ChunkSlot::Loaded(_)))
+            .map(|(index, _)| *index)
+            .collect()
+    }
+
+    /// Returns roughly how many bytes each chunk held in memory takes up, along with the
+    /// generation it was last modified at
+    ///
+    /// Ordered from the least recently modified chunk to the most recently modified one
+    pub fn get_chunk_usage(&self) -> Vec<(ChunkPos, usize, u64)> {
+        let mut usage: Vec<(ChunkPos, usize, u64)> = self
+            .chunks
+            .iter()
+            .filter_map(|(index, slot)| match *slot {
+                ChunkSlot::Loaded(ref chunk) => {
+                    let modified = self.modified.get(&*index).map_or(0, |x| *x);
+                    Some((*index, chunk.memory_estimate(), modified))
+                }
+                ChunkSlot::Evicted(..) => None,
+            })
+            .collect();
+        usage.sort_by_key(|&(index, _, modified)| (modified, index));
+        usage
+    }
+
+    /// Returns roughly how many bytes the chunks held in memory take up
+    pub fn memory_estimate(&self) -> usize {
+        self.get_chunk_usage().iter().map(|x| x.1).sum()
+    }
+
+    /// Evicts the least recently modified chunks in memory to the chunk provider, until the ones
+    /// left take up no more than budget bytes
+    ///
+    /// Fails like evict_chunks
+    pub fn evict_to_budget(&self, budget: usize) -> io::Result<World> {
+        let usage = self.get_chunk_usage();
+        let mut total: usize = usage.iter().map(|x| x.1).sum();
+        let mut evicted = HashSet::new();
+        for (index, size, _) in usage {
+            if total <= budget {
+                break;
+            }
+            total -= size;
+            evicted = evicted.insert(index);
         }
+        if evicted.is_empty() {
+            return Ok(self.clone());
+        }
+        self.evict_chunks(|index| evicted.contains(&index))
+    }
+
+    /// Evicts every chunk in memory whose index matches the predicate to the chunk provider
+    ///
+    /// Views of the world taken before the eviction keep their chunks in memory.
+    ///
+    /// Fails if the world has no chunk provider, or the provider fails to store a chunk
+    pub fn evict_chunks<F>(&self, predicate: F) -> io::Result<World>
+    where
+        F: Fn(ChunkPos) -> bool,
+    {
+        let provider = match self.provider {
+            Some(ref provider) => provider,
+            None => return Err(io::Error::other("world has no chunk provider")),
+        };
+
+        let mut chunks = self.chunks.clone();
+        for (index, slot) in self.chunks.iter() {
+            if let ChunkSlot::Loaded(ref chunk) = *slot {
+                if predicate(*index) {
+                    let evicted = EvictedChunk::evict(chunk, provider)?;
+                    let slot = ChunkSlot::Evicted(Arc::new(evicted), chunk.get_dict());
+                    chunks = chunks.insert(*index, slot);
+                }
+            }
+        }
+
+        Ok(World {
+            chunks,
+            ..self.clone()
+        })
+    }
+
+    /// Returns the current generation of this world
+    ///
+    /// The generation increases every time a chunk is modified, which every committed transaction
+    /// changing this world does, so it can be kept as a marker and later passed to dirty_since.
+    /// It only counts changes to this world, see Rewind::generation for one counting every
+    /// transaction in the worldline.
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the index of every chunk modified after the given generation, in order
+    ///
+    /// Evicting or reloading a chunk does not count as modifying it.
+    pub fn dirty_since(&self, generation: u64) -> Vec<ChunkPos> {
+        let mut dirty: Vec<ChunkPos> = self
+            .modified
+            .iter()
+            .filter(|x| *x.1 > generation)
+            .map(|x| *x.0)
+            .collect();
+        dirty.sort();
+        dirty
+    }
+
+    /// Returns the width of the chunks of this world
+    pub fn get_chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Returns the height of the chunks of this world
+    pub fn get_chunk_height(&self) -> usize {
+        self.chunk_height
+    }
+
+    /// Returns the (x, y, z) dimensions of the chunks of this world
+    pub fn get_chunk_dims(&self) -> (usize, usize, usize) {
+        (self.chunk_size, self.chunk_height, self.chunk_size)
+    }
+
+    /// Gets the index of the chunk containing the provided corrdinate
+    pub fn get_chunk_index(&self, x: i32, y: i32, z: i32) -> ChunkPos {
+        BlockPos::new(x, y, z).get_chunk_pos(self.get_chunk_dims())
+    }
+
+    /// Gets the chunk at a specified index
+    ///
+    /// With a baseline, the blocks of the chunk that have never been set are read from it, which
+    /// reads every block of the chunk. Fails if the chunk was evicted and the chunk provider can
+    /// not load it back.
+    pub fn get_chunk_at(&self, x: i32, y: i32, z: i32) -> io::Result<Option<Chunk>> {
+        let index = self.get_chunk_index(x, y, z);
+        let chunk = match self.load_chunk(index)? {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+        if chunk.get_default_block() != unset_block() {
+            return Ok(Some(chunk));
+        }
+        // Fill in the blocks that have never been set, on top of the default at the bottom of
+        // the chunk
+        let (x_size, y_size, z_size) = self.get_chunk_dims();
+        let origin = index.get_origin((x_size, y_size, z_size)).get_coords();
+        let bottom = self.defaults.get_block(origin.1);
+        let mut filled = chunk.map_blocks(|block| {
+            if *block.get_block() == unset_block() {
+                bottom.clone()
+            } else {
+                block
+            }
+        });
+        for x in 0..x_size {
+            for y in 0..y_size {
+                for z in 0..z_size {
+                    if *chunk.get_block(x, y, z).get_block() != unset_block() {
+                        continue;
+                    }
+                    let (bx, by, bz) = (
+                        origin.0 + x as i32,
+                        origin.1 + y as i32,
+                        origin.2 + z as i32,
+                    );
+                    let block = original_block(&self.defaults, &self.baseline, bx, by, bz);
+                    if block != bottom {
+                        filled = filled.set_block(x, y, z, block);
+                    }
+                }
+            }
+        }
+        Ok(Some(filled))
+    }
+
+    /// Gets the chunk at a specified index as it is stored, without reading the baseline
+    fn load_chunk(&self, index: ChunkPos) -> io::Result<Option<Chunk>> {
+        self.chunks.get(&index).map(|x| x.load()).transpose()
     }
 
     /// Returns true if the chunk at the specificed index exists
-    pub fn has_chunk_at(&self, x: i32, y: i32) -> bool {
-        let index = self.get_chunk_index(x, y);
+    pub fn has_chunk_at(&self, x: i32, y: i32, z: i32) -> bool {
+        let index = self.get_chunk_index(x, y, z);
         self.chunks.contains_key(&index)
     }
 
     /// Takes coordianates and turns them into their in chunks version
     fn convert_coords(&self, x: i32, y: i32, z: i32) -> (usize, usize, usize) {
-        let x = (x.abs() as usize) % self.chunk_size;
-        let y = (y.abs() as usize) % self.chunk_size;
-        let z = (z.abs() as usize) % self.chunk_size;
-        (x, y, z)
+        BlockPos::new(x, y, z).get_offset(self.get_chunk_dims())
     }
 
     /// Gets the block at a specified index, if it exists
-    pub fn get_block_at(&self, x: i32, y: i32, z: i32) -> Option<MetaBlock> {
-        let chunk = self.get_chunk_at(x, y);
-        let (x, y, z) = self.convert_coords(x, y, z);
-        if let Some(chunk) = chunk {
-            Some(chunk.get_block(x, y, z))
-        } else {
-            None
-        }
+    ///
+    /// Fails if the chunk was evicted and the chunk provider can not load it back.
+    pub fn get_block_at(&self, x: i32, y: i32, z: i32) -> io::Result<Option<MetaBlock>> {
+        let chunk = self.load_chunk(self.get_chunk_index(x, y, z))?;
+        let offset = self.convert_coords(x, y, z);
+        let (defaults, baseline) = (&self.defaults, &self.baseline);
+        Ok(chunk.map(|chunk| cell_block(&chunk, defaults, baseline, offset, (x, y, z))))
     }
 
     /// Attempts to get the specified block
     ///
-    /// Will return the default if that block does not exist
-    pub fn get_block_defaulting(&self, x: i32, y: i32, z: i32) -> MetaBlock {
-        let maybe_block = self.get_block_at(x, y, z);
+    /// Will return the default at that height if that block does not exist. Fails like
+    /// get_block_at.
+    pub fn get_block_defaulting(&self, x: i32, y: i32, z: i32) -> io::Result<MetaBlock> {
+        let maybe_block = self.get_block_at(x, y, z)?;
         if let Some(block) = maybe_block {
-            block
+            Ok(block)
         } else {
-            self.default_block
+            Ok(self.get_default_block_at(x, y, z))
+        }
+    }
+
+    /// Returns every block in the box between the two corners, inclusive, along with its
+    /// coordinates
+    ///
+    /// The corners can be given in any order. Blocks are visited one intersecting chunk at a
+    /// time, and each chunk is only looked up, or reloaded from the chunk provider, once. A chunk
+    /// the provider can not load back yields a single error in place of its blocks.
+    pub fn get_blocks_in_region(
+        &self,
+        min: (i32, i32, i32),
+        max: (i32, i32, i32),
+    ) -> impl Iterator<Item = io::Result<((i32, i32, i32), MetaBlock)>> {
+        let region = Region::new(min, max);
+        let (min, max) = (region.get_min(), region.get_max());
+        let dims = self.get_chunk_dims();
+        let low = BlockPos::new(min.0, min.1, min.2).get_chunk_pos(dims);
+        let high = BlockPos::new(max.0, max.1, max.2).get_chunk_pos(dims);
+
+        let mut chunks = Vec::new();
+        for x in low.get_x()..=high.get_x() {
+            for y in low.get_y()..=high.get_y() {
+                for z in low.get_z()..=high.get_z() {
+                    let index = ChunkPos::new(x, y, z);
+                    chunks.push((index, self.chunks.get(&index)));
+                }
+            }
         }
+
+        let originals = (self.defaults.clone(), self.baseline.clone());
+        chunks.into_iter().flat_map(move |(index, slot)| {
+            let chunk = match slot.map(|slot| slot.load()).transpose() {
+                Ok(chunk) => chunk,
+                Err(error) => return Box::new(iter::once(Err(error))) as BlockIter,
+            };
+            let (defaults, baseline) = originals.clone();
+            // Clamp the region to this chunk
+            let origin = index.get_origin(dims).get_coords();
+            let low = (
+                min.0.max(origin.0),
+                min.1.max(origin.1),
+                min.2.max(origin.2),
+            );
+            let high = (
+                max.0.min(origin.0 + (dims.0 - 1) as i32),
+                max.1.min(origin.1 + (dims.1 - 1) as i32),
+                max.2.min(origin.2 + (dims.2 - 1) as i32),
+            );
+
+            let blocks = (low.0..=high.0)
+                .flat_map(move |x| {
+                    (low.1..=high.1).flat_map(move |y| (low.2..=high.2).map(move |z| (x, y, z)))
+                })
+                .map(move |(x, y, z)| {
+                    let block = match chunk {
+                        Some(ref chunk) => {
+                            let offset = (
+                                (x - origin.0) as usize,
+                                (y - origin.1) as usize,
+                                (z - origin.2) as usize,
+                            );
+                            cell_block(chunk, &defaults, &baseline, offset, (x, y, z))
+                        }
+                        None => original_block(&defaults, &baseline, x, y, z),
+                    };
+                    Ok(((x, y, z), block))
+                });
+            Box::new(blocks) as BlockIter
+        })
+    }
+
+    /// Returns every block that differs from the block the location has never been set is made
+    /// of, see get_default_block_at, along with its coordinates
+    ///
+    /// Only visits the blocks that have been set, one chunk at a time, reloading evicted chunks
+    /// from the chunk provider as it goes. With default layers, blocks can be set back to the
+    /// chunk's own default where that is not what they started as, so each chunk is compared
+    /// against the chunk as it was created instead. With a baseline, only the blocks that have
+    /// been set are read from it, to compare them against. A chunk the provider can not load
+    /// back yields a single error in place of its blocks.
+    pub fn modified_blocks(
+        &self,
+    ) -> impl Iterator<Item = io::Result<((i32, i32, i32), MetaBlock)>> {
+        let dims = self.get_chunk_dims();
+        let uniform = self.baseline.is_none() && self.defaults.is_uniform();
+        // Whether the blocks that have been set still have to be compared with the originals
+        let compare = uniform || self.baseline.is_some();
+        let world = self.clone();
+        let chunks: Vec<_> = self
+            .chunks
+            .iter()
+            .map(|(index, slot)| (*index, slot))
+            .collect();
+
+        chunks.into_iter().flat_map(move |(index, slot)| {
+            let chunk = match slot.load() {
+                Ok(chunk) => chunk,
+                Err(error) => return Box::new(iter::once(Err(error))) as BlockIter,
+            };
+            let changes = if uniform || chunk.get_default_block() == unset_block() {
+                chunk.modified_blocks()
+            } else {
+                world.empty_chunk(index).diff(&chunk).get_changes().to_vec()
+            };
+            let world = world.clone();
+            let blocks = changes
+                .into_iter()
+                .map(move |(offset, block)| (index.get_block_pos(offset, dims).get_coords(), block))
+                .filter(move |&((x, y, z), ref block)| {
+                    !compare || *block != world.get_default_block_at(x, y, z)
+                })
+                .map(Ok);
+            Box::new(blocks) as BlockIter
+        })
+    }
+
+    /// Counts how many of each block the world is made of, in total and per chunk
+    ///
+    /// Only visits the blocks that have been set, the remaining blocks of each chunk are counted
+    /// as the chunk's default. With a baseline, the remaining blocks are read from it instead, so
+    /// every block is visited. Fails if an evicted chunk can not be loaded back.
+    pub fn block_counts(&self) -> io::Result<BlockCounts> {
+        let mut counts = BlockCounts::new();
+        for (index, slot) in self.chunks.iter() {
+            let chunk = slot.load()?;
+            let (x_size, y_size, z_size) = chunk.get_size();
+            if chunk.get_default_block() == unset_block() {
+                let origin = index.get_origin((x_size, y_size, z_size)).get_coords();
+                for x in 0..x_size {
+                    for y in 0..y_size {
+                        for z in 0..z_size {
+                            let coords = (
+                                origin.0 + x as i32,
+                                origin.1 + y as i32,
+                                origin.2 + z as i32,
+                            );
+                            let block = cell_block(
+                                &chunk,
+                                &self.defaults,
+                                &self.baseline,
+                                (x, y, z),
+                                coords,
+                            );
+                            counts.add(*index, block, 1);
+                        }
+                    }
+                }
+                continue;
+            }
+            let modified = chunk.modified_blocks();
+            let default_block = MetaBlock::fuse(chunk.get_default_block(), MetaData::new());
+            counts.add(
+                *index,
+                default_block,
+                x_size * y_size * z_size - modified.len(),
+            );
+            for (_, block) in modified {
+                counts.add(*index, block, 1);
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Returns the height of the highest block in the column that differs from the default, along
+    /// with the block itself
+    ///
+    /// Uses the heightmaps of the chunks in the column, from the top down, rather than scanning
+    /// the column. None if every block in the column is the default.
+    ///
+    /// With default layers, a layer that differs from the default at the bottom of its chunk
+    /// counts as set. With a baseline, the column of each chunk is scanned instead, reading the
+    /// blocks that have never been set from it. Fails if an evicted chunk of the column can not be
+    /// loaded back.
+    pub fn highest_block(&self, x: i32, z: i32) -> io::Result<Option<(i32, MetaBlock)>> {
+        let index = self.get_chunk_index(x, 0, z);
+        let (cx, _, cz) = self.convert_coords(x, 0, z);
+        let ys: Vec<i32> = match self.columns.get(&(index.get_x(), index.get_z())) {
+            Some(ys) => ys.iter().map(|y| *y).collect(),
+            None => return Ok(None),
+        };
+
+        for y in ys.into_iter().rev() {
+            let chunk = match self
+                .chunks
+                .get(&ChunkPos::new(index.get_x(), y, index.get_z()))
+            {
+                Some(slot) => slot.load()?,
+                None => continue,
+            };
+            if chunk.get_default_block() == unset_block() {
+                let bottom = y * self.chunk_height as i32;
+                let default_block = self.defaults.get_block(bottom);
+                for height in (0..self.chunk_height).rev() {
+                    let coords = (x, bottom + height as i32, z);
+                    let offset = (cx, height, cz);
+                    let block = cell_block(&chunk, &self.defaults, &self.baseline, offset, coords);
+                    if block != default_block {
+                        return Ok(Some((coords.1, block)));
+                    }
+                }
+                continue;
+            }
+            if let Some(height) = chunk.get_height(cx, cz) {
+                let y = y * self.chunk_height as i32 + height as i32;
+                return Ok(Some((y, chunk.get_block(cx, height, cz))));
+            }
+        }
+        Ok(None)
     }
 
     /// Returns true if the chunk a block is in exists, false otherwise
-    pub fn block_exists(&self, x: i32, y: i32, _z: i32) -> bool {
-        let (x, y) = self.get_chunk_index(x, y);
-        self.has_chunk_at(x, y)
+    pub fn block_exists(&self, x: i32, y: i32, z: i32) -> bool {
+        self.has_chunk_at(x, y, z)
     }
 
     /// Sets the block at the specified location, creating the chunk if it
     /// doesnt exist
-    pub fn set_block_defaulting(&self, x: i32, y: i32, z: i32, block: MetaBlock) -> World {
-        let index = self.get_chunk_index(x, y);
+    ///
+    /// Fails if the chunk was evicted and the chunk provider can not load it back.
+    pub fn set_block_defaulting(
+        &self,
+        x: i32,
+        y: i32,
+        z: i32,
+        block: MetaBlock,
+    ) -> io::Result<World> {
         let (cx, cy, cz) = self.convert_coords(x, y, z);
-        let empty_chunk = Chunk::new(*self.default_block.get_block());
-        let old_chunk = self.chunks.get(&index).unwrap_or(Arc::new(empty_chunk));
-        let new_chunks = self.chunks
-            .insert(index, old_chunk.set_block(cx, cy, cz, block));
+        self.update_chunk(x, y, z, |chunk| chunk.set_block(cx, cy, cz, block))
+    }
+
+    /// Gets the biome of the cell containing the specified location
+    ///
+    /// Cells that have never been set have the default biome. Fails like get_block_at.
+    pub fn get_biome(&self, x: i32, y: i32, z: i32) -> io::Result<Biome> {
+        let (cx, cy, cz) = self.convert_coords(x, y, z);
+        Ok(match self.load_chunk(self.get_chunk_index(x, y, z))? {
+            Some(chunk) => chunk.get_biome(cx, cy, cz),
+            None => Biome::default(),
+        })
+    }
+
+    /// Sets the biome of the cell containing the specified location, creating the chunk if it
+    /// doesnt exist
+    ///
+    /// Fails like set_block_defaulting.
+    pub fn set_biome(&self, x: i32, y: i32, z: i32, biome: Biome) -> io::Result<World> {
+        let (cx, cy, cz) = self.convert_coords(x, y, z);
+        self.update_chunk(x, y, z, |chunk| chunk.set_biome(cx, cy, cz, biome))
+    }
+
+    /// Returns the entity with the given uuid, if it is in this world
+    ///
+    /// Fails if its chunk was evicted and the chunk provider can not load it back.
+    pub fn get_entity(&self, id: Uuid) -> io::Result<Option<Entity>> {
+        let slot = match self.entities.get(&id) {
+            Some(index) => self.chunks.get(&*index),
+            None => None,
+        };
+        match slot {
+            Some(slot) => Ok(slot.load()?.get_entity(id)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the index of the chunk the entity with the given uuid is in, if it is in this world
+    pub fn get_entity_chunk(&self, id: Uuid) -> Option<ChunkPos> {
+        self.entities.get(&id).map(|x| *x)
+    }
+
+    /// Returns the number of entities in this world
+    pub fn get_entity_count(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Returns every entity in the box between the two corners, inclusive, ordered by uuid
+    ///
+    /// Entities are in the box if the block they are in is. Only the chunks holding entities
+    /// are looked up, or reloaded from the chunk provider, and it fails if one of them can not be
+    /// loaded back.
+    pub fn get_entities_in_region(
+        &self,
+        min: (i32, i32, i32),
+        max: (i32, i32, i32),
+    ) -> io::Result<Vec<Entity>> {
+        let region = Region::new(min, max);
+        let (min, max) = (region.get_min(), region.get_max());
+        let low = self.get_chunk_index(min.0, min.1, min.2);
+        let high = self.get_chunk_index(max.0, max.1, max.2);
+        let indices: OrdSet<ChunkPos> = self
+            .entities
+            .values()
+            .filter(|index| {
+                (low.get_x()..=high.get_x()).contains(&index.get_x())
+                    && (low.get_y()..=high.get_y()).contains(&index.get_y())
+                    && (low.get_z()..=high.get_z()).contains(&index.get_z())
+            })
+            .map(|index| *index)
+            .collect();
+        let mut entities = Vec::new();
+        for slot in indices.iter().filter_map(|index| self.chunks.get(&*index)) {
+            entities.extend(slot.load()?.get_entities().into_iter().filter(|entity| {
+                let (x, y, z) = entity.get_block_coords();
+                region.contains(x, y, z)
+            }));
+        }
+        entities.sort_by_key(|x| x.get_id());
+        Ok(entities)
+    }
 
+    /// Adds an entity to the chunk containing its position, creating the chunk if it doesnt
+    /// exist
+    ///
+    /// Replaces the entity with the same uuid, moving it to its new chunk if it was in another.
+    /// Fails if either chunk was evicted and the chunk provider can not load it back.
+    pub fn set_entity(&self, entity: Entity) -> io::Result<World> {
+        let id = entity.get_id();
+        let (x, y, z) = entity.get_block_coords();
+        let index = self.get_chunk_index(x, y, z);
+        let world = match self.entities.get(&id) {
+            Some(old) if *old != index => self.remove_entity(id)?,
+            _ => self.clone(),
+        };
+        let world = world.update_chunk(x, y, z, |chunk| chunk.set_entity(entity))?;
+        Ok(World {
+            entities: world.entities.insert(id, index),
+            ..world
+        })
+    }
+
+    /// Removes the entity with the given uuid from this world, if it is in it
+    ///
+    /// Fails like get_entity.
+    pub fn remove_entity(&self, id: Uuid) -> io::Result<World> {
+        let index = match self.entities.get(&id) {
+            Some(index) => *index,
+            None => return Ok(self.clone()),
+        };
+        let (x, y, z) = index.get_origin(self.get_chunk_dims()).get_coords();
+        let world = self.update_chunk(x, y, z, |chunk| chunk.remove_entity(id))?;
+        Ok(World {
+            entities: world.entities.remove(&id),
+            ..world
+        })
+    }
+
+    /// Returns a copy of this world with every block passed through f
+    ///
+    /// Evicted chunks are loaded, and every chunk counts as modified. The baseline is left as it
+    /// is, so blocks that have never been set are not mapped where they are read from it. Fails if
+    /// an evicted chunk can not be loaded back.
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> io::Result<World> {
+        let generation = self.generation + 1;
+        let mut chunks = HashMap::new();
+        let mut modified = HashMap::new();
+        for (index, slot) in self.chunks.iter() {
+            let chunk = slot.load()?.map_blocks(|block| {
+                if *block.get_block() == unset_block() {
+                    block
+                } else {
+                    f(block)
+                }
+            });
+            chunks = chunks.insert(*index, ChunkSlot::Loaded(Box::new(chunk)));
+            modified = modified.insert(*index, generation);
+        }
+        Ok(World {
+            chunks,
+            generation,
+            modified,
+            defaults: Arc::new(self.defaults.map_blocks(&f)),
+            ..self.clone()
+        })
+    }
+
+    /// Returns a copy of this world with the chunk at the given index taken from another world
+    ///
+    /// Lets a chunk be updated on an older view of the world, then installed into a newer one,
+    /// as long as nothing else modified that chunk in between. Both worlds must have the same
+    /// chunk size. Does nothing if the other world does not have the chunk.
+    ///
+    /// The entities of the chunk come along with it. An entity the other world moved into it
+    /// from a chunk that is not taken along is in both chunks, and looked up in this one, so a
+    /// writer moving entities must keep the chunks they move out of from being installed over.
+    pub fn with_chunk_from(&self, other: &World, index: ChunkPos) -> World {
+        let slot = match other.chunks.get(&index) {
+            Some(slot) => (*slot).clone(),
+            None => return self.clone(),
+        };
+        let column = (index.get_x(), index.get_z());
+        let ys = self
+            .columns
+            .get(&column)
+            .map(|x| (*x).clone())
+            .unwrap_or_default();
+        let mut entities = self.entities.clone();
+        for (id, at) in self.entities.iter() {
+            if *at == index {
+                entities = entities.remove(&*id);
+            }
+        }
+        for (id, at) in other.entities.iter() {
+            if *at == index {
+                entities = entities.insert(*id, index);
+            }
+        }
+        let generation = self.generation + 1;
         World {
-            chunks: new_chunks,
-            default_block: self.default_block,
-            chunk_size: self.chunk_size,
+            chunks: self.chunks.insert(index, slot),
+            columns: self.columns.insert(column, ys.insert(index.get_y())),
+            generation,
+            modified: self.modified.insert(index, generation),
+            entities,
+            ..self.clone()
+        }
+    }
+
+    /// Creates the chunk at the given index, as it is before it has been written to
+    ///
+    /// The chunk's own default is the default block at its lowest height, and the layers of any
+    /// other default block it spans are set explicitly. With a baseline, the chunk is empty
+    /// instead, every block of it being read from the baseline until it is set, see cell_block.
+    fn empty_chunk(&self, index: ChunkPos) -> Chunk {
+        let (x_size, y_size, z_size) = self.get_chunk_dims();
+        let chunk = if self.baseline.is_some() {
+            Chunk::with_size(unset_block(), x_size, y_size, z_size)
+        } else {
+            let bottom = index.get_y() * y_size as i32;
+            let default_block = self.defaults.get_block(bottom);
+            let mut chunk = Chunk::with_size(*default_block.get_block(), x_size, y_size, z_size);
+            let top = bottom + y_size as i32 - 1;
+            let layers = self.defaults.get_layers();
+            for (i, &(min_y, ref block)) in layers.iter().enumerate() {
+                let max_y = layers.get(i + 1).map_or(top, |x| x.0 - 1).min(top);
+                if max_y < bottom || min_y > top || *block == default_block {
+                    continue;
+                }
+                let low = (min_y.max(bottom) - bottom) as usize;
+                chunk = chunk.fill_layers(low, (max_y - bottom) as usize, block.clone());
+            }
+            chunk
+        };
+        match self.dictonary {
+            Some(ref dictonary) => chunk.set_dict(dictonary),
+            None => chunk,
+        }
+    }
+
+    /// Makes sure every chunk intersecting the region is held in memory
+    ///
+    /// Chunks that do not exist yet are created, and evicted ones are loaded back from the
+    /// provider, so a large edit of the region does not pay for either halfway through. Neither
+    /// counts as modifying the chunk, see dirty_since. Fails if an evicted chunk can not be loaded
+    /// back.
+    pub fn ensure_chunks(&self, region: Region) -> io::Result<World> {
+        let (min_x, min_y, min_z) = region.get_min();
+        let (max_x, max_y, max_z) = region.get_max();
+        let min = self.get_chunk_index(min_x, min_y, min_z);
+        let max = self.get_chunk_index(max_x, max_y, max_z);
+        let mut chunks = self.chunks.clone();
+        let mut columns = self.columns.clone();
+        for x in min.get_x()..=max.get_x() {
+            for z in min.get_z()..=max.get_z() {
+                let mut ys = columns
+                    .get(&(x, z))
+                    .map(|x| (*x).clone())
+                    .unwrap_or_default();
+                for y in min.get_y()..=max.get_y() {
+                    let index = ChunkPos::new(x, y, z);
+                    let chunk = match chunks.get(&index) {
+                        Some(slot) => match *slot {
+                            ChunkSlot::Loaded(_) => continue,
+                            ChunkSlot::Evicted(..) => slot.load()?,
+                        },
+                        None => self.empty_chunk(index),
+                    };
+                    chunks = chunks.insert(index, ChunkSlot::Loaded(Box::new(chunk)));
+                    ys = ys.insert(y);
+                }
+                columns = columns.insert((x, z), ys);
+            }
+        }
+        Ok(World {
+            chunks,
+            columns,
+            ..self.clone()
+        })
+    }
+
+    /// Replaces the chunk containing the specified location with an updated version of itself,
+    /// creating the chunk if it doesnt exist
+    fn update_chunk<F>(&self, x: i32, y: i32, z: i32, update: F) -> io::Result<World>
+    where
+        F: FnOnce(&Chunk) -> Chunk,
+    {
+        let index = self.get_chunk_index(x, y, z);
+        let mut columns = self.columns.clone();
+        let old_chunk = match self.chunks.get(&index) {
+            Some(slot) => slot.load()?,
+            None => {
+                let column = (index.get_x(), index.get_z());
+                let ys = columns
+                    .get(&column)
+                    .map(|x| (*x).clone())
+                    .unwrap_or_default();
+                columns = columns.insert(column, ys.insert(index.get_y()));
+                self.empty_chunk(index)
+            }
+        };
+        let new_chunk = ChunkSlot::Loaded(Box::new(update(&old_chunk)));
+        let generation = self.generation + 1;
+
+        Ok(World {
+            chunks: self.chunks.insert(index, new_chunk),
+            columns,
+            generation,
+            modified: self.modified.insert(index, generation),
+            ..self.clone()
+        })
+    }
+}
+
+/// The default block of the chunks of a world with a baseline
+///
+/// Stands for a block that has never been set, which is read from the baseline whenever it is
+/// needed, rather than copied into the chunk when it is created.
+fn unset_block() -> Block {
+    Block::new_from_ids(u16::MAX, u16::MAX)
+}
+
+/// Returns the block at an offset into a chunk, or the block its coordinates are originally made
+/// of if it has never been set
+fn cell_block(
+    chunk: &Chunk,
+    defaults: &DefaultLayers,
+    baseline: &Option<Arc<dyn BaselineWorld>>,
+    (x, y, z): (usize, usize, usize),
+    (bx, by, bz): (i32, i32, i32),
+) -> MetaBlock {
+    let block = chunk.get_block(x, y, z);
+    if *block.get_block() == unset_block() {
+        original_block(defaults, baseline, bx, by, bz)
+    } else {
+        block
+    }
+}
+
+/// Returns the block a location that has never been set is made of, from the baseline if it
+/// has one there, or else the default layers
+fn original_block(
+    defaults: &DefaultLayers,
+    baseline: &Option<Arc<dyn BaselineWorld>>,
+    x: i32,
+    y: i32,
+    z: i32,
+) -> MetaBlock {
+    baseline
+        .as_ref()
+        .and_then(|baseline| baseline.get_block(x, y, z))
+        .unwrap_or_else(|| defaults.get_block(y))
+}
+
+/// A read-only view of a world, along with the worldline generation it reflects
+///
+/// Obtaining and cloning one is cheap, as the world is shared behind an Arc, and nothing can
+/// change it, so it can be polled and handed between threads freely.
+#[derive(Clone)]
+pub struct WorldSnapshot {
+    world: Arc<World>,
+    generation: u64,
+}
+
+impl WorldSnapshot {
+    /// Creates a new snapshot of the world, as it was at the given generation
+    pub fn new(world: Arc<World>, generation: u64) -> WorldSnapshot {
+        WorldSnapshot { world, generation }
+    }
+
+    /// Returns the world
+    pub fn get_world(&self) -> &World {
+        &self.world
+    }
+
+    /// Returns the generation of the worldline the world reflects, see Rewind::generation
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns true if this snapshot reflects a later generation than the other one
+    pub fn is_newer_than(&self, other: &WorldSnapshot) -> bool {
+        self.generation > other.generation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn chunk_sizes() {
+        assert_eq!(
+            World::with_chunk_height(block(0), 64)
+                .unwrap()
+                .get_chunk_dims(),
+            (CHUNK_SIZE, 64, CHUNK_SIZE)
+        );
+        assert!(World::with_chunk_height(block(0), 10).is_none());
+        assert!(World::with_chunk_height(block(0), 0).is_none());
+        assert!(World::with_chunk_size(block(0), 6, 4).is_none());
+    }
+
+    #[test]
+    fn blocks_in_region() {
+        let world = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_block_defaulting(-1, 0, 3, block(1))
+            .unwrap()
+            .set_block_defaulting(4, 2, -5, block(2))
+            .unwrap()
+            .set_block_defaulting(9, 0, 0, block(3))
+            .unwrap();
+
+        let blocks: Vec<_> = world
+            .get_blocks_in_region((5, 2, 3), (-2, 0, -5))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(blocks.len(), 8 * 3 * 9);
+        for &((x, y, z), ref found) in &blocks {
+            assert!(*found == world.get_block_defaulting(x, y, z).unwrap());
         }
+        let set: Vec<_> = blocks.iter().filter(|x| x.1 != block(0)).collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn modified_blocks() {
+        let world = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_block_defaulting(-1, 0, 3, block(1))
+            .unwrap()
+            .set_block_defaulting(9, 0, 0, block(3))
+            .unwrap()
+            .set_block_defaulting(9, 0, 0, block(0))
+            .unwrap();
+
+        let modified: Vec<_> = world.modified_blocks().map(Result::unwrap).collect();
+        assert_eq!(modified.len(), 1);
+        assert!(modified[0] == ((-1, 0, 3), block(1)));
+    }
+
+    #[test]
+    fn highest_block() {
+        let world = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_block_defaulting(-1, -6, 3, block(1))
+            .unwrap()
+            .set_block_defaulting(-1, 9, 3, block(2))
+            .unwrap()
+            .set_block_defaulting(-1, 2, 3, block(3))
+            .unwrap();
+        assert!(world.highest_block(-1, 3).unwrap() == Some((9, block(2))));
+        assert!(world.highest_block(-1, 2).unwrap().is_none());
+
+        let world = world.set_block_defaulting(-1, 9, 3, block(0)).unwrap();
+        assert!(world.highest_block(-1, 3).unwrap() == Some((2, block(3))));
+    }
+
+    #[test]
+    fn block_counts() {
+        let world = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_block_defaulting(-1, 0, 3, block(1))
+            .unwrap()
+            .set_block_defaulting(-2, 0, 3, block(1))
+            .unwrap()
+            .set_block_defaulting(9, 0, 0, block(2))
+            .unwrap();
+
+        let counts = world.block_counts().unwrap();
+        assert_eq!(counts.get_count(block(1)), 2);
+        assert_eq!(counts.get_count(block(2)), 1);
+        assert_eq!(counts.get_count(block(0)), 2 * 64 - 3);
+        let chunk = counts.get_chunk_counts(ChunkPos::new(-1, 0, 0)).unwrap();
+        assert_eq!(chunk.get(&block(1)), Some(&2));
+    }
+
+    #[test]
+    fn dirty_since() {
+        let world = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_block_defaulting(0, 0, 0, block(1))
+            .unwrap()
+            .set_block_defaulting(5, 0, 0, block(1))
+            .unwrap();
+        assert_eq!(world.dirty_since(0).len(), 2);
+
+        let mark = world.get_generation();
+        assert!(world.dirty_since(mark).is_empty());
+        let world = world
+            .set_block_defaulting(6, 0, 0, block(2))
+            .unwrap()
+            .set_biome(0, -1, 0, Biome::new(3))
+            .unwrap();
+        let dirty = world.dirty_since(mark);
+        assert!(dirty == vec![ChunkPos::new(0, -1, 0), ChunkPos::new(1, 0, 0)]);
+    }
+
+    #[test]
+    fn evict_to_budget() {
+        let provider = Arc::new(MemoryChunkProvider::new());
+        let world = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_chunk_provider(provider.clone())
+            .set_block_defaulting(0, 0, 0, block(1))
+            .unwrap()
+            .set_block_defaulting(4, 0, 0, block(2))
+            .unwrap()
+            .set_block_defaulting(8, 0, 0, block(3))
+            .unwrap()
+            .set_block_defaulting(1, 0, 0, block(4))
+            .unwrap();
+        let usage = world.get_chunk_usage();
+        assert!(usage[0].0 == ChunkPos::new(1, 0, 0));
+        assert!(usage[2].0 == ChunkPos::new(0, 0, 0));
+
+        // Only the most recently modified chunk fits
+        let budget = usage[2].1;
+        let evicted = world.evict_to_budget(budget).unwrap();
+        assert!(evicted.memory_estimate() <= budget);
+        assert!(evicted.get_loaded_chunks() == vec![ChunkPos::new(0, 0, 0)]);
+        assert_eq!(provider.len(), 2);
+        assert!(evicted.get_block_defaulting(4, 0, 0).unwrap() == block(2));
+        assert!(
+            world
+                .evict_to_budget(usize::MAX)
+                .unwrap()
+                .get_loaded_chunks()
+                .len()
+                == 3
+        );
+        assert!(World::new(block(0)).evict_to_budget(0).is_ok());
+    }
+
+    #[test]
+    fn ensure_chunks() {
+        let provider = Arc::new(MemoryChunkProvider::new());
+        let world = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_chunk_provider(provider.clone())
+            .set_block_defaulting(0, 0, 0, block(1))
+            .unwrap()
+            .evict_chunks(|_| true)
+            .unwrap();
+        let mark = world.get_generation();
+
+        let ensured = world
+            .ensure_chunks(Region::new((-1, 0, 0), (3, 5, 3)))
+            .unwrap();
+        let mut loaded = ensured.get_loaded_chunks();
+        loaded.sort();
+        assert_eq!(loaded.len(), 4);
+        assert!(loaded[0] == ChunkPos::new(-1, 0, 0));
+        assert!(ensured.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        assert!(ensured.highest_block(0, 0).unwrap() == Some((0, block(1))));
+        assert!(ensured.dirty_since(mark).is_empty());
+        assert!(world.get_loaded_chunks().is_empty());
+    }
+
+    #[test]
+    fn entities() {
+        let zombie = Entity::new(Uuid::new_v4(), "minecraft:zombie", (1.5, 0.0, 1.5));
+        let frame = Entity::new(Uuid::new_v4(), "minecraft:item_frame", (-0.5, 2.0, 3.0));
+        let empty = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_chunk_provider(Arc::new(MemoryChunkProvider::new()));
+        let world = empty
+            .set_entity(zombie.clone())
+            .unwrap()
+            .set_entity(frame.clone())
+            .unwrap();
+        assert_eq!(world.get_entity_count(), 2);
+        assert!(world.get_entity(frame.get_id()).unwrap() == Some(frame.clone()));
+        assert!(
+            world.get_entities_in_region((0, 0, 0), (3, 3, 3)).unwrap() == vec![zombie.clone()]
+        );
+        assert_eq!(
+            world
+                .get_entities_in_region((-1, 0, 0), (1, 2, 3))
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // Moving an entity to another chunk takes it out of the old one
+        let moved = zombie.set_position((9.0, 0.0, 1.0));
+        let world = world.set_entity(moved.clone()).unwrap();
+        assert!(world.get_entity(zombie.get_id()).unwrap() == Some(moved.clone()));
+        assert_eq!(
+            world
+                .get_chunk_at(0, 0, 0)
+                .unwrap()
+                .unwrap()
+                .get_entity_count(),
+            0
+        );
+        assert!(world.get_entities_in_region((8, 0, 0), (11, 3, 3)).unwrap() == vec![moved]);
+
+        // Entities survive eviction, and chunks taken from another world bring theirs along
+        let world = world.evict_chunks(|_| true).unwrap();
+        assert!(world.get_entity(frame.get_id()).unwrap() == Some(frame.clone()));
+        let removed = world.remove_entity(frame.get_id()).unwrap();
+        assert!(removed.get_entity(frame.get_id()).unwrap().is_none());
+        assert_eq!(removed.get_entity_count(), 1);
+        let index = world.get_chunk_index(-1, 2, 3);
+        let restored = removed.with_chunk_from(&world, index);
+        assert!(restored.get_entity(frame.get_id()).unwrap() == Some(frame));
+        assert_eq!(empty.get_entity_count(), 0);
+    }
+
+    #[test]
+    fn default_layers() {
+        let mut layers = DefaultLayers::new(block(0));
+        layers
+            .add_layer(0, block(7))
+            .add_layer(1, block(1))
+            .add_layer(6, block(0));
+        let world = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_default_layers(layers);
+        assert!(world.get_block_defaulting(0, -1, 0).unwrap() == block(0));
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(7));
+        assert!(world.get_block_defaulting(0, 5, 0).unwrap() == block(1));
+        assert!(world.get_block_defaulting(0, 6, 0).unwrap() == block(0));
+
+        // New chunks are made of the layers they span
+        let world = world.set_block_defaulting(0, 2, 0, block(2)).unwrap();
+        assert!(world.get_block_defaulting(1, 0, 1).unwrap() == block(7));
+        assert!(world.get_block_defaulting(1, 3, 1).unwrap() == block(1));
+        let world = world.set_block_defaulting(0, 5, 0, block(0)).unwrap();
+        assert!(world.get_block_defaulting(1, 5, 1).unwrap() == block(1));
+        assert!(world.get_block_defaulting(1, 6, 1).unwrap() == block(0));
+        // Setting a block to its chunk's own default still counts as a change
+        let world = world.set_block_defaulting(0, 3, 0, block(7)).unwrap();
+        let mut modified: Vec<_> = world.modified_blocks().map(Result::unwrap).collect();
+        modified.sort_by_key(|x| x.0);
+        assert!(
+            modified
+                == vec![
+                    ((0, 2, 0), block(2)),
+                    ((0, 3, 0), block(7)),
+                    ((0, 5, 0), block(0))
+                ]
+        );
+        let region: Vec<_> = world
+            .get_blocks_in_region((0, -1, 0), (0, 9, 0))
+            .map(Result::unwrap)
+            .map(|x| x.1)
+            .collect();
+        assert!(region[0] == block(0) && region[1] == block(7) && region[10] == block(0));
+    }
+
+    #[test]
+    fn baseline() {
+        let baseline = |x: i32, y: i32, _| {
+            if y < 0 {
+                Some(block(4 + x.rem_euclid(2) as u16))
+            } else {
+                None
+            }
+        };
+        let world = World::with_chunk_size(block(0), 4, 4)
+            .unwrap()
+            .set_baseline(Arc::new(baseline));
+        assert!(world.get_block_defaulting(1, -1, 0).unwrap() == block(5));
+        assert!(world.get_block_defaulting(1, 0, 0).unwrap() == block(0));
+
+        // New chunks are read from the baseline, and only changes count as modified
+        let world = world.set_block_defaulting(0, -2, 0, block(1)).unwrap();
+        assert!(world.get_block_at(2, -1, 3).unwrap() == Some(block(4)));
+        assert!(world.get_block_at(3, -4, 3).unwrap() == Some(block(5)));
+        assert!(
+            world
+                .modified_blocks()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+                == vec![((0, -2, 0), block(1))]
+        );
+        let region: Vec<_> = world
+            .get_blocks_in_region((1, -1, 5), (1, 0, 5))
+            .map(Result::unwrap)
+            .map(|x| x.1)
+            .collect();
+        assert!(region == vec![block(5), block(0)]);
+        let chunk = world.get_chunk_at(2, -1, 3).unwrap().unwrap();
+        assert!(chunk.get_block(2, 3, 3) == block(4) && chunk.get_block(0, 2, 0) == block(1));
+        assert!(chunk.get_default_block() == *block(0).get_block());
+    }
+
+    #[test]
+    fn baseline_read_lazily() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let counter = reads.clone();
+        let baseline = move |_, y: i32, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if y < 0 {
+                Some(block(5))
+            } else {
+                None
+            }
+        };
+        let world = World::new(block(0))
+            .set_baseline(Arc::new(baseline))
+            .set_block_defaulting(0, -1, 0, block(1))
+            .unwrap()
+            .set_block_defaulting(1, -1, 0, block(5))
+            .unwrap();
+        assert!(world.get_block_defaulting(0, -1, 0).unwrap() == block(1));
+        assert!(world.get_block_defaulting(2, -1, 0).unwrap() == block(5));
+        assert!(world.get_block_defaulting(2, 0, 0).unwrap() == block(0));
+        let modified: Vec<_> = world.modified_blocks().map(Result::unwrap).collect();
+        assert!(modified == vec![((0, -1, 0), block(1))]);
+        assert!(world.highest_block(3, 3).unwrap() == Some((-1, block(5))));
+
+        // Only the blocks read above were looked up, not the whole chunk
+        assert!(reads.load(Ordering::SeqCst) < 10);
+    }
+
+    #[test]
+    fn dictionary_names() {
+        let mut table = BlockTable::new("minecraft");
+        table.add_name("air");
+        table.add_name("stone");
+        let mut dictonary = BlockDictonary::new();
+        dictonary.add_table(table);
+        let dictonary = Arc::new(dictonary);
+
+        let world = World::with_chunk_size(block(0), 4, 4).unwrap();
+        assert!(world
+            .set_block_by_name(0, 0, 0, ("minecraft", "stone"))
+            .unwrap()
+            .is_none());
+        let world = world.set_dict(&dictonary);
+        assert!(world
+            .set_block_by_name(0, 0, 0, ("minecraft", "dirt"))
+            .unwrap()
+            .is_none());
+        let world = world
+            .set_block_by_name(0, 0, 0, ("minecraft", "stone"))
+            .unwrap()
+            .unwrap();
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        let name = world.get_block_name(0, 0, 0).unwrap().unwrap();
+        assert_eq!((name.0.as_str(), name.1.as_str()), ("minecraft", "stone"));
+        assert!(world
+            .get_chunk_at(0, 0, 0)
+            .unwrap()
+            .unwrap()
+            .get_dict()
+            .is_some());
     }
 }
@@ -0,0 +1,161 @@
+//! Provides guards that can veto transactions before they are committed
+//!
+//! Guards let plugins, such as region protection, enforce who may edit what at the level of the
+//! engine, so no path around the plugin can change a protected block. Every guard is consulted
+//! before a transaction is committed, and any one of them can veto it. Vetoed transactions are
+//! rejected, and show up on the stream of rejections with the reason Vetoed.
+//!
+//! Guards only see transactions applied to this Rewind. Transactions merged in from other nodes
+//! have already been committed elsewhere, and are not checked again.
+
+use data::*;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// A transaction about to be committed, as seen by a guard
+///
+/// For an Undo, the world and coordinates are those of the transaction it ultimately undoes, as
+/// that is where the Undo changes the world.
+#[derive(Clone, Debug)]
+pub struct Attempt {
+    transaction: RawTransaction,
+    target: RawTransaction,
+}
+
+impl Attempt {
+    /// Creates an attempt to commit a transaction, changing whatever target affects
+    pub fn new(transaction: RawTransaction, target: RawTransaction) -> Attempt {
+        Attempt {
+            transaction,
+            target,
+        }
+    }
+
+    /// Returns the transaction being committed
+    pub fn get_transaction(&self) -> RawTransaction {
+        self.transaction.clone()
+    }
+
+    /// Returns the owner of the transaction being committed
+    pub fn get_owner(&self) -> Uuid {
+        self.transaction.get_owner()
+    }
+
+    /// Returns the type of the transaction being committed
+    pub fn get_transaction_type(&self) -> &TransactionType {
+        self.transaction.get_transaction_type()
+    }
+
+    /// Returns the world the transaction changes
+    pub fn get_world(&self) -> WorldId {
+        self.target.get_world()
+    }
+
+    /// Returns the coordinates of the block the transaction changes, if any
+    ///
+    /// An Explode changes many blocks, and only says where it went off here, see
+    /// get_affected_coords
+    pub fn get_coords(&self) -> Option<(i32, i32, i32)> {
+        self.target.get_coords()
+    }
+
+    /// Returns the coordinates of every block the transaction changes
+    pub fn get_affected_coords(&self) -> Vec<(i32, i32, i32)> {
+        self.target.get_affected_coords()
+    }
+}
+
+/// Decides whether transactions may be committed
+pub trait TransactionGuard: Send + Sync {
+    /// Returns true if the transaction may be committed, false to veto it
+    fn allows(&self, attempt: &Attempt) -> bool;
+}
+
+/// Custom guards can be written as closures
+impl<F> TransactionGuard for F
+where
+    F: Fn(&Attempt) -> bool + Send + Sync,
+{
+    fn allows(&self, attempt: &Attempt) -> bool {
+        self(attempt)
+    }
+}
+
+/// Protects claimed regions, so only their members can edit them
+///
+/// Transactions outside of every claim are allowed. Inside a claim, only its members can commit
+/// transactions, and where claims overlap the owner has to be a member of each of them. An
+/// explosion has to be allowed at every block it destroys.
+pub struct ProtectedRegions {
+    claims: Vec<(WorldId, Region, HashSet<Uuid>)>,
+}
+
+impl ProtectedRegions {
+    /// Creates a new guard, with no claims
+    pub fn new() -> ProtectedRegions {
+        ProtectedRegions { claims: Vec::new() }
+    }
+
+    /// Claims a region of a world for the given members
+    pub fn add_claim(
+        &mut self,
+        world: WorldId,
+        region: Region,
+        members: HashSet<Uuid>,
+    ) -> &mut Self {
+        self.claims.push((world, region, members));
+        self
+    }
+}
+
+impl Default for ProtectedRegions {
+    fn default() -> ProtectedRegions {
+        ProtectedRegions::new()
+    }
+}
+
+impl TransactionGuard for ProtectedRegions {
+    fn allows(&self, attempt: &Attempt) -> bool {
+        attempt.get_affected_coords().into_iter().all(|(x, y, z)| {
+            self.claims
+                .iter()
+                .filter(|(world, region, _)| {
+                    *world == attempt.get_world() && region.contains(x, y, z)
+                })
+                .all(|(_, _, members)| members.contains(&attempt.get_owner()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claims_only_allow_members() {
+        let (member, stranger) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut guard = ProtectedRegions::new();
+        let members: HashSet<Uuid> = vec![member].into_iter().collect();
+        guard.add_claim(
+            WorldId::OVERWORLD,
+            Region::new((0, 0, 0), (9, 9, 9)),
+            members,
+        );
+        let attempt = |owner, x| {
+            let set = RawTransactionBuilder::new(TransactionType::new_set(MetaBlock::fuse(
+                Block::new_from_ids(0, 1),
+                MetaData::new(),
+            )))
+            .set_owner(owner)
+            .set_x_coord(x)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+            Attempt::new(set.clone(), set)
+        };
+        assert!(guard.allows(&attempt(member, 5)));
+        assert!(!guard.allows(&attempt(stranger, 5)));
+        assert!(guard.allows(&attempt(stranger, 10)));
+    }
+}
@@ -0,0 +1,540 @@
+//! Provides bulk imports of historical transactions, from archives or other logging plugins
+//!
+//! Applying millions of transactions one at a time locks, updates and publishes a world for
+//! every one of them. An import instead reads everything first and builds the Rewind in one go,
+//! replaying the history of every block once. Input is read in batches of lines, each parsed in
+//! parallel with the parallel feature, and every distinct block state is only resolved through
+//! the dictionary once, however often it appears.
+//!
+//! Two kinds of input are understood, both one JSON object per line, with blank lines skipped:
+//! committed transactions, like the body of a worldline export, which keep their ids, and
+//! ImportRecords, the flat format for converting the logs of other plugins, which are committed
+//! in the order they are read. Transactions in the JSON Lines interchange format of the jsonl
+//! module keep their ids as well.
+//!
+//! While migrating away from another logging plugin, both can run side by side. TailRecords is a
+//! maintenance task that pulls the records added to a RecordSource since its last run, and
+//! applies them to a live Rewind like any other transaction. Adapters reading the database of a
+//! particular plugin, like the block tables of CoreProtect or LogBlock, implement RecordSource.
+
+use chrono::{DateTime, FixedOffset};
+use data::*;
+use maintenance::MaintenanceTask;
+use parallel;
+use serde_json;
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+use {Rewind, RewindBuilder};
+
+/// Number of lines parsed together by default
+pub const DEFAULT_BATCH_SIZE: usize = 65536;
+
+/// A single block change, as written by tools converting the logs of other plugins
+///
+/// Records with an expected block become Replaces, and the rest Sets. Blocks are flattened
+/// block states, like `minecraft:oak_stairs[facing=east,half=top]`, resolved with the dictionary
+/// of the builder.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportRecord {
+    /// RFC 3339 timestamp
+    #[serde(default)]
+    pub time: Option<DateTime<FixedOffset>>,
+    /// Defaults to the null Uuid
+    #[serde(default)]
+    pub owner: Option<Uuid>,
+    /// Defaults to the overworld
+    #[serde(default)]
+    pub world: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    /// The block placed
+    pub block: String,
+    /// The block a Replace expected to find
+    #[serde(default)]
+    pub expected: Option<String>,
+    /// Name of an ActionCategory
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+/// Imports transactions in bulk into a new Rewind, see the module documentation
+pub struct Importer {
+    builder: RewindBuilder,
+    batch_size: usize,
+}
+
+impl Importer {
+    /// Creates an importer building Rewinds configured like the builder
+    pub fn new(builder: RewindBuilder) -> Importer {
+        Importer {
+            builder,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Sets how many lines are parsed together
+    ///
+    /// Larger batches keep more threads busy, but hold more lines in memory. Returns None,
+    /// leaving the importer untouched, if batch_size is zero.
+    pub fn set_batch_size(&mut self, batch_size: usize) -> Option<&mut Self> {
+        if batch_size == 0 {
+            return None;
+        }
+        self.batch_size = batch_size;
+        Some(self)
+    }
+
+    /// Returns the number of lines parsed together
+    pub fn get_batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Builds a Rewind from transactions in the JSON Lines interchange format, which keep their
+    /// ids, see the jsonl module for the schema
+    ///
+    /// Fails with InvalidData, naming the line, if one does not follow the schema
+    pub fn import_jsonl<R: BufRead>(&self, reader: R) -> io::Result<Rewind> {
+        let mut transactions: Vec<Transaction> = Vec::new();
+        self.for_each_batch(reader, |lines| {
+            let parsed = parallel::map(lines, |(_, line)| parse_jsonl(line));
+            for (&(number, _), transaction) in lines.iter().zip(parsed) {
+                transactions.push(transaction.map_err(|e| at_line(number, e))?);
+            }
+            Ok(())
+        })?;
+        Ok(self.builder.build_from_transactions(transactions))
+    }
+
+    /// Builds a Rewind from committed transactions, one per line, which keep their ids
+    ///
+    /// Fails with InvalidData, naming the line, if one is not a transaction
+    pub fn import_transactions<R: BufRead>(&self, reader: R) -> io::Result<Rewind> {
+        let mut transactions: Vec<Transaction> = Vec::new();
+        self.for_each_batch(reader, |lines| {
+            let parsed =
+                parallel::map(lines, |(_, line)| serde_json::from_str::<Transaction>(line));
+            for (&(number, _), transaction) in lines.iter().zip(parsed) {
+                transactions.push(transaction.map_err(|e| at_line(number, e))?);
+            }
+            Ok(())
+        })?;
+        Ok(self.builder.build_from_transactions(transactions))
+    }
+
+    /// Builds a Rewind from ImportRecords, one per line, committed in the order they are read
+    ///
+    /// The transactions get ids counting up from zero, stamped with the node id of the builder,
+    /// or the random one the Rewind gets if it has none.
+    /// Fails with InvalidData, naming the line, if one is not a record, or refers to a block
+    /// that is malformed or not in the dictionary.
+    pub fn import_records<R: BufRead>(&self, reader: R) -> io::Result<Rewind> {
+        let mut builder = self.builder.clone();
+        let node_id = builder.pick_node_id();
+        builder.set_node_id(node_id);
+        let dictonary = builder.dictonary.clone();
+        let mut blocks: HashMap<String, Option<MetaBlock>> = HashMap::new();
+        let mut transactions: Vec<Transaction> = Vec::new();
+        self.for_each_batch(reader, |lines| {
+            let parsed = parallel::map(lines, |(_, line)| {
+                serde_json::from_str::<ImportRecord>(line)
+            });
+            let mut records = Vec::with_capacity(parsed.len());
+            for (&(number, _), record) in lines.iter().zip(parsed) {
+                records.push(record.map_err(|e| at_line(number, e))?);
+            }
+
+            // Resolve the states not seen in an earlier batch, each one only once
+            let mut unseen: Vec<String> = records
+                .iter()
+                .flat_map(|x| Some(&x.block).into_iter().chain(x.expected.as_ref()))
+                .filter(|x| !blocks.contains_key(*x))
+                .cloned()
+                .collect();
+            unseen.sort();
+            unseen.dedup();
+            let resolved = parallel::map(&unseen, |state| {
+                dictonary.as_ref().and_then(|x| x.encode_state(state))
+            });
+            blocks.extend(unseen.into_iter().zip(resolved));
+
+            let built = parallel::map(&records, |record| build_record(record, &blocks));
+            for (&(number, _), raw) in lines.iter().zip(built) {
+                let raw = raw.map_err(|e| at_line(number, e))?;
+                let id = TransactionID::new_from_parts(transactions.len() as u32, node_id);
+                transactions.push(Transaction::new(raw, id));
+            }
+            Ok(())
+        })?;
+        Ok(builder.build_from_transactions(transactions))
+    }
+
+    /// Reads the lines in batches, skipping blank ones, and passes each batch on, every line
+    /// paired with its line number
+    fn for_each_batch<R, F>(&self, reader: R, mut f: F) -> io::Result<()>
+    where
+        R: BufRead,
+        F: FnMut(&[(usize, String)]) -> io::Result<()>,
+    {
+        let mut batch: Vec<(usize, String)> = Vec::with_capacity(self.batch_size);
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                batch.push((i + 1, line));
+            }
+            if batch.len() == self.batch_size {
+                f(&batch)?;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            f(&batch)?;
+        }
+        Ok(())
+    }
+}
+
+/// A log of records that keeps growing, like the table of a logging plugin that is still running
+///
+/// Every record has an id, and records added later have higher ids, like the row ids of a
+/// database table.
+pub trait RecordSource: Send {
+    /// Returns up to limit records with ids above after, ordered by id, each paired with its id
+    fn fetch_after(&mut self, after: u64, limit: usize) -> io::Result<Vec<(u64, ImportRecord)>>;
+}
+
+/// Counts of what a TailRecords has done, shared with the task once it is handed to the
+/// maintenance worker
+#[derive(Clone, Default)]
+pub struct TailProgress {
+    cursor: Arc<AtomicU64>,
+    applied: Arc<AtomicUsize>,
+    rejected: Arc<AtomicUsize>,
+    skipped: Arc<AtomicUsize>,
+}
+
+impl TailProgress {
+    /// Returns the id of the last record handled, to resume tailing from after a restart
+    pub fn get_cursor(&self) -> u64 {
+        self.cursor.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of records committed
+    pub fn get_applied(&self) -> usize {
+        self.applied.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of records the Rewind rejected, see Rewind::subscribe_rejections
+    pub fn get_rejected(&self) -> usize {
+        self.rejected.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of records that could not be turned into transactions, because they
+    /// refer to a block or category that is not known
+    pub fn get_skipped(&self) -> usize {
+        self.skipped.load(Ordering::SeqCst)
+    }
+}
+
+/// Applies the records added to a source since the last run, see the module documentation
+///
+/// Records that can not be turned into transactions are skipped rather than retried, so one bad
+/// row can not hold up the rest. Failing to fetch is retried at the next run.
+pub struct TailRecords<S: RecordSource> {
+    source: S,
+    batch_size: usize,
+    blocks: HashMap<String, Option<MetaBlock>>,
+    progress: TailProgress,
+}
+
+impl<S: RecordSource> TailRecords<S> {
+    /// Creates a task tailing the source, starting with the records after the cursor
+    pub fn new(source: S, cursor: u64) -> TailRecords<S> {
+        let progress = TailProgress::default();
+        progress.cursor.store(cursor, Ordering::SeqCst);
+        TailRecords {
+            source,
+            batch_size: DEFAULT_BATCH_SIZE,
+            blocks: HashMap::new(),
+            progress,
+        }
+    }
+
+    /// Sets how many records are fetched at a time
+    ///
+    /// Returns None, leaving the task untouched, if batch_size is zero
+    pub fn set_batch_size(&mut self, batch_size: usize) -> Option<&mut Self> {
+        if batch_size == 0 {
+            return None;
+        }
+        self.batch_size = batch_size;
+        Some(self)
+    }
+
+    /// Returns a handle to the progress of the task
+    pub fn get_progress(&self) -> TailProgress {
+        self.progress.clone()
+    }
+
+    /// Applies every record added to the source since the last poll, returning how many were
+    /// committed
+    ///
+    /// Blocks are resolved with the dictionary of the Rewind, and every record is applied like
+    /// apply_transaction, so it is validated, guarded and can conflict.
+    pub fn poll(&mut self, rewind: &Rewind) -> io::Result<usize> {
+        let dictonary = rewind.get_dict();
+        let mut applied = 0;
+        loop {
+            let cursor = self.progress.get_cursor();
+            let records = self.source.fetch_after(cursor, self.batch_size)?;
+            let fetched = records.len();
+            for (id, record) in records {
+                for state in Some(&record.block)
+                    .into_iter()
+                    .chain(record.expected.as_ref())
+                {
+                    if !self.blocks.contains_key(state) {
+                        let block = dictonary.as_ref().and_then(|x| x.encode_state(state));
+                        self.blocks.insert(state.clone(), block);
+                    }
+                }
+                let counter = match build_record(&record, &self.blocks) {
+                    Ok(raw) => match rewind.apply_transaction(raw) {
+                        Some(_) => {
+                            applied += 1;
+                            &self.progress.applied
+                        }
+                        None => &self.progress.rejected,
+                    },
+                    Err(_) => &self.progress.skipped,
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                self.progress.cursor.store(id, Ordering::SeqCst);
+            }
+            if fetched < self.batch_size {
+                return Ok(applied);
+            }
+        }
+    }
+}
+
+impl<S: RecordSource> MaintenanceTask for TailRecords<S> {
+    fn run(&mut self, rewind: &Rewind) -> io::Result<()> {
+        self.poll(rewind).map(|_| ())
+    }
+}
+
+/// Turns a record into a transaction, looking its blocks up among the resolved states
+fn build_record(
+    record: &ImportRecord,
+    blocks: &HashMap<String, Option<MetaBlock>>,
+) -> Result<RawTransaction, String> {
+    let block = |state: &String| {
+        blocks
+            .get(state)
+            .cloned()
+            .flatten()
+            .ok_or_else(|| format!("unknown block {}", state))
+    };
+    let transaction_type = match record.expected {
+        Some(ref expected) => TransactionType::new_replace(block(expected)?, block(&record.block)?),
+        None => TransactionType::new_set(block(&record.block)?),
+    };
+    let mut builder = RawTransactionBuilder::new(transaction_type);
+    builder
+        .set_world(WorldId::new(record.world))
+        .set_x_coord(record.x)
+        .set_y_coord(record.y)
+        .set_z_coord(record.z);
+    if let Some(time) = record.time {
+        builder.set_time(time);
+    }
+    if let Some(owner) = record.owner {
+        builder.set_owner(owner);
+    }
+    if let Some(ref name) = record.category {
+        let category =
+            ActionCategory::from_name(name).ok_or_else(|| format!("unknown category {}", name))?;
+        builder.set_category(category);
+    }
+    // Sets and Replaces with coordinates always build
+    Ok(builder.build_transaction().unwrap())
+}
+
+/// Wraps an error as an InvalidData io::Error, naming the line it occured on
+fn at_line<E: ToString>(line: usize, error: E) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, error.to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn dictonary() -> Arc<BlockDictonary> {
+        let mut dictonary = BlockDictonary::new();
+        let mut table = BlockTable::new("minecraft");
+        table.add_pair("air", 0);
+        table.add_pair("stone", 1);
+        table.add_pair("oak_stairs", 2);
+        dictonary.add_pair(table, 0);
+        Arc::new(dictonary)
+    }
+
+    #[test]
+    fn records_are_imported() {
+        let air = MetaBlock::fuse(Block::new_from_ids(0, 0), MetaData::new());
+        let mut builder = RewindBuilder::new(air);
+        builder.set_dict(dictonary());
+        builder.set_node_id(0);
+        let mut importer = Importer::new(builder);
+        assert!(importer.set_batch_size(0).is_none());
+        importer.set_batch_size(2).unwrap();
+
+        let input = r#"{"x": 0, "y": 0, "z": 0, "block": "minecraft:stone"}
+
+{"x": 0, "y": 0, "z": 0, "block": "minecraft:oak_stairs[facing=east]", "expected": "minecraft:stone", "time": "2020-01-01T00:00:00+01:00"}
+{"x": 1, "y": 0, "z": 0, "block": "minecraft:stone", "world": 1, "category": "burn"}
+{"x": 0, "y": 0, "z": 0, "block": "minecraft:air", "expected": "minecraft:stone"}"#;
+        let rewind = importer.import_records(input.as_bytes()).unwrap();
+        let transactions = rewind.get_transactions();
+        assert_eq!(transactions.len(), 4);
+        assert_eq!(
+            transactions[3].get_id(),
+            TransactionID::new_from_parts(3, 0)
+        );
+        assert!(transactions[1].get_transaction().get_time().is_some());
+        assert_eq!(
+            transactions[2].get_transaction().get_category(),
+            Some(ActionCategory::Burn)
+        );
+
+        // The last Replace conflicts, and replaying history rejects it like applying it would
+        let stairs = dictonary().encode_state("minecraft:oak_stairs[facing=east]");
+        assert!(
+            Some(
+                rewind
+                    .get_world_state()
+                    .get_block_defaulting(0, 0, 0)
+                    .unwrap()
+            ) == stairs
+        );
+        let nether = rewind.with_world(WorldId::new(1)).get_world_state();
+        assert!(
+            Some(nether.get_block_defaulting(1, 0, 0).unwrap())
+                == dictonary().encode_state("minecraft:stone")
+        );
+
+        let unknown = importer
+            .import_records(
+                "\n\n{\"x\": 0, \"y\": 0, \"z\": 0, \"block\": \"minecraft:dirt\"}".as_bytes(),
+            )
+            .err()
+            .unwrap();
+        assert_eq!(unknown.kind(), io::ErrorKind::InvalidData);
+        assert!(unknown.to_string().contains("line 3"));
+    }
+
+    /// Rows that can be appended to while the task holds the source
+    struct Table(Arc<Mutex<Vec<(u64, ImportRecord)>>>);
+
+    impl RecordSource for Table {
+        fn fetch_after(
+            &mut self,
+            after: u64,
+            limit: usize,
+        ) -> io::Result<Vec<(u64, ImportRecord)>> {
+            let rows = self.0.lock().unwrap();
+            Ok(rows
+                .iter()
+                .filter(|x| x.0 > after)
+                .take(limit)
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn record(x: i32, block: &str) -> ImportRecord {
+        serde_json::from_str(&format!(
+            r#"{{"x": {}, "y": 0, "z": 0, "block": "{}"}}"#,
+            x, block
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn tails_new_records() {
+        let air = MetaBlock::fuse(Block::new_from_ids(0, 0), MetaData::new());
+        let rewind = RewindBuilder::new(air).set_dict(dictonary()).build();
+        let rows = Arc::new(Mutex::new(vec![
+            (3, record(0, "minecraft:stone")),
+            (7, record(1, "minecraft:dirt")),
+            (8, record(2, "minecraft:stone")),
+        ]));
+        let mut tail = TailRecords::new(Table(rows.clone()), 0);
+        tail.set_batch_size(2).unwrap();
+        let progress = tail.get_progress();
+
+        assert_eq!(tail.poll(&rewind).unwrap(), 2);
+        assert_eq!((progress.get_cursor(), progress.get_skipped()), (8, 1));
+        assert_eq!(tail.poll(&rewind).unwrap(), 0);
+
+        rows.lock()
+            .unwrap()
+            .push((9, record(3, "minecraft:oak_stairs")));
+        assert_eq!(tail.poll(&rewind).unwrap(), 1);
+        assert_eq!((progress.get_cursor(), progress.get_applied()), (9, 3));
+        assert_eq!(rewind.get_transactions().len(), 3);
+
+        // Resuming from the cursor only picks up what is new
+        let mut resumed = TailRecords::new(Table(rows), progress.get_cursor());
+        assert_eq!(resumed.poll(&rewind).unwrap(), 0);
+    }
+
+    #[test]
+    fn archives_keep_their_ids() {
+        let air = MetaBlock::fuse(Block::new_from_ids(0, 0), MetaData::new());
+        let rewind = Rewind::new(air.clone());
+        for id in 1..40 {
+            let set = RawTransactionBuilder::new(TransactionType::new_set(MetaBlock::fuse(
+                Block::new_from_ids(0, id),
+                MetaData::new(),
+            )))
+            .set_x_coord(i32::from(id % 3))
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+            rewind.apply_transaction(set);
+        }
+        let mut archive = Vec::new();
+        for transaction in rewind.get_transactions() {
+            serde_json::to_writer(&mut archive, &transaction).unwrap();
+            archive.push(b'\n');
+        }
+
+        let mut importer = Importer::new(RewindBuilder::new(air));
+        importer.set_batch_size(7).unwrap();
+        let imported = importer.import_transactions(&archive[..]).unwrap();
+        assert!(imported.get_transactions() == rewind.get_transactions());
+        for x in 0..3 {
+            assert!(
+                imported
+                    .get_world_state()
+                    .get_block_defaulting(x, 0, 0)
+                    .unwrap()
+                    == rewind
+                        .get_world_state()
+                        .get_block_defaulting(x, 0, 0)
+                        .unwrap()
+            );
+        }
+        assert!(importer.import_transactions("{}".as_bytes()).is_err());
+    }
+}
@@ -1,14 +1,83 @@
 //! Contains the heart and soul of the module, the rewind data structure
+extern crate arc_swap;
+#[cfg(feature = "arrow")]
+extern crate arrow_array;
+#[cfg(feature = "arrow")]
+extern crate arrow_schema;
 extern crate chrono;
+extern crate flate2;
 extern crate im;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+#[cfg(feature = "python")]
+extern crate pyo3;
+// PyO3's macros expand to paths starting with ::core, which 2015 edition resolves from the root
+#[cfg(feature = "python")]
+extern crate core;
+#[cfg(any(test, feature = "proptest"))]
+extern crate proptest;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate uuid;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
 
+pub mod analysis;
+#[cfg(any(test, feature = "proptest"))]
+pub mod arbitrary;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bounded;
+pub mod conflict;
 pub mod data;
+pub mod guard;
+pub mod import;
+mod locks;
+pub mod maintenance;
+pub mod manager;
+pub mod ordering;
+mod parallel;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod query;
+mod rejections;
+pub mod replication;
+pub mod retry;
+pub mod sim;
+mod snapshot;
 pub mod storage;
+mod stream;
+mod telemetry;
+pub mod validator;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+use bounded::*;
+use chrono::{DateTime, Local, TimeZone, Utc};
+use conflict::*;
 use data::*;
+use guard::*;
 use im::*;
-use std::sync::{Arc, Mutex, RwLock};
+use import::Importer;
+use locks::ChunkLocks;
+use maintenance::*;
+use ordering::TimeOrdering;
+use query::Query;
+use rejections::{LogWriter, RejectionLog};
+use retry::{RetryPolicy, RetryReport};
+use snapshot::Snapshot;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+use stream::{Subscribers, Watchers};
+use uuid::Uuid;
+use validator::*;
 
 /// The heart and soul of the library, the Rewind datastructre
 ///
@@ -17,293 +86,4979 @@ use std::sync::{Arc, Mutex, RwLock};
 /// An immutable view of the world can be obtained with get_world_state.
 ///
 /// All rewinds derived from the same initial object share a common state.
+///
+/// Every world, such as the dimensions of a server, shares one worldline but has its own blocks.
+/// Transactions are applied to the world they name, while the block queries of a Rewind look at
+/// the world it was created for, the overworld unless changed with with_world.
+///
+/// Writes to different chunks proceed concurrently. Each write locks its own chunk, updates it
+/// on a view of the world, and only holds the locks on the world and the worldline for long
+/// enough to install the chunk and commit the transaction. Views of the world are read without
+/// locking, so get_world_state never waits on writers.
 #[derive(Clone)]
 pub struct Rewind {
     world_line: Arc<RwLock<WorldLine>>,
-    world: Arc<RwLock<World>>,
+    /// Replaced as a whole on every commit, so readers always see a consistent set of worlds
+    worlds: Arc<Snapshot<HashMap<WorldId, World>>>,
+    /// Held by whatever is modifying a chunk, from reading it to installing the new version
+    chunk_locks: Arc<ChunkLocks>,
+    world_id: WorldId,
+    /// World that has never been written to, with the configured chunk size
+    empty_world: World,
+    /// Version of empty_world for each world with a baseline
+    empty_worlds: HashMap<WorldId, World>,
     default_block: MetaBlock,
+    subscribers: Arc<Mutex<Subscribers<Transaction>>>,
+    watchers: Arc<Mutex<Watchers>>,
+    bounded: Arc<BoundedSenders>,
+    rejections: Arc<Mutex<Subscribers<RejectedTransaction>>>,
+    rejection_log: Arc<Mutex<RejectionLog>>,
+    conflict_policy: Arc<dyn ConflictPolicy>,
+    guards: Arc<Vec<Arc<dyn TransactionGuard>>>,
+    /// Validators, in the order they run, shared with every Rewind sharing this one's state
+    validators: Arc<RwLock<Vec<Arc<dyn TransactionValidator>>>>,
+    time_ordering: TimeOrdering,
+    /// Background worker, if one has been started, stopped once every Rewind sharing it is gone
+    maintenance: Arc<Mutex<Option<MaintenanceWorker>>>,
 }
 
-impl Rewind {
-    /// Creates a new Rewind with an empty worldline and an empty world
-    pub fn new(default_block: MetaBlock) -> Rewind {
-        let world_line = WorldLine::new();
-        let world = World::new(default_block);
-        Rewind {
-            world_line: Arc::new(RwLock::new(world_line)),
-            world: Arc::new(RwLock::new(world)),
-            default_block,
+/// A builder for Rewinds
+///
+/// Everything but the default block is optional, and defaults to the behavior of Rewind::new
+#[derive(Clone)]
+pub struct RewindBuilder {
+    defaults: DefaultLayers,
+    baselines: HashMap<WorldId, Arc<dyn BaselineWorld>>,
+    node_id: Option<u32>,
+    chunk_size: usize,
+    chunk_height: usize,
+    chunk_provider: Option<Arc<dyn ChunkProvider>>,
+    dictonary: Option<Arc<BlockDictonary>>,
+    conflict_policy: Arc<dyn ConflictPolicy>,
+    guards: Vec<Arc<dyn TransactionGuard>>,
+    validators: Vec<Arc<dyn TransactionValidator>>,
+    rejection_log_capacity: usize,
+    rejection_log_writer: Option<LogWriter>,
+    time_ordering: TimeOrdering,
+}
+
+impl RewindBuilder {
+    /// Creates a new RewindBuilder
+    ///
+    /// Requires the default block, as that is the only non-optional data
+    pub fn new(default_block: MetaBlock) -> RewindBuilder {
+        RewindBuilder {
+            defaults: DefaultLayers::new(default_block),
+            baselines: HashMap::new(),
+            node_id: None,
+            chunk_size: CHUNK_SIZE,
+            chunk_height: CHUNK_SIZE,
+            chunk_provider: None,
+            dictonary: None,
+            conflict_policy: Arc::new(RejectConflicts),
+            guards: Vec::new(),
+            validators: Vec::new(),
+            rejection_log_capacity: 0,
+            rejection_log_writer: None,
+            time_ordering: TimeOrdering::Unordered,
         }
     }
 
-    /// Returns an immutable view of the world
+    /// Sets the node id, for Rewinds that are one node of a multi-primary deployment
     ///
-    /// Will block until the RwLock on world becomes free
-    pub fn get_world_state(&self) -> World {
-        let world = self.world.read().unwrap();
-        (*world).clone()
+    /// Every node accepting writes must have a distinct node id. Transactions committed by the
+    /// Rewind are stamped with it, keeping their ids unique across nodes so worldlines can later
+    /// be merged with sync_with. Defaults to a random node id, picked when the Rewind is built.
+    pub fn set_node_id(&mut self, node_id: u32) -> &mut Self {
+        self.node_id = Some(node_id);
+        self
     }
 
-    /// Will attempt to apply the given RawTransaction to the world
+    /// Returns the node id that was set, or a random one if none was
+    pub(crate) fn pick_node_id(&self) -> u32 {
+        self.node_id.unwrap_or_else(|| {
+            let bytes = Uuid::new_v4();
+            let bytes = bytes.as_bytes();
+            u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        })
+    }
+
+    /// Sets the size of the chunks of every world, chunk_size blocks wide and chunk_height tall
     ///
-    /// If the transaction is sucsufully applied, a full Transaction will be returned,
-    /// otherwise a None will be returned
+    /// Defaults to CHUNK_SIZE in every direction. Returns None, leaving the builder untouched, if
+    /// World::with_chunk_size would reject the sizes.
+    pub fn set_chunk_size(&mut self, chunk_size: usize, chunk_height: usize) -> Option<&mut Self> {
+        World::with_chunk_size(self.defaults.get_base(), chunk_size, chunk_height)?;
+        self.chunk_size = chunk_size;
+        self.chunk_height = chunk_height;
+        Some(self)
+    }
+
+    /// Sets the default block at each height, in place of the single default block
     ///
-    /// This function will obtain write locks on both world and world_line, and will block until they
-    /// are avaible
-    pub fn apply_transaction(&self, transaction: RawTransaction) -> Option<Transaction> {
-        // First obtain the locks for the world and the world_line
-        let mut world = self.world.write().unwrap();
-        let mut world_line = self.world_line.write().unwrap();
+    /// The base of the layers replaces the default block the builder was created with.
+    pub fn set_default_layers(&mut self, layers: DefaultLayers) -> &mut Self {
+        self.defaults = layers;
+        self
+    }
 
-        // Unwrap and process the transaction
-        let transaction_type = transaction.get_transaction_type();
-        match transaction_type {
-            TransactionType::Set { block_set } => {
-                if let Some((x, y, z)) = transaction.get_coords() {
-                    *world = world.set_block_defaulting(x, y, z, block_set);
-                    Some(world_line.add_transaction(transaction))
-                } else {
-                    None
-                }
-            }
-            TransactionType::Replace {
-                block_current,
-                block_set,
-            } => {
-                if let Some((x, y, z)) = transaction.get_coords() {
-                    let old_block = world.get_block_defaulting(x, y, z);
-                    if old_block == block_current {
-                        *world = world.set_block_defaulting(x, y, z, block_set);
-                        Some(world_line.add_transaction(transaction))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }
-            TransactionType::Undo { transaction: tid } => {
-                // Make sure the transaction exists
-                if let Some(_) = world_line.lookup_transaction(tid) {
-                    // Add the Undo transaction to history first
-                    let final_trans = world_line.add_transaction(transaction);
-                    // Get the undone block
-                    let (x, y, z) = world_line.get_undone_block(tid).unwrap();
-                    // run the history
-                    let history: Vec<Transaction> = world_line.get_block_history(x, y, z);
-                    let new_block = run_history((&history).into_iter(), self.default_block);
-
-                    *world = world.set_block_defaulting(x, y, z, new_block);
-
-                    Some(final_trans)
-                } else {
-                    None
-                }
-            }
-        }
+    /// Sets the baseline supplying the original blocks of one of the worlds
+    ///
+    /// The world starts out as its baseline, falling back to the default layers where the
+    /// baseline has nothing, and only changes made on top of it are recorded. Baselines are not
+    /// part of worldline exports, so they have to be set again on import.
+    pub fn set_baseline(&mut self, world: WorldId, baseline: Arc<dyn BaselineWorld>) -> &mut Self {
+        self.baselines = self.baselines.insert(world, baseline);
+        self
     }
 
-    /// Returns the history of the block
+    /// Sets the provider chunks evicted with Rewind::evict_chunks are handed to
     ///
-    /// A history is a list of (MetaBlock, Transaction) pairs, describing the state of the block, and the
-    /// transaction that resulted in that state. Pairs are arranged incronological order, with the
-    /// oldest first
+    /// Without one, chunks can not be evicted and stay in memory.
+    pub fn set_chunk_provider(&mut self, provider: Arc<dyn ChunkProvider>) -> &mut Self {
+        self.chunk_provider = Some(provider);
+        self
+    }
+
+    /// Sets the dictionary shared by every world, and attached to all of their chunks
     ///
-    /// This function aquires a readlock on the world line, and will block until it is available
-    pub fn get_block_history(&self, x: i32, y: i32, z: i32) -> Vec<(MetaBlock, Transaction)> {
-        // Aquire the readlock on the world_line
-        let world_line = self.world_line.read().unwrap();
-        let transactions: Vec<Transaction> = world_line.get_block_history(x, y, z);
+    /// Without one, blocks can only be referred to by their numerical ids.
+    pub fn set_dict(&mut self, dictonary: Arc<BlockDictonary>) -> &mut Self {
+        self.dictonary = Some(dictonary);
+        self
+    }
 
-        let mut output = Vec::new();
+    /// Sets the policy deciding what happens to conflicting transactions
+    ///
+    /// Defaults to RejectConflicts. Nodes that merge their worldlines, and replicas, must use
+    /// the same policy to converge.
+    pub fn set_conflict_policy<P: ConflictPolicy + 'static>(&mut self, policy: P) -> &mut Self {
+        self.conflict_policy = Arc::new(policy);
+        self
+    }
+
+    /// Adds a guard, consulted before every transaction applied to the Rewind is committed
+    ///
+    /// Guards are consulted in the order they were added, and any one of them can veto a
+    /// transaction.
+    pub fn add_guard<G: TransactionGuard + 'static>(&mut self, guard: G) -> &mut Self {
+        self.guards.push(Arc::new(guard));
+        self
+    }
+
+    /// Adds a validator to the end of the chain every applied transaction is passed through
+    ///
+    /// See Rewind::add_validator
+    pub fn add_validator<V: TransactionValidator + 'static>(&mut self, validator: V) -> &mut Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    /// Sets how many of the most recent rejected transactions are kept, see recent_rejections
+    ///
+    /// Defaults to zero, keeping none.
+    pub fn set_rejection_log_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.rejection_log_capacity = capacity;
+        self
+    }
+
+    /// Sets a writer every rejected transaction is appended to, as a line of JSON
+    ///
+    /// Every rejection is appended, whatever the capacity of the rejection log, and the writer
+    /// is flushed after each one. Failing to write is ignored.
+    pub fn set_rejection_log_writer<W: Write + Send + 'static>(&mut self, writer: W) -> &mut Self {
+        self.rejection_log_writer = Some(Arc::new(Mutex::new(writer)));
+        self
+    }
 
-        for (i, transaction) in (&transactions).into_iter().enumerate() {
-            let history = (&transactions).into_iter().take(i);
-            let block: MetaBlock = run_history(history, self.default_block);
-            output.push((block, *transaction));
+    /// Sets what happens to transactions earlier than the latest time already committed, see the
+    /// ordering module
+    ///
+    /// Defaults to TimeOrdering::Unordered. Returns None, leaving the builder untouched, if the
+    /// tolerance is negative.
+    pub fn set_time_ordering(&mut self, ordering: TimeOrdering) -> Option<&mut Self> {
+        if ordering
+            .get_tolerance()
+            .is_some_and(|x| x < chrono::Duration::zero())
+        {
+            return None;
         }
+        self.time_ordering = ordering;
+        Some(self)
+    }
 
-        output
+    /// Creates a Rewind with an empty worldline and an empty world
+    pub fn build(&self) -> Rewind {
+        self.build_from_transactions(Vec::new())
     }
-}
 
-/// Runs history on a slice of transactions
-fn run_history<'a>(
-    history: impl Iterator<Item = &'a Transaction>,
-    default_block: MetaBlock,
-) -> MetaBlock {
-    // Vector to hold history
-    let history: Vector<Transaction> = history.collect();
-    // History without any of the Undos present
-    let new_history: Vec<Transaction> = (&history)
-        .into_iter()
-        .filter(|x| !x.is_undo())
-        .map(|x| (*x).clone())
-        .collect();
-    // Only the undos, and only the IDs
-    let undos: Vec<TransactionID> = (&history)
-        .into_iter()
-        .filter(|x| x.is_undo())
-        .map(|x| x.get_id())
-        .collect();
-    // Remove the undone transactions
-    let final_history: Vec<Transaction> = new_history
-        .into_iter()
-        .filter(|x| !undos.contains(&x.get_id()))
-        .collect();
+    /// Creates a Rewind from transactions that have already been committed
+    ///
+    /// The transactions keep their ids, and the world is rebuilt by running the history of every
+    /// block they touch.
+    pub fn build_from_transactions<I>(&self, transactions: I) -> Rewind
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let mut world_line = WorldLine::new();
+        world_line.node_id = self.pick_node_id();
+        for transaction in transactions {
+            world_line.insert_transaction(transaction);
+        }
 
-    // Actually run history on the slice
-    let mut block = default_block;
-    for transaction in final_history.into_iter() {
-        let transaction_type = transaction.get_transaction().get_transaction_type();
-        match transaction_type {
-            TransactionType::Set { block_set } => {
-                block = block_set;
-            }
-            TransactionType::Replace {
-                block_set,
-                block_current,
-            } => {
-                if block == block_current {
-                    block = block_set;
-                }
-            }
-            _ => (),
+        // The sizes were validated when they were set
+        let mut empty_world =
+            World::with_chunk_size(self.defaults.get_base(), self.chunk_size, self.chunk_height)
+                .unwrap()
+                .set_default_layers(self.defaults.clone());
+        if let Some(ref provider) = self.chunk_provider {
+            empty_world = empty_world.set_chunk_provider(provider.clone());
+        }
+        if let Some(ref dictonary) = self.dictonary {
+            empty_world = empty_world.set_dict(dictonary);
+        }
+
+        let rewind = Rewind {
+            world_line: Arc::new(RwLock::new(WorldLine::new())),
+            worlds: Arc::new(Snapshot::new(HashMap::new())),
+            chunk_locks: Arc::new(ChunkLocks::new()),
+            world_id: WorldId::default(),
+            empty_worlds: self
+                .baselines
+                .iter()
+                .map(|(world, baseline)| (*world, empty_world.set_baseline((*baseline).clone())))
+                .collect(),
+            empty_world,
+            default_block: self.defaults.get_base(),
+            subscribers: Arc::new(Mutex::new(Subscribers::new())),
+            watchers: Arc::new(Mutex::new(Watchers::new())),
+            bounded: Arc::new(BoundedSenders::new()),
+            rejections: Arc::new(Mutex::new(Subscribers::new())),
+            rejection_log: Arc::new(Mutex::new(RejectionLog::new(
+                self.rejection_log_capacity,
+                self.rejection_log_writer.clone(),
+            ))),
+            conflict_policy: self.conflict_policy.clone(),
+            guards: Arc::new(self.guards.clone()),
+            validators: Arc::new(RwLock::new(self.validators.clone())),
+            time_ordering: self.time_ordering,
+            maintenance: Arc::new(Mutex::new(None)),
+        };
+
+        // Every history is independent, so they can be replayed in parallel. Nothing is evicted
+        // from the worlds while they are built, so none of the writes below has a chunk to load.
+        let mut worlds = HashMap::new();
+        let histories: Vec<_> = world_line.get_block_histories().into_iter().collect();
+        let replayed = parallel::map(&histories, |&((world_id, (x, y, z)), ref history)| {
+            run_history(
+                history.iter(),
+                (x, y, z),
+                rewind.default_at(world_id, x, y, z),
+                &*rewind.conflict_policy,
+            )
+        });
+        for (&((world_id, (x, y, z)), _), block) in histories.iter().zip(replayed) {
+            worlds = rewind
+                .set_block_in(&worlds, world_id, x, y, z, block)
+                .expect("chunks are not evicted while building");
+        }
+        let cells: Vec<_> = world_line.get_touched_biome_cells().into_iter().collect();
+        let replayed = parallel::map(&cells, |key| {
+            let (world_id, cell) = **key;
+            run_biome_history(&world_line.get_biome_history(world_id, cell))
+        });
+        for (key, biome) in cells.iter().zip(replayed) {
+            let (world_id, cell) = **key;
+            let size = BIOME_CELL_SIZE as i32;
+            let updated = rewind
+                .world_in(&worlds, world_id)
+                .set_biome(cell.0 * size, cell.1 * size, cell.2 * size, biome)
+                .expect("chunks are not evicted while building");
+            worlds = worlds.insert(world_id, updated);
         }
+        for key in world_line.get_touched_decorations() {
+            let (world_id, entity) = *key;
+            worlds = rewind
+                .recompute_decoration(&worlds, &world_line, world_id, entity)
+                .expect("chunks are not evicted while building");
+        }
+        *rewind.world_line.write().unwrap() = world_line;
+        telemetry::chunk_count(&worlds);
+        rewind.worlds.lock().publish(worlds);
+        rewind
     }
-    block
 }
 
-/// Contains and manages the list of transactions in a world
-#[derive(Clone)]
-struct WorldLine {
-    /// The list of transactions is stored as an OrdMap to allow lookup by transaction id
-    /// when there have been inserted transaction revisions
-    transactions: OrdMap<TransactionID, Transaction>,
-}
+impl Rewind {
+    /// Creates a new Rewind with an empty worldline and an empty world
+    pub fn new(default_block: MetaBlock) -> Rewind {
+        RewindBuilder::new(default_block).build()
+    }
 
-impl WorldLine {
-    /// Creates a new WorldLine, with an empty transaction log
-    fn new() -> WorldLine {
-        WorldLine {
-            transactions: OrdMap::new(),
-        }
+    /// Creates a new Rewind, with an empty worldline and an empty world, for one node of a
+    /// multi-primary deployment
+    ///
+    /// Every node accepting writes must have a distinct node id. Transactions committed here are
+    /// stamped with it, keeping their ids unique across nodes so worldlines can later be merged
+    /// with sync_with. Rewinds built without one get a random node id instead.
+    pub fn with_node_id(default_block: MetaBlock, node_id: u32) -> Rewind {
+        RewindBuilder::new(default_block)
+            .set_node_id(node_id)
+            .build()
     }
 
-    /// Adds a transaction to the worldline
-    fn add_transaction(&mut self, transaction: RawTransaction) -> Transaction {
-        // Get the TransactionID of the last transaction in the worldline
-        let last_transaction = self.transactions.get_max();
-        let id = match last_transaction {
-            Some((t, _)) => t.increment_major(),
-            None => TransactionID::new(),
-        };
+    /// Creates a new Rewind from transactions that have already been committed
+    ///
+    /// The transactions keep their ids, and the world is rebuilt by running the history of every
+    /// block they touch.
+    pub fn from_transactions<I>(transactions: I, default_block: MetaBlock) -> Rewind
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        RewindBuilder::new(default_block).build_from_transactions(transactions)
+    }
 
-        let new_transaction = Transaction::new(transaction, id);
+    /// Reads a worldline previously written with export_worldline
+    ///
+    /// The transactions are read like Importer::import_transactions. Fails with InvalidData if the
+    /// input is not a worldline export
+    pub fn import_worldline<R: BufRead>(mut reader: R) -> io::Result<Rewind> {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(invalid_data("missing worldline header"));
+        }
+        let header: WorldLineHeader = serde_json::from_str(&header).map_err(invalid_data)?;
+        if header.version != WORLDLINE_VERSION {
+            return Err(invalid_data("unsupported worldline version"));
+        }
 
-        // Add the new transaction to the list
-        self.transactions = self.transactions.insert(id, new_transaction);
+        let mut builder = RewindBuilder::new(header.default_block);
+        if let Some(layers) = header.default_layers {
+            builder.set_default_layers(layers);
+        }
+        // Line numbers in errors count from the line after the header
+        Importer::new(builder).import_transactions(reader)
+    }
 
-        new_transaction
+    /// Writes the worldline out, so it can be read back with import_worldline
+    ///
+    /// The export is a header line followed by one JSON encoded transaction per line, oldest
+    /// first.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn export_worldline<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let header = WorldLineHeader {
+            version: WORLDLINE_VERSION,
+            default_block: self.default_block.clone(),
+            default_layers: Some(self.get_default_layers()).filter(|x| !x.is_uniform()),
+        };
+        serde_json::to_writer(&mut writer, &header).map_err(invalid_data)?;
+        writeln!(writer)?;
+        for transaction in self.get_transactions() {
+            serde_json::to_writer(&mut writer, &transaction).map_err(invalid_data)?;
+            writeln!(writer)?;
+        }
+        writer.flush()
     }
 
-    /// Get a particular transaction
-    fn lookup_transaction(&self, transaction_id: TransactionID) -> Option<Transaction> {
-        if let Some(x) = self.transactions.get(&transaction_id) {
-            Some(*x)
-        } else {
-            None
+    /// Writes an audit record of every transaction matching the filter, oldest first
+    ///
+    /// Records are streamed out as CSV or JSON Lines, see the audit module for their columns.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn export_audit<W: Write>(
+        &self,
+        writer: W,
+        format: AuditFormat,
+        filter: &TransactionFilter,
+    ) -> io::Result<()> {
+        let world_line = self.world_line.read().unwrap();
+        let mut audit = AuditWriter::new(writer, format)?;
+        for transaction in world_line.transactions.values() {
+            if filter.matches(&transaction) {
+                audit.write(&transaction)?;
+            }
         }
+        audit.finish().map(|_| ())
     }
 
-    /// Checks to see if a transaction has been undone, and then returns the Undo transaction
-    fn get_undo(&self, transaction_id: TransactionID) -> Option<TransactionID> {
-        let transactions = self.transactions.clone();
-        // Check for a transaction that undoes this one
-        for (k, v) in transactions.into_iter() {
-            match v.get_transaction().get_transaction_type() {
-                TransactionType::Undo { transaction } => {
-                    if transaction == transaction_id {
-                        // We can safely return the first undo, as undoing a transaction multiple
-                        // times has the same effect as undoing it once
-                        return Some(transaction);
-                    }
-                }
-                _ => (),
+    /// Writes every transaction matching the filter as JSON Lines, oldest first
+    ///
+    /// Every transaction is one flat JSON object, see the jsonl module for the schema. Read the
+    /// lines back with Importer::import_jsonl.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn export_jsonl<W: Write>(&self, writer: W, filter: &TransactionFilter) -> io::Result<()> {
+        let world_line = self.world_line.read().unwrap();
+        let mut jsonl = JsonlWriter::new(writer);
+        for transaction in world_line.transactions.values() {
+            if filter.matches(&transaction) {
+                jsonl.write(&transaction)?;
             }
         }
-        None
+        jsonl.finish().map(|_| ())
     }
 
-    /// Returns the entire undo history for a transaction
-    fn get_undo_history(&self, transaction_id: TransactionID) -> Vec<TransactionID> {
-        let mut history = Vec::new();
+    /// Writes a timelapse of a region out, sampling the worldline at fixed intervals
+    ///
+    /// The state of a block in a frame is the one left by the transactions that occured at or
+    /// before the time of the frame, so transactions without a time never show up. See the
+    /// timelapse module for the format. The histories of the blocks are replayed in parallel with
+    /// the parallel feature.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn export_timelapse<W: Write>(
+        &self,
+        mut writer: W,
+        timelapse: &Timelapse,
+    ) -> io::Result<()> {
+        let times = timelapse.get_frame_times();
+        let region = timelapse.get_region();
+        let (blocks, states) = {
+            let world_line = self.world_line.read().unwrap();
+            let blocks: Vec<(i32, i32, i32)> = world_line
+                .get_touched_blocks()
+                .into_iter()
+                .filter(|key| {
+                    key.0 == self.world_id && region.contains((key.1).0, (key.1).1, (key.1).2)
+                })
+                .map(|key| key.1)
+                .collect();
+            // The state of each block in every frame
+            let states: Vec<Vec<MetaBlock>> = parallel::map(&blocks, |&(x, y, z)| {
+                let history = world_line.get_block_history(self.world_id, x, y, z);
+                times
+                    .iter()
+                    .map(|&frame| {
+                        run_history(
+                            history.iter().filter(|t| {
+                                t.get_transaction()
+                                    .get_time()
+                                    .is_some_and(|time| time <= frame)
+                            }),
+                            (x, y, z),
+                            self.default_at(self.world_id, x, y, z),
+                            &*self.conflict_policy,
+                        )
+                    })
+                    .collect()
+            });
+            (blocks, states)
+        };
 
-        let mut transaction = Some(transaction_id);
-        while let Some(t) = transaction {
-            history.push(t);
-            transaction = self.get_undo(t);
+        serde_json::to_writer(&mut writer, &timelapse.get_header()).map_err(invalid_data)?;
+        writeln!(writer)?;
+        for (i, time) in times.iter().enumerate() {
+            let keyframe = timelapse.is_keyframe(i);
+            let changed = blocks
+                .iter()
+                .zip(&states)
+                .filter(|(&(x, y, z), states)| {
+                    if keyframe {
+                        states[i] != self.default_at(self.world_id, x, y, z)
+                    } else {
+                        states[i] != states[i - 1]
+                    }
+                })
+                .map(|(coords, states)| (*coords, states[i].clone()))
+                .collect();
+            let frame = TimelapseFrame {
+                frame: i,
+                time: time.to_rfc3339(),
+                keyframe,
+                blocks: changed,
+            };
+            serde_json::to_writer(&mut writer, &frame).map_err(invalid_data)?;
+            writeln!(writer)?;
         }
+        writer.flush()
+    }
 
-        history
+    /// Returns a new Rewind with an equivalent, but smaller, worldline
+    ///
+    /// Transactions that have been undone are dropped, along with every Undo, as neither has any
+    /// effect on the world anymore. The remaining transactions keep their ids.
+    pub fn compact(&self) -> Rewind {
+        let world_line = self.world_line.read().unwrap();
+        let transactions: Vec<Transaction> = world_line
+            .transactions
+            .values()
+            .filter(|x| !x.is_undo() && !world_line.is_undone(x.get_id()))
+            .map(|x| (*x).clone())
+            .collect();
+        self.builder().build_from_transactions(transactions)
     }
 
-    /// Returns a set of transactions that have been applied to a particular block
+    /// Returns a new Rewind with every block in the worldline and the worlds passed through f
     ///
-    /// Does not include Undos
-    fn get_transactions_for_block(&self, x: i32, y: i32, z: i32) -> OrdSet<TransactionID> {
-        let mut set = OrdSet::new();
-        let coords = (x, y, z);
+    /// Transactions keep their ids, and the worlds are rebuilt from the mapped history.
+    pub fn map_blocks<F: Fn(MetaBlock) -> MetaBlock>(&self, f: F) -> Rewind {
+        self.rebuild_mapped(self.builder(), f)
+    }
 
-        let transactions = self.transactions.clone();
-        for (k, v) in transactions.into_iter() {
-            if v.get_transaction().get_coords() == Some(coords) {
-                set = set.insert(k);
-            }
+    /// Returns a new Rewind with every block converted by a migration, using its new dictionary
+    pub fn migrate(&self, migration: &BlockMigration) -> Rewind {
+        let mut builder = self.builder();
+        builder.set_dict(migration.get_to());
+        self.rebuild_mapped(builder, |block| migration.migrate_block(block))
+    }
+
+    /// Builds a Rewind from this one's history, with every block passed through f
+    fn rebuild_mapped<F>(&self, mut builder: RewindBuilder, f: F) -> Rewind
+    where
+        F: Fn(MetaBlock) -> MetaBlock,
+    {
+        let transactions: Vec<Transaction> = self
+            .world_line
+            .read()
+            .unwrap()
+            .transactions
+            .values()
+            .map(|x| x.map_blocks(&f))
+            .collect();
+        builder.defaults = self.empty_world.get_default_layers().map_blocks(&f);
+        builder.build_from_transactions(transactions)
+    }
+
+    /// Returns a builder configured like this Rewind
+    fn builder(&self) -> RewindBuilder {
+        let rejection_log = self.rejection_log.lock().unwrap();
+        RewindBuilder {
+            defaults: self.empty_world.get_default_layers().clone(),
+            baselines: self
+                .empty_worlds
+                .iter()
+                .filter_map(|(world, empty)| empty.get_baseline().map(|x| (*world, x)))
+                .collect(),
+            node_id: Some(self.world_line.read().unwrap().node_id),
+            chunk_size: self.empty_world.get_chunk_size(),
+            chunk_height: self.empty_world.get_chunk_height(),
+            chunk_provider: self.empty_world.get_chunk_provider(),
+            dictonary: self.empty_world.get_dict(),
+            conflict_policy: self.conflict_policy.clone(),
+            guards: (*self.guards).clone(),
+            validators: self.validators.read().unwrap().clone(),
+            rejection_log_capacity: rejection_log.get_capacity(),
+            rejection_log_writer: rejection_log.get_writer(),
+            time_ordering: self.time_ordering,
         }
+    }
 
-        set
+    /// Adds a validator to the end of the chain every applied transaction is passed through
+    ///
+    /// The validator applies to every Rewind sharing this one's state, from the next transaction
+    /// applied on. See the validator module.
+    ///
+    /// This function aquires a writelock on the validators, and will block until it is available
+    pub fn add_validator(&self, validator: Box<dyn TransactionValidator>) {
+        let mut validators = self.validators.write().unwrap();
+        validators.push(Arc::from(validator));
     }
 
-    /// Returns the history of all transactions to affect this particular block
+    /// Returns an isolated copy of this Rewind, for trying out edits before making them for real
     ///
-    /// In chronological order, oldest first
-    fn get_block_history(&self, x: i32, y: i32, z: i32) -> Vec<Transaction> {
-        // Get the initial list of transactions
-        let mut set = self.get_transactions_for_block(x, y, z);
+    /// The sandbox starts with the same worldline and worlds, and shares their structure, so
+    /// creating it is cheap no matter how large they are. From then on the two are independent:
+    /// transactions applied to one are never seen by the other, and subscribers, watchers and
+    /// the maintenance worker stay with this Rewind, as does the writer of the rejection log.
+    /// The sandbox keeps the guards, validators and conflict policy, so edits are accepted or
+    /// rejected like they would be here.
+    ///
+    /// Compare the result with diff_against, and apply the edits for real to this Rewind.
+    /// Transactions committed in the sandbox must not be merged back, their ids collide with the
+    /// ones this Rewind hands out.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn sandbox(&self) -> Rewind {
+        let mut builder = self.builder();
+        builder.rejection_log_writer = None;
+        let sandbox = builder.build().with_world(self.world_id);
+        // Commits publish the worlds while holding the world_line write lock, so the two agree
+        let world_line = self.world_line.read().unwrap();
+        *sandbox.world_line.write().unwrap() = world_line.clone();
+        sandbox.worlds.lock().publish((*self.worlds.load()).clone());
+        sandbox
+    }
 
-        // Check to see if any of the transactions have been undone
-        // and insert them into the set
-        for k in set.clone().into_iter() {
-            let vec = self.get_undo_history(*k);
-            for v in vec {
-                set = set.insert(v);
+    /// Returns every block in the region whose state differs between this Rewind and another,
+    /// such as a sandbox of it
+    ///
+    /// Each entry is the coordinates of a block, its state here, and its state in the other
+    /// Rewind, ordered by coordinates. Only the world block queries look at is compared. Fails if
+    /// a chunk of either world was evicted and can not be loaded back.
+    ///
+    /// This function aquires a readlock on both world lines, one after the other, and will
+    /// block until they are available
+    pub fn diff_against(&self, other: &Rewind, region: Region) -> io::Result<Vec<BlockDiff>> {
+        let touched = |rewind: &Rewind| -> OrdSet<(i32, i32, i32)> {
+            let world_line = rewind.world_line.read().unwrap();
+            world_line
+                .get_touched_blocks()
+                .into_iter()
+                .filter(|key| {
+                    key.0 == self.world_id && region.contains((key.1).0, (key.1).1, (key.1).2)
+                })
+                .map(|key| key.1)
+                .collect()
+        };
+        let blocks = touched(self).union(touched(other));
+        let ours = self.get_world_state();
+        let theirs = other.world_in(&other.worlds.load(), self.world_id);
+        let mut diff = Vec::new();
+        for coords in blocks.into_iter() {
+            let (x, y, z) = *coords;
+            let before = ours.get_block_defaulting(x, y, z)?;
+            let after = theirs.get_block_defaulting(x, y, z)?;
+            if before != after {
+                diff.push((*coords, before, after));
             }
         }
+        Ok(diff)
+    }
 
-        // Look up the transactions and add them to the output
-        let mut output: Vec<Transaction> = Vec::new();
-        for id in set.into_iter() {
-            let transaction = self.lookup_transaction(*id).unwrap();
-            output.push(transaction);
-        }
+    /// Returns a Rewind sharing this one's state, whose block queries look at the given world
+    pub fn with_world(&self, world: WorldId) -> Rewind {
+        let mut rewind = self.clone();
+        rewind.world_id = world;
+        rewind
+    }
 
-        output
+    /// Returns the world block queries look at
+    pub fn get_world_id(&self) -> WorldId {
+        self.world_id
     }
 
-    /// Returns the block affected by this undo
+    /// Returns the default block of the world
     ///
-    /// FIXME: Will break when we upgrade to affected block sets
-    fn get_undone_block(&self, transaction: TransactionID) -> Option<(i32, i32, i32)> {
-        // Make sure the transaction exists
-        if let Some(t) = self.lookup_transaction(transaction) {
-            match t.get_transaction().get_transaction_type() {
-                TransactionType::Undo { transaction: tid } => self.get_undone_block(tid),
-                x => t.get_transaction().get_coords(),
-            }
-        } else {
-            None
+    /// With default layers, this is the block below the lowest layer.
+    pub fn get_default_block(&self) -> MetaBlock {
+        self.default_block.clone()
+    }
+
+    /// Returns the default block at each height
+    pub fn get_default_layers(&self) -> DefaultLayers {
+        self.empty_world.get_default_layers().clone()
+    }
+
+    /// Returns the block a location of one of the worlds is made of before it is ever set
+    fn default_at(&self, world: WorldId, x: i32, y: i32, z: i32) -> MetaBlock {
+        match self.empty_worlds.get(&world) {
+            Some(empty) => empty.get_default_block_at(x, y, z),
+            None => self.empty_world.get_default_block_at(x, y, z),
+        }
+    }
+
+    /// Returns one of the worlds from the map, or an empty one if it doesn't exist
+    fn world_in(&self, worlds: &HashMap<WorldId, World>, world: WorldId) -> World {
+        match (worlds.get(&world), self.empty_worlds.get(&world)) {
+            (Some(world), _) | (None, Some(world)) => (*world).clone(),
+            (None, None) => self.empty_world.clone(),
         }
     }
+
+    /// Sets a block in one of the worlds of the map, creating the world if it doesn't exist
+    fn set_block_in(
+        &self,
+        worlds: &HashMap<WorldId, World>,
+        world: WorldId,
+        x: i32,
+        y: i32,
+        z: i32,
+        block: MetaBlock,
+    ) -> io::Result<HashMap<WorldId, World>> {
+        let updated = self
+            .world_in(worlds, world)
+            .set_block_defaulting(x, y, z, block)?;
+        Ok(worlds.insert(world, updated))
+    }
+
+    /// Returns the dictionary shared by every world, if there is one
+    ///
+    /// Name-based lookups are available on the worlds, see World::get_block_name
+    pub fn get_dict(&self) -> Option<Arc<BlockDictonary>> {
+        self.empty_world.get_dict()
+    }
+
+    /// Returns every transaction in the worldline, in chronological order, oldest first
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn get_transactions(&self) -> Vec<Transaction> {
+        let world_line = self.world_line.read().unwrap();
+        world_line
+            .transactions
+            .values()
+            .map(|x| (*x).clone())
+            .collect()
+    }
+
+    /// Returns the generation of the world, the number of transactions committed to it so far
+    ///
+    /// The generation goes up by one with every committed transaction, including ones merged in
+    /// from other nodes, and never goes down, see apply_transaction_if_version.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn generation(&self) -> u64 {
+        self.world_line.read().unwrap().generation
+    }
+
+    /// Returns every transaction matching the filter, in chronological order, oldest first
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn get_transactions_matching(&self, filter: &TransactionFilter) -> Vec<Transaction> {
+        self.query().set_filter(filter).iter().collect()
+    }
+
+    /// Returns every transaction that wrote text onto a block containing every word of the
+    /// query, in chronological order, oldest first
+    ///
+    /// Text is the content of signs and other block entities, see the text module. Words are
+    /// matched whole and regardless of case, so `Hello` finds a sign reading `hello world!`.
+    /// Transactions are found even if their text has since been undone or overwritten, so
+    /// moderators can see who wrote it.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn search_text(&self, query: &str) -> Vec<Transaction> {
+        let world_line = self.world_line.read().unwrap();
+        world_line
+            .search_text(query)
+            .into_iter()
+            .filter_map(|x| world_line.lookup_transaction(x))
+            .collect()
+    }
+
+    /// Returns a query over every transaction committed so far, see the query module
+    ///
+    /// The query works on a snapshot of the world line, so transactions committed after this
+    /// call are not seen by it.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn query(&self) -> Query {
+        self.world_line.read().unwrap().query()
+    }
+
+    /// Subscribes to the stream of committed transactions
+    ///
+    /// Every transaction successfully applied after this call will be sent down the returned
+    /// channel, in the order it was committed. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<Transaction> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.subscribe()
+    }
+
+    /// Subscribes to the stream of committed transactions, holding at most capacity of them
+    ///
+    /// Every transaction successfully applied after this call is sent to the returned receiver,
+    /// in the order it was committed, until it holds capacity transactions that have not been
+    /// read. From then on the overflow policy decides what happens. Writes never wait on a slow
+    /// consumer, and it never makes memory grow beyond its capacity, plus the outbox shared by
+    /// the subscriptions with OverflowPolicy::Block. Dropping the receiver unsubscribes.
+    ///
+    /// Returns None if the capacity is zero
+    pub fn subscribe_bounded(
+        &self,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> Option<BoundedReceiver> {
+        if capacity == 0 {
+            return None;
+        }
+        let (sender, receiver) = bounded::bounded(capacity, policy);
+        self.bounded.subscribe(sender);
+        Some(receiver)
+    }
+
+    /// Watches a region of the world of this Rewind for changes
+    ///
+    /// Every transaction committed after this call that changes a block inside the region is
+    /// sent down the returned channel, in the order it was committed. That includes Undos of
+    /// transactions inside the region, and SetBiomes at coordinates inside it. Dropping the
+    /// receiver stops watching.
+    pub fn watch(&self, region: Region) -> Receiver<Transaction> {
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.watch(self.world_id, region)
+    }
+
+    /// Subscribes to the stream of rejected transactions
+    ///
+    /// Every transaction that fails to apply after this call will be sent down the returned
+    /// channel, along with the reason it was rejected. Dropping the receiver unsubscribes.
+    pub fn subscribe_rejections(&self) -> Receiver<RejectedTransaction> {
+        let mut rejections = self.rejections.lock().unwrap();
+        rejections.subscribe()
+    }
+
+    /// Returns the most recent rejected transactions, oldest first
+    ///
+    /// Only as many as the capacity set with RewindBuilder::set_rejection_log_capacity are kept,
+    /// by default none are.
+    ///
+    /// This function aquires a lock on the rejection log, and will block until it is available
+    pub fn recent_rejections(&self) -> Vec<RejectedTransaction> {
+        self.rejection_log.lock().unwrap().get_recent()
+    }
+
+    /// Sends a rejected transaction to the subscribers of subscribe_rejections, and records it in
+    /// the rejection log
+    fn reject(&self, rejected: RejectedTransaction) {
+        self.rejections.lock().unwrap().publish(rejected.clone());
+        self.rejection_log.lock().unwrap().record(rejected);
+    }
+
+    /// Subscribes to the stream of committed transactions, along with everything committed so far
+    ///
+    /// Returns every transaction committed before the subscription, oldest first, and a channel
+    /// receiving every transaction committed after it, with no gaps or overlap between the two.
+    pub fn subscribe_with_history(&self) -> (Vec<Transaction>, Receiver<Transaction>) {
+        // Commits publish while holding the world_line lock, so holding it here guarantees every
+        // commit lands either in the history or in the channel
+        let world_line = self.world_line.read().unwrap();
+        let receiver = self.subscribers.lock().unwrap().subscribe();
+        let history = world_line
+            .transactions
+            .values()
+            .map(|x| (*x).clone())
+            .collect();
+        (history, receiver)
+    }
+
+    /// Merges transactions committed by other nodes into this worldline
+    ///
+    /// Transactions already present are skipped, so merging is idempotent, and as the world is a
+    /// function of the set of transactions alone, nodes that have merged the same transactions
+    /// converge on the same world no matter the order they arrived in.
+    ///
+    /// Returns the number of transactions that were new, or a report of why one could not be
+    /// merged, with the reason Conflict, naming the transaction present, if a different
+    /// transaction already has its id. The transactions before it stay merged.
+    pub fn merge_transactions<I>(&self, transactions: I) -> Result<usize, Rejection>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let mut merged = 0;
+        for transaction in transactions {
+            if self.replay_transaction(transaction)? {
+                merged += 1;
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Exchanges transactions with another node, so both end up with the same worldline
+    ///
+    /// Returns the number of transactions that were new to either side, or a report of why one
+    /// could not be merged, see merge_transactions
+    pub fn sync_with(&self, other: &Rewind) -> Result<usize, Rejection> {
+        let received = self.merge_transactions(other.get_transactions())?;
+        let sent = other.merge_transactions(self.get_transactions())?;
+        Ok(received + sent)
+    }
+
+    /// Applies a transaction committed by a primary, keeping its id, so this Rewind mirrors it
+    /// exactly
+    ///
+    /// The transaction has to come after every transaction already in the worldline, which holds
+    /// for a primary's transactions applied in the order it committed them. Its block is
+    /// recomputed from history, so it ends up in the same state as on the primary, whatever the
+    /// conflict policy decided there. Validators and guards are not consulted, as the primary
+    /// already did.
+    ///
+    /// Returns the transaction, or a report of why it was rejected, with the reason OutOfOrder
+    /// if its id does not come after the latest one, NotFound if it is an Undo of a transaction
+    /// that is not in the worldline, or Incomplete if it changes a block but has no coordinates.
+    ///
+    /// This function will lock the chunk the transaction affects, and then obtain write locks
+    /// on both world and world_line to commit it, blocking until they are avaible.
+    pub fn apply_committed(&self, transaction: Transaction) -> Result<Transaction, Rejection> {
+        let raw = transaction.get_transaction().clone();
+        let id = transaction.get_id();
+        let result = self.replay_checked(transaction.clone(), |world_line| {
+            let latest = world_line.transactions.get_max().map(|(id, _)| *id);
+            if latest.is_some_and(|latest| id <= latest) {
+                return Err(RejectionReason::OutOfOrder.into());
+            }
+            match *raw.get_transaction_type() {
+                TransactionType::Undo { transaction: tid } => world_line
+                    .lookup_transaction(tid)
+                    .map(|_| ())
+                    .ok_or(RejectionReason::NotFound.into()),
+                TransactionType::Explode { .. } | TransactionType::PistonMove { .. } => Ok(()),
+                _ => raw
+                    .get_coords()
+                    .map(|_| ())
+                    .ok_or(RejectionReason::Incomplete.into()),
+            }
+        });
+        result.map(|_| transaction).map_err(|rejection| {
+            let rejection = rejection.or_coords(raw.get_coords());
+            self.reject(RejectedTransaction::new(raw, rejection.clone()));
+            rejection
+        })
+    }
+
+    /// Applies a transaction that has already been committed elsewhere, keeping its id
+    ///
+    /// The affected block is recomputed from its history, so the result is deterministic no
+    /// matter the state of the world.
+    ///
+    /// Returns false, doing nothing, if the transaction is already present, or a report with the
+    /// reason Conflict if a different transaction has its id
+    fn replay_transaction(&self, transaction: Transaction) -> Result<bool, Rejection> {
+        let id = transaction.get_id();
+        let raw = transaction.get_transaction().clone();
+        let mut present = false;
+        let result = self.replay_checked(transaction, |world_line| {
+            match world_line.lookup_transaction(id) {
+                Some(ref existing) if *existing.get_transaction() == raw => {
+                    present = true;
+                    Err(RejectionReason::OutOfOrder.into())
+                }
+                Some(_) => Err(Rejection::new_taken_id(id, raw.get_coords())),
+                None => Ok(()),
+            }
+        });
+        match result {
+            Ok(()) => Ok(true),
+            Err(_) if present => Ok(false),
+            Err(rejection) => Err(rejection),
+        }
+    }
+
+    /// Applies a transaction that has already been committed elsewhere, keeping its id, if check
+    /// passes on the worldline it is about to be inserted into
+    fn replay_checked<C>(&self, transaction: Transaction, check: C) -> Result<(), Rejection>
+    where
+        C: FnOnce(&WorldLine) -> Result<(), Rejection>,
+    {
+        let target = match *transaction.get_transaction().get_transaction_type() {
+            TransactionType::Undo { transaction: tid } => {
+                self.world_line.read().unwrap().get_undone_transaction(tid)
+            }
+            _ => Some(transaction.get_transaction().clone()),
+        };
+        let _chunks = target.as_ref().map(|x| self.lock_target(x));
+        let mut worlds = self.worlds.lock();
+        let mut world_line = self.world_line.write().unwrap();
+
+        check(&world_line)?;
+        // Recompute on a copy, so nothing is inserted if a chunk can not be loaded back
+        let mut updated_line = world_line.clone();
+        updated_line.insert_transaction(transaction.clone());
+        if let Some(ref target) = target {
+            let recomputed = self.recompute(&worlds.get(), &updated_line, target)?;
+            telemetry::chunk_count(&recomputed);
+            worlds.publish(recomputed);
+        }
+        *world_line = updated_line;
+
+        self.publish(transaction, target);
+        Ok(())
+    }
+
+    /// Sends a committed transaction to every subscriber, and whoever watches the block target
+    /// changes, and the bounded subscriptions
+    fn publish(&self, transaction: Transaction, target: Option<RawTransaction>) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .publish(transaction.clone());
+        self.watchers
+            .lock()
+            .unwrap()
+            .publish(transaction.clone(), target);
+        self.bounded.push(transaction);
+    }
+
+    /// Locks every chunk whatever the transaction affects is in, none if it affects nothing
+    ///
+    /// The transaction must not be an Undo
+    fn lock_target(&self, target: &RawTransaction) -> Vec<MutexGuard<'_, ()>> {
+        self.lock_targets(std::slice::from_ref(target))
+    }
+
+    /// Locks every chunk whatever the transactions affect is in, along with the chunk every
+    /// decoration they place, update or break is in right now
+    ///
+    /// A decoration moving out of another chunk removes it from that chunk as well, so a writer
+    /// to that chunk must not work on a view still holding it. Moving a decoration takes the
+    /// lock on the chunk it is in, so once that is held it can not move, but it may have moved
+    /// before, in which case the chunks are locked again.
+    ///
+    /// The transactions must not be Undos
+    fn lock_targets(&self, targets: &[RawTransaction]) -> Vec<MutexGuard<'_, ()>> {
+        loop {
+            let decorations = self.decoration_chunks(targets);
+            let chunks = targets
+                .iter()
+                .flat_map(|target| self.target_chunks(target))
+                .chain(decorations.iter().copied());
+            let guards = self.chunk_locks.lock_all(chunks);
+            if self.decoration_chunks(targets) == decorations {
+                return guards;
+            }
+        }
+    }
+
+    /// Returns the world and index of the chunk every decoration the transactions place, update
+    /// or break is in right now, if it is in a world
+    fn decoration_chunks(&self, targets: &[RawTransaction]) -> Vec<(WorldId, ChunkPos)> {
+        let worlds = self.worlds.load();
+        targets
+            .iter()
+            .filter_map(|target| {
+                let entity = target.get_decoration_entity()?;
+                let world = self.world_in(&worlds, target.get_world());
+                world
+                    .get_entity_chunk(entity)
+                    .map(|x| (target.get_world(), x))
+            })
+            .collect()
+    }
+
+    /// Returns the world and index of every chunk whatever the transaction affects is in
+    fn target_chunks(&self, target: &RawTransaction) -> Vec<(WorldId, ChunkPos)> {
+        target
+            .get_affected_coords()
+            .into_iter()
+            .map(|(x, y, z)| {
+                (
+                    target.get_world(),
+                    self.empty_world.get_chunk_index(x, y, z),
+                )
+            })
+            .collect()
+    }
+
+    /// Recomputes whatever the transaction affects, a block, every block of an explosion or
+    /// piston move, a biome cell or a decoration, from its history
+    ///
+    /// The transaction must not be an Undo. Fails if a chunk it affects was evicted and can not be
+    /// loaded back.
+    fn recompute(
+        &self,
+        worlds: &HashMap<WorldId, World>,
+        world_line: &WorldLine,
+        target: &RawTransaction,
+    ) -> io::Result<HashMap<WorldId, World>> {
+        if let Some(entity) = target.get_decoration_entity() {
+            return self.recompute_decoration(worlds, world_line, target.get_world(), entity);
+        }
+        if target.is_multi_block() {
+            let mut worlds = worlds.clone();
+            for coords in target.get_affected_coords() {
+                worlds = self.recompute_block(&worlds, world_line, target.get_world(), coords)?;
+            }
+            return Ok(worlds);
+        }
+        match target.get_coords() {
+            Some((x, y, z)) if target.is_set_biome() => {
+                let cell = BlockPos::new(x, y, z).get_biome_cell();
+                self.recompute_biome(worlds, world_line, target.get_world(), cell)
+            }
+            Some(coords) => self.recompute_block(worlds, world_line, target.get_world(), coords),
+            None => Ok(worlds.clone()),
+        }
+    }
+
+    /// Recomputes a block from its history
+    fn recompute_block(
+        &self,
+        worlds: &HashMap<WorldId, World>,
+        world_line: &WorldLine,
+        world: WorldId,
+        (x, y, z): (i32, i32, i32),
+    ) -> io::Result<HashMap<WorldId, World>> {
+        let history = world_line.get_block_history(world, x, y, z);
+        let block = run_history(
+            history.iter(),
+            (x, y, z),
+            self.default_at(world, x, y, z),
+            &*self.conflict_policy,
+        );
+        self.set_block_in(worlds, world, x, y, z, block)
+    }
+
+    /// Recomputes a biome cell from its history
+    fn recompute_biome(
+        &self,
+        worlds: &HashMap<WorldId, World>,
+        world_line: &WorldLine,
+        world: WorldId,
+        cell: (i32, i32, i32),
+    ) -> io::Result<HashMap<WorldId, World>> {
+        let history = world_line.get_biome_history(world, cell);
+        let biome = run_biome_history(&history);
+        let size = BIOME_CELL_SIZE as i32;
+        let updated = self.world_in(worlds, world).set_biome(
+            cell.0 * size,
+            cell.1 * size,
+            cell.2 * size,
+            biome,
+        )?;
+        Ok(worlds.insert(world, updated))
+    }
+
+    /// Recomputes a decoration from its history, removing it from the world if it was broken
+    fn recompute_decoration(
+        &self,
+        worlds: &HashMap<WorldId, World>,
+        world_line: &WorldLine,
+        world: WorldId,
+        entity: Uuid,
+    ) -> io::Result<HashMap<WorldId, World>> {
+        let history = world_line.get_decoration_history(world, entity);
+        let current = self.world_in(worlds, world);
+        let updated = match run_decoration_history(&history) {
+            Some((coords, decoration)) => current.set_entity(decoration.to_entity(entity, coords)),
+            None => current.remove_entity(entity),
+        }?;
+        Ok(worlds.insert(world, updated))
+    }
+
+    /// Returns an immutable view of the world
+    ///
+    /// Never blocks, the view is whatever the world looked like after the last commit
+    pub fn get_world_state(&self) -> World {
+        self.world_in(&self.worlds.load(), self.world_id)
+    }
+
+    /// Returns an immutable view of the world, along with the generation it is at
+    ///
+    /// The view is exactly the world after the number of transactions given by the generation,
+    /// so a consumer can keep the generation and later compare it with generation() to cheaply
+    /// tell whether anything has changed since, without diffing.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn get_world_state_with_generation(&self) -> (World, u64) {
+        // Commits publish the world while holding the world_line write lock, so the two agree
+        let world_line = self.world_line.read().unwrap();
+        (self.get_world_state(), world_line.generation)
+    }
+
+    /// Returns a read-only snapshot of the world, along with the generation it is at
+    ///
+    /// Taking a snapshot does not copy the world, and snapshots can be kept and compared, so it
+    /// is cheap enough for renderers to poll every frame, and only redraw when the generation
+    /// has moved on.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn snapshot(&self) -> WorldSnapshot {
+        let (world, generation) = self.get_world_state_with_generation();
+        WorldSnapshot::new(Arc::new(world), generation)
+    }
+
+    /// Evicts every chunk in memory matching the predicate to the chunk provider, in every world
+    ///
+    /// The chunks are reloaded when they are next read or written. Views of the world obtained
+    /// before the eviction keep their chunks in memory until they are dropped.
+    ///
+    /// Fails if the Rewind was built without a chunk provider, or the provider fails to store a
+    /// chunk. Chunks evicted before a failure stay evicted.
+    ///
+    /// Will block until no other write to the world is being committed
+    pub fn evict_chunks<F>(&self, predicate: F) -> io::Result<()>
+    where
+        F: Fn(WorldId, ChunkPos) -> bool,
+    {
+        let mut worlds = self.worlds.lock();
+        for (world_id, world) in worlds.get().iter() {
+            let evicted = world.evict_chunks(|index| predicate(*world_id, index))?;
+            let updated = worlds.get().insert(*world_id, evicted);
+            worlds.publish(updated);
+        }
+        Ok(())
+    }
+
+    /// Makes sure every chunk of the world intersecting the region is held in memory, see
+    /// World::ensure_chunks
+    ///
+    /// Run it before a large paste or rollback of the region, so the writes do not have to create
+    /// or reload chunks one at a time. Chunks loaded this way can be evicted again like any other.
+    ///
+    /// Will block until no other write to the world is being committed. Fails if an evicted chunk
+    /// can not be loaded back, without loading any of them.
+    pub fn ensure_chunks(&self, region: Region) -> io::Result<()> {
+        let mut worlds = self.worlds.lock();
+        let world = self.world_in(&worlds.get(), self.world_id);
+        let current = worlds
+            .get()
+            .insert(self.world_id, world.ensure_chunks(region)?);
+        telemetry::chunk_count(&current);
+        worlds.publish(current);
+        Ok(())
+    }
+
+    /// Evicts the least recently modified chunks in memory to the chunk provider, across every
+    /// world, until the ones left take up no more than budget bytes
+    ///
+    /// How recently chunks of different worlds were modified is compared by how many chunk
+    /// modifications their world has seen since. Memory use is an estimate, see
+    /// World::memory_estimate. Fails like evict_chunks.
+    ///
+    /// Returns the number of chunks evicted
+    ///
+    /// Will block until no other write to the world is being committed
+    pub fn enforce_memory_budget(&self, budget: usize) -> io::Result<usize> {
+        let mut worlds = self.worlds.lock();
+        let mut usage: Vec<(u64, WorldId, ChunkPos, usize)> = Vec::new();
+        for (world_id, world) in worlds.get().iter() {
+            let generation = world.get_generation();
+            for (index, size, modified) in world.get_chunk_usage() {
+                usage.push((generation - modified, *world_id, index, size));
+            }
+        }
+        // Most idle first
+        usage.sort_by(|a, b| b.0.cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+
+        let mut total: usize = usage.iter().map(|x| x.3).sum();
+        let mut evicted: HashMap<WorldId, HashSet<ChunkPos>> = HashMap::new();
+        let mut count = 0;
+        for (_, world_id, index, size) in usage {
+            if total <= budget {
+                break;
+            }
+            total -= size;
+            count += 1;
+            let chunks = evicted
+                .get(&world_id)
+                .map_or_else(HashSet::new, |x| (*x).clone());
+            evicted = evicted.insert(world_id, chunks.insert(index));
+        }
+        for (world_id, chunks) in evicted.iter() {
+            let world = self.world_in(&worlds.get(), *world_id);
+            let updated = world.evict_chunks(|index| chunks.contains(&index))?;
+            let current = worlds.get().insert(*world_id, updated);
+            worlds.publish(current);
+        }
+        telemetry::chunks_evicted(count, total);
+        Ok(count)
+    }
+
+    /// Evicts every chunk in memory that has not been modified recently to the chunk provider, in
+    /// every world
+    ///
+    /// A chunk is evicted once its world has seen at least idle_generations chunk modifications
+    /// since it was last modified, see World::get_generation. Fails like evict_chunks.
+    ///
+    /// Will block until no other write to the world is being committed
+    pub fn evict_idle_chunks(&self, idle_generations: u64) -> io::Result<()> {
+        let mut recent = HashMap::new();
+        for (world_id, world) in self.worlds.load().iter() {
+            let since = world.get_generation().saturating_sub(idle_generations);
+            let dirty: HashSet<ChunkPos> = world.dirty_since(since).into_iter().collect();
+            recent = recent.insert(*world_id, dirty);
+        }
+        self.evict_chunks(|world_id, index| match recent.get(&world_id) {
+            Some(dirty) => !dirty.contains(&index),
+            None => false,
+        })
+    }
+
+    /// Starts a background worker running the tasks every interval, off the hot path
+    ///
+    /// The worker is shared by every Rewind sharing this one's state, and runs until
+    /// stop_maintenance is called or all of them are dropped. Returns false, doing nothing, if a
+    /// worker is already running.
+    pub fn start_maintenance(
+        &self,
+        interval: Duration,
+        tasks: Vec<Box<dyn MaintenanceTask>>,
+    ) -> bool {
+        let mut maintenance = self.maintenance.lock().unwrap();
+        if maintenance.is_some() {
+            return false;
+        }
+        // The worker's own Rewind must not keep the worker alive
+        let mut rewind = self.clone();
+        rewind.maintenance = Arc::new(Mutex::new(None));
+        *maintenance = Some(MaintenanceWorker::start(rewind, interval, tasks));
+        true
+    }
+
+    /// Stops the background worker, letting the task it is running finish
+    ///
+    /// Returns false if no worker was running
+    pub fn stop_maintenance(&self) -> bool {
+        // Taken out first, so the lock is not held while waiting for the worker
+        let worker = self.maintenance.lock().unwrap().take();
+        worker.is_some()
+    }
+
+    /// Will attempt to apply the given RawTransaction to the world
+    ///
+    /// If the transaction is sucsufully applied, a full Transaction will be returned,
+    /// otherwise a None will be returned. See try_apply_transaction for why it failed.
+    ///
+    /// The transaction is first passed through every validator, and what they return is applied.
+    /// Every guard is consulted before it is committed, and rejected transactions are sent to the
+    /// subscribers of subscribe_rejections.
+    ///
+    /// If a transaction with the same request id has already been committed, that transaction is
+    /// returned instead, and nothing is applied.
+    ///
+    /// This function will lock the chunk the transaction affects, and then briefly obtain write
+    /// locks on both world and world_line to commit it, blocking until they are avaible. Writes
+    /// to other chunks do not wait on each other.
+    pub fn apply_transaction(&self, transaction: RawTransaction) -> Option<Transaction> {
+        self.try_apply_transaction(transaction).ok()
+    }
+
+    /// Will attempt to apply the given RawTransaction to the world, like apply_transaction
+    ///
+    /// If the transaction is rejected, a report of why is returned instead, holding the
+    /// coordinates it failed on, and for a conflicting Replace the block it expected, the block
+    /// it found, and the transaction that last wrote it.
+    pub fn try_apply_transaction(
+        &self,
+        transaction: RawTransaction,
+    ) -> Result<Transaction, Rejection> {
+        self.apply_at_version(transaction, None)
+    }
+
+    /// Will attempt to apply the given RawTransaction to the world, like try_apply_transaction,
+    /// retrying with a backoff if it conflicts with another writer
+    ///
+    /// After a conflict, a Replace is only submitted again once the block is back in the state it
+    /// expects. Other rejections are returned straight away. See the retry module.
+    ///
+    /// This function blocks the calling thread while waiting between attempts, and takes the
+    /// locks apply_transaction does on every attempt.
+    pub fn apply_with_retry(
+        &self,
+        transaction: RawTransaction,
+        policy: &RetryPolicy,
+    ) -> RetryReport {
+        retry::apply_with_retry(self, transaction, policy)
+    }
+
+    /// Will attempt to apply the given RawTransaction to the world, but only if the world is
+    /// still at the expected generation
+    ///
+    /// This allows optimistic concurrency: a cache reads the world along with its generation,
+    /// decides what to write, and writes conditioned on that generation. If any transaction was
+    /// committed in between, this one is rejected with the reason Stale, and the cache should
+    /// re-read and try again. Otherwise it behaves exactly like try_apply_transaction.
+    pub fn apply_transaction_if_version(
+        &self,
+        transaction: RawTransaction,
+        expected_version: u64,
+    ) -> Result<Transaction, Rejection> {
+        self.apply_at_version(transaction, Some(expected_version))
+    }
+
+    /// Applies a transaction, if the world is at the expected generation when there is one
+    fn apply_at_version(
+        &self,
+        transaction: RawTransaction,
+        expected: Option<u64>,
+    ) -> Result<Transaction, Rejection> {
+        let result = self.validate(transaction.clone()).and_then(|validated| {
+            match *validated.get_transaction_type() {
+                TransactionType::Undo { transaction: tid } => {
+                    let started = Instant::now();
+                    let result = self.apply_undo(validated, tid, expected);
+                    telemetry::undo_finished(started);
+                    result
+                }
+                TransactionType::Explode { .. } | TransactionType::PistonMove { .. } => {
+                    self.apply_to_chunks(validated, expected)
+                }
+                _ => self.apply_to_chunk(validated, expected),
+            }
+        });
+        telemetry::transaction_applied(&transaction, result.is_ok());
+        result.map_err(|rejection| {
+            let rejection = rejection.or_coords(transaction.get_coords());
+            self.reject(RejectedTransaction::new(transaction, rejection.clone()));
+            rejection
+        })
+    }
+
+    /// Passes a transaction through the chain of validators
+    fn validate(&self, transaction: RawTransaction) -> Result<RawTransaction, Rejection> {
+        let validators = self.validators.read().unwrap();
+        validators
+            .iter()
+            .try_fold(transaction, |transaction, validator| {
+                validator
+                    .validate(transaction)
+                    .ok_or(RejectionReason::Invalid.into())
+            })
+    }
+
+    /// Returns true if every guard allows the transaction, which changes whatever target affects
+    fn is_allowed(&self, transaction: &RawTransaction, target: &RawTransaction) -> bool {
+        let attempt = Attempt::new(transaction.clone(), target.clone());
+        self.guards.iter().all(|x| x.allows(&attempt))
+    }
+
+    /// Applies a transaction that changes a single chunk, a Set, Replace, SetBiome, or one of
+    /// the decoration transactions
+    fn apply_to_chunk(
+        &self,
+        transaction: RawTransaction,
+        expected: Option<u64>,
+    ) -> Result<Transaction, Rejection> {
+        let (x, y, z) = transaction
+            .get_coords()
+            .ok_or(RejectionReason::Incomplete)?;
+        if !self.is_allowed(&transaction, &transaction) {
+            return Err(Rejection::new(RejectionReason::Vetoed, Some((x, y, z))));
+        }
+        let world_id = transaction.get_world();
+        let index = self.empty_world.get_chunk_index(x, y, z);
+
+        // Nothing else can modify the chunk while it is locked, so it can be updated on a view of
+        // the world without holding the lock on the world. A decoration also locks the chunk it
+        // moves out of.
+        let _chunks = self.lock_target(&transaction);
+        // A retry of a transaction that was already committed gets it back, the chunk being
+        // locked means the first attempt is either fully committed or not at all
+        let existing = self.world_line.read().unwrap().lookup_request(&transaction);
+        if let Some(existing) = existing {
+            return Ok(existing);
+        }
+        let world = self.world_in(&self.worlds.load(), world_id);
+        let updated = self.update_world(&world, &transaction, || {
+            self.world_line
+                .read()
+                .unwrap()
+                .get_block_history(world_id, x, y, z)
+        })?;
+
+        // Install the chunk into the current version of the world, and commit
+        let mut worlds = self.worlds.lock();
+        let mut world_line = self.world_line.write().unwrap();
+        world_line.check_generation(expected)?;
+        let transaction = world_line.order_time(transaction, self.time_ordering)?;
+        let current = worlds.get();
+        let installed = if transaction.is_decoration() {
+            // A decoration can move out of another chunk, which has to lose it as well, and
+            // placing one never conflicts, so it is simply applied to the current world
+            self.update_world(&self.world_in(&current, world_id), &transaction, Vec::new)?
+        } else {
+            self.world_in(&current, world_id)
+                .with_chunk_from(&updated, index)
+        };
+        let current = current.insert(world_id, installed);
+        telemetry::chunk_count(&current);
+        worlds.publish(current);
+        let committed = world_line.add_transaction(transaction.clone());
+
+        // Publish while still holding the locks, so subscribers see commit order
+        self.publish(committed.clone(), Some(transaction));
+        Ok(committed)
+    }
+
+    /// Applies an Explode or PistonMove, which can change blocks in any number of chunks
+    ///
+    /// Every chunk it changes blocks in is locked, and as neither ever conflicts it is simply
+    /// applied to the current world
+    fn apply_to_chunks(
+        &self,
+        transaction: RawTransaction,
+        expected: Option<u64>,
+    ) -> Result<Transaction, Rejection> {
+        if !self.is_allowed(&transaction, &transaction) {
+            return Err(Rejection::new(
+                RejectionReason::Vetoed,
+                transaction.get_coords(),
+            ));
+        }
+        let world_id = transaction.get_world();
+        let _chunks = self.lock_target(&transaction);
+        let mut worlds = self.worlds.lock();
+        let mut world_line = self.world_line.write().unwrap();
+        if let Some(existing) = world_line.lookup_request(&transaction) {
+            return Ok(existing);
+        }
+        world_line.check_generation(expected)?;
+        let transaction = world_line.order_time(transaction, self.time_ordering)?;
+        let current = worlds.get();
+        let updated =
+            self.update_world(&self.world_in(&current, world_id), &transaction, Vec::new)?;
+        let current = current.insert(world_id, updated);
+        telemetry::chunk_count(&current);
+        worlds.publish(current);
+        let committed = world_line.add_transaction(transaction.clone());
+
+        // Publish while still holding the locks, so subscribers see commit order
+        self.publish(committed.clone(), Some(transaction));
+        Ok(committed)
+    }
+
+    /// Applies a Set, Replace, SetBiome, Explode, PistonMove or decoration transaction to a
+    /// world, returning the updated world
+    ///
+    /// Only when a Replace conflicts is history called, to get the history of its block
+    fn update_world<H>(
+        &self,
+        world: &World,
+        transaction: &RawTransaction,
+        history: H,
+    ) -> Result<World, Rejection>
+    where
+        H: FnOnce() -> Vec<Transaction>,
+    {
+        // Explosions and piston moves blindly set every block they change, wherever they took
+        // place
+        if transaction.is_multi_block() {
+            let mut updated = world.clone();
+            for (x, y, z) in transaction.get_affected_coords() {
+                if let Some(block) = transaction.get_block_set_at((x, y, z)) {
+                    updated = updated
+                        .set_block_defaulting(x, y, z, block)
+                        .map_err(|error| Rejection::from(error).or_coords(Some((x, y, z))))?;
+                }
+            }
+            return Ok(updated);
+        }
+        let (x, y, z) = transaction
+            .get_coords()
+            .ok_or(RejectionReason::Incomplete)?;
+        let unavailable = |error: io::Error| Rejection::from(error).or_coords(Some((x, y, z)));
+        let updated = match transaction.get_transaction_type() {
+            TransactionType::Set { block_set } => {
+                world.set_block_defaulting(x, y, z, block_set.get())
+            }
+            TransactionType::Replace {
+                block_current,
+                block_set,
+            } => {
+                let old_block = world.get_block_defaulting(x, y, z).map_err(unavailable)?;
+                if old_block != block_current.get() {
+                    // Only dig up the last writer when there actually is a conflict
+                    let history = history();
+                    let (_, last_writer) = run_history_tracking(
+                        history.iter(),
+                        (x, y, z),
+                        world.get_default_block_at(x, y, z),
+                        &*self.conflict_policy,
+                    );
+                    let conflict =
+                        Conflict::new(transaction.clone(), old_block.clone(), last_writer.clone());
+                    match conflict::resolve(&*self.conflict_policy, &conflict) {
+                        Resolution::Apply => (),
+                        Resolution::Record => return Ok(world.clone()),
+                        Resolution::Reject => {
+                            return Err(Rejection::new_conflict(
+                                (x, y, z),
+                                block_current.get(),
+                                old_block,
+                                last_writer.map(|x| x.get_id()),
+                            ))
+                        }
+                    }
+                }
+                world.set_block_defaulting(x, y, z, block_set.get())
+            }
+            TransactionType::SetBiome { biome } => world.set_biome(x, y, z, *biome),
+            TransactionType::SetDecoration { entity, decoration } => {
+                world.set_entity(decoration.to_entity(*entity, (x, y, z)))
+            }
+            TransactionType::RemoveDecoration { entity } => world.remove_entity(*entity),
+            TransactionType::Undo { .. }
+            | TransactionType::Explode { .. }
+            | TransactionType::PistonMove { .. } => return Err(RejectionReason::Incomplete.into()),
+        };
+        updated.map_err(unavailable)
+    }
+
+    /// Applies transactions all together, so either every one of them is committed or none are
+    ///
+    /// Each transaction is validated and guarded like in apply_transaction, and applied on top of
+    /// the ones before it. If any is rejected, nothing is committed, and only that one is sent to
+    /// the subscribers of subscribe_rejections.
+    ///
+    /// This function will lock every chunk the transactions affect, and then obtain write locks
+    /// on both world and world_line for as long as it takes to apply them all.
+    fn apply_all(&self, transactions: Vec<RawTransaction>) -> Option<Vec<Transaction>> {
+        match self.try_apply_all(&transactions) {
+            Ok(committed) => {
+                for transaction in &transactions {
+                    telemetry::transaction_applied(transaction, true);
+                }
+                Some(committed)
+            }
+            Err(rejected) => {
+                telemetry::transaction_applied(&rejected.get_transaction(), false);
+                self.reject(*rejected);
+                None
+            }
+        }
+    }
+
+    /// Applies transactions all together, returning the first one rejected, and why, on failure
+    fn try_apply_all(
+        &self,
+        transactions: &[RawTransaction],
+    ) -> Result<Vec<Transaction>, Box<RejectedTransaction>> {
+        // Fills in the coordinates of the transaction, if the rejection did not say where
+        let reject = |transaction: &RawTransaction, rejection: Rejection| {
+            let rejection = rejection.or_coords(transaction.get_coords());
+            Box::new(RejectedTransaction::new(transaction.clone(), rejection))
+        };
+        let mut validated = Vec::new();
+        for transaction in transactions {
+            validated.push(
+                self.validate(transaction.clone())
+                    .map_err(|x| reject(transaction, x))?,
+            );
+        }
+        // Transactions never change, so what each one affects can be found before locking it
+        let targets: Vec<Option<RawTransaction>> = {
+            let world_line = self.world_line.read().unwrap();
+            validated
+                .iter()
+                .map(|x| match *x.get_transaction_type() {
+                    TransactionType::Undo { transaction } => {
+                        world_line.get_undone_transaction(transaction)
+                    }
+                    _ => Some(x.clone()),
+                })
+                .collect()
+        };
+        for (i, target) in targets.iter().enumerate() {
+            if let Some(target) = target
+                .as_ref()
+                .filter(|x| !self.is_allowed(&validated[i], x))
+            {
+                let rejection = Rejection::new(RejectionReason::Vetoed, target.get_coords());
+                return Err(reject(&transactions[i], rejection));
+            }
+        }
+
+        let locked: Vec<RawTransaction> = targets.iter().flatten().cloned().collect();
+        let _chunks = self.lock_targets(&locked);
+        let mut worlds = self.worlds.lock();
+        let mut world_line = self.world_line.write().unwrap();
+
+        // Work on copies, so nothing is committed unless everything applies
+        let mut current = (*worlds.get()).clone();
+        let mut updated_line = world_line.clone();
+        let mut committed = Vec::new();
+        // Whether each transaction is new, rather than a retry of one already committed
+        let mut fresh = Vec::new();
+        for (i, transaction) in validated.into_iter().enumerate() {
+            let rejected = |rejection| reject(&transactions[i], rejection);
+            if let Some(existing) = updated_line.lookup_request(&transaction) {
+                committed.push(existing);
+                fresh.push(false);
+                continue;
+            }
+            fresh.push(true);
+            let transaction = updated_line
+                .order_time(transaction, self.time_ordering)
+                .map_err(rejected)?;
+            match *transaction.get_transaction_type() {
+                TransactionType::Undo { transaction: tid } => {
+                    updated_line
+                        .lookup_transaction(tid)
+                        .ok_or(rejected(RejectionReason::NotFound.into()))?;
+                    committed.push(updated_line.add_transaction(transaction));
+                    if let Some(ref target) = targets[i] {
+                        current = self
+                            .recompute(&current, &updated_line, target)
+                            .map_err(|error| rejected(error.into()))?;
+                    }
+                }
+                _ => {
+                    let world_id = transaction.get_world();
+                    let world = self.world_in(&current, world_id);
+                    let updated = self
+                        .update_world(&world, &transaction, || {
+                            let (x, y, z) = transaction.get_coords().unwrap();
+                            updated_line.get_block_history(world_id, x, y, z)
+                        })
+                        .map_err(rejected)?;
+                    current = current.insert(world_id, updated);
+                    committed.push(updated_line.add_transaction(transaction));
+                }
+            }
+        }
+
+        telemetry::chunk_count(&current);
+        worlds.publish(current);
+        *world_line = updated_line;
+        // Publish while still holding the locks, so subscribers see commit order
+        for ((transaction, target), fresh) in committed.iter().zip(targets).zip(fresh) {
+            if fresh {
+                self.publish(transaction.clone(), target);
+            }
+        }
+        Ok(committed)
+    }
+
+    /// Undoes the n most recent transactions of an owner that have not been undone yet
+    ///
+    /// Undos are never undone this way, only the transactions that changed the world. The Undos
+    /// are committed by the moderator, all together, so either all of them are or none are. They
+    /// are returned in the same order as what they undo, most recent first.
+    ///
+    /// This function aquires a readlock on the world line to find what to undo, and then applies
+    /// the Undos like apply_transaction, blocking until every chunk they affect is available
+    pub fn undo_last(&self, owner: Uuid, n: usize, moderator: Uuid) -> Option<Vec<Transaction>> {
+        let targets: Vec<Transaction> = self
+            .query()
+            .set_owner(owner)
+            .set_newest_first()
+            .set_exclude_undos()
+            .set_exclude_undone()
+            .set_limit(n)
+            .iter()
+            .collect();
+        let undos = targets
+            .iter()
+            .filter_map(|x| {
+                RawTransactionBuilder::new(TransactionType::new_undo(x.get_id()))
+                    .set_owner(moderator)
+                    .set_world(x.get_transaction().get_world())
+                    .set_time_now()
+                    .build_transaction()
+            })
+            .collect();
+        self.apply_all(undos)
+    }
+
+    /// Restores the blocks of a region to how they were at the given time
+    ///
+    /// Every block in the region that has changed since then is set back to the state left by
+    /// the transactions that occured at or before that time. Transactions without a time are not
+    /// taken into account, and biomes are left alone. The Sets are recorded in history like any
+    /// other edit, all with the given owner and the same time, and are applied all together, so
+    /// either the whole region is restored or none of it is.
+    ///
+    /// Returns the Sets committed, ordered by coordinates, or None if one was rejected
+    ///
+    /// This function aquires a readlock on the world line to find the changes, and then applies
+    /// them like apply_transaction, blocking until every chunk they affect is available
+    pub fn restore_region<Tz: TimeZone>(
+        &self,
+        min: (i32, i32, i32),
+        max: (i32, i32, i32),
+        at: DateTime<Tz>,
+        owner: Uuid,
+    ) -> Option<Vec<Transaction>> {
+        let at = at.with_timezone(&Utc);
+        let region = Region::new(min, max);
+        let changes: Vec<((i32, i32, i32), MetaBlock)> = {
+            let world_line = self.world_line.read().unwrap();
+            let blocks: Vec<(i32, i32, i32)> = world_line
+                .get_touched_blocks()
+                .into_iter()
+                .filter(|key| {
+                    key.0 == self.world_id && region.contains((key.1).0, (key.1).1, (key.1).2)
+                })
+                .map(|key| key.1)
+                .collect();
+            let replayed = parallel::map(&blocks, |&(x, y, z)| {
+                let history = world_line.get_block_history(self.world_id, x, y, z);
+                let current = run_history(
+                    history.iter(),
+                    (x, y, z),
+                    self.default_at(self.world_id, x, y, z),
+                    &*self.conflict_policy,
+                );
+                let past = run_history(
+                    history.iter().filter(|t| {
+                        t.get_transaction()
+                            .get_time()
+                            .is_some_and(|time| time <= at)
+                    }),
+                    (x, y, z),
+                    self.default_at(self.world_id, x, y, z),
+                    &*self.conflict_policy,
+                );
+                (current, past)
+            });
+            blocks
+                .into_iter()
+                .zip(replayed)
+                .filter(|(_, (current, past))| current != past)
+                .map(|(coords, (_, past))| (coords, past))
+                .collect()
+        };
+
+        // Every Set shares the same time, like the edits of a single session
+        let now = Local::now();
+        let sets = changes
+            .into_iter()
+            .filter_map(|((x, y, z), block)| {
+                RawTransactionBuilder::new(TransactionType::new_set(block))
+                    .set_owner(owner)
+                    .set_world(self.world_id)
+                    .set_time(now)
+                    .set_x_coord(x)
+                    .set_y_coord(y)
+                    .set_z_coord(z)
+                    .build_transaction()
+            })
+            .collect();
+        self.apply_all(sets)
+    }
+
+    /// Applies an Undo, rerunning the history of whatever it undoes
+    fn apply_undo(
+        &self,
+        transaction: RawTransaction,
+        tid: TransactionID,
+        expected: Option<u64>,
+    ) -> Result<Transaction, Rejection> {
+        // Transactions never change, so what the Undo affects can be found before locking it
+        let target = self.world_line.read().unwrap().get_undone_transaction(tid);
+        if let Some(target) = target
+            .as_ref()
+            .filter(|x| !self.is_allowed(&transaction, x))
+        {
+            return Err(Rejection::new(RejectionReason::Vetoed, target.get_coords()));
+        }
+        let _chunks = target.as_ref().map(|x| self.lock_target(x));
+        let mut worlds = self.worlds.lock();
+        let mut world_line = self.world_line.write().unwrap();
+
+        if let Some(existing) = world_line.lookup_request(&transaction) {
+            return Ok(existing);
+        }
+        world_line.check_generation(expected)?;
+        // Make sure the transaction exists
+        world_line
+            .lookup_transaction(tid)
+            .ok_or(RejectionReason::NotFound)?;
+        let transaction = world_line.order_time(transaction, self.time_ordering)?;
+        // Add the Undo transaction to a copy of the history first, so nothing is committed if a
+        // chunk can not be loaded back
+        let mut updated_line = world_line.clone();
+        let committed = updated_line.add_transaction(transaction);
+        // Rerun the history of whatever was undone
+        if let Some(ref target) = target {
+            let recomputed = self.recompute(&worlds.get(), &updated_line, target)?;
+            telemetry::chunk_count(&recomputed);
+            worlds.publish(recomputed);
+        }
+        *world_line = updated_line;
+
+        // Publish while still holding the locks, so subscribers see commit order
+        self.publish(committed.clone(), target);
+        Ok(committed)
+    }
+
+    /// Returns the history of the block
+    ///
+    /// A history is a list of (MetaBlock, Transaction) pairs, describing the state of the block, and the
+    /// transaction that resulted in that state. Pairs are arranged incronological order, with the
+    /// oldest first
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn get_block_history(&self, x: i32, y: i32, z: i32) -> Vec<(MetaBlock, Transaction)> {
+        let started = Instant::now();
+        // Aquire the readlock on the world_line
+        let world_line = self.world_line.read().unwrap();
+        let output = self.run_block_history(&world_line, x, y, z);
+        telemetry::history_query_finished("block_history", started);
+        output
+    }
+
+    /// Returns the history of the block, only including the transactions of the given owner, and
+    /// those within the given time range
+    ///
+    /// Either criteria can be left out. The states are those of the full history, so each one is
+    /// still the state of the block just after the transaction, no matter who did what in between.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn get_block_history_filtered(
+        &self,
+        x: i32,
+        y: i32,
+        z: i32,
+        owner: Option<Uuid>,
+        range: Option<TimeRange>,
+    ) -> Vec<(MetaBlock, Transaction)> {
+        let started = Instant::now();
+        let world_line = self.world_line.read().unwrap();
+        let output = self
+            .run_block_history(&world_line, x, y, z)
+            .into_iter()
+            .filter(|(_, t)| owner.is_none_or(|owner| t.get_transaction().get_owner() == owner))
+            .filter(|(_, t)| range.is_none_or(|range| range.contains_transaction(t)))
+            .collect();
+        telemetry::history_query_finished("block_history", started);
+        output
+    }
+
+    /// Returns whether a Replace changed its block, or None if the transaction is not a Replace
+    /// in the worldline
+    ///
+    /// A Replace whose precondition did not hold, and that was resolved with Resolution::Record,
+    /// is in history without having changed its block. Replaces are resolved again whenever
+    /// history is replayed, so this describes history as it is now, and an undone Replace has not
+    /// changed its block either.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn is_replace_applied(&self, id: TransactionID) -> Option<bool> {
+        let world_line = self.world_line.read().unwrap();
+        let transaction = world_line.lookup_transaction(id)?;
+        let raw = transaction.get_transaction();
+        if !matches!(raw.get_transaction_type(), TransactionType::Replace { .. }) {
+            return None;
+        }
+        let (x, y, z) = raw.get_coords()?;
+        let world_id = raw.get_world();
+        let history = world_line.get_block_history(world_id, x, y, z);
+        let steps = run_history_steps(
+            history.iter(),
+            (x, y, z),
+            self.default_at(world_id, x, y, z),
+            &*self.conflict_policy,
+        );
+        Some(steps.iter().any(|step| step.2.get_id() == id))
+    }
+
+    /// Returns the state of a block entering a window of time, and every transition within it
+    ///
+    /// The state entering the window is the block as left by every transaction that occured
+    /// before start. The transitions are the transactions that occured from start up to but not
+    /// including end, each paired with the state of the block just after it, like
+    /// get_block_history. Transactions without a time are in neither.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn get_block_history_between<Tz: TimeZone>(
+        &self,
+        x: i32,
+        y: i32,
+        z: i32,
+        start: DateTime<Tz>,
+        end: DateTime<Tz>,
+    ) -> (MetaBlock, Vec<(MetaBlock, Transaction)>) {
+        let (start, end) = (start.with_timezone(&Utc), end.with_timezone(&Utc));
+        let started = Instant::now();
+        let world_line = self.world_line.read().unwrap();
+        let history = world_line.get_block_history(self.world_id, x, y, z);
+        let entering = run_history(
+            history.iter().filter(|t| {
+                t.get_transaction()
+                    .get_time()
+                    .is_some_and(|time| time < start)
+            }),
+            (x, y, z),
+            self.default_at(self.world_id, x, y, z),
+            &*self.conflict_policy,
+        );
+        let transitions = match TimeRange::new(start, end) {
+            Some(range) => self
+                .run_block_history(&world_line, x, y, z)
+                .into_iter()
+                .filter(|(_, t)| range.contains_transaction(t))
+                .collect(),
+            None => Vec::new(),
+        };
+        telemetry::history_query_finished("block_history", started);
+        (entering, transitions)
+    }
+
+    /// Runs the history of a block in the world of this Rewind, pairing each transaction with the
+    /// state of the block just after it
+    fn run_block_history(
+        &self,
+        world_line: &WorldLine,
+        x: i32,
+        y: i32,
+        z: i32,
+    ) -> Vec<(MetaBlock, Transaction)> {
+        let transactions: Vec<Transaction> = world_line.get_block_history(self.world_id, x, y, z);
+
+        let mut output = Vec::new();
+
+        for (i, transaction) in transactions.iter().enumerate() {
+            let history = transactions.iter().take(i + 1);
+            let block: MetaBlock = run_history(
+                history,
+                (x, y, z),
+                self.default_at(self.world_id, x, y, z),
+                &*self.conflict_policy,
+            );
+            output.push((block, transaction.clone()));
+        }
+
+        output
+    }
+
+    /// Returns the block as it was just after the given transaction was committed
+    ///
+    /// Undos committed after that transaction are not taken into account.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn get_block_as_of(&self, x: i32, y: i32, z: i32, transaction: TransactionID) -> MetaBlock {
+        let started = Instant::now();
+        let world_line = self.world_line.read().unwrap();
+        let history = world_line.get_block_history(self.world_id, x, y, z);
+        let block = run_history(
+            history.iter().filter(|t| t.get_id() <= transaction),
+            (x, y, z),
+            self.default_at(self.world_id, x, y, z),
+            &*self.conflict_policy,
+        );
+        telemetry::history_query_finished("block_as_of", started);
+        block
+    }
+
+    /// Returns a transaction and every transaction it caused, such as the liquid flow from a
+    /// bucket placement
+    ///
+    /// Follows the causes recorded with RawTransactionBuilder::set_cause, through any number of
+    /// steps, and returns the transactions in chronological order, starting with the given one.
+    /// Returns an empty list if the transaction does not exist.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn trace_cause_chain(&self, transaction: TransactionID) -> Vec<Transaction> {
+        let world_line = self.world_line.read().unwrap();
+        if world_line.lookup_transaction(transaction).is_none() {
+            return Vec::new();
+        }
+        world_line
+            .trace_cause_chain(transaction)
+            .into_iter()
+            .filter_map(|x| world_line.lookup_transaction(x))
+            .collect()
+    }
+
+    /// Returns the history of a decoration, such as an item frame
+    ///
+    /// A history is a list of (Option<Decoration>, Transaction) pairs, describing what the
+    /// decoration looked like and held just after each transaction, None once it was broken.
+    /// Pairs are arranged in chronological order, with the oldest first.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn get_decoration_history(&self, entity: Uuid) -> Vec<(Option<Decoration>, Transaction)> {
+        let started = Instant::now();
+        let world_line = self.world_line.read().unwrap();
+        let history = world_line.get_decoration_history(self.world_id, entity);
+        let output = (0..history.len())
+            .map(|i| {
+                let state = run_decoration_history(&history[..=i]).map(|x| x.1);
+                (state, history[i].clone())
+            })
+            .collect();
+        telemetry::history_query_finished("decoration_history", started);
+        output
+    }
+
+    /// Returns the uuid of every decoration placed, changed or broken inside the region,
+    /// ordered by uuid
+    ///
+    /// Decorations that have since been broken are included, so whatever used to hang in a
+    /// region can be found, and its history looked up with get_decoration_history.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn get_decorations_in_region(
+        &self,
+        min: (i32, i32, i32),
+        max: (i32, i32, i32),
+    ) -> Vec<Uuid> {
+        let region = Region::new(min, max);
+        let world_line = self.world_line.read().unwrap();
+        let entities: OrdSet<Uuid> = world_line
+            .transactions
+            .values()
+            .map(|x| x.get_transaction().clone())
+            .filter(|raw| raw.get_world() == self.world_id)
+            .filter(|raw| match raw.get_coords() {
+                Some((x, y, z)) => region.contains(x, y, z),
+                None => false,
+            })
+            .filter_map(|raw| raw.get_decoration_entity())
+            .collect();
+        entities.into_iter().map(|x| *x).collect()
+    }
+
+    /// Returns every block in the region that differs between two points in history
+    ///
+    /// Each entry is the coordinates of a block, its state just after from, and its state just
+    /// after to, ordered by coordinates. The histories of the blocks are replayed in parallel
+    /// with the parallel feature.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn diff_region(
+        &self,
+        region: Region,
+        from: TransactionID,
+        to: TransactionID,
+    ) -> Vec<((i32, i32, i32), MetaBlock, MetaBlock)> {
+        let started = Instant::now();
+        let world_line = self.world_line.read().unwrap();
+        let blocks: Vec<(i32, i32, i32)> = world_line
+            .get_touched_blocks()
+            .into_iter()
+            .filter(|key| {
+                key.0 == self.world_id && region.contains((key.1).0, (key.1).1, (key.1).2)
+            })
+            .map(|key| key.1)
+            .collect();
+
+        let replayed = parallel::map(&blocks, |&(x, y, z)| {
+            let history = world_line.get_block_history(self.world_id, x, y, z);
+            let before = run_history(
+                history.iter().filter(|t| t.get_id() <= from),
+                (x, y, z),
+                self.default_at(self.world_id, x, y, z),
+                &*self.conflict_policy,
+            );
+            let after = run_history(
+                history.iter().filter(|t| t.get_id() <= to),
+                (x, y, z),
+                self.default_at(self.world_id, x, y, z),
+                &*self.conflict_policy,
+            );
+            (before, after)
+        });
+        let output = blocks
+            .into_iter()
+            .zip(replayed)
+            .filter(|(_, (before, after))| before != after)
+            .map(|(coords, (before, after))| (coords, before, after))
+            .collect();
+        telemetry::history_query_finished("diff_region", started);
+        output
+    }
+
+    /// Returns every change to a block across all worlds, in the order they were committed
+    ///
+    /// Each change is the block before the transaction, the block after it, and the transaction.
+    /// Undone transactions, and Replaces that did not apply, are left out.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    fn get_block_changes(&self) -> Vec<(MetaBlock, MetaBlock, Transaction)> {
+        let world_line = self.world_line.read().unwrap();
+        let blocks: Vec<(WorldId, (i32, i32, i32))> = world_line
+            .get_touched_blocks()
+            .into_iter()
+            .map(|x| *x)
+            .collect();
+        let replayed = parallel::map(&blocks, |&(world, (x, y, z))| {
+            let history = world_line.get_block_history(world, x, y, z);
+            run_history_steps(
+                history.iter(),
+                (x, y, z),
+                self.default_at(world, x, y, z),
+                &*self.conflict_policy,
+            )
+        });
+        let mut changes: Vec<_> = replayed.into_iter().flatten().collect();
+        changes.sort_by_key(|x| x.2.get_id());
+        changes
+    }
+
+    /// Returns the n owners who changed the most blocks within the time range, along with how
+    /// many blocks each changed, most first
+    ///
+    /// Every block an explosion or piston move affected counts, and transactions that do not
+    /// change blocks, like SetBiomes, count once. Undos are not counted, while what they undid
+    /// still is, as it did happen. Ties are broken by uuid.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn top_owners_by_changes(&self, range: TimeRange, n: usize) -> Vec<(Uuid, usize)> {
+        let mut counts: BTreeMap<Uuid, usize> = BTreeMap::new();
+        for transaction in self
+            .query()
+            .set_time_range(range)
+            .set_exclude_undos()
+            .iter()
+        {
+            let raw = transaction.get_transaction();
+            *counts.entry(raw.get_owner()).or_insert(0) += raw.get_affected_coords().len().max(1);
+        }
+        top_counts(counts, n)
+    }
+
+    /// Returns the n blocks broken the most within the time range, along with how many of each
+    /// were broken, most first
+    ///
+    /// A block is broken when it is replaced with the default block, like in analysis, and
+    /// blocks are counted regardless of their metadata. Undone transactions, and Replaces that did
+    /// not apply, are left out. Ties are broken by block id.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn top_blocks_broken(&self, range: TimeRange, n: usize) -> Vec<(Block, usize)> {
+        let default_block = self.get_default_block();
+        // Keyed by provider and id, so ties come out in order
+        let mut counts: BTreeMap<(u16, u16), usize> = BTreeMap::new();
+        for (before, after, transaction) in self.get_block_changes() {
+            if range.contains_transaction(&transaction)
+                && after == default_block
+                && before != default_block
+            {
+                let block = before.get_block();
+                *counts
+                    .entry((block.get_provider(), block.get_id()))
+                    .or_insert(0) += 1;
+            }
+        }
+        top_counts(counts, n)
+            .into_iter()
+            .map(|((provider, id), count)| (Block::new_from_ids(provider, id), count))
+            .collect()
+    }
+
+    /// Returns the number of block changes within the time range in each cell of a grid over the
+    /// region, see the heatmap module
+    ///
+    /// Every block an explosion or piston move affected inside the region counts. Undos, SetBiomes
+    /// and decoration transactions are not counted, while what Undos undid still is, as it did
+    /// happen. Returns None if the cell size is zero.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn heatmap(&self, region: Region, cell_size: u32, range: TimeRange) -> Option<Heatmap> {
+        let mut heatmap = Heatmap::new(region, cell_size)?;
+        let transactions = self
+            .query()
+            .set_world(self.world_id)
+            .set_region(region)
+            .set_time_range(range)
+            .set_exclude_undos()
+            .iter();
+        for transaction in transactions {
+            let raw = transaction.get_transaction();
+            if raw.is_set_biome() || raw.is_decoration() {
+                continue;
+            }
+            for coords in raw.get_affected_coords() {
+                heatmap.add(coords);
+            }
+        }
+        Some(heatmap)
+    }
+
+    /// Previews rolling back every transaction matching the filter, without changing anything
+    ///
+    /// A rollback undoes every matching Set, Replace, Explode and PistonMove that has not
+    /// already been undone, Undos, SetBiomes and decoration transactions are left alone.
+    /// Transactions caused by a rolled back transaction, such as the liquid flowing from a bucket
+    /// placement, are rolled back with it, whether they match the filter or not. The plan lists
+    /// every block that would change and what it would change to, found by replaying the history
+    /// of each affected block without the rolled back transactions, along with counts per player
+    /// and per block for review.
+    ///
+    /// This function aquires a readlock on the world line, and will block until it is available
+    pub fn preview_rollback(&self, filter: &TransactionFilter) -> RollbackPlan {
+        let world_line = self.world_line.read().unwrap();
+        let matching = world_line
+            .transactions
+            .values()
+            .filter(|x| filter.matches(x))
+            .map(|x| x.get_id());
+        let transactions: Vec<Transaction> = world_line
+            .trace_cause_chains(matching)
+            .into_iter()
+            .filter_map(|x| world_line.lookup_transaction(*x))
+            .filter(|x| !x.is_undo() && !x.is_set_biome() && !x.is_decoration())
+            .filter(|x| !world_line.is_undone(x.get_id()))
+            .collect();
+        let rolled_back: OrdSet<TransactionID> = transactions.iter().map(|x| x.get_id()).collect();
+        let blocks: Vec<(WorldId, (i32, i32, i32))> = transactions
+            .iter()
+            .flat_map(|x| {
+                let raw = x.get_transaction();
+                let world = raw.get_world();
+                raw.get_affected_coords()
+                    .into_iter()
+                    .map(move |coords| (world, coords))
+            })
+            .collect::<OrdSet<_>>()
+            .into_iter()
+            .map(|x| *x)
+            .collect();
+
+        let replayed = parallel::map(&blocks, |&(world, (x, y, z))| {
+            let history = world_line.get_block_history(world, x, y, z);
+            let before = run_history(
+                history.iter(),
+                (x, y, z),
+                self.default_at(world, x, y, z),
+                &*self.conflict_policy,
+            );
+            let after = run_history(
+                history
+                    .iter()
+                    .filter(|t| !rolled_back.contains(&t.get_id())),
+                (x, y, z),
+                self.default_at(world, x, y, z),
+                &*self.conflict_policy,
+            );
+            (before, after)
+        });
+        let changes = blocks
+            .into_iter()
+            .zip(replayed)
+            .filter(|(_, (before, after))| before != after)
+            .map(|((world, coords), (before, after))| {
+                BlockChange::new(world, coords, before, after)
+            })
+            .collect();
+        let as_of = world_line.transactions.get_max().map(|(id, _)| *id);
+        RollbackPlan::new(transactions, changes, as_of)
+    }
+
+    /// Applies a rollback plan, committing an Undo of each of its transactions as the owner
+    ///
+    /// Returns the committed Undos, or None, applying nothing, if any block of the plan has been
+    /// written to since it was previewed, as the plan may no longer say what the rollback does.
+    ///
+    /// This function aquires a readlock on the world line to check the plan, and then applies
+    /// each Undo like apply_transaction
+    pub fn apply_plan(&self, plan: &RollbackPlan, owner: Uuid) -> Option<Vec<Transaction>> {
+        let blocks: OrdSet<(WorldId, (i32, i32, i32))> = plan
+            .get_transactions()
+            .iter()
+            .flat_map(|x| {
+                let raw = x.get_transaction();
+                let world = raw.get_world();
+                raw.get_affected_coords()
+                    .into_iter()
+                    .map(move |coords| (world, coords))
+            })
+            .collect();
+        {
+            let world_line = self.world_line.read().unwrap();
+            let mut newer = world_line
+                .transactions
+                .values()
+                .filter(|x| plan.get_as_of().is_none_or(|as_of| x.get_id() > as_of));
+            let stale = newer.any(|x| {
+                let target = match *x.get_transaction().get_transaction_type() {
+                    TransactionType::Undo { transaction } => {
+                        world_line.get_undone_transaction(transaction)
+                    }
+                    _ => Some(x.get_transaction().clone()),
+                };
+                target.is_some_and(|raw| {
+                    raw.get_affected_coords()
+                        .into_iter()
+                        .any(|coords| blocks.contains(&(raw.get_world(), coords)))
+                })
+            });
+            if stale {
+                return None;
+            }
+        }
+
+        let undos = plan
+            .get_transactions()
+            .iter()
+            .filter_map(|x| {
+                let undo = RawTransactionBuilder::new(TransactionType::new_undo(x.get_id()))
+                    .set_owner(owner)
+                    .set_world(x.get_transaction().get_world())
+                    .set_time_now()
+                    .build_transaction()?;
+                self.apply_transaction(undo)
+            })
+            .collect();
+        Some(undos)
+    }
+}
+
+/// Runs history on a slice of transactions, for the block at the given coordinates
+fn run_history<'a>(
+    history: impl Iterator<Item = &'a Transaction>,
+    coords: (i32, i32, i32),
+    default_block: MetaBlock,
+    policy: &dyn ConflictPolicy,
+) -> MetaBlock {
+    run_history_tracking(history, coords, default_block, policy).0
+}
+
+/// Runs history on a slice of transactions, also returning the last transaction to change the
+/// block
+///
+/// Replaces whose precondition does not hold are resolved with the policy
+fn run_history_tracking<'a>(
+    history: impl Iterator<Item = &'a Transaction>,
+    coords: (i32, i32, i32),
+    default_block: MetaBlock,
+    policy: &dyn ConflictPolicy,
+) -> (MetaBlock, Option<Transaction>) {
+    match run_history_steps(history, coords, default_block.clone(), policy).pop() {
+        Some((_, block, transaction)) => (block, Some(transaction)),
+        None => (default_block, None),
+    }
+}
+
+/// Runs history on a slice of transactions, returning every transaction that changed the block
+///
+/// Each step is the block before the transaction, the block after it, and the transaction, in
+/// chronological order. Undone transactions, and Replaces the policy did not apply, are left out.
+fn run_history_steps<'a>(
+    history: impl Iterator<Item = &'a Transaction>,
+    coords: (i32, i32, i32),
+    default_block: MetaBlock,
+    policy: &dyn ConflictPolicy,
+) -> Vec<(MetaBlock, MetaBlock, Transaction)> {
+    // Vector to hold history
+    let history: Vec<Transaction> = history.cloned().collect();
+    // History without any of the Undos present, or anything they undid
+    let final_history: Vec<Transaction> = history
+        .iter()
+        .filter(|x| !x.is_undo() && !is_undone(x.get_id(), &history))
+        .cloned()
+        .collect();
+
+    // Actually run history on the slice
+    let mut block = default_block;
+    let mut steps: Vec<(MetaBlock, MetaBlock, Transaction)> = Vec::new();
+    for transaction in final_history.into_iter() {
+        let raw = transaction.get_transaction();
+        let set = match raw.get_transaction_type() {
+            TransactionType::Replace { block_current, .. } => {
+                let resolution = if block == block_current.get() {
+                    Resolution::Apply
+                } else {
+                    let last_writer = steps.last().map(|x| x.2.clone());
+                    let conflict = Conflict::new(raw.clone(), block.clone(), last_writer);
+                    conflict::resolve(policy, &conflict)
+                };
+                if resolution == Resolution::Apply {
+                    raw.get_block_set_at(coords)
+                } else {
+                    None
+                }
+            }
+            _ => raw.get_block_set_at(coords),
+        };
+        if let Some(set) = set {
+            steps.push((block, set.clone(), transaction));
+            block = set;
+        }
+    }
+    steps
+}
+
+/// Runs the history of a biome cell
+fn run_biome_history(history: &[Transaction]) -> Biome {
+    history
+        .iter()
+        .filter(|x| !x.is_undo() && !is_undone(x.get_id(), history))
+        .filter_map(|x| match *x.get_transaction().get_transaction_type() {
+            TransactionType::SetBiome { biome } => Some(biome),
+            _ => None,
+        })
+        .next_back()
+        .unwrap_or_default()
+}
+
+/// Runs the history of a decoration, returning the block it is in and what it looks like, or
+/// None if it has been broken, or never placed
+fn run_decoration_history(history: &[Transaction]) -> Option<((i32, i32, i32), Decoration)> {
+    history
+        .iter()
+        .rfind(|x| x.is_decoration() && !x.is_undo() && !is_undone(x.get_id(), history))
+        .and_then(|x| {
+            let raw = x.get_transaction();
+            match raw.get_transaction_type() {
+                TransactionType::SetDecoration { decoration, .. } => {
+                    Some((raw.get_coords()?, decoration.clone()))
+                }
+                _ => None,
+            }
+        })
+}
+
+/// Returns the n largest counts, largest first, with ties in the order of their keys
+fn top_counts<K: Ord>(counts: BTreeMap<K, usize>, n: usize) -> Vec<(K, usize)> {
+    let mut counts: Vec<(K, usize)> = counts.into_iter().collect();
+    // Sorting is stable, so ties stay in the order of their keys
+    counts.sort_by_key(|x| std::cmp::Reverse(x.1));
+    counts.truncate(n);
+    counts
+}
+
+/// Returns true if the transaction has been undone by an Undo in history
+///
+/// An Undo only counts if it has not itself been undone
+fn is_undone(transaction_id: TransactionID, history: &[Transaction]) -> bool {
+    history
+        .iter()
+        .any(|x| match *x.get_transaction().get_transaction_type() {
+            TransactionType::Undo { transaction } if transaction == transaction_id => {
+                !is_undone(x.get_id(), history)
+            }
+            _ => false,
+        })
+}
+
+/// Version of the worldline export format
+const WORLDLINE_VERSION: u32 = 1;
+
+/// First line of a worldline export
+#[derive(Serialize, Deserialize)]
+struct WorldLineHeader {
+    version: u32,
+    default_block: MetaBlock,
+    /// Left out when the default block is the same at every height
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_layers: Option<DefaultLayers>,
+}
+
+/// Wraps an error as an InvalidData io::Error
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// A block in one of the worlds
+type BlockKey = (WorldId, (i32, i32, i32));
+
+/// The coordinates of a block, along with its state before and after a change
+type BlockDiff = ((i32, i32, i32), MetaBlock, MetaBlock);
+
+/// Contains and manages the list of transactions in a world
+#[derive(Clone)]
+struct WorldLine {
+    /// The list of transactions is stored as an OrdMap to allow lookup by transaction id
+    /// when there have been inserted transaction revisions
+    transactions: OrdMap<TransactionID, Transaction>,
+    /// Id of the transaction committed with each request id
+    requests: OrdMap<Uuid, TransactionID>,
+    /// Ids of the transactions that wrote each word of text onto a block, see search_text
+    text_index: OrdMap<String, OrdSet<TransactionID>>,
+    /// Number of transactions committed to the worldline
+    generation: u64,
+    /// Latest wall-clock time of any transaction in the worldline
+    latest_time: Option<DateTime<Utc>>,
+    /// Node id stamped on transactions committed here, as their sub_id
+    node_id: u32,
+}
+
+impl WorldLine {
+    /// Creates a new WorldLine, with an empty transaction log
+    fn new() -> WorldLine {
+        WorldLine {
+            transactions: OrdMap::new(),
+            requests: OrdMap::new(),
+            text_index: OrdMap::new(),
+            generation: 0,
+            latest_time: None,
+            node_id: 0,
+        }
+    }
+
+    /// Adds a transaction to the worldline
+    ///
+    /// The major id acts as a lamport clock: it is one past the largest major id seen so far,
+    /// including transactions merged from other nodes, so the order of ids always respects
+    /// causality.
+    fn add_transaction(&mut self, transaction: RawTransaction) -> Transaction {
+        // Get the TransactionID of the last transaction in the worldline
+        let last_transaction = self.transactions.get_max();
+        let id = match last_transaction {
+            Some((t, _)) => TransactionID::new_from_parts(t.get_id() + 1, self.node_id),
+            None => TransactionID::new_from_parts(0, self.node_id),
+        };
+
+        let new_transaction = Transaction::new(transaction, id);
+
+        // Add the new transaction to the list
+        self.insert_transaction(new_transaction.clone());
+
+        new_transaction
+    }
+
+    /// Inserts an already committed transaction into the worldline, keeping its id
+    fn insert_transaction(&mut self, transaction: Transaction) {
+        self.generation += 1;
+        self.latest_time = self
+            .latest_time
+            .max(transaction.get_transaction().get_time());
+        if let Some(request_id) = transaction.get_transaction().get_request_id() {
+            self.requests = self.requests.insert(request_id, transaction.get_id());
+        }
+        self.index_text(&transaction);
+        self.transactions = self.transactions.insert(transaction.get_id(), transaction);
+    }
+
+    /// Adds the words of any text a transaction writes onto a block to the text index
+    ///
+    /// Only Sets and Replaces write text, moving a sign with a piston does not count as writing it.
+    fn index_text(&mut self, transaction: &Transaction) {
+        let block = match transaction.get_transaction().get_transaction_type() {
+            TransactionType::Set { block_set } | TransactionType::Replace { block_set, .. } => {
+                block_set.get()
+            }
+            _ => return,
+        };
+        for text in get_block_text(&block) {
+            for word in get_words(&text) {
+                let ids = self.text_index.get(&word).map(|x| (*x).clone());
+                let ids = ids.unwrap_or_default().insert(transaction.get_id());
+                self.text_index = self.text_index.insert(word, ids);
+            }
+        }
+    }
+
+    /// Returns the ids of every transaction that wrote text containing every word of the query,
+    /// in order
+    fn search_text(&self, query: &str) -> Vec<TransactionID> {
+        let mut words = get_words(query).into_iter();
+        let first = match words.next() {
+            Some(word) => word,
+            None => return Vec::new(),
+        };
+        let mut ids = self
+            .text_index
+            .get(&first)
+            .map(|x| (*x).clone())
+            .unwrap_or_default();
+        for word in words {
+            let matching = match self.text_index.get(&word) {
+                Some(matching) => matching,
+                None => return Vec::new(),
+            };
+            ids = ids.intersection((*matching).clone());
+        }
+        ids.iter().map(|x| *x).collect()
+    }
+
+    /// Fails with the reason Stale unless the worldline is at the expected generation, if there
+    /// is one
+    fn check_generation(&self, expected: Option<u64>) -> Result<(), Rejection> {
+        match expected {
+            Some(expected) if expected != self.generation => Err(RejectionReason::Stale.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Orders a transaction about to be committed after the latest time in the worldline,
+    /// returning the transaction to commit in its place, see TimeOrdering
+    fn order_time(
+        &self,
+        transaction: RawTransaction,
+        ordering: TimeOrdering,
+    ) -> Result<RawTransaction, Rejection> {
+        ordering
+            .order(transaction, self.latest_time)
+            .map_err(Rejection::from)
+    }
+
+    /// Returns the transaction already committed with the same request id as this one, if any
+    fn lookup_request(&self, transaction: &RawTransaction) -> Option<Transaction> {
+        let id = self.requests.get(&transaction.get_request_id()?)?;
+        self.lookup_transaction(*id)
+    }
+
+    /// Get a particular transaction
+    fn lookup_transaction(&self, transaction_id: TransactionID) -> Option<Transaction> {
+        self.transactions.get(&transaction_id).map(|x| (*x).clone())
+    }
+
+    /// Returns the ids of every Undo transaction that undoes this one
+    fn get_undos(&self, transaction_id: TransactionID) -> Vec<TransactionID> {
+        let transactions = self.transactions.clone();
+        let mut undos = Vec::new();
+        // Check for transactions that undo this one
+        for (k, v) in transactions.into_iter() {
+            if let TransactionType::Undo { transaction } =
+                *v.get_transaction().get_transaction_type()
+            {
+                if transaction == transaction_id {
+                    undos.push(*k);
+                }
+            }
+        }
+        undos
+    }
+
+    /// Returns a query over a snapshot of this worldline
+    fn query(&self) -> Query {
+        Query::new(self.clone())
+    }
+
+    /// Returns true if the transaction has been undone
+    ///
+    /// An Undo only counts if it has not itself been undone
+    fn is_undone(&self, transaction_id: TransactionID) -> bool {
+        self.get_undos(transaction_id)
+            .into_iter()
+            .any(|undo| !self.is_undone(undo))
+    }
+
+    /// Returns the entire undo history for a transaction
+    ///
+    /// This includes the transaction itself, every Undo of it, every Undo of those, and so on
+    fn get_undo_history(&self, transaction_id: TransactionID) -> Vec<TransactionID> {
+        let mut history = Vec::new();
+
+        let mut pending = vec![transaction_id];
+        while let Some(t) = pending.pop() {
+            history.push(t);
+            pending.extend(self.get_undos(t));
+        }
+
+        history
+    }
+
+    /// Returns the transactions caused by any of the given ones, directly or through others
+    /// they caused, along with the given transactions themselves
+    fn trace_cause_chains<I>(&self, transaction_ids: I) -> OrdSet<TransactionID>
+    where
+        I: IntoIterator<Item = TransactionID>,
+    {
+        let mut caused: BTreeMap<TransactionID, Vec<TransactionID>> = BTreeMap::new();
+        for (id, transaction) in self.transactions.iter() {
+            if let Some(cause) = transaction.get_transaction().get_cause() {
+                caused.entry(cause).or_default().push(*id);
+            }
+        }
+
+        let mut chain = OrdSet::new();
+        let mut pending: Vec<TransactionID> = transaction_ids.into_iter().collect();
+        while let Some(t) = pending.pop() {
+            if chain.contains(&t) {
+                continue;
+            }
+            chain = chain.insert(t);
+            if let Some(ids) = caused.get(&t) {
+                pending.extend(ids);
+            }
+        }
+        chain
+    }
+
+    /// Returns the chain of transactions caused by a transaction
+    ///
+    /// This includes the transaction itself, every transaction that names it as its cause, every
+    /// transaction that names one of those, and so on, in chronological order
+    fn trace_cause_chain(&self, transaction_id: TransactionID) -> Vec<TransactionID> {
+        self.trace_cause_chains(Some(transaction_id))
+            .into_iter()
+            .map(|x| *x)
+            .collect()
+    }
+
+    /// Returns the world and coordinates of every block a transaction has been applied to
+    fn get_touched_blocks(&self) -> OrdSet<(WorldId, (i32, i32, i32))> {
+        let mut set = OrdSet::new();
+        for transaction in self.transactions.values() {
+            let raw = transaction.get_transaction();
+            if raw.is_set_biome() || raw.is_decoration() {
+                continue;
+            }
+            for coords in raw.get_affected_coords() {
+                set = set.insert((raw.get_world(), coords));
+            }
+        }
+        set
+    }
+
+    /// Returns the world and cell of every biome cell a transaction has been applied to
+    fn get_touched_biome_cells(&self) -> OrdSet<(WorldId, (i32, i32, i32))> {
+        let mut set = OrdSet::new();
+        for transaction in self.transactions.values() {
+            let raw = transaction.get_transaction();
+            match raw.get_coords() {
+                Some((x, y, z)) if raw.is_set_biome() => {
+                    let cell = BlockPos::new(x, y, z).get_biome_cell();
+                    set = set.insert((raw.get_world(), cell));
+                }
+                _ => (),
+            }
+        }
+        set
+    }
+
+    /// Returns a set of transactions that have been applied to a particular block
+    ///
+    /// Does not include Undos, SetBiomes, or decoration transactions
+    fn get_transactions_for_block(
+        &self,
+        world: WorldId,
+        x: i32,
+        y: i32,
+        z: i32,
+    ) -> OrdSet<TransactionID> {
+        let mut set = OrdSet::new();
+        let coords = (x, y, z);
+
+        let transactions = self.transactions.clone();
+        for (k, v) in transactions.into_iter() {
+            let raw = v.get_transaction();
+            if raw.get_world() == world
+                && raw.affects_coords(coords)
+                && !raw.is_set_biome()
+                && !raw.is_decoration()
+            {
+                set = set.insert(k);
+            }
+        }
+
+        set
+    }
+
+    /// Returns the history of all transactions to affect this particular block
+    ///
+    /// In chronological order, oldest first
+    fn get_block_history(&self, world: WorldId, x: i32, y: i32, z: i32) -> Vec<Transaction> {
+        // Get the initial list of transactions
+        let set = self.get_transactions_for_block(world, x, y, z);
+        self.with_undo_history(set)
+    }
+
+    /// Returns the history of every block a transaction has been applied to, like
+    /// get_block_history, in a single pass over the worldline rather than one per block
+    fn get_block_histories(&self) -> BTreeMap<BlockKey, Vec<Transaction>> {
+        let mut undos: BTreeMap<TransactionID, Vec<TransactionID>> = BTreeMap::new();
+        let mut blocks: BTreeMap<BlockKey, Vec<TransactionID>> = BTreeMap::new();
+        for (id, transaction) in self.transactions.iter() {
+            let raw = transaction.get_transaction();
+            if let TransactionType::Undo { transaction } = *raw.get_transaction_type() {
+                undos.entry(transaction).or_default().push(*id);
+            }
+            if raw.is_set_biome() || raw.is_decoration() {
+                continue;
+            }
+            for coords in raw.get_affected_coords() {
+                blocks
+                    .entry((raw.get_world(), coords))
+                    .or_default()
+                    .push(*id);
+            }
+        }
+
+        blocks
+            .into_iter()
+            .map(|(key, mut pending)| {
+                // Follow the chains of Undos, like with_undo_history
+                let mut history = BTreeSet::new();
+                while let Some(id) = pending.pop() {
+                    if history.insert(id) {
+                        pending.extend(undos.get(&id).into_iter().flatten());
+                    }
+                }
+                let history = history
+                    .into_iter()
+                    .filter_map(|id| self.lookup_transaction(id))
+                    .collect();
+                (key, history)
+            })
+            .collect()
+    }
+
+    /// Returns the history of all SetBiomes to affect a particular biome cell
+    ///
+    /// In chronological order, oldest first
+    fn get_biome_history(&self, world: WorldId, cell: (i32, i32, i32)) -> Vec<Transaction> {
+        let mut set = OrdSet::new();
+        for (k, v) in self.transactions.clone().into_iter() {
+            let raw = v.get_transaction();
+            match raw.get_coords() {
+                Some((x, y, z))
+                    if raw.get_world() == world
+                        && raw.is_set_biome()
+                        && BlockPos::new(x, y, z).get_biome_cell() == cell =>
+                {
+                    set = set.insert(k);
+                }
+                _ => (),
+            }
+        }
+        self.with_undo_history(set)
+    }
+
+    /// Returns the world and uuid of every decoration a transaction has been applied to
+    fn get_touched_decorations(&self) -> OrdSet<(WorldId, Uuid)> {
+        let mut set = OrdSet::new();
+        for transaction in self.transactions.values() {
+            let raw = transaction.get_transaction();
+            if let Some(entity) = raw.get_decoration_entity() {
+                set = set.insert((raw.get_world(), entity));
+            }
+        }
+        set
+    }
+
+    /// Returns the history of all transactions to affect a particular decoration
+    ///
+    /// In chronological order, oldest first
+    fn get_decoration_history(&self, world: WorldId, entity: Uuid) -> Vec<Transaction> {
+        let mut set = OrdSet::new();
+        for (k, v) in self.transactions.clone().into_iter() {
+            let raw = v.get_transaction();
+            if raw.get_world() == world && raw.get_decoration_entity() == Some(entity) {
+                set = set.insert(k);
+            }
+        }
+        self.with_undo_history(set)
+    }
+
+    /// Looks up a set of transactions, along with their entire undo history
+    ///
+    /// In chronological order, oldest first
+    fn with_undo_history(&self, mut set: OrdSet<TransactionID>) -> Vec<Transaction> {
+        // Check to see if any of the transactions have been undone
+        // and insert them into the set
+        for k in set.clone().into_iter() {
+            let vec = self.get_undo_history(*k);
+            for v in vec {
+                set = set.insert(v);
+            }
+        }
+
+        // Look up the transactions and add them to the output
+        let mut output: Vec<Transaction> = Vec::new();
+        for id in set.into_iter() {
+            let transaction = self.lookup_transaction(*id).unwrap();
+            output.push(transaction);
+        }
+
+        output
+    }
+
+    /// Returns the transaction ultimately affected by this undo, following chains of Undos
+    ///
+    /// The transaction can affect more than one block, see RawTransaction::get_affected_coords
+    fn get_undone_transaction(&self, transaction: TransactionID) -> Option<RawTransaction> {
+        // Make sure the transaction exists
+        if let Some(t) = self.lookup_transaction(transaction) {
+            let raw = t.get_transaction();
+            match *raw.get_transaction_type() {
+                TransactionType::Undo { transaction: tid } => self.get_undone_transaction(tid),
+                _ => Some(raw.clone()),
+            }
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, Timelike};
+    use uuid::Uuid;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    fn set_at(id: u16, x: i32, y: i32, z: i32) -> RawTransaction {
+        RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+            .set_owner(Uuid::new_v4())
+            .set_x_coord(x)
+            .set_y_coord(y)
+            .set_z_coord(z)
+            .build_transaction()
+            .unwrap()
+    }
+
+    #[test]
+    fn subscribers_see_committed_transactions() {
+        let rewind = Rewind::new(block(0));
+        let stream = rewind.subscribe();
+
+        let committed = rewind.apply_transaction(set_at(1, 1, 2, 3)).unwrap();
+
+        assert!(stream.try_recv().unwrap() == committed);
+        assert!(stream.try_recv().is_err());
+    }
+
+    #[test]
+    fn bounded_subscribers_drop_oldest() {
+        let rewind = Rewind::new(block(0));
+        assert!(rewind.subscribe_bounded(0, OverflowPolicy::Block).is_none());
+        let stream = rewind
+            .subscribe_bounded(2, OverflowPolicy::DropOldest)
+            .unwrap();
+        let committed: Vec<Transaction> = (0..5)
+            .map(|x| rewind.apply_transaction(set_at(1, x, 0, 0)).unwrap())
+            .collect();
+        assert!(stream.try_recv().as_ref() == Some(&committed[3]));
+        assert!(stream.try_recv().as_ref() == Some(&committed[4]));
+        assert!(stream.try_recv().is_none());
+        assert_eq!(stream.get_dropped(), 3);
+        drop(stream);
+        rewind.apply_transaction(set_at(1, 9, 0, 0)).unwrap();
+        assert!(rewind.bounded.is_empty());
+    }
+
+    #[test]
+    fn blocked_subscribers_do_not_stall_writes() {
+        let rewind = Rewind::new(block(0));
+        let stream = rewind.subscribe_bounded(1, OverflowPolicy::Block).unwrap();
+
+        // Nothing is read while committing, yet every commit returns, and the outbox holds no
+        // more than the subscription
+        let committed: Vec<Transaction> = (0..10)
+            .map(|x| rewind.apply_transaction(set_at(1, x, 0, 0)).unwrap())
+            .collect();
+        assert!(rewind.bounded.outbox_len() <= 1);
+        assert_eq!(rewind.get_block_history(9, 0, 0).len(), 1);
+
+        // The latest transaction is never dropped, and whatever was is counted
+        let mut read = Vec::new();
+        while read.last() != committed.last() {
+            read.push(stream.recv().unwrap());
+        }
+        let mut rest = committed.iter();
+        assert!(read.iter().all(|x| rest.any(|y| x == y)));
+        assert_eq!(read.len() as u64 + stream.get_dropped(), 10);
+        assert!(stream.try_recv().is_none());
+    }
+
+    #[test]
+    fn watchers_only_see_their_region() {
+        let rewind = Rewind::new(block(0));
+        let watched = rewind.watch(Region::new((0, 0, 0), (3, 3, 3)));
+        let nether = rewind
+            .with_world(WorldId::new(1))
+            .watch(Region::new((0, 0, 0), (3, 3, 3)));
+
+        let inside = rewind.apply_transaction(set_at(1, 1, 1, 1)).unwrap();
+        rewind.apply_transaction(set_at(1, 4, 1, 1)).unwrap();
+        let undo = rewind.apply_transaction(undo(inside.get_id())).unwrap();
+        let redo = rewind
+            .apply_transaction(super::tests::undo(undo.get_id()))
+            .unwrap();
+        assert!(watched.try_iter().eq(vec![inside, undo, redo]));
+        assert!(nether.try_recv().is_err());
+    }
+
+    fn undo(transaction: TransactionID) -> RawTransaction {
+        RawTransactionBuilder::new(TransactionType::new_undo(transaction))
+            .build_transaction()
+            .unwrap()
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let rewind = Rewind::new(block(0));
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let second = rewind.apply_transaction(set_at(2, 0, 0, 0)).unwrap();
+
+        let undo_second = rewind.apply_transaction(undo(second.get_id())).unwrap();
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(1)
+        );
+
+        rewind
+            .apply_transaction(undo(undo_second.get_id()))
+            .unwrap();
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(2)
+        );
+
+        let history = rewind.get_block_history(0, 0, 0);
+        assert_eq!(history.len(), 4);
+        assert!(history[0].0 == block(1));
+        assert!(history[1].0 == block(2));
+    }
+
+    #[test]
+    fn block_history_includes_each_transaction() {
+        let rewind = Rewind::new(block(0));
+        let first = rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let second = rewind.apply_transaction(set_at(2, 0, 0, 0)).unwrap();
+
+        // Each state is the block right after its transaction, not right before
+        let history = rewind.get_block_history(0, 0, 0);
+        assert_eq!(history.len(), 2);
+        assert!(history[0] == (block(1), first));
+        assert!(history[1] == (block(2), second));
+    }
+
+    #[test]
+    fn map_blocks_rewrites_history() {
+        let rewind = Rewind::new(block(0));
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let replace = RawTransactionBuilder::new(TransactionType::new_replace(block(1), block(2)))
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+        rewind.apply_transaction(replace).unwrap();
+
+        let shifted = rewind.map_blocks(|x| block(x.get_block().get_id() + 10));
+        assert!(shifted.get_default_block() == block(10));
+        let world = shifted.get_world_state();
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(12));
+        assert!(world.get_block_defaulting(1, 0, 0).unwrap() == block(10));
+        // The replace still applies, as its expected block was mapped too
+        assert_eq!(shifted.get_block_history(0, 0, 0).len(), 2);
+    }
+
+    #[test]
+    fn worldline_round_trip() {
+        let rewind = Rewind::new(block(0));
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let second = rewind.apply_transaction(set_at(2, -5, 3, 7)).unwrap();
+        rewind.apply_transaction(undo(second.get_id())).unwrap();
+        rewind.apply_transaction(set_at(3, 1, 1, 1)).unwrap();
+
+        let mut buffer = Vec::new();
+        rewind.export_worldline(&mut buffer).unwrap();
+        let imported = Rewind::import_worldline(&buffer[..]).unwrap();
+
+        assert!(imported.get_transactions() == rewind.get_transactions());
+        let world = imported.get_world_state();
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        assert!(world.get_block_defaulting(-5, 3, 7).unwrap() == block(0));
+        assert!(world.get_block_defaulting(1, 1, 1).unwrap() == block(3));
+
+        let compacted = rewind.compact();
+        assert_eq!(compacted.get_transactions().len(), 2);
+        let world = compacted.get_world_state();
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        assert!(world.get_block_defaulting(-5, 3, 7).unwrap() == block(0));
+    }
+
+    /// Asserts both rewinds have the same worldline and the same blocks
+    fn assert_converged(a: &Rewind, b: &Rewind) {
+        assert!(a.get_transactions() == b.get_transactions());
+        let (world_a, world_b) = (a.get_world_state(), b.get_world_state());
+        for transaction in a.get_transactions() {
+            if let Some((x, y, z)) = transaction.get_transaction().get_coords() {
+                assert!(
+                    world_a.get_block_defaulting(x, y, z).unwrap()
+                        == world_b.get_block_defaulting(x, y, z).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nodes_converge() {
+        let a = Rewind::with_node_id(block(0), 1);
+        let b = Rewind::with_node_id(block(0), 2);
+
+        // Concurrent writes to the same block, at the same logical time
+        let from_a = a.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let from_b = b.apply_transaction(set_at(2, 0, 0, 0)).unwrap();
+        assert!(from_a.get_id() != from_b.get_id());
+        b.apply_transaction(set_at(3, 5, 5, 5)).unwrap();
+
+        assert_eq!(a.sync_with(&b).unwrap(), 3);
+        assert_converged(&a, &b);
+        // Node 2 breaks the tie, so its write is the later one
+        assert!(a.get_world_state().get_block_defaulting(0, 0, 0).unwrap() == block(2));
+
+        // Writes after syncing are ordered after everything seen so far
+        let undo = a.apply_transaction(undo(from_b.get_id())).unwrap();
+        assert!(undo.get_id() > from_b.get_id());
+        let replace = RawTransactionBuilder::new(TransactionType::new_replace(block(3), block(4)))
+            .set_x_coord(5)
+            .set_y_coord(5)
+            .set_z_coord(5)
+            .build_transaction()
+            .unwrap();
+        b.apply_transaction(replace).unwrap();
+
+        // Merging in either direction, repeatedly, lands in the same place
+        assert_eq!(b.merge_transactions(a.get_transactions()).unwrap(), 1);
+        assert_eq!(a.sync_with(&b).unwrap(), 1);
+        assert_eq!(a.sync_with(&b).unwrap(), 0);
+        assert_converged(&a, &b);
+        assert!(a.get_world_state().get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        assert!(a.get_world_state().get_block_defaulting(5, 5, 5).unwrap() == block(4));
+    }
+
+    #[test]
+    fn default_nodes_converge() {
+        let a = Rewind::new(block(0));
+        let b = Rewind::new(block(0));
+
+        // Neither node was given an id, yet their concurrent writes do not collide
+        let from_a = a.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let from_b = b.apply_transaction(set_at(2, 0, 0, 0)).unwrap();
+        assert!(from_a.get_id() != from_b.get_id());
+        assert_eq!(a.sync_with(&b).unwrap(), 2);
+        assert_converged(&a, &b);
+        assert_eq!(a.get_transactions().len(), 2);
+        let later = if from_a.get_id() > from_b.get_id() {
+            1
+        } else {
+            2
+        };
+        assert!(a.get_world_state().get_block_defaulting(0, 0, 0).unwrap() == block(later));
+    }
+
+    #[test]
+    fn taken_ids_conflict() {
+        let a = Rewind::with_node_id(block(0), 1);
+        let b = Rewind::with_node_id(block(0), 1);
+        let from_a = a.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        b.apply_transaction(set_at(2, 0, 0, 0)).unwrap();
+
+        // Same id, different content, so it is neither merged nor skipped as a duplicate
+        let rejection = b.merge_transactions(a.get_transactions()).unwrap_err();
+        assert_eq!(rejection.get_reason(), RejectionReason::Conflict);
+        assert_eq!(rejection.get_coords(), Some((0, 0, 0)));
+        assert_eq!(rejection.get_conflicting(), Some(from_a.get_id()));
+        assert!(b.sync_with(&a).is_err());
+        assert_eq!(b.get_transactions().len(), 1);
+        assert!(b.get_world_state().get_block_defaulting(0, 0, 0).unwrap() == block(2));
+
+        // The same transaction again is still a duplicate
+        assert_eq!(a.merge_transactions(a.get_transactions()).unwrap(), 0);
+    }
+
+    #[test]
+    fn mirror_committed_transactions() {
+        let primary = Rewind::new(block(0));
+        let mirror = Rewind::new(block(0));
+        let stream = primary.subscribe();
+        let first = primary.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        primary.apply_transaction(set_at(2, 0, 0, 0)).unwrap();
+        primary.apply_transaction(undo(first.get_id())).unwrap();
+        for transaction in stream.try_iter() {
+            assert!(mirror.apply_committed(transaction.clone()) == Ok(transaction));
+        }
+        assert!(mirror.get_transactions() == primary.get_transactions());
+        assert!(
+            mirror
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(2)
+        );
+
+        // Replaying anything at or before the latest id is rejected
+        let rejection = mirror.apply_committed(first).unwrap_err();
+        assert_eq!(rejection.get_reason(), RejectionReason::OutOfOrder);
+        assert_eq!(rejection.get_coords(), Some((0, 0, 0)));
+        let missing = Transaction::new(
+            undo(TransactionID::new_from_parts(99, 0)),
+            TransactionID::new_from_parts(100, 0),
+        );
+        assert_eq!(
+            mirror.apply_committed(missing).unwrap_err().get_reason(),
+            RejectionReason::NotFound
+        );
+        assert!(mirror.get_transactions() == primary.get_transactions());
+    }
+
+    #[test]
+    fn worlds_share_worldline() {
+        let overworld = Rewind::new(block(0));
+        let nether = overworld.with_world(WorldId::NETHER);
+
+        overworld.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let in_nether = RawTransactionBuilder::new(TransactionType::new_set(block(2)))
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .set_world(WorldId::NETHER)
+            .build_transaction()
+            .unwrap();
+        let committed = overworld.apply_transaction(in_nether).unwrap();
+
+        assert_eq!(nether.get_transactions().len(), 2);
+        assert_eq!(nether.get_block_history(0, 0, 0).len(), 1);
+        assert!(
+            overworld
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(1)
+        );
+        assert!(
+            nether
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(2)
+        );
+
+        // Rebuilding from the shared worldline keeps the worlds apart
+        let rebuilt = Rewind::from_transactions(overworld.get_transactions(), block(0));
+        let rebuilt_nether = rebuilt.with_world(WorldId::NETHER);
+        assert!(
+            rebuilt_nether
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(2)
+        );
+
+        // Undos land in the world of the transaction they undo
+        overworld
+            .apply_transaction(undo(committed.get_id()))
+            .unwrap();
+        assert!(
+            nether
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(0)
+        );
+        assert!(
+            overworld
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(1)
+        );
+    }
+
+    #[test]
+    fn concurrent_writes_to_chunks() {
+        let rewind = RewindBuilder::new(block(0))
+            .set_chunk_size(4, 4)
+            .unwrap()
+            .build();
+        // Every thread writes its own column of blocks, spread over chunks shared with the others
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let rewind = rewind.clone();
+                std::thread::spawn(move || {
+                    for y in 0..40 {
+                        rewind
+                            .apply_transaction(set_at(i as u16 + 1, i, y, i % 3))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let world = rewind.get_world_state();
+        for i in 0..8 {
+            for y in 0..40 {
+                assert!(world.get_block_defaulting(i, y, i % 3).unwrap() == block(i as u16 + 1));
+            }
+        }
+        assert_eq!(rewind.get_transactions().len(), 8 * 40);
+        let rebuilt = Rewind::from_transactions(rewind.get_transactions(), block(0));
+        assert!(rebuilt.get_world_state().modified_blocks().count() == 8 * 40);
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        let rewind = Rewind::new(block(0));
+        let mut ids = Vec::new();
+        for i in 0..300 {
+            let committed = rewind
+                .apply_transaction(set_at(i % 7 + 1, i as i32 % 40, 0, i as i32 / 40))
+                .unwrap();
+            ids.push(committed.get_id());
+        }
+        for id in ids.iter().step_by(5) {
+            rewind.apply_transaction(undo(*id)).unwrap();
+        }
+
+        let rebuilt = Rewind::from_transactions(rewind.get_transactions(), block(0));
+        let world = rewind.get_world_state();
+        let blocks: Vec<_> = rebuilt
+            .get_world_state()
+            .modified_blocks()
+            .map(Result::unwrap)
+            .collect();
+        assert!(!blocks.is_empty());
+        assert!(blocks
+            .iter()
+            .all(|&((x, y, z), ref block)| world.get_block_defaulting(x, y, z).unwrap() == *block));
+
+        let region = Region::new((0, 0, 0), (39, 0, 7));
+        let diff = rewind.diff_region(region, ids[99], ids[299]);
+        assert!(diff.windows(2).all(|x| x[0].0 < x[1].0));
+        for ((x, y, z), before, after) in diff {
+            assert!(before == rewind.get_block_as_of(x, y, z, ids[99]));
+            assert!(after == rewind.get_block_as_of(x, y, z, ids[299]));
+        }
+    }
+
+    #[test]
+    fn audit_matching_transactions() {
+        let rewind = Rewind::with_node_id(block(0), 0);
+        let first = rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        rewind.apply_transaction(set_at(2, 1, 0, 0)).unwrap();
+        rewind.apply_transaction(undo(first.get_id())).unwrap();
+
+        let mut filter = TransactionFilter::new();
+        filter.set_region(Region::new((0, 0, 0), (0, 0, 0)));
+        let mut csv = Vec::new();
+        rewind
+            .export_audit(&mut csv, AuditFormat::Csv, &filter)
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().starts_with("0.0,"));
+
+        let mut json = Vec::new();
+        rewind
+            .export_audit(&mut json, AuditFormat::JsonLines, &TransactionFilter::new())
+            .unwrap();
+        let records: Vec<AuditRecord> = json
+            .split(|x| *x == b'\n')
+            .filter(|x| !x.is_empty())
+            .map(|x| serde_json::from_slice(x).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].action, "undo");
+        assert_eq!(records[2].cause.as_ref().unwrap(), "0.0");
+    }
+
+    #[test]
+    fn jsonl_round_trip() {
+        let rewind = Rewind::new(block(0));
+        let first = rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        rewind.apply_transaction(set_at(2, 1, 0, 0)).unwrap();
+        rewind.apply_transaction(undo(first.get_id())).unwrap();
+
+        let mut jsonl = Vec::new();
+        rewind
+            .export_jsonl(&mut jsonl, &TransactionFilter::new())
+            .unwrap();
+        let imported = Importer::new(RewindBuilder::new(block(0)))
+            .import_jsonl(&jsonl[..])
+            .unwrap();
+        assert!(imported.get_transactions() == rewind.get_transactions());
+        assert!(
+            imported
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(0)
+        );
+        assert!(
+            imported
+                .get_world_state()
+                .get_block_defaulting(1, 0, 0)
+                .unwrap()
+                == block(2)
+        );
+
+        // The same transactions always export to the same bytes
+        let mut again = Vec::new();
+        imported
+            .export_jsonl(&mut again, &TransactionFilter::new())
+            .unwrap();
+        assert!(again == jsonl);
+    }
+
+    #[test]
+    fn rollback_preview_and_apply() {
+        let rewind = Rewind::new(block(0));
+        let griefer = Uuid::new_v4();
+        let grief = |id, x| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_owner(griefer)
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        rewind.apply_transaction(grief(9, 0)).unwrap();
+        rewind.apply_transaction(grief(9, 1)).unwrap();
+        let undone = rewind.apply_transaction(grief(9, 2)).unwrap();
+        rewind.apply_transaction(undo(undone.get_id())).unwrap();
+        rewind.apply_transaction(set_at(2, 3, 0, 0)).unwrap();
+
+        let mut filter = TransactionFilter::new();
+        filter.set_owner(griefer);
+        let plan = rewind.preview_rollback(&filter);
+        assert_eq!(plan.get_transactions().len(), 2);
+        let changes: Vec<_> = plan
+            .get_changes()
+            .iter()
+            .map(|x| (x.get_coords(), x.get_before(), x.get_after()))
+            .collect();
+        assert!(
+            changes
+                == vec![
+                    ((0, 0, 0), block(9), block(1)),
+                    ((1, 0, 0), block(9), block(0))
+                ]
+        );
+        assert_eq!(plan.get_owner_counts()[&griefer], 2);
+        assert_eq!(plan.get_removed_counts()[&block(9)], 2);
+        assert_eq!(plan.get_restored_counts()[&block(1)], 1);
+        // Nothing changes until the plan is applied
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(9)
+        );
+
+        // Writes elsewhere leave the plan valid, writes to its blocks do not
+        rewind.apply_transaction(set_at(3, 4, 0, 0)).unwrap();
+        let moderator = Uuid::new_v4();
+        let stale = rewind.preview_rollback(&filter);
+        rewind.apply_transaction(set_at(4, 1, 0, 0)).unwrap();
+        assert!(rewind.apply_plan(&stale, moderator).is_none());
+        assert!(rewind.apply_plan(&plan, moderator).is_none());
+
+        let plan = rewind.preview_rollback(&filter);
+        assert_eq!(plan.get_changes().len(), 1);
+        let undos = rewind.apply_plan(&plan, moderator).unwrap();
+        assert_eq!(undos.len(), 2);
+        assert!(undos
+            .iter()
+            .all(|x| x.get_transaction().get_owner() == moderator));
+        let world = rewind.get_world_state();
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        assert!(world.get_block_defaulting(1, 0, 0).unwrap() == block(4));
+        assert!(rewind.preview_rollback(&filter).is_empty());
+    }
+
+    #[test]
+    fn rollbacks_follow_liquid_flow() {
+        let rewind = Rewind::new(block(0));
+        let griefer = Uuid::new_v4();
+        let water = |x, cause: Option<TransactionID>| {
+            let mut builder = RawTransactionBuilder::new(TransactionType::new_set(block(8)));
+            builder.set_x_coord(x).set_y_coord(0).set_z_coord(0);
+            match cause {
+                Some(cause) => builder.set_cause(cause),
+                None => builder.set_owner(griefer),
+            };
+            builder.build_transaction().unwrap()
+        };
+        let bucket = rewind.apply_transaction(water(0, None)).unwrap();
+        let flow = rewind
+            .apply_transaction(water(1, Some(bucket.get_id())))
+            .unwrap();
+        rewind.apply_transaction(set_at(1, 5, 0, 0)).unwrap();
+        let further = rewind
+            .apply_transaction(water(2, Some(flow.get_id())))
+            .unwrap();
+        assert_eq!(flow.get_transaction().get_cause(), Some(bucket.get_id()));
+
+        let chain: Vec<_> = rewind
+            .trace_cause_chain(bucket.get_id())
+            .iter()
+            .map(|x| x.get_id())
+            .collect();
+        assert!(chain == vec![bucket.get_id(), flow.get_id(), further.get_id()]);
+        assert_eq!(rewind.trace_cause_chain(further.get_id()).len(), 1);
+        assert!(rewind
+            .trace_cause_chain(TransactionID::new_from_parts(99, 0))
+            .is_empty());
+
+        // Rolling back the bucket also rolls back the water that flowed from it
+        let mut filter = TransactionFilter::new();
+        filter.set_owner(griefer);
+        let plan = rewind.preview_rollback(&filter);
+        assert_eq!(plan.get_transactions().len(), 3);
+        assert_eq!(plan.get_changes().len(), 3);
+        rewind.apply_plan(&plan, Uuid::new_v4()).unwrap();
+        let world = rewind.get_world_state();
+        assert!((0..3).all(|x| world.get_block_defaulting(x, 0, 0).unwrap() == block(0)));
+        assert!(world.get_block_defaulting(5, 0, 0).unwrap() == block(1));
+    }
+
+    #[test]
+    fn piston_moves_keep_both_ends() {
+        let rewind = Rewind::new(block(0));
+        rewind.apply_transaction(set_at(1, 255, 0, 0)).unwrap();
+        rewind.apply_transaction(set_at(2, 256, 0, 0)).unwrap();
+        // Push both blocks one along, across a chunk border
+        let moves = PistonMove::new(vec![
+            ((256, 0, 0), (257, 0, 0), block(2)),
+            ((255, 0, 0), (256, 0, 0), block(1)),
+        ]);
+        let push = RawTransactionBuilder::new(TransactionType::new_piston_move(moves, block(0)))
+            .build_transaction()
+            .unwrap();
+        let push = rewind.apply_transaction(push).unwrap();
+        let blocks = |rewind: &Rewind| {
+            let world = rewind.get_world_state();
+            (255..258)
+                .map(|x| world.get_block_defaulting(x, 0, 0).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert!(blocks(&rewind) == vec![block(0), block(1), block(2)]);
+        let history: Vec<_> = rewind
+            .get_block_history(256, 0, 0)
+            .into_iter()
+            .map(|x| x.0)
+            .collect();
+        assert!(history == vec![block(2), block(1)]);
+        assert_eq!(rewind.get_block_history(257, 0, 0).len(), 1);
+
+        let undone = rewind.apply_transaction(undo(push.get_id())).unwrap();
+        assert!(blocks(&rewind) == vec![block(1), block(2), block(0)]);
+        rewind.apply_transaction(undo(undone.get_id())).unwrap();
+        assert!(blocks(&rewind) == vec![block(0), block(1), block(2)]);
+        let rebuilt = Rewind::from_transactions(rewind.get_transactions(), block(0));
+        assert!(blocks(&rebuilt) == blocks(&rewind));
+    }
+
+    #[test]
+    fn explosions_are_one_transaction() {
+        let member = Uuid::new_v4();
+        let mut claims = ProtectedRegions::new();
+        let members = vec![member].into_iter().collect();
+        claims.add_claim(
+            WorldId::OVERWORLD,
+            Region::new((0, 5, 0), (0, 5, 0)),
+            members,
+        );
+        let rewind = RewindBuilder::new(block(0)).add_guard(claims).build();
+        let explode = |blocks: Vec<((i32, i32, i32), MetaBlock)>| {
+            RawTransactionBuilder::new(TransactionType::new_explode(
+                Explosion::new(blocks),
+                block(0),
+            ))
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap()
+        };
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        rewind.apply_transaction(set_at(2, 300, 0, 0)).unwrap();
+        let watched = rewind.watch(Region::new((300, 0, 0), (300, 0, 0)));
+        let explosion = rewind
+            .apply_transaction(explode(vec![
+                ((0, 0, 0), block(1)),
+                ((0, 1, 0), block(0)),
+                ((300, 0, 0), block(2)),
+            ]))
+            .unwrap();
+
+        // Blocks in every chunk are destroyed, but not where the explosion went off
+        let world = rewind.get_world_state();
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(0));
+        assert!(world.get_block_defaulting(300, 0, 0).unwrap() == block(0));
+        assert_eq!(rewind.get_block_history(300, 0, 0).len(), 2);
+        assert_eq!(rewind.get_block_history(0, 1, 0).len(), 1);
+        assert!(watched.try_recv().unwrap() == explosion);
+        let mut filter = TransactionFilter::new();
+        filter.set_region(Region::new((300, 0, 0), (300, 0, 0)));
+        assert_eq!(rewind.get_transactions_matching(&filter).len(), 2);
+
+        // Undoing it restores every block at once
+        let undone = rewind.apply_transaction(undo(explosion.get_id())).unwrap();
+        let world = rewind.get_world_state();
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        assert!(world.get_block_defaulting(300, 0, 0).unwrap() == block(2));
+        assert!(watched.try_recv().unwrap() == undone);
+
+        // And so does rolling it back
+        rewind.apply_transaction(undo(undone.get_id())).unwrap();
+        let mut filter = TransactionFilter::new();
+        filter.set_region(Region::new((0, 1, 0), (0, 1, 0)));
+        let plan = rewind.preview_rollback(&filter);
+        assert_eq!(plan.get_transactions().len(), 1);
+        assert_eq!(plan.get_changes().len(), 2);
+        let rebuilt = Rewind::from_transactions(rewind.get_transactions(), block(0));
+        assert!(
+            rebuilt
+                .get_world_state()
+                .get_block_defaulting(300, 0, 0)
+                .unwrap()
+                == block(0)
+        );
+
+        // An explosion reaching into a claim is vetoed as a whole
+        assert!(rewind
+            .apply_transaction(explode(vec![((1, 0, 0), block(0)), ((0, 5, 0), block(0))]))
+            .is_none());
+        assert_eq!(rewind.get_block_history(1, 0, 0).len(), 0);
+    }
+
+    #[test]
+    fn rollbacks_by_category() {
+        let rewind = Rewind::new(block(0));
+        let categorized = |id, x, category| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .set_category(category)
+                .build_transaction()
+                .unwrap()
+        };
+        rewind
+            .apply_transaction(categorized(1, 0, ActionCategory::Build))
+            .unwrap();
+        rewind
+            .apply_transaction(categorized(1, 1, ActionCategory::Build))
+            .unwrap();
+        let burnt = rewind
+            .apply_transaction(categorized(0, 0, ActionCategory::Burn))
+            .unwrap();
+        rewind.apply_transaction(set_at(2, 2, 0, 0)).unwrap();
+        assert_eq!(
+            burnt.get_transaction().get_category(),
+            Some(ActionCategory::Burn)
+        );
+
+        // Reverting the fire damage keeps everything that was built
+        let mut filter = TransactionFilter::new();
+        filter.set_categories(vec![ActionCategory::Burn].into_iter().collect());
+        assert!(rewind.get_transactions_matching(&filter) == vec![burnt]);
+        let plan = rewind.preview_rollback(&filter);
+        rewind.apply_plan(&plan, Uuid::new_v4()).unwrap();
+        let world = rewind.get_world_state();
+        assert!((0..2).all(|x| world.get_block_defaulting(x, 0, 0).unwrap() == block(1)));
+        assert!(world.get_block_defaulting(2, 0, 0).unwrap() == block(2));
+
+        // Uncategorized transactions never match
+        filter.set_categories(ActionCategory::ALL.iter().cloned().collect());
+        assert_eq!(rewind.get_transactions_matching(&filter).len(), 3);
+    }
+
+    #[test]
+    fn guards_veto_transactions() {
+        let member = Uuid::new_v4();
+        let mut claims = ProtectedRegions::new();
+        let members = vec![member].into_iter().collect();
+        claims.add_claim(
+            WorldId::OVERWORLD,
+            Region::new((0, 0, 0), (0, 0, 0)),
+            members,
+        );
+        let rewind = RewindBuilder::new(block(0))
+            .add_guard(claims)
+            .add_guard(|x: &Attempt| !x.get_transaction().is_set_biome())
+            .build();
+        let rejections = rewind.subscribe_rejections();
+        let owned = |owner, raw: RawTransaction| {
+            RawTransactionBuilder::new(raw.get_transaction_type().clone())
+                .set_owner(owner)
+                .set_x_coord(0)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+
+        let first = rewind
+            .apply_transaction(owned(member, set_at(1, 0, 0, 0)))
+            .unwrap();
+        assert!(rewind.apply_transaction(set_at(2, 0, 0, 0)).is_none());
+        let rejected = rejections.try_recv().unwrap();
+        assert_eq!(rejected.get_reason(), RejectionReason::Vetoed);
+        assert!(
+            *rejected.get_transaction().get_transaction_type()
+                == TransactionType::new_set(block(2))
+        );
+
+        // Undos are checked against the block they change
+        assert!(rewind.apply_transaction(undo(first.get_id())).is_none());
+        assert_eq!(
+            rejections.try_recv().unwrap().get_reason(),
+            RejectionReason::Vetoed
+        );
+        assert!(rewind
+            .apply_transaction(owned(member, undo(first.get_id())))
+            .is_some());
+        let missing = undo(TransactionID::new_from_parts(99, 0));
+        assert!(rewind.apply_transaction(missing).is_none());
+        assert_eq!(
+            rejections.try_recv().unwrap().get_reason(),
+            RejectionReason::NotFound
+        );
+        assert!(rejections.try_recv().is_err());
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(0)
+        );
+    }
+
+    #[test]
+    fn validators_rewrite_and_reject() {
+        let rewind = Rewind::new(block(0));
+        let rejections = rewind.subscribe_rejections();
+        rewind.add_validator(Box::new(ClampHeight::new(0, 255).unwrap()));
+        let shared = rewind.with_world(WorldId::new(1));
+        shared.add_validator(Box::new(|x: RawTransaction| {
+            if x.get_owner().is_nil() {
+                None
+            } else {
+                Some(x)
+            }
+        }));
+
+        let committed = rewind.apply_transaction(set_at(1, 0, 300, 0)).unwrap();
+        assert_eq!(committed.get_transaction().get_coords(), Some((0, 255, 0)));
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 255, 0)
+                .unwrap()
+                == block(1)
+        );
+        assert!(rewind.apply_transaction(undo(committed.get_id())).is_none());
+        assert_eq!(
+            rejections.try_recv().unwrap().get_reason(),
+            RejectionReason::Invalid
+        );
+    }
+
+    #[test]
+    fn request_ids_deduplicate_retries() {
+        let rewind = Rewind::new(block(0));
+        let stream = rewind.subscribe();
+        let request = Uuid::new_v4();
+        let submit = |id, x| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_request_id(request)
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+
+        let committed = rewind.apply_transaction(submit(1, 0)).unwrap();
+        assert_eq!(committed.get_transaction().get_request_id(), Some(request));
+        assert!(rewind.apply_transaction(submit(1, 0)).as_ref() == Some(&committed));
+        assert!(
+            rewind
+                .apply_all(vec![set_at(2, 1, 0, 0), submit(1, 0)])
+                .unwrap()[1]
+                == committed
+        );
+        assert_eq!(rewind.get_transactions().len(), 2);
+        assert_eq!(stream.try_iter().count(), 2);
+
+        // Request ids survive a round trip through an export
+        let mut buffer = Vec::new();
+        rewind.export_worldline(&mut buffer).unwrap();
+        let imported = Rewind::import_worldline(&buffer[..]).unwrap();
+        assert!(imported.apply_transaction(submit(1, 0)) == Some(committed));
+        assert_eq!(imported.get_transactions().len(), 2);
+    }
+
+    #[test]
+    fn apply_if_version_detects_changes() {
+        let rewind = Rewind::new(block(0));
+        assert_eq!(rewind.generation(), 0);
+        let read = rewind.generation();
+        let first = rewind
+            .apply_transaction_if_version(set_at(1, 0, 0, 0), read)
+            .unwrap();
+        assert_eq!(rewind.generation(), 1);
+
+        // Someone else wrote in between, so writing at the old generation fails
+        let stale = rewind
+            .apply_transaction_if_version(set_at(2, 5, 0, 0), read)
+            .unwrap_err();
+        assert_eq!(stale.get_reason(), RejectionReason::Stale);
+        assert_eq!(stale.get_coords(), Some((5, 0, 0)));
+        assert!(rewind
+            .apply_transaction_if_version(undo(first.get_id()), read)
+            .is_err());
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(5, 0, 0)
+                .unwrap()
+                == block(0)
+        );
+        assert_eq!(rewind.generation(), 1);
+
+        assert!(rewind
+            .apply_transaction_if_version(undo(first.get_id()), rewind.generation())
+            .is_ok());
+        assert_eq!(rewind.generation(), 2);
+    }
+
+    #[test]
+    fn world_state_with_generation() {
+        let rewind = Rewind::new(block(0));
+        let nether = rewind.with_world(WorldId::NETHER);
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let (world, generation) = rewind.get_world_state_with_generation();
+        assert_eq!(generation, 1);
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        let chunks = world.get_generation();
+
+        // The generation counts transactions in every world, unlike the one of the world itself
+        let in_nether = RawTransactionBuilder::new(TransactionType::new_set(block(2)))
+            .set_world(WorldId::NETHER)
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+        nether.apply_transaction(in_nether).unwrap();
+        assert_eq!(rewind.generation(), 2);
+        assert_eq!(nether.get_world_state_with_generation().1, 2);
+        assert_eq!(rewind.get_world_state().get_generation(), chunks);
+
+        let snapshot = rewind.snapshot();
+        assert_eq!(snapshot.get_generation(), 2);
+        assert!(snapshot.get_world().get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        rewind.apply_transaction(set_at(3, 0, 0, 0)).unwrap();
+        let later = rewind.snapshot();
+        assert!(later.is_newer_than(&snapshot));
+        assert!(!snapshot.is_newer_than(&snapshot.clone()));
+        // Older snapshots are left untouched by later commits
+        assert!(snapshot.get_world().get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        assert!(later.get_world().get_block_defaulting(0, 0, 0).unwrap() == block(3));
+    }
+
+    #[test]
+    fn sandboxes_are_isolated() {
+        let rewind = Rewind::new(block(0));
+        let stream = rewind.subscribe();
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let sandbox = rewind.sandbox();
+        assert!(sandbox.get_transactions() == rewind.get_transactions());
+        assert_eq!(sandbox.generation(), 1);
+
+        sandbox.apply_transaction(set_at(2, 0, 0, 0)).unwrap();
+        sandbox.apply_transaction(set_at(3, 4, 0, 0)).unwrap();
+        rewind.apply_transaction(set_at(4, 9, 0, 0)).unwrap();
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(1)
+        );
+        assert!(
+            sandbox
+                .get_world_state()
+                .get_block_defaulting(9, 0, 0)
+                .unwrap()
+                == block(0)
+        );
+        assert_eq!(stream.try_iter().count(), 2);
+
+        let region = Region::new((0, 0, 0), (5, 5, 5));
+        let diff = rewind.diff_against(&sandbox, region).unwrap();
+        assert!(
+            diff == vec![
+                ((0, 0, 0), block(1), block(2)),
+                ((4, 0, 0), block(0), block(3))
+            ]
+        );
+        assert!(rewind.diff_against(&rewind, region).unwrap().is_empty());
+    }
+
+    #[test]
+    fn recent_rejections_are_kept() {
+        let rewind = RewindBuilder::new(block(0))
+            .set_rejection_log_capacity(2)
+            .build();
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let conflict = |x| {
+            RawTransactionBuilder::new(TransactionType::new_replace(block(2), block(3)))
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        rewind.apply_transaction(undo(TransactionID::new_from_parts(99, 0)));
+        rewind.apply_transaction(conflict(0));
+        rewind.undo_last(Uuid::nil(), 1, Uuid::nil());
+        rewind.apply_all(vec![set_at(1, 1, 0, 0), conflict(1)]);
+
+        let recent = rewind.recent_rejections();
+        assert_eq!(recent.len(), 2);
+        assert!(recent[0].get_transaction() == conflict(0));
+        assert_eq!(recent[1].get_reason(), RejectionReason::Conflict);
+        assert_eq!(recent[1].get_rejection().get_coords(), Some((1, 0, 0)));
+        assert!(recent[0].get_time() <= recent[1].get_time());
+
+        // Sandboxes keep their own log, and by default nothing is kept
+        let sandbox = rewind.sandbox();
+        sandbox.apply_transaction(conflict(0));
+        assert_eq!(rewind.recent_rejections().len(), 2);
+        assert_eq!(sandbox.recent_rejections().len(), 1);
+        assert!(Rewind::new(block(0)).recent_rejections().is_empty());
+    }
+
+    #[test]
+    fn rejections_report_conflicts() {
+        let rewind = Rewind::new(block(0));
+        let rejections = rewind.subscribe_rejections();
+        let written = rewind.apply_transaction(set_at(1, 4, 5, 6)).unwrap();
+        let replace = RawTransactionBuilder::new(TransactionType::new_replace(block(2), block(3)))
+            .set_x_coord(4)
+            .set_y_coord(5)
+            .set_z_coord(6)
+            .build_transaction()
+            .unwrap();
+
+        let rejection = rewind.try_apply_transaction(replace).unwrap_err();
+        assert_eq!(rejection.get_reason(), RejectionReason::Conflict);
+        assert_eq!(rejection.get_coords(), Some((4, 5, 6)));
+        assert!(rejection.get_expected() == Some(block(2)));
+        assert!(rejection.get_actual() == Some(block(1)));
+        assert_eq!(rejection.get_conflicting(), Some(written.get_id()));
+        assert!(rejections.try_recv().unwrap().get_rejection() == rejection);
+
+        // Undos fail at the block of what they undo
+        let missing = rewind
+            .try_apply_transaction(undo(TransactionID::new_from_parts(99, 0)))
+            .unwrap_err();
+        assert_eq!(missing.get_reason(), RejectionReason::NotFound);
+        assert_eq!(missing.get_coords(), None);
+        assert_eq!(missing.get_conflicting(), None);
+    }
+
+    #[test]
+    fn filtered_block_history() {
+        let rewind = Rewind::new(block(0));
+        let steve = Uuid::new_v4();
+        let start = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 0, 0, 0)
+            .unwrap();
+        let set = |id, owner, minutes| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_owner(owner)
+                .set_time(start + chrono::Duration::minutes(minutes))
+                .set_x_coord(0)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        rewind.apply_transaction(set(1, steve, 0)).unwrap();
+        rewind.apply_transaction(set(2, Uuid::new_v4(), 1)).unwrap();
+        rewind.apply_transaction(set(3, steve, 2)).unwrap();
+        rewind.apply_transaction(set(4, steve, 3)).unwrap();
+
+        let history = rewind.get_block_history_filtered(0, 0, 0, Some(steve), None);
+        let blocks: Vec<MetaBlock> = history.iter().map(|x| x.0.clone()).collect();
+        assert!(blocks == vec![block(1), block(3), block(4)]);
+        let range = TimeRange::new(
+            start + chrono::Duration::minutes(1),
+            start + chrono::Duration::minutes(3),
+        );
+        let history = rewind.get_block_history_filtered(0, 0, 0, None, range);
+        let blocks: Vec<MetaBlock> = history.iter().map(|x| x.0.clone()).collect();
+        assert!(blocks == vec![block(2), block(3)]);
+        assert_eq!(
+            rewind.get_block_history_filtered(0, 0, 0, None, None).len(),
+            4
+        );
+        assert!(TimeRange::new(start, start - chrono::Duration::minutes(1)).is_none());
+
+        let minute = |x| start + chrono::Duration::minutes(x);
+        let (entering, transitions) =
+            rewind.get_block_history_between(0, 0, 0, minute(1), minute(3));
+        assert!(entering == block(1));
+        assert!(transitions
+            .iter()
+            .map(|x| x.0.clone())
+            .eq(vec![block(2), block(3)]));
+        let (entering, transitions) =
+            rewind.get_block_history_between(0, 0, 0, minute(9), minute(1));
+        assert!(entering == block(4) && transitions.is_empty());
+    }
+
+    #[test]
+    fn times_compare_in_utc() {
+        let rewind = Rewind::new(block(0));
+        let zone = |hours| FixedOffset::east_opt(hours * 3600).unwrap();
+        // 08:00 UTC, submitted from two hours ahead
+        let early = zone(2).with_ymd_and_hms(2020, 1, 1, 10, 0, 0).unwrap();
+        // 09:00 UTC, submitted from five hours behind
+        let late = zone(-5).with_ymd_and_hms(2020, 1, 1, 4, 0, 0).unwrap();
+        let set = |id, x| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        let late = rewind.apply_transaction(set(2, 1).with_time(late)).unwrap();
+        let early = rewind
+            .apply_transaction(set(1, 0).with_time(early))
+            .unwrap();
+
+        let (early, late) = (early.get_transaction(), late.get_transaction());
+        assert!(early.get_time() < late.get_time());
+        assert_eq!(early.get_time().unwrap().hour(), 8);
+        assert_eq!(early.get_offset(), Some(zone(2)));
+        assert_eq!(early.get_local_time().unwrap().hour(), 10);
+
+        // 08:30 to 09:30 UTC, given in a third zone
+        let range = TimeRange::new(
+            zone(1).with_ymd_and_hms(2020, 1, 1, 9, 30, 0).unwrap(),
+            zone(1).with_ymd_and_hms(2020, 1, 1, 10, 30, 0).unwrap(),
+        )
+        .unwrap();
+        let matched = rewind
+            .query()
+            .set_time_range(range)
+            .iter()
+            .collect::<Vec<_>>();
+        assert!(matched.len() == 1 && matched[0].get_transaction() == late);
+
+        // Written in the submitter's offset, as before
+        let json = serde_json::to_string(&early).unwrap();
+        assert!(json.contains("2020-01-01T10:00:00+02:00"));
+        let read: RawTransaction = serde_json::from_str(&json).unwrap();
+        assert!(read == *early);
+    }
+
+    #[test]
+    fn strict_time_ordering() {
+        let start = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 0, 0, 0)
+            .unwrap();
+        let set =
+            |id, minutes| set_at(id, 0, 0, 0).with_time(start + chrono::Duration::minutes(minutes));
+        let tolerance = chrono::Duration::minutes(1);
+        assert!(RewindBuilder::new(block(0))
+            .set_time_ordering(TimeOrdering::Reject(-tolerance))
+            .is_none());
+
+        let rewind = RewindBuilder::new(block(0))
+            .set_time_ordering(TimeOrdering::Reject(tolerance))
+            .unwrap()
+            .build();
+        rewind.apply_transaction(set(1, 10)).unwrap();
+        assert!(rewind.apply_transaction(set(2, 9)).is_some());
+        let rejection = rewind.try_apply_transaction(set(3, 5)).unwrap_err();
+        assert_eq!(rejection.get_reason(), RejectionReason::Backdated);
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(2)
+        );
+        // Transactions without a time are left alone
+        assert!(rewind.apply_transaction(set_at(3, 0, 0, 0)).is_some());
+        // The sandbox orders time like the Rewind it came from
+        assert!(rewind.sandbox().apply_transaction(set(4, 5)).is_none());
+
+        let rewind = RewindBuilder::new(block(0))
+            .set_time_ordering(TimeOrdering::Restamp(tolerance))
+            .unwrap()
+            .build();
+        rewind.apply_transaction(set(1, 10)).unwrap();
+        let restamped = rewind.apply_all(vec![set(2, 5), set(3, 11)]).unwrap();
+        let times: Vec<_> = restamped
+            .iter()
+            .map(|x| x.get_transaction().get_time().unwrap())
+            .collect();
+        assert!(
+            times
+                == vec![
+                    set(1, 10).get_time().unwrap(),
+                    set(3, 11).get_time().unwrap()
+                ]
+        );
+    }
+
+    #[test]
+    fn undo_last_of_owner() {
+        let rewind = Rewind::new(block(0));
+        let (steve, moderator) = (Uuid::new_v4(), Uuid::new_v4());
+        let by_steve = |id, x| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_owner(steve)
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        for x in 0..4 {
+            rewind.apply_transaction(by_steve(1, x)).unwrap();
+        }
+        let undone = rewind.apply_transaction(by_steve(2, 3)).unwrap();
+        rewind.apply_transaction(undo(undone.get_id())).unwrap();
+        rewind.apply_transaction(set_at(3, 2, 0, 0)).unwrap();
+        let stream = rewind.subscribe();
+
+        let undos = rewind.undo_last(steve, 2, moderator).unwrap();
+        assert_eq!(undos.len(), 2);
+        assert!(undos
+            .iter()
+            .all(|x| x.get_transaction().get_owner() == moderator));
+        assert!(stream.try_iter().eq(undos.iter().cloned()));
+        let world = rewind.get_world_state();
+        let blocks: Vec<MetaBlock> = (0..4)
+            .map(|x| world.get_block_defaulting(x, 0, 0).unwrap())
+            .collect();
+        assert!(blocks == vec![block(1), block(1), block(3), block(0)]);
+
+        // Only what is left gets undone
+        assert_eq!(rewind.undo_last(steve, 5, moderator).unwrap().len(), 2);
+        assert!(rewind.undo_last(steve, 5, moderator).unwrap().is_empty());
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(0)
+        );
+    }
+
+    #[test]
+    fn heatmap_of_changes() {
+        let rewind = Rewind::new(block(0));
+        let start = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 0, 0, 0)
+            .unwrap();
+        let set = |x, z, minutes| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(1)))
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(z)
+                .set_time(start + chrono::Duration::minutes(minutes))
+                .build_transaction()
+                .unwrap()
+        };
+        rewind.apply_transaction(set(0, 0, 1)).unwrap();
+        let undone = rewind.apply_transaction(set(1, 1, 1)).unwrap();
+        rewind.apply_transaction(undo(undone.get_id())).unwrap();
+        rewind.apply_transaction(set(15, 0, 1)).unwrap();
+        // Outside the region, and before the range
+        rewind.apply_transaction(set(16, 0, 1)).unwrap();
+        rewind.apply_transaction(set(0, 0, 0)).unwrap();
+        let explosion = Explosion::new((14..18).map(|x| ((x, 0, 9), block(1))));
+        let explode = RawTransactionBuilder::new(TransactionType::new_explode(explosion, block(0)))
+            .set_time(start + chrono::Duration::minutes(2))
+            .build_transaction()
+            .unwrap();
+        rewind.apply_transaction(explode).unwrap();
+
+        let region = Region::new((0, 0, 0), (15, 255, 15));
+        let range = TimeRange::new(
+            start + chrono::Duration::minutes(1),
+            start + chrono::Duration::hours(1),
+        )
+        .unwrap();
+        let heatmap = rewind.heatmap(region, 8, range).unwrap();
+        assert!(heatmap.get_counts() == [2, 1, 0, 2]);
+        assert!(rewind.heatmap(region, 0, range).is_none());
+    }
+
+    #[test]
+    fn leaderboards() {
+        let rewind = Rewind::new(block(0));
+        let start = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 0, 0, 0)
+            .unwrap();
+        let (steve, alex) = (Uuid::new_v4(), Uuid::new_v4());
+        let set = |owner, id, x, minutes| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_owner(owner)
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .set_time(start + chrono::Duration::minutes(minutes))
+                .build_transaction()
+                .unwrap()
+        };
+        for x in 0..3 {
+            rewind.apply_transaction(set(steve, 1, x, 1)).unwrap();
+        }
+        rewind.apply_transaction(set(steve, 2, 3, 1)).unwrap();
+        rewind.apply_transaction(set(steve, 1, 5, 1)).unwrap();
+        // Before the range
+        rewind.apply_transaction(set(alex, 3, 4, 0)).unwrap();
+        let explosion = Explosion::new((1..5).map(|x| ((x, 0, 0), block(1))));
+        let explode = RawTransactionBuilder::new(TransactionType::new_explode(explosion, block(0)))
+            .set_owner(alex)
+            .set_time(start + chrono::Duration::minutes(2))
+            .build_transaction()
+            .unwrap();
+        rewind.apply_transaction(explode).unwrap();
+
+        let range = TimeRange::new(
+            start + chrono::Duration::minutes(1),
+            start + chrono::Duration::hours(1),
+        )
+        .unwrap();
+        assert!(rewind.top_owners_by_changes(range, 5) == vec![(steve, 5), (alex, 4)]);
+        assert!(rewind.top_owners_by_changes(range, 1) == vec![(steve, 5)]);
+        // The explosion recorded every block as block 1, what was really there is what counts
+        assert!(
+            rewind.top_blocks_broken(range, 5)
+                == vec![
+                    (*block(1).get_block(), 2),
+                    (*block(2).get_block(), 1),
+                    (*block(3).get_block(), 1)
+                ]
+        );
+        assert!(rewind.top_blocks_broken(range, 1) == vec![(*block(1).get_block(), 2)]);
+    }
+
+    #[test]
+    fn search_sign_text() {
+        let rewind = Rewind::new(block(0));
+        let (steve, alex) = (Uuid::new_v4(), Uuid::new_v4());
+        let sign = |text: &str| {
+            let meta = MetaData::new()
+                .set_nbt("minecraft:text", NbtValue::String(text.to_string()))
+                .unwrap();
+            MetaBlock::fuse(Block::new_from_ids(0, 2), meta)
+        };
+        let write = |owner, text: &str| {
+            RawTransactionBuilder::new(TransactionType::new_set(sign(text)))
+                .set_owner(owner)
+                .set_x_coord(0)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        let rude = rewind
+            .apply_transaction(write(steve, "Alex smells"))
+            .unwrap();
+        rewind.apply_transaction(undo(rude.get_id())).unwrap();
+        let polite = rewind
+            .apply_transaction(write(alex, "Welcome, Steve!"))
+            .unwrap();
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+
+        // Undone and overwritten text is still found, along with who wrote it
+        let found = rewind.search_text("SMELLS");
+        assert!(found == vec![rude]);
+        assert!(found[0].get_transaction().get_owner() == steve);
+        assert!(rewind.search_text("welcome steve") == vec![polite]);
+        assert!(rewind.search_text("welcome alex").is_empty());
+        assert!(rewind.search_text("").is_empty());
+    }
+
+    #[test]
+    fn queries_are_lazy_and_ranged() {
+        let rewind = Rewind::new(block(0));
+        let steve = Uuid::new_v4();
+        let mut ids = Vec::new();
+        for x in 0..6 {
+            let mut builder = RawTransactionBuilder::new(TransactionType::new_set(block(1)));
+            builder.set_x_coord(x).set_y_coord(0).set_z_coord(0);
+            if x % 2 == 0 {
+                builder.set_owner(steve);
+            }
+            ids.push(
+                rewind
+                    .apply_transaction(builder.build_transaction().unwrap())
+                    .unwrap()
+                    .get_id(),
+            );
+        }
+        let snapshot = rewind.query();
+        rewind.apply_transaction(undo(ids[4])).unwrap();
+
+        // The snapshot does not see the undo, a fresh query does
+        assert_eq!(snapshot.count(), 6);
+        assert_eq!(rewind.query().count(), 7);
+        assert_eq!(rewind.query().set_exclude_undos().count(), 6);
+
+        let by_steve: Vec<TransactionID> = rewind
+            .query()
+            .set_owner(steve)
+            .set_newest_first()
+            .iter()
+            .map(|x| x.get_id())
+            .collect();
+        assert!(by_steve == vec![ids[4], ids[2], ids[0]]);
+        let latest = rewind
+            .query()
+            .set_owner(steve)
+            .set_newest_first()
+            .set_exclude_undone()
+            .first()
+            .unwrap();
+        assert!(latest.get_id() == ids[2]);
+
+        let ranged: Vec<TransactionID> = rewind
+            .query()
+            .set_after(ids[1])
+            .set_before(ids[5])
+            .set_limit(2)
+            .iter()
+            .map(|x| x.get_id())
+            .collect();
+        assert!(ranged == vec![ids[2], ids[3]]);
+    }
+
+    #[test]
+    fn restore_region_to_time() {
+        let rewind = Rewind::new(block(0));
+        let start = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 0, 0, 0)
+            .unwrap();
+        let set = |id, x, minutes| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_time(start + chrono::Duration::minutes(minutes))
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        rewind.apply_transaction(set(1, 0, 0)).unwrap();
+        rewind.apply_transaction(set(1, 1, 0)).unwrap();
+        rewind.apply_transaction(set(2, 0, 5)).unwrap();
+        rewind.apply_transaction(set(2, 2, 5)).unwrap();
+        rewind.apply_transaction(set(2, 9, 5)).unwrap();
+
+        let owner = Uuid::new_v4();
+        let at = start + chrono::Duration::minutes(1);
+        let sets = rewind
+            .restore_region((0, 0, 0), (5, 0, 0), at, owner)
+            .unwrap();
+        let coords: Vec<_> = sets
+            .iter()
+            .map(|x| x.get_transaction().get_coords().unwrap())
+            .collect();
+        assert_eq!(coords, vec![(0, 0, 0), (2, 0, 0)]);
+        assert!(sets
+            .iter()
+            .all(|x| x.get_transaction().get_owner() == owner));
+        assert!(sets[0].get_transaction().get_time() == sets[1].get_transaction().get_time());
+        let world = rewind.get_world_state();
+        let blocks: Vec<MetaBlock> = [0, 1, 2, 9]
+            .iter()
+            .map(|&x| world.get_block_defaulting(x, 0, 0).unwrap())
+            .collect();
+        assert!(blocks == vec![block(1), block(1), block(0), block(2)]);
+        assert!(rewind
+            .restore_region((0, 0, 0), (5, 0, 0), at, owner)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn timelapse_keyframes_and_deltas() {
+        let rewind = Rewind::new(block(0));
+        let start = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 0, 0, 0)
+            .unwrap();
+        let minute = |x| start + chrono::Duration::minutes(x);
+        let set = |id, x, minutes| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_time(minute(minutes))
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        rewind.apply_transaction(set(1, 0, 0)).unwrap();
+        rewind.apply_transaction(set(2, 1, 1)).unwrap();
+        rewind.apply_transaction(set(3, 0, 2)).unwrap();
+        rewind.apply_transaction(set(4, 9, 2)).unwrap();
+
+        let region = Region::new((0, 0, 0), (5, 0, 0));
+        assert!(Timelapse::new(region, start, minute(3), chrono::Duration::zero()).is_none());
+        let mut timelapse =
+            Timelapse::new(region, start, minute(3), chrono::Duration::minutes(1)).unwrap();
+        timelapse.set_keyframe_every(3).unwrap();
+        let mut output = Vec::new();
+        rewind.export_timelapse(&mut output, &timelapse).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut lines = output.lines();
+        let header: TimelapseHeader = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(header.frames, 4);
+        assert_eq!(header.interval_seconds, 60);
+        let frames: Vec<TimelapseFrame> = lines.map(|x| serde_json::from_str(x).unwrap()).collect();
+        let blocks: Vec<_> = frames.iter().map(|x| &x.blocks).collect();
+        assert!(frames
+            .iter()
+            .map(|x| x.keyframe)
+            .eq(vec![true, false, false, true]));
+        assert!(*blocks[0] == vec![((0, 0, 0), block(1))]);
+        assert!(*blocks[1] == vec![((1, 0, 0), block(2))]);
+        assert!(*blocks[2] == vec![((0, 0, 0), block(3))]);
+        assert!(*blocks[3] == vec![((0, 0, 0), block(3)), ((1, 0, 0), block(2))]);
+    }
+
+    #[test]
+    fn configurable_chunk_size() {
+        assert!(RewindBuilder::new(block(0)).set_chunk_size(0, 16).is_none());
+        let rewind = RewindBuilder::new(block(0))
+            .set_chunk_size(16, 16)
+            .unwrap()
+            .build();
+        rewind.apply_transaction(set_at(1, -1, -1, -1)).unwrap();
+        rewind.apply_transaction(set_at(2, 16, 0, 0)).unwrap();
+
+        let world = rewind.compact().get_world_state();
+        assert_eq!(world.get_chunk_dims(), (16, 16, 16));
+        assert!(world.get_block_defaulting(-1, -1, -1).unwrap() == block(1));
+        assert!(world.get_block_defaulting(16, 0, 0).unwrap() == block(2));
+        assert!(world.get_block_defaulting(15, 0, 0).unwrap() == block(0));
+    }
+
+    #[test]
+    fn evicted_chunks_reload() {
+        let provider = Arc::new(MemoryChunkProvider::new());
+        let rewind = RewindBuilder::new(block(0))
+            .set_chunk_size(16, 16)
+            .unwrap()
+            .set_chunk_provider(provider.clone())
+            .build();
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        rewind.apply_transaction(set_at(2, 20, 0, 0)).unwrap();
+        let before = rewind.get_world_state();
+
+        rewind.evict_chunks(|_, index| index.get_x() == 0).unwrap();
+        assert_eq!(provider.len(), 1);
+        let world = rewind.get_world_state();
+        assert_eq!(world.get_loaded_chunks().len(), 1);
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+
+        // Writing to an evicted chunk loads it back, leaving older views alone
+        rewind.apply_transaction(set_at(3, 1, 0, 0)).unwrap();
+        assert!(world.get_block_defaulting(1, 0, 0).unwrap() == block(0));
+        assert!(before.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        drop(world);
+        let world = rewind.get_world_state();
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        assert!(world.get_block_defaulting(1, 0, 0).unwrap() == block(3));
+        assert!(provider.is_empty());
+    }
+
+    /// Accepts every chunk, but can never load one back
+    struct LosingChunkProvider;
+
+    impl ChunkProvider for LosingChunkProvider {
+        fn store(&self, _: &Chunk) -> io::Result<u64> {
+            Ok(0)
+        }
+
+        fn load(&self, _: u64) -> io::Result<Chunk> {
+            Err(io::Error::other("chunk was lost"))
+        }
+
+        fn remove(&self, _: u64) {}
+    }
+
+    #[test]
+    fn lost_chunks_reject() {
+        let rewind = RewindBuilder::new(block(0))
+            .set_chunk_size(16, 16)
+            .unwrap()
+            .set_chunk_provider(Arc::new(LosingChunkProvider))
+            .build();
+        let set = rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        rewind.apply_transaction(set_at(2, 20, 0, 0)).unwrap();
+        rewind.evict_chunks(|_, index| index.get_x() == 0).unwrap();
+
+        // Reading the lost chunk fails, the rest of the world is still there
+        let world = rewind.get_world_state();
+        assert!(world.get_block_defaulting(0, 0, 0).is_err());
+        assert!(world.modified_blocks().any(|x| x.is_err()));
+        assert!(world.get_block_defaulting(20, 0, 0).unwrap() == block(2));
+
+        // Writes to it are rejected without being committed, writes elsewhere still apply
+        let rejection = rewind
+            .try_apply_transaction(set_at(3, 1, 0, 0))
+            .unwrap_err();
+        assert_eq!(rejection.get_reason(), RejectionReason::Unavailable);
+        assert_eq!(rejection.get_coords(), Some((1, 0, 0)));
+        let rejection = rewind
+            .try_apply_transaction(undo(set.get_id()))
+            .unwrap_err();
+        assert_eq!(rejection.get_reason(), RejectionReason::Unavailable);
+        assert!(rewind
+            .ensure_chunks(Region::new((0, 0, 0), (0, 0, 0)))
+            .is_err());
+        assert_eq!(rewind.get_transactions().len(), 2);
+        assert!(rewind.apply_transaction(set_at(4, 21, 0, 0)).is_some());
+    }
+
+    #[test]
+    fn idle_chunks_evicted() {
+        let provider = Arc::new(MemoryChunkProvider::new());
+        let rewind = RewindBuilder::new(block(0))
+            .set_chunk_size(16, 16)
+            .unwrap()
+            .set_chunk_provider(provider.clone())
+            .build();
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        for x in 0..3 {
+            rewind.apply_transaction(set_at(2, 20 + x, 0, 0)).unwrap();
+        }
+
+        rewind.evict_idle_chunks(4).unwrap();
+        assert_eq!(provider.len(), 0);
+        rewind.evict_idle_chunks(3).unwrap();
+        assert_eq!(provider.len(), 1);
+        let world = rewind.get_world_state();
+        assert!(world.get_loaded_chunks() == vec![ChunkPos::new(1, 0, 0)]);
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+    }
+
+    #[test]
+    fn default_layers_in_history() {
+        let mut layers = DefaultLayers::new(block(0));
+        layers
+            .add_layer(0, block(7))
+            .add_layer(1, block(1))
+            .add_layer(60, block(0));
+        let rewind = RewindBuilder::new(block(0))
+            .set_default_layers(layers.clone())
+            .build();
+        assert!(rewind.get_default_block() == block(0));
+
+        // Replaces expect the default at their height
+        let replace = |y, current| {
+            RawTransactionBuilder::new(TransactionType::new_replace(current, block(2)))
+                .set_x_coord(0)
+                .set_y_coord(y)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        assert!(rewind.apply_transaction(replace(10, block(0))).is_none());
+        let mined = rewind.apply_transaction(replace(10, block(1))).unwrap();
+        rewind.apply_transaction(set_at(3, 0, 0, 0)).unwrap();
+        assert!(rewind.get_block_as_of(0, 10, 0, mined.get_id()) == block(2));
+        let undo = RawTransactionBuilder::new(TransactionType::new_undo(mined.get_id()))
+            .build_transaction()
+            .unwrap();
+        rewind.apply_transaction(undo).unwrap();
+        let world = rewind.get_world_state();
+        assert!(world.get_block_defaulting(0, 10, 0).unwrap() == block(1));
+        assert!(world.get_block_defaulting(0, 100, 0).unwrap() == block(0));
+
+        // The layers survive an export, and a rebuild from history
+        let mut buffer = Vec::new();
+        rewind.export_worldline(&mut buffer).unwrap();
+        let imported = Rewind::import_worldline(&buffer[..]).unwrap();
+        assert!(imported.get_default_layers() == layers);
+        let world = imported.get_world_state();
+        assert!(world.get_block_defaulting(0, 10, 0).unwrap() == block(1));
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(3));
+    }
+
+    #[test]
+    fn baselines_are_overlaid() {
+        let baseline = |_, y: i32, _| if y < 0 { Some(block(5)) } else { None };
+        let rewind = RewindBuilder::new(block(0))
+            .set_chunk_size(16, 16)
+            .unwrap()
+            .set_baseline(WorldId::OVERWORLD, Arc::new(baseline))
+            .build();
+        let nether = rewind.with_world(WorldId::NETHER);
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(3, -3, 3)
+                .unwrap()
+                == block(5)
+        );
+        assert!(
+            nether
+                .get_world_state()
+                .get_block_defaulting(3, -3, 3)
+                .unwrap()
+                == block(0)
+        );
+
+        // Only deviations from the baseline are recorded
+        let mine = RawTransactionBuilder::new(TransactionType::new_replace(block(5), block(0)))
+            .set_x_coord(0)
+            .set_y_coord(-1)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+        let mined = rewind.apply_transaction(mine).unwrap();
+        rewind.apply_transaction(set_at(2, 1, -1, 0)).unwrap();
+        let world = rewind.get_world_state();
+        assert!(world.get_block_defaulting(0, -1, 0).unwrap() == block(0));
+        assert!(world.get_block_defaulting(2, -1, 0).unwrap() == block(5));
+        assert_eq!(world.modified_blocks().count(), 2);
+        assert_eq!(rewind.get_transactions().len(), 2);
+
+        let undo = RawTransactionBuilder::new(TransactionType::new_undo(mined.get_id()))
+            .build_transaction()
+            .unwrap();
+        rewind.apply_transaction(undo).unwrap();
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, -1, 0)
+                .unwrap()
+                == block(5)
+        );
+        let rebuilt = rewind.compact();
+        assert!(
+            rebuilt
+                .get_world_state()
+                .get_block_defaulting(0, -1, 0)
+                .unwrap()
+                == block(5)
+        );
+        assert!(
+            rebuilt
+                .get_world_state()
+                .get_block_defaulting(1, -1, 0)
+                .unwrap()
+                == block(2)
+        );
+    }
+
+    #[test]
+    fn memory_budget_evicts_least_recent() {
+        let provider = Arc::new(MemoryChunkProvider::new());
+        let rewind = RewindBuilder::new(block(0))
+            .set_chunk_size(16, 16)
+            .unwrap()
+            .set_chunk_provider(provider.clone())
+            .build();
+        let nether = rewind.with_world(WorldId::NETHER);
+        let in_nether = RawTransactionBuilder::new(TransactionType::new_set(block(3)))
+            .set_world(WorldId::NETHER)
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+        rewind.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        nether.apply_transaction(in_nether).unwrap();
+        rewind.apply_transaction(set_at(2, 20, 0, 0)).unwrap();
+        rewind.apply_transaction(set_at(2, 21, 0, 0)).unwrap();
+        let chunk = rewind.get_world_state().memory_estimate() / 2;
+
+        assert_eq!(rewind.enforce_memory_budget(usize::MAX).unwrap(), 0);
+        // The overworld chunk at the origin has been idle the longest, then the nether one
+        assert_eq!(rewind.enforce_memory_budget(2 * chunk).unwrap(), 1);
+        let world = rewind.get_world_state();
+        assert!(world.get_loaded_chunks() == vec![ChunkPos::new(1, 0, 0)]);
+        assert_eq!(nether.get_world_state().get_loaded_chunks().len(), 1);
+        assert_eq!(rewind.enforce_memory_budget(0).unwrap(), 2);
+        assert_eq!(provider.len(), 3);
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(1)
+        );
+        assert!(
+            nether
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(3)
+        );
+        assert!(Rewind::new(block(0)).enforce_memory_budget(0).is_ok());
+
+        rewind
+            .ensure_chunks(Region::new((0, 0, 0), (20, 0, 0)))
+            .unwrap();
+        let world = rewind.get_world_state();
+        assert_eq!(world.get_loaded_chunks().len(), 2);
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(1));
+        assert_eq!(nether.get_world_state().get_loaded_chunks().len(), 0);
+    }
+
+    #[test]
+    fn biomes_have_their_own_history() {
+        let rewind = Rewind::new(block(0));
+        let set_biome = |id, x| {
+            RawTransactionBuilder::new(TransactionType::new_set_biome(Biome::new(id)))
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        rewind.apply_transaction(set_at(1, 1, 0, 0)).unwrap();
+        rewind.apply_transaction(set_biome(7, 0)).unwrap();
+        let second = rewind.apply_transaction(set_biome(8, 3)).unwrap();
+
+        // Both land in the same cell, and leave the block alone
+        assert!(rewind.get_world_state().get_biome(1, 1, 1).unwrap() == Biome::new(8));
+        assert!(rewind.get_world_state().get_biome(4, 0, 0).unwrap() == Biome::default());
+        assert_eq!(rewind.get_block_history(1, 0, 0).len(), 1);
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(1, 0, 0)
+                .unwrap()
+                == block(1)
+        );
+
+        rewind.apply_transaction(undo(second.get_id())).unwrap();
+        assert!(rewind.get_world_state().get_biome(1, 1, 1).unwrap() == Biome::new(7));
+        let rebuilt = Rewind::from_transactions(rewind.get_transactions(), block(0));
+        assert!(rebuilt.get_world_state().get_biome(2, 2, 2).unwrap() == Biome::new(7));
+        assert!(
+            rebuilt
+                .get_world_state()
+                .get_block_defaulting(1, 0, 0)
+                .unwrap()
+                == block(1)
+        );
+    }
+
+    #[test]
+    fn decorations_move_past_concurrent_writes() {
+        let rewind = Rewind::new(block(0));
+        let frame = Uuid::new_v4();
+        let empty = Decoration::new(DecorationKind::ItemFrame, 2).unwrap();
+        let hang_at = move |x| {
+            RawTransactionBuilder::new(TransactionType::new_set_decoration(frame, empty.clone()))
+                .set_x_coord(x)
+                .set_y_coord(1)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        rewind.apply_transaction(hang_at(0)).unwrap();
+        // The frame is only ever in one of the two chunks
+        let held = |world: &World| -> usize {
+            [0, 300]
+                .iter()
+                .filter_map(|&x| world.get_chunk_at(x, 1, 0).unwrap())
+                .map(|x| x.get_entities().len())
+                .sum()
+        };
+
+        // One thread moves the frame back and forth between two chunks, while the others keep
+        // writing to the first
+        let mover = {
+            let rewind = rewind.clone();
+            std::thread::spawn(move || {
+                for i in 0..4000 {
+                    let x = if i % 2 == 0 { 0 } else { 300 };
+                    rewind.apply_transaction(hang_at(x)).unwrap();
+                }
+            })
+        };
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let rewind = rewind.clone();
+                std::thread::spawn(move || {
+                    for y in 0..1000 {
+                        rewind.apply_transaction(set_at(1, i, y % 16, 0)).unwrap();
+                        assert_eq!(held(&rewind.get_world_state()), 1);
+                    }
+                })
+            })
+            .collect();
+        mover.join().unwrap();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let world = rewind.get_world_state();
+        assert_eq!(held(&world), 1);
+        assert_eq!(
+            world.get_entity(frame).unwrap().unwrap().get_block_coords(),
+            (300, 1, 0)
+        );
+    }
+
+    #[test]
+    fn decorations_have_their_own_history() {
+        let rewind = Rewind::new(block(0));
+        let frame = Uuid::new_v4();
+        let decorate = |transaction_type, x| {
+            RawTransactionBuilder::new(transaction_type)
+                .set_x_coord(x)
+                .set_y_coord(1)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        let empty = Decoration::new(DecorationKind::ItemFrame, 2).unwrap();
+        let holding = empty
+            .set_item(EquipmentSlot::MainHand, Some(block(5)))
+            .unwrap();
+        rewind.apply_transaction(set_at(1, 0, 1, 0)).unwrap();
+        rewind
+            .apply_transaction(decorate(
+                TransactionType::new_set_decoration(frame, holding.clone()),
+                0,
+            ))
+            .unwrap();
+        let robbed = rewind
+            .apply_transaction(decorate(
+                TransactionType::new_set_decoration(frame, empty.clone()),
+                0,
+            ))
+            .unwrap();
+
+        // Decorations leave the block they hang in alone
+        let world = rewind.get_world_state();
+        assert!(world.get_entity(frame).unwrap().unwrap().get_decoration() == Some(empty.clone()));
+        assert!(world.get_block_defaulting(0, 1, 0).unwrap() == block(1));
+        assert_eq!(rewind.get_block_history(0, 1, 0).len(), 1);
+
+        // Undoing the theft puts the item back, and the frame can be broken and moved
+        rewind.apply_transaction(undo(robbed.get_id())).unwrap();
+        let entity = rewind.get_world_state().get_entity(frame).unwrap().unwrap();
+        assert!(entity.get_decoration() == Some(holding.clone()));
+        assert_eq!(entity.get_block_coords(), (0, 1, 0));
+        rewind
+            .apply_transaction(decorate(TransactionType::new_remove_decoration(frame), 0))
+            .unwrap();
+        assert!(rewind
+            .get_world_state()
+            .get_entity(frame)
+            .unwrap()
+            .is_none());
+        rewind
+            .apply_transaction(decorate(
+                TransactionType::new_set_decoration(frame, empty.clone()),
+                300,
+            ))
+            .unwrap();
+
+        let history = rewind.get_decoration_history(frame);
+        let states: Vec<_> = history.iter().map(|x| x.0.clone()).collect();
+        assert!(
+            states
+                == vec![
+                    Some(holding.clone()),
+                    Some(empty.clone()),
+                    Some(holding),
+                    None,
+                    Some(empty)
+                ]
+        );
+        assert!(rewind.get_decorations_in_region((0, 0, 0), (1, 1, 1)) == vec![frame]);
+        assert!(rewind
+            .get_decorations_in_region((2, 0, 0), (9, 9, 9))
+            .is_empty());
+
+        // Rebuilding from history places the frame in its new chunk only
+        let rebuilt = Rewind::from_transactions(rewind.get_transactions(), block(0));
+        let world = rebuilt.get_world_state();
+        assert_eq!(
+            world.get_entity(frame).unwrap().unwrap().get_block_coords(),
+            (300, 1, 0)
+        );
+        assert_eq!(
+            world
+                .get_chunk_at(0, 0, 0)
+                .unwrap()
+                .unwrap()
+                .get_entity_count(),
+            0
+        );
+        assert_eq!(rewind.get_world_state().get_entity_count(), 1);
+        assert_eq!(
+            rewind
+                .get_world_state()
+                .get_chunk_at(0, 0, 0)
+                .unwrap()
+                .unwrap()
+                .get_entity_count(),
+            0
+        );
+    }
 }
@@ -0,0 +1,47 @@
+//! Provides sharded locks over chunks, so writes to different chunks can proceed concurrently
+
+use data::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+
+/// Number of shards, chunks hashing to the same shard share a lock
+const SHARDS: usize = 64;
+
+/// A fixed set of locks, each guarding every chunk that hashes to it
+pub struct ChunkLocks {
+    shards: Vec<Mutex<()>>,
+}
+
+impl ChunkLocks {
+    /// Creates a new set of chunk locks, with none held
+    pub fn new() -> ChunkLocks {
+        ChunkLocks {
+            shards: (0..SHARDS).map(|_| Mutex::new(())).collect(),
+        }
+    }
+
+    /// Locks many chunks at once, blocking until no other writer holds any of them
+    ///
+    /// Shards are always locked in the same order, so two writers locking overlapping sets of
+    /// chunks can not deadlock. The chunks stay locked until the returned guards are dropped.
+    pub fn lock_all<I>(&self, chunks: I) -> Vec<MutexGuard<'_, ()>>
+    where
+        I: IntoIterator<Item = (WorldId, ChunkPos)>,
+    {
+        let mut shards: Vec<usize> = chunks.into_iter().map(|(w, c)| shard(w, c)).collect();
+        shards.sort_unstable();
+        shards.dedup();
+        shards
+            .into_iter()
+            .map(|x| self.shards[x].lock().unwrap())
+            .collect()
+    }
+}
+
+/// Returns the shard a chunk of a world hashes to
+fn shard(world: WorldId, chunk: ChunkPos) -> usize {
+    let mut hasher = DefaultHasher::new();
+    (world, chunk).hash(&mut hasher);
+    hasher.finish() as usize % SHARDS
+}
@@ -0,0 +1,156 @@
+//! Provides a background worker running maintenance off the hot path
+//!
+//! A Rewind can own one worker, started with Rewind::start_maintenance. Every interval, the
+//! worker runs each of its tasks in turn, on its own thread. Stopping the worker, or dropping the
+//! last Rewind sharing it, lets the task currently running finish and then joins the thread.
+
+use std::io;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use Rewind;
+
+/// A piece of maintenance, run periodically by the worker
+pub trait MaintenanceTask: Send {
+    /// Runs the task once
+    ///
+    /// A failed run is retried at the next interval
+    fn run(&mut self, rewind: &Rewind) -> io::Result<()>;
+}
+
+/// Evicts chunks that have not been modified recently to the chunk provider
+///
+/// A chunk counts as idle once its world has seen the given number of chunk modifications since
+/// the chunk was last modified, see Rewind::evict_idle_chunks
+pub struct EvictIdleChunks {
+    idle_generations: u64,
+}
+
+impl EvictIdleChunks {
+    /// Creates a task evicting chunks idle for the given number of generations
+    pub fn new(idle_generations: u64) -> EvictIdleChunks {
+        EvictIdleChunks { idle_generations }
+    }
+}
+
+impl MaintenanceTask for EvictIdleChunks {
+    fn run(&mut self, rewind: &Rewind) -> io::Result<()> {
+        rewind.evict_idle_chunks(self.idle_generations)
+    }
+}
+
+/// Evicts the least recently modified chunks to the chunk provider whenever the chunks in
+/// memory take up more than a budget, see Rewind::enforce_memory_budget
+pub struct EnforceMemoryBudget {
+    budget: usize,
+}
+
+impl EnforceMemoryBudget {
+    /// Creates a task keeping the chunks in memory within budget bytes
+    pub fn new(budget: usize) -> EnforceMemoryBudget {
+        EnforceMemoryBudget { budget }
+    }
+}
+
+impl MaintenanceTask for EnforceMemoryBudget {
+    fn run(&mut self, rewind: &Rewind) -> io::Result<()> {
+        rewind.enforce_memory_budget(self.budget).map(|_| ())
+    }
+}
+
+/// Handle to a running worker
+///
+/// Stops the worker, waiting for it to finish, when dropped
+pub(crate) struct MaintenanceWorker {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MaintenanceWorker {
+    /// Starts a worker running the tasks every interval
+    ///
+    /// The Rewind given to the worker must not own the worker itself, or it would never stop
+    pub(crate) fn start(
+        rewind: Rewind,
+        interval: Duration,
+        mut tasks: Vec<Box<dyn MaintenanceTask>>,
+    ) -> MaintenanceWorker {
+        MaintenanceWorker::spawn(interval, move || {
+            for task in tasks.iter_mut() {
+                // Failed tasks are retried at the next interval
+                let _ = task.run(&rewind);
+            }
+        })
+    }
+
+    /// Starts a worker calling run every interval
+    pub(crate) fn spawn<F>(interval: Duration, mut run: F) -> MaintenanceWorker
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let (stop, stopped) = channel();
+        let thread = thread::spawn(move || {
+            // Any message, or the handle going away, stops the worker
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                run();
+            }
+        });
+        MaintenanceWorker {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for MaintenanceWorker {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    struct Count(Arc<AtomicUsize>);
+
+    impl MaintenanceTask for Count {
+        fn run(&mut self, _: &Rewind) -> io::Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn start_and_stop() {
+        let rewind = Rewind::new(MetaBlock::fuse(Block::new_from_ids(0, 0), MetaData::new()));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<Box<dyn MaintenanceTask>> = vec![Box::new(Count(runs.clone()))];
+        assert!(rewind.start_maintenance(Duration::from_millis(1), tasks));
+        assert!(!rewind.start_maintenance(Duration::from_millis(1), Vec::new()));
+
+        let started = Instant::now();
+        while runs.load(Ordering::SeqCst) < 3 {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(rewind.stop_maintenance());
+        let stopped = runs.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(runs.load(Ordering::SeqCst), stopped);
+        assert!(!rewind.stop_maintenance());
+
+        // Dropping the last Rewind stops the worker too
+        let tasks: Vec<Box<dyn MaintenanceTask>> = vec![Box::new(Count(runs.clone()))];
+        assert!(rewind.start_maintenance(Duration::from_millis(1), tasks));
+        drop(rewind);
+        assert_eq!(Arc::strong_count(&runs), 1);
+    }
+}
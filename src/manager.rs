@@ -0,0 +1,215 @@
+//! Provides a manager for many Rewinds, such as one per server or per minigame arena
+//!
+//! Each Rewind is an independent instance with its own worldline, stored under a key. Instances
+//! are built from one template RewindBuilder, so they share its dictionary, conflict policy,
+//! guards and validators, and one background worker runs maintenance over all of them.
+
+use data::*;
+use maintenance::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use {Rewind, RewindBuilder};
+
+/// Creates the chunk provider of an instance, from its key
+type ProviderFactory = dyn Fn(&str) -> Arc<dyn ChunkProvider> + Send + Sync;
+
+/// Owns many Rewinds, each stored under a key
+pub struct RewindManager {
+    template: RewindBuilder,
+    providers: Option<Arc<ProviderFactory>>,
+    rewinds: Arc<RwLock<HashMap<String, Rewind>>>,
+    /// Background worker, if one has been started, stopped when the manager is dropped
+    maintenance: Mutex<Option<MaintenanceWorker>>,
+}
+
+impl RewindManager {
+    /// Creates a new manager, without any instances, building them from the template
+    pub fn new(template: RewindBuilder) -> RewindManager {
+        RewindManager {
+            template,
+            providers: None,
+            rewinds: Arc::new(RwLock::new(HashMap::new())),
+            maintenance: Mutex::new(None),
+        }
+    }
+
+    /// Gives every instance created from now on its own chunk provider, made from its key
+    ///
+    /// Providers store chunks by world and position, so instances sharing the provider of the
+    /// template would overwrite each other's evicted chunks.
+    pub fn set_chunk_providers<F>(&mut self, factory: F) -> &mut Self
+    where
+        F: Fn(&str) -> Arc<dyn ChunkProvider> + Send + Sync + 'static,
+    {
+        self.providers = Some(Arc::new(factory));
+        self
+    }
+
+    /// Returns the dictionary shared by every instance, if there is one
+    pub fn get_dict(&self) -> Option<Arc<BlockDictonary>> {
+        self.template.dictonary.clone()
+    }
+
+    /// Returns the instance stored under the key, if there is one
+    ///
+    /// This function aquires a readlock on the instances, and will block until it is available
+    pub fn get(&self, key: &str) -> Option<Rewind> {
+        let rewinds = self.rewinds.read().unwrap();
+        rewinds.get(key).cloned()
+    }
+
+    /// Returns the instance stored under the key, creating an empty one if there is none
+    ///
+    /// This function aquires a writelock on the instances, and will block until it is available
+    pub fn get_or_create(&self, key: &str) -> Rewind {
+        if let Some(rewind) = self.get(key) {
+            return rewind;
+        }
+        let mut rewinds = self.rewinds.write().unwrap();
+        rewinds
+            .entry(key.to_string())
+            .or_insert_with(|| self.build(key))
+            .clone()
+    }
+
+    /// Builds a new, empty, instance for the key
+    fn build(&self, key: &str) -> Rewind {
+        let mut builder = self.template.clone();
+        if let Some(ref providers) = self.providers {
+            builder.set_chunk_provider(providers(key));
+        }
+        builder.build()
+    }
+
+    /// Stores an existing Rewind under the key, such as one read with import_worldline
+    ///
+    /// Returns the instance it replaced, if any
+    pub fn insert(&self, key: &str, rewind: Rewind) -> Option<Rewind> {
+        let mut rewinds = self.rewinds.write().unwrap();
+        rewinds.insert(key.to_string(), rewind)
+    }
+
+    /// Stops managing the instance stored under the key, returning it
+    pub fn remove(&self, key: &str) -> Option<Rewind> {
+        let mut rewinds = self.rewinds.write().unwrap();
+        rewinds.remove(key)
+    }
+
+    /// Returns the key of every instance, in no particular order
+    pub fn get_keys(&self) -> Vec<String> {
+        let rewinds = self.rewinds.read().unwrap();
+        rewinds.keys().cloned().collect()
+    }
+
+    /// Applies a transaction to the instance stored under the key, creating it if needed
+    ///
+    /// See Rewind::apply_transaction
+    pub fn apply_transaction(&self, key: &str, transaction: RawTransaction) -> Option<Transaction> {
+        self.get_or_create(key).apply_transaction(transaction)
+    }
+
+    /// Starts a background worker running the tasks over every instance, every interval
+    ///
+    /// Each run goes through the instances one after the other, running every task on each, so
+    /// however many instances there are only one thread is used. Instances added later are
+    /// picked up by the next run. Returns false, doing nothing, if a worker is already running.
+    pub fn start_maintenance(
+        &self,
+        interval: Duration,
+        mut tasks: Vec<Box<dyn MaintenanceTask>>,
+    ) -> bool {
+        let mut maintenance = self.maintenance.lock().unwrap();
+        if maintenance.is_some() {
+            return false;
+        }
+        let rewinds = self.rewinds.clone();
+        *maintenance = Some(MaintenanceWorker::spawn(interval, move || {
+            // Run on a copy, so the instances are not locked while tasks run
+            let instances: Vec<Rewind> = rewinds.read().unwrap().values().cloned().collect();
+            for rewind in instances {
+                for task in tasks.iter_mut() {
+                    // Failed tasks are retried at the next interval
+                    let _ = task.run(&rewind);
+                }
+            }
+        }));
+        true
+    }
+
+    /// Stops the background worker, waiting for the run in progress to finish
+    ///
+    /// Returns false if no worker was running
+    pub fn stop_maintenance(&self) -> bool {
+        let worker = self.maintenance.lock().unwrap().take();
+        worker.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn routes_by_key() {
+        let mut template = RewindBuilder::new(block(0));
+        template.set_chunk_size(16, 16).unwrap();
+        let mut manager = RewindManager::new(template);
+        let providers: Arc<Mutex<Vec<Arc<MemoryChunkProvider>>>> = Arc::default();
+        let created = providers.clone();
+        manager.set_chunk_providers(move |_| {
+            let provider = Arc::new(MemoryChunkProvider::new());
+            created.lock().unwrap().push(provider.clone());
+            provider
+        });
+
+        let set = |id| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+                .set_x_coord(0)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap()
+        };
+        manager.apply_transaction("lobby", set(1)).unwrap();
+        manager.apply_transaction("arena", set(2)).unwrap();
+        manager.apply_transaction("arena", set(3)).unwrap();
+        let mut keys = manager.get_keys();
+        keys.sort();
+        assert_eq!(keys, vec!["arena", "lobby"]);
+        assert!(manager.get("lobby").unwrap().get_transactions().len() == 1);
+        let arena = manager.get_or_create("arena");
+        assert!(
+            arena
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(3)
+        );
+        assert!(manager.get("missing").is_none());
+
+        // One worker evicts the chunks of every instance, into their own providers
+        let tasks: Vec<Box<dyn MaintenanceTask>> = vec![Box::new(EvictIdleChunks::new(0))];
+        assert!(manager.start_maintenance(Duration::from_millis(1), tasks));
+        let started = std::time::Instant::now();
+        while providers.lock().unwrap().iter().any(|x| x.is_empty()) {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(manager.stop_maintenance());
+        assert!(
+            arena
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(3)
+        );
+
+        assert!(manager.remove("lobby").is_some());
+        assert_eq!(manager.get_keys(), vec!["arena"]);
+    }
+}
@@ -0,0 +1,107 @@
+//! Provides strict ordering of transactions by wall-clock time
+//!
+//! Transactions are ordered by their ids, and the times they carry are whatever the submitter
+//! said, so a client with a slow clock can commit a transaction that claims to have happened
+//! before ones committed long ago. Servers that roll back by time, with restore_region or a
+//! time range, would then miss it. A Rewind can instead be configured to hold the wall-clock
+//! axis monotonic, up to a tolerance for clocks that are only slightly out of step.
+//!
+//! The ordering is checked as transactions are committed, after validators and guards.
+//! Transactions without a time are never part of a time range, and are left alone. Transactions
+//! merged in from other nodes have already been ordered where they were committed, and are not
+//! ordered again.
+
+use chrono::{DateTime, Duration, Utc};
+use data::*;
+
+/// What to do with transactions that are earlier than the latest time already committed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimeOrdering {
+    /// Transactions keep the time they were submitted with, however early
+    Unordered,
+    /// Transactions more than the tolerance earlier than the latest time committed are rejected,
+    /// with the reason Backdated
+    Reject(Duration),
+    /// Transactions more than the tolerance earlier than the latest time committed are
+    /// restamped with that time, keeping the offset they were submitted with
+    Restamp(Duration),
+}
+
+impl TimeOrdering {
+    /// Returns the tolerance, or None when transactions are unordered
+    pub fn get_tolerance(&self) -> Option<Duration> {
+        match *self {
+            TimeOrdering::Unordered => None,
+            TimeOrdering::Reject(tolerance) | TimeOrdering::Restamp(tolerance) => Some(tolerance),
+        }
+    }
+
+    /// Returns the transaction to commit in place of this one, given the latest time committed
+    /// so far, or why it can not be
+    pub fn order(
+        &self,
+        transaction: RawTransaction,
+        latest: Option<DateTime<Utc>>,
+    ) -> Result<RawTransaction, RejectionReason> {
+        let (time, latest) = match (transaction.get_time(), latest) {
+            (Some(time), Some(latest)) => (time, latest),
+            _ => return Ok(transaction),
+        };
+        match *self {
+            TimeOrdering::Reject(tolerance) if time + tolerance < latest => {
+                Err(RejectionReason::Backdated)
+            }
+            TimeOrdering::Restamp(tolerance) if time + tolerance < latest => {
+                let offset = transaction.get_offset().unwrap();
+                Ok(transaction.with_time(latest.with_timezone(&offset)))
+            }
+            _ => Ok(transaction),
+        }
+    }
+}
+
+/// Transactions are unordered by default
+impl Default for TimeOrdering {
+    fn default() -> TimeOrdering {
+        TimeOrdering::Unordered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone};
+
+    #[test]
+    fn early_transactions_are_caught() {
+        let zone = FixedOffset::east_opt(3600).unwrap();
+        let latest = Utc.with_ymd_and_hms(2020, 1, 1, 12, 0, 0).unwrap();
+        let set = |minutes| {
+            RawTransactionBuilder::new(TransactionType::new_set(MetaBlock::fuse(
+                Block::new_from_ids(0, 1),
+                MetaData::new(),
+            )))
+            .set_time((latest - Duration::minutes(minutes)).with_timezone(&zone))
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap()
+        };
+        let tolerance = Duration::minutes(1);
+
+        let reject = TimeOrdering::Reject(tolerance);
+        assert!(reject.order(set(1), Some(latest)) == Ok(set(1)));
+        assert!(reject.order(set(2), Some(latest)) == Err(RejectionReason::Backdated));
+        assert!(reject.order(set(2), None) == Ok(set(2)));
+
+        let restamped = TimeOrdering::Restamp(tolerance)
+            .order(set(2), Some(latest))
+            .unwrap();
+        assert_eq!(restamped.get_time(), Some(latest));
+        assert_eq!(restamped.get_offset(), Some(zone));
+
+        assert!(TimeOrdering::Unordered.order(set(60), Some(latest)) == Ok(set(60)));
+        assert_eq!(TimeOrdering::default().get_tolerance(), None);
+    }
+}
@@ -0,0 +1,42 @@
+//! Provides parallel replay of independent histories
+//!
+//! Rebuilding a world or diffing a region replays the history of every block it touches, and
+//! each history only depends on the worldline. With the parallel feature the histories are
+//! replayed on rayon's thread pool, otherwise one after the other. Either way the results come
+//! back in the order of the input, so both produce the same output.
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Maps every item through f, keeping their order
+#[cfg(feature = "parallel")]
+pub fn map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync + Send,
+{
+    items.par_iter().map(f).collect()
+}
+
+/// Maps every item through f, keeping their order
+#[cfg(not(feature = "parallel"))]
+pub fn map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    F: Fn(&T) -> R,
+{
+    items.iter().map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_order() {
+        let items: Vec<usize> = (0..1000).collect();
+        let mapped = map(&items, |x| x * 2);
+        assert!(mapped.into_iter().eq((0..1000).map(|x| x * 2)));
+        assert!(map(&Vec::<usize>::new(), |x| *x).is_empty());
+    }
+}
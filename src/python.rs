@@ -0,0 +1,225 @@
+//! PyO3 bindings exposing the worldline query surface to python
+//!
+//! Enabled with the `python` feature, and built into an extension module named `rewind` (e.g.
+//! with maturin). Queries return lists of flat dicts, so they can be handed straight to
+//! `pandas.DataFrame`.
+
+use chrono::prelude::*;
+use data::*;
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::fs::File;
+use std::io::BufReader;
+use uuid::Uuid;
+use Rewind;
+
+/// A read-only view of an exported worldline
+#[pyclass(name = "WorldLine", frozen)]
+pub struct PyWorldLine {
+    rewind: Rewind,
+}
+
+#[pymethods]
+impl PyWorldLine {
+    /// Loads a worldline export from a file
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<PyWorldLine> {
+        let file = File::open(path).map_err(|e| PyIOError::new_err(e.to_string()))?;
+        let rewind = Rewind::import_worldline(BufReader::new(file))
+            .map_err(|e| PyIOError::new_err(e.to_string()))?;
+        Ok(PyWorldLine { rewind })
+    }
+
+    /// Loads a worldline from the text of a worldline export
+    #[staticmethod]
+    fn from_export(export: &str) -> PyResult<PyWorldLine> {
+        let rewind = Rewind::import_worldline(export.as_bytes())
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyWorldLine { rewind })
+    }
+
+    fn __len__(&self) -> usize {
+        self.rewind.get_transactions().len()
+    }
+
+    /// Returns the transactions matching the filter as a list of dicts, oldest first
+    ///
+    /// Times are RFC 3339 strings, the region is the box between min and max, inclusive, and
+    /// categories are names like `burn`.
+    #[pyo3(signature = (owner=None, start=None, end=None, min=None, max=None, categories=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn transactions<'py>(
+        &self,
+        py: Python<'py>,
+        owner: Option<&str>,
+        start: Option<&str>,
+        end: Option<&str>,
+        min: Option<(i32, i32, i32)>,
+        max: Option<(i32, i32, i32)>,
+        categories: Option<Vec<String>>,
+    ) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let mut filter = TransactionFilter::new();
+        if let Some(owner) = owner {
+            let owner = Uuid::parse_str(owner).map_err(|e| PyValueError::new_err(e.to_string()))?;
+            filter.set_owner(owner);
+        }
+        if let Some(start) = start {
+            filter.set_start(parse_time(start)?);
+        }
+        if let Some(end) = end {
+            filter.set_end(parse_time(end)?);
+        }
+        match (min, max) {
+            (Some(min), Some(max)) => {
+                filter.set_region(Region::new(min, max));
+            }
+            (None, None) => (),
+            _ => return Err(PyValueError::new_err("min and max must be given together")),
+        }
+        if let Some(categories) = categories {
+            let categories = categories
+                .iter()
+                .map(|name| {
+                    ActionCategory::from_name(name)
+                        .ok_or_else(|| PyValueError::new_err(format!("unknown category {}", name)))
+                })
+                .collect::<PyResult<_>>()?;
+            filter.set_categories(categories);
+        }
+
+        self.rewind
+            .get_transactions_matching(&filter)
+            .iter()
+            .map(|transaction| transaction_record(py, transaction))
+            .collect()
+    }
+
+    /// Returns the history of a block as a list of dicts, oldest first
+    ///
+    /// Each dict describes a transaction, plus the state of the block just after it in the
+    /// `state_*` keys.
+    fn block_history<'py>(
+        &self,
+        py: Python<'py>,
+        x: i32,
+        y: i32,
+        z: i32,
+    ) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.rewind
+            .get_block_history(x, y, z)
+            .iter()
+            .map(|(block, transaction)| {
+                let record = transaction_record(py, transaction)?;
+                set_block(&record, "state_", block)?;
+                Ok(record)
+            })
+            .collect()
+    }
+
+    /// Returns the block as it was just after the transaction with the given major id
+    ///
+    /// Blocks are (provider, id, data_value) tuples.
+    fn block_as_of(&self, x: i32, y: i32, z: i32, id: u32) -> (u16, u16, Option<i32>) {
+        let id = TransactionID::new_from_parts(id, u32::MAX);
+        block_tuple(&self.rewind.get_block_as_of(x, y, z, id))
+    }
+}
+
+/// The python module
+#[pymodule]
+fn rewind(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyWorldLine>()
+}
+
+fn parse_time(time: &str) -> PyResult<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(time).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+fn block_tuple(block: &MetaBlock) -> (u16, u16, Option<i32>) {
+    (
+        block.get_block().get_provider(),
+        block.get_block().get_id(),
+        block.get_meta_data().get_data_value(),
+    )
+}
+
+/// Adds a block to a record as prefixed provider, block_id and data_value keys
+fn set_block(record: &Bound<'_, PyDict>, prefix: &str, block: &MetaBlock) -> PyResult<()> {
+    let (provider, id, data_value) = block_tuple(block);
+    record.set_item(format!("{}provider", prefix), provider)?;
+    record.set_item(format!("{}block_id", prefix), id)?;
+    record.set_item(format!("{}data_value", prefix), data_value)
+}
+
+/// Flattens a transaction into a dict
+fn transaction_record<'py>(
+    py: Python<'py>,
+    transaction: &Transaction,
+) -> PyResult<Bound<'py, PyDict>> {
+    let raw = transaction.get_transaction();
+    let record = PyDict::new(py);
+    record.set_item("id", transaction.get_id().get_id())?;
+    record.set_item("sub_id", transaction.get_id().get_sub_id())?;
+    record.set_item("owner", raw.get_owner().to_string())?;
+    record.set_item("time", raw.get_time().map(|x| x.to_rfc3339()))?;
+    record.set_item("utc_offset", raw.get_offset().map(|x| x.local_minus_utc()))?;
+    let coords = raw.get_coords();
+    record.set_item("x", coords.map(|c| c.0))?;
+    record.set_item("y", coords.map(|c| c.1))?;
+    record.set_item("z", coords.map(|c| c.2))?;
+    record.set_item("world", raw.get_world().get_id())?;
+    record.set_item("category", raw.get_category().map(|x| x.get_name()))?;
+    match raw.get_transaction_type() {
+        TransactionType::Set { block_set } => {
+            record.set_item("action", "set")?;
+            set_block(&record, "", &block_set.get())?;
+        }
+        TransactionType::Replace {
+            block_current,
+            block_set,
+        } => {
+            record.set_item("action", "replace")?;
+            set_block(&record, "", &block_set.get())?;
+            set_block(&record, "replaced_", &block_current.get())?;
+        }
+        TransactionType::Undo { transaction } => {
+            record.set_item("action", "undo")?;
+            record.set_item("undone_id", transaction.get_id())?;
+            record.set_item("undone_sub_id", transaction.get_sub_id())?;
+        }
+        TransactionType::SetBiome { biome } => {
+            record.set_item("action", "set_biome")?;
+            record.set_item("biome", biome.get_id())?;
+        }
+        TransactionType::SetDecoration { entity, decoration } => {
+            record.set_item("action", "set_decoration")?;
+            record.set_item("entity", entity.to_string())?;
+            record.set_item("kind", decoration.get_kind().get_entity_type())?;
+            record.set_item("rotation", decoration.get_rotation())?;
+        }
+        TransactionType::RemoveDecoration { entity } => {
+            record.set_item("action", "remove_decoration")?;
+            record.set_item("entity", entity.to_string())?;
+        }
+        TransactionType::Explode {
+            explosion,
+            block_set,
+        } => {
+            record.set_item("action", "explode")?;
+            set_block(&record, "", &block_set.get())?;
+            record.set_item("destroyed", explosion.get_coords())?;
+        }
+        TransactionType::PistonMove { moves, block_set } => {
+            record.set_item("action", "piston_move")?;
+            set_block(&record, "", &block_set.get())?;
+            let moves: Vec<_> = moves
+                .get_moves()
+                .into_iter()
+                .map(|(from, to, _)| (from, to))
+                .collect();
+            record.set_item("moves", moves)?;
+        }
+    }
+    Ok(record)
+}
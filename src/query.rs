@@ -0,0 +1,406 @@
+//! Provides queries over the worldline, that lazily iterate over matching transactions
+//!
+//! A query is built up from a snapshot of the worldline, with the same criteria as a
+//! TransactionFilter, plus a range of transaction ids, an order and a limit. Nothing is looked at
+//! until it is iterated over: ranges of ids are cut out of the worldline's ordered index, and
+//! iteration stops as soon as the limit is reached, so asking for the last few transactions of a
+//! player does not walk the whole history.
+//!
+//! Queries can also be written as text, like `owner:Steve action:break radius:20 time:<1h`, so
+//! chat commands and the command line tool understand the same syntax, see Query::parse.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use data::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use uuid::Uuid;
+use WorldLine;
+
+/// A query over the transactions of a worldline, see the module documentation
+#[derive(Clone)]
+pub struct Query {
+    world_line: WorldLine,
+    filter: TransactionFilter,
+    after: Option<TransactionID>,
+    before: Option<TransactionID>,
+    newest_first: bool,
+    limit: Option<usize>,
+    exclude_undos: bool,
+    exclude_undone: bool,
+}
+
+impl Query {
+    /// Creates a new query matching every transaction of the worldline, oldest first
+    pub(crate) fn new(world_line: WorldLine) -> Query {
+        Query {
+            world_line,
+            filter: TransactionFilter::new(),
+            after: None,
+            before: None,
+            newest_first: false,
+            limit: None,
+            exclude_undos: false,
+            exclude_undone: false,
+        }
+    }
+
+    /// Only match transactions matching the filter, replacing any criteria set before
+    pub fn set_filter(&mut self, filter: &TransactionFilter) -> &mut Self {
+        self.filter = filter.clone();
+        self
+    }
+
+    /// Only match transactions performed by the given owner, see TransactionFilter::set_owner
+    pub fn set_owner(&mut self, owner: Uuid) -> &mut Self {
+        self.filter.set_owner(owner);
+        self
+    }
+
+    /// Only match transactions that occured at or after the given time, see
+    /// TransactionFilter::set_start
+    pub fn set_start<Tz: TimeZone>(&mut self, start: DateTime<Tz>) -> &mut Self {
+        self.filter.set_start(start);
+        self
+    }
+
+    /// Only match transactions that occured before the given time, see
+    /// TransactionFilter::set_end
+    pub fn set_end<Tz: TimeZone>(&mut self, end: DateTime<Tz>) -> &mut Self {
+        self.filter.set_end(end);
+        self
+    }
+
+    /// Only match transactions that occured within the range, see TransactionFilter::set_time_range
+    pub fn set_time_range(&mut self, range: TimeRange) -> &mut Self {
+        self.filter.set_time_range(range);
+        self
+    }
+
+    /// Only match transactions affecting a block inside the region, see
+    /// TransactionFilter::set_region
+    pub fn set_region(&mut self, region: Region) -> &mut Self {
+        self.filter.set_region(region);
+        self
+    }
+
+    /// Only match transactions taking place in the given world
+    pub fn set_world(&mut self, world: WorldId) -> &mut Self {
+        self.filter.set_world(world);
+        self
+    }
+
+    /// Only match transactions in one of the given categories, see
+    /// TransactionFilter::set_categories
+    pub fn set_categories(&mut self, categories: HashSet<ActionCategory>) -> &mut Self {
+        self.filter.set_categories(categories);
+        self
+    }
+
+    /// Only match transactions committed after the given one
+    pub fn set_after(&mut self, transaction: TransactionID) -> &mut Self {
+        self.after = Some(transaction);
+        self
+    }
+
+    /// Only match transactions committed before the given one
+    pub fn set_before(&mut self, transaction: TransactionID) -> &mut Self {
+        self.before = Some(transaction);
+        self
+    }
+
+    /// Iterate from the most recent transaction back to the oldest, rather than oldest first
+    pub fn set_newest_first(&mut self) -> &mut Self {
+        self.newest_first = true;
+        self
+    }
+
+    /// Stop after the given number of matching transactions
+    pub fn set_limit(&mut self, limit: usize) -> &mut Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Leave out Undos
+    pub fn set_exclude_undos(&mut self) -> &mut Self {
+        self.exclude_undos = true;
+        self
+    }
+
+    /// Leave out transactions that have been undone
+    ///
+    /// Whether a transaction has been undone is only checked once it matches everything else,
+    /// as it is the most expensive check
+    pub fn set_exclude_undone(&mut self) -> &mut Self {
+        self.exclude_undone = true;
+        self
+    }
+
+    /// Returns a lazy iterator over the matching transactions
+    ///
+    /// The iterator works on the snapshot the query was made from, and does not hold any locks.
+    pub fn iter(&self) -> impl Iterator<Item = Transaction> {
+        let mut range = self.world_line.transactions.clone();
+        if let Some(after) = self.after {
+            range = range.split(&after).1;
+        }
+        if let Some(before) = self.before {
+            range = range.split(&before).0;
+        }
+        let ordered: Box<dyn Iterator<Item = _>> = if self.newest_first {
+            Box::new(range.iter().rev())
+        } else {
+            Box::new(range.iter())
+        };
+
+        let query = self.clone();
+        ordered
+            .map(|(_, transaction)| (*transaction).clone())
+            .filter(move |x| query.matches(x))
+            .take(self.limit.unwrap_or(usize::MAX))
+    }
+
+    /// Returns the first matching transaction, stopping as soon as it is found
+    pub fn first(&self) -> Option<Transaction> {
+        self.iter().next()
+    }
+
+    /// Returns the number of matching transactions, up to the limit
+    pub fn count(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Adds the criteria written in the text to the query
+    ///
+    /// The text is a list of `key:value` terms separated by whitespace:
+    ///
+    /// - `owner:<name or uuid>`, transactions by the player, with names looked up in the context
+    /// - `action:<category>`, transactions in the category, like `break` or `burn`. Several
+    ///   categories can be given separated by commas, or with several terms
+    /// - `radius:<blocks>`, transactions within that many blocks of the center of the context,
+    ///   in its world
+    /// - `time:<duration>`, transactions from within the duration before the time of the context.
+    ///   Durations are a number and a unit, one of `s`, `m`, `h`, `d` or `w`, like `30m`.
+    ///   `time:<1h` is the same as `time:1h`, `time:>1h` matches transactions older than that
+    /// - `limit:<n>`, at most n transactions
+    ///
+    /// Keys are not case sensitive. On an error the query is left partly updated.
+    pub fn parse(&mut self, text: &str, context: &QueryContext) -> Result<&mut Self, QueryError> {
+        let mut categories = HashSet::new();
+        for term in text.split_whitespace() {
+            let (key, value) = match term.find(':') {
+                Some(i) => (term[..i].to_ascii_lowercase(), &term[i + 1..]),
+                None => return Err(QueryError::InvalidTerm(term.to_string())),
+            };
+            let invalid = || QueryError::InvalidValue(key.clone(), value.to_string());
+            match key.as_str() {
+                "owner" => {
+                    let owner = Uuid::parse_str(value)
+                        .ok()
+                        .or_else(|| context.get_player(value))
+                        .ok_or_else(|| QueryError::UnknownPlayer(value.to_string()))?;
+                    self.set_owner(owner);
+                }
+                "action" => {
+                    for name in value.split(',') {
+                        categories.insert(ActionCategory::from_name(name).ok_or_else(invalid)?);
+                    }
+                }
+                "radius" => {
+                    let radius: i32 = value.parse().map_err(|_| invalid())?;
+                    let (world, (x, y, z)) = context.center.ok_or(QueryError::NoCenter)?;
+                    self.set_world(world).set_region(Region::new(
+                        (x - radius, y - radius, z - radius),
+                        (x + radius, y + radius, z + radius),
+                    ));
+                }
+                "time" => {
+                    if let Some(age) = value.strip_prefix('>') {
+                        self.set_end(context.now - parse_duration(age).ok_or_else(invalid)?);
+                    } else {
+                        let age = value.strip_prefix('<').unwrap_or(value);
+                        self.set_start(context.now - parse_duration(age).ok_or_else(invalid)?);
+                    }
+                }
+                "limit" => {
+                    self.set_limit(value.parse().map_err(|_| invalid())?);
+                }
+                _ => return Err(QueryError::UnknownKey(key)),
+            }
+        }
+        if !categories.is_empty() {
+            self.set_categories(categories);
+        }
+        Ok(self)
+    }
+
+    /// Returns true if the transaction matches every criteria of the query, other than its range
+    fn matches(&self, transaction: &Transaction) -> bool {
+        if self.exclude_undos && transaction.is_undo() {
+            return false;
+        }
+        if !self.filter.matches(transaction) {
+            return false;
+        }
+        !(self.exclude_undone && self.world_line.is_undone(transaction.get_id()))
+    }
+}
+
+/// What a text query is relative to, see Query::parse
+///
+/// This is where the player running the query stands, the time they ran it, and the names of the
+/// players they can refer to.
+#[derive(Clone, Debug)]
+pub struct QueryContext {
+    now: DateTime<Utc>,
+    center: Option<(WorldId, (i32, i32, i32))>,
+    /// Keyed by lowercased name
+    players: HashMap<String, Uuid>,
+}
+
+impl QueryContext {
+    /// Creates a new context for a query run at the given time, without a center or any players
+    pub fn new<Tz: TimeZone>(now: DateTime<Tz>) -> QueryContext {
+        QueryContext {
+            now: now.with_timezone(&Utc),
+            center: None,
+            players: HashMap::new(),
+        }
+    }
+
+    /// Sets the block radius terms are measured from
+    pub fn set_center(&mut self, world: WorldId, coords: (i32, i32, i32)) -> &mut Self {
+        self.center = Some((world, coords));
+        self
+    }
+
+    /// Adds a player that can be referred to by name, names are not case sensitive
+    pub fn add_player(&mut self, name: &str, uuid: Uuid) -> &mut Self {
+        self.players.insert(name.to_lowercase(), uuid);
+        self
+    }
+
+    /// Returns the uuid of the player with the given name, if known
+    pub fn get_player(&self, name: &str) -> Option<Uuid> {
+        self.players.get(&name.to_lowercase()).cloned()
+    }
+}
+
+/// Why a text query could not be parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryError {
+    /// A term was not of the form `key:value`
+    InvalidTerm(String),
+    /// The key of a term is not one of the known ones
+    UnknownKey(String),
+    /// The value of a term, given along with its key, could not be understood
+    InvalidValue(String, String),
+    /// An owner was neither a uuid nor the name of a known player
+    UnknownPlayer(String),
+    /// A radius was given, but the context has no center
+    NoCenter,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryError::InvalidTerm(ref term) => write!(f, "expected key:value, got {}", term),
+            QueryError::UnknownKey(ref key) => write!(f, "unknown key {}", key),
+            QueryError::InvalidValue(ref key, ref value) => {
+                write!(f, "invalid {}: {}", key, value)
+            }
+            QueryError::UnknownPlayer(ref name) => write!(f, "unknown player {}", name),
+            QueryError::NoCenter => f.write_str("radius needs a position to be measured from"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Parses a duration like `30m`, a number followed by one of `s`, `m`, `h`, `d` or `w`
+fn parse_duration(text: &str) -> Option<Duration> {
+    let unit = text.chars().last()?;
+    let amount: i64 = text[..text.len() - unit.len_utf8()].parse().ok()?;
+    match unit.to_ascii_lowercase() {
+        's' => Duration::try_seconds(amount),
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        'w' => Duration::try_weeks(amount),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+    use Rewind;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn text_queries() {
+        let rewind = Rewind::new(block(0));
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 12, 0, 0)
+            .unwrap();
+        let steve = Uuid::new_v4();
+        let set = |x, minutes, category| {
+            RawTransactionBuilder::new(TransactionType::new_set(block(1)))
+                .set_owner(steve)
+                .set_x_coord(x)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .set_time(now - Duration::minutes(minutes))
+                .set_category(category)
+                .build_transaction()
+                .unwrap()
+        };
+        rewind
+            .apply_transaction(set(0, 90, ActionCategory::Break))
+            .unwrap();
+        rewind
+            .apply_transaction(set(5, 30, ActionCategory::Break))
+            .unwrap();
+        rewind
+            .apply_transaction(set(50, 10, ActionCategory::Break))
+            .unwrap();
+        rewind
+            .apply_transaction(set(1, 5, ActionCategory::Build))
+            .unwrap();
+
+        let mut context = QueryContext::new(now);
+        context
+            .set_center(WorldId::OVERWORLD, (0, 0, 0))
+            .add_player("Steve", steve);
+        let run = |text: &str| -> Result<Vec<i32>, QueryError> {
+            let mut query = rewind.query();
+            query.parse(text, &context)?;
+            Ok(query
+                .iter()
+                .map(|x| x.get_transaction().get_coords().unwrap().0)
+                .collect())
+        };
+
+        assert!(run("owner:steve action:break radius:20 time:>1h") == Ok(vec![0]));
+        assert!(run("OWNER:Steve action:break radius:20 time:1h") == Ok(vec![5]));
+        assert!(run("action:break,build time:<45m") == Ok(vec![5, 50, 1]));
+        assert!(run("action:break limit:2") == Ok(vec![0, 5]));
+        assert!(run(&format!("owner:{}", Uuid::new_v4())) == Ok(vec![]));
+
+        assert!(run("owner:Alex") == Err(QueryError::UnknownPlayer("Alex".to_string())));
+        assert!(
+            run("action:arson") == Err(QueryError::InvalidValue("action".into(), "arson".into()))
+        );
+        assert!(run("time:1y") == Err(QueryError::InvalidValue("time".into(), "1y".into())));
+        assert!(run("steve") == Err(QueryError::InvalidTerm("steve".to_string())));
+        assert!(run("block:stone") == Err(QueryError::UnknownKey("block".to_string())));
+        let mut query = rewind.query();
+        assert!(
+            query.parse("radius:5", &QueryContext::new(now)).err() == Some(QueryError::NoCenter)
+        );
+    }
+}
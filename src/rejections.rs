@@ -0,0 +1,110 @@
+//! Provides a log of recently rejected transactions, for diagnosing edits that keep failing
+//!
+//! Subscribing to rejections only sees what is rejected while listening, so when a plugin's edits
+//! keep failing, an operator has to have been watching already. The log keeps the most recent
+//! rejections in memory, and can append every one of them to a writer as a line of JSON, so they
+//! are still around after a restart.
+
+use data::*;
+use serde_json;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Where appended rejections are written to, shared by every Rewind built with it
+pub(crate) type LogWriter = Arc<Mutex<dyn Write + Send>>;
+
+/// The most recent rejections, at most capacity of them, oldest first
+pub(crate) struct RejectionLog {
+    capacity: usize,
+    recent: VecDeque<RejectedTransaction>,
+    writer: Option<LogWriter>,
+}
+
+impl RejectionLog {
+    /// Creates an empty log keeping at most capacity rejections, appending every one to the
+    /// writer if there is one
+    pub fn new(capacity: usize, writer: Option<LogWriter>) -> RejectionLog {
+        RejectionLog {
+            capacity,
+            recent: VecDeque::with_capacity(capacity),
+            writer,
+        }
+    }
+
+    /// Returns the number of rejections the log keeps
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the writer rejections are appended to, if there is one
+    pub fn get_writer(&self) -> Option<LogWriter> {
+        self.writer.clone()
+    }
+
+    /// Records a rejection, dropping the oldest one if the log is full
+    ///
+    /// Failing to append it to the writer is ignored, the transaction was rejected either way.
+    pub fn record(&mut self, rejected: RejectedTransaction) {
+        if self.capacity > 0 {
+            if self.recent.len() == self.capacity {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(rejected.clone());
+        }
+        if let Some(ref writer) = self.writer {
+            let mut writer = writer.lock().unwrap();
+            let _ = serde_json::to_writer(&mut *writer, &rejected)
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(writer))
+                .and_then(|_| writer.flush());
+        }
+    }
+
+    /// Returns every rejection kept, oldest first
+    pub fn get_recent(&self) -> Vec<RejectedTransaction> {
+        self.recent.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(reason: RejectionReason) -> RejectedTransaction {
+        let block = MetaBlock::fuse(Block::new_from_ids(0, 1), MetaData::new());
+        let transaction = RawTransactionBuilder::new(TransactionType::new_set(block))
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap();
+        RejectedTransaction::new(transaction, reason.into())
+    }
+
+    #[test]
+    fn keeps_the_most_recent() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut log = RejectionLog::new(2, Some(written.clone() as LogWriter));
+        log.record(rejected(RejectionReason::Invalid));
+        log.record(rejected(RejectionReason::Vetoed));
+        log.record(rejected(RejectionReason::Stale));
+
+        let reasons: Vec<RejectionReason> =
+            log.get_recent().iter().map(|x| x.get_reason()).collect();
+        assert!(reasons == vec![RejectionReason::Vetoed, RejectionReason::Stale]);
+
+        // Every rejection is appended, not just the ones kept
+        let written = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        let read: Vec<RejectedTransaction> = written
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[0].get_reason(), RejectionReason::Invalid);
+
+        let mut disabled = RejectionLog::new(0, None);
+        disabled.record(rejected(RejectionReason::Invalid));
+        assert!(disabled.get_recent().is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+//! Provides primary/replica replication of a Rewind
+//!
+//! A primary hands out a ReplicationFeed, a snapshot of its worldline plus a stream of every
+//! transaction committed after it. A Replica catches up from the snapshot and then applies the
+//! stream deterministically, keeping the primary's transaction ids, so read-heavy consumers like
+//! map servers can stay in sync without touching the primary.
+
+use conflict::ConflictPolicy;
+use data::*;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+use {Rewind, RewindBuilder};
+
+/// Everything a replica needs to follow a primary
+pub struct ReplicationFeed {
+    defaults: DefaultLayers,
+    baselines: im::HashMap<WorldId, Arc<dyn BaselineWorld>>,
+    conflict_policy: Arc<dyn ConflictPolicy>,
+    snapshot: Vec<Transaction>,
+    updates: Receiver<Transaction>,
+}
+
+impl ReplicationFeed {
+    /// Creates a new feed following the given primary
+    pub fn new(primary: &Rewind) -> ReplicationFeed {
+        let (snapshot, updates) = primary.subscribe_with_history();
+        ReplicationFeed {
+            defaults: primary.get_default_layers(),
+            baselines: primary.builder().baselines,
+            conflict_policy: primary.conflict_policy.clone(),
+            snapshot,
+            updates,
+        }
+    }
+
+    /// Returns the transactions the primary had committed when the feed was created
+    pub fn get_snapshot(&self) -> &[Transaction] {
+        &self.snapshot
+    }
+}
+
+/// A read-only copy of a primary Rewind, kept in sync through a ReplicationFeed
+///
+/// Transactions should never be applied to the replica's Rewind directly, as they would not make
+/// it back to the primary, and would collide with the ids the primary assigns.
+pub struct Replica {
+    rewind: Rewind,
+    updates: Receiver<Transaction>,
+    connected: bool,
+}
+
+impl Replica {
+    /// Creates a new replica, catching up from the feed's snapshot
+    ///
+    /// The replica resolves conflicts with the same policy as the primary
+    pub fn new(feed: ReplicationFeed) -> Replica {
+        let mut builder = RewindBuilder::new(feed.defaults.get_base());
+        builder.set_default_layers(feed.defaults);
+        builder.baselines = feed.baselines;
+        builder.conflict_policy = feed.conflict_policy;
+        Replica {
+            rewind: builder.build_from_transactions(feed.snapshot),
+            updates: feed.updates,
+            connected: true,
+        }
+    }
+
+    /// Returns the replicated Rewind, for reading
+    pub fn get_rewind(&self) -> &Rewind {
+        &self.rewind
+    }
+
+    /// Returns false once the primary has gone away and every transaction it sent was applied
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Applies every transaction the primary has sent so far, without blocking
+    ///
+    /// Returns the number of transactions applied
+    pub fn sync(&mut self) -> usize {
+        let mut applied = 0;
+        loop {
+            match self.updates.try_recv() {
+                Ok(transaction) => {
+                    // Ids only come from the primary, so they are never taken by another
+                    let _ = self.rewind.replay_transaction(transaction);
+                    applied += 1;
+                }
+                Err(TryRecvError::Empty) => return applied,
+                Err(TryRecvError::Disconnected) => {
+                    self.connected = false;
+                    return applied;
+                }
+            }
+        }
+    }
+
+    /// Waits up to the timeout for the next transaction, then applies everything available
+    ///
+    /// Returns the number of transactions applied
+    pub fn sync_timeout(&mut self, timeout: Duration) -> usize {
+        match self.updates.recv_timeout(timeout) {
+            Ok(transaction) => {
+                let _ = self.rewind.replay_transaction(transaction);
+                1 + self.sync()
+            }
+            Err(RecvTimeoutError::Timeout) => 0,
+            Err(RecvTimeoutError::Disconnected) => {
+                self.connected = false;
+                0
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    fn set_at(id: u16, x: i32, y: i32, z: i32) -> RawTransaction {
+        RawTransactionBuilder::new(TransactionType::new_set(block(id)))
+            .set_owner(Uuid::new_v4())
+            .set_x_coord(x)
+            .set_y_coord(y)
+            .set_z_coord(z)
+            .build_transaction()
+            .unwrap()
+    }
+
+    #[test]
+    fn replica_follows_primary() {
+        let primary = Rewind::new(block(0));
+        primary.apply_transaction(set_at(1, 0, 0, 0)).unwrap();
+        let second = primary.apply_transaction(set_at(2, 1, 0, 0)).unwrap();
+
+        let mut replica = Replica::new(ReplicationFeed::new(&primary));
+        assert!(replica.get_rewind().get_transactions() == primary.get_transactions());
+
+        primary.apply_transaction(set_at(3, 0, 0, 0)).unwrap();
+        let undo = RawTransactionBuilder::new(TransactionType::new_undo(second.get_id()))
+            .build_transaction()
+            .unwrap();
+        primary.apply_transaction(undo).unwrap();
+
+        assert_eq!(replica.sync(), 2);
+        assert!(replica.get_rewind().get_transactions() == primary.get_transactions());
+        let world = replica.get_rewind().get_world_state();
+        assert!(world.get_block_defaulting(0, 0, 0).unwrap() == block(3));
+        assert!(world.get_block_defaulting(1, 0, 0).unwrap() == block(0));
+
+        drop(primary);
+        assert_eq!(replica.sync(), 0);
+        assert!(!replica.is_connected());
+    }
+}
@@ -0,0 +1,278 @@
+//! Provides retrying of transactions that conflict with concurrent writers
+//!
+//! In a busy world a Replace often fails because another writer changed the block between the
+//! client reading it and the Replace being applied. Many such states are transient, like a
+//! block being broken and placed back, so rather than every caller writing its own retry loop,
+//! Rewind::apply_with_retry retries with an exponential backoff.
+//!
+//! Only conflicts are retried. Every other rejection, such as a guard vetoing the transaction,
+//! would happen again, so it is returned straight away. After a conflict the precondition of the
+//! Replace is checked against the current world before each retry, and the transaction is only
+//! submitted again once it holds, so waiting does not flood the rejection log.
+
+use data::*;
+use std::thread;
+use std::time::{Duration, Instant};
+use Rewind;
+
+/// How often, and how patiently, to retry a conflicting transaction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a policy making at most max_attempts attempts, the first one included, waiting
+    /// 1ms after the first conflict and doubling that after each one, up to 100ms
+    ///
+    /// Returns None if max_attempts is zero
+    pub fn new(max_attempts: usize) -> Option<RetryPolicy> {
+        if max_attempts == 0 {
+            return None;
+        }
+        Some(RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(100),
+        })
+    }
+
+    /// Sets how long to wait after the first conflict, and the most to wait after any one
+    ///
+    /// Returns None, leaving the policy untouched, if initial is longer than max
+    pub fn set_backoff(&mut self, initial: Duration, max: Duration) -> Option<&mut Self> {
+        if initial > max {
+            return None;
+        }
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        Some(self)
+    }
+
+    /// Returns the most attempts made, the first one included
+    pub fn get_max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Returns how long to wait after the given failed attempt, counting from zero
+    pub fn get_backoff(&self, attempt: usize) -> Duration {
+        let factor = 1u32.checked_shl(attempt as u32).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |x| x.min(self.max_backoff))
+    }
+
+    /// Returns true if a rejection for the reason is worth retrying
+    pub fn is_retryable(&self, reason: RejectionReason) -> bool {
+        reason == RejectionReason::Conflict
+    }
+}
+
+/// Five attempts, with the backoff of RetryPolicy::new
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::new(5).unwrap()
+    }
+}
+
+/// What happened while applying a transaction with retries
+#[derive(Clone, Debug)]
+pub struct RetryReport {
+    result: Result<Transaction, Rejection>,
+    rejections: Vec<Rejection>,
+    submitted: usize,
+    waited: Duration,
+    elapsed: Duration,
+}
+
+impl RetryReport {
+    /// Returns the committed transaction, or the last rejection if every attempt failed
+    pub fn get_result(&self) -> Result<Transaction, Rejection> {
+        self.result.clone()
+    }
+
+    /// Returns true if the transaction was committed
+    pub fn is_applied(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// Returns the number of attempts made, the one that succeeded included
+    pub fn get_attempts(&self) -> usize {
+        self.rejections.len() + usize::from(self.is_applied())
+    }
+
+    /// Returns the number of attempts the transaction was actually submitted in, the others
+    /// having found its precondition still did not hold
+    pub fn get_submitted(&self) -> usize {
+        self.submitted
+    }
+
+    /// Returns the rejection of every failed attempt, oldest first
+    ///
+    /// Attempts that were not submitted report the block they found, but not which transaction
+    /// wrote it.
+    pub fn get_rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+
+    /// Returns the time spent waiting between attempts
+    pub fn get_waited(&self) -> Duration {
+        self.waited
+    }
+
+    /// Returns the time taken by every attempt together, waiting included
+    pub fn get_elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+/// Applies a transaction, retrying conflicts, see Rewind::apply_with_retry
+pub(crate) fn apply_with_retry(
+    rewind: &Rewind,
+    transaction: RawTransaction,
+    policy: &RetryPolicy,
+) -> RetryReport {
+    let started = Instant::now();
+    let mut rejections: Vec<Rejection> = Vec::new();
+    let mut submitted = 0;
+    let mut waited = Duration::from_secs(0);
+    let result = loop {
+        // The first attempt is always submitted, the precondition is only worth checking after
+        // a conflict
+        let unmet = if rejections.is_empty() {
+            None
+        } else {
+            unmet_precondition(rewind, transaction.clone())
+        };
+        let attempt = match unmet {
+            Some(rejection) => Err(rejection),
+            None => {
+                submitted += 1;
+                rewind.try_apply_transaction(transaction.clone())
+            }
+        };
+        match attempt {
+            Ok(committed) => break Ok(committed),
+            Err(rejection) => {
+                rejections.push(rejection.clone());
+                if !policy.is_retryable(rejection.get_reason())
+                    || rejections.len() >= policy.max_attempts
+                {
+                    break Err(rejection);
+                }
+                let backoff = policy.get_backoff(rejections.len() - 1);
+                thread::sleep(backoff);
+                waited += backoff;
+            }
+        }
+    };
+    RetryReport {
+        result,
+        rejections,
+        submitted,
+        waited,
+        elapsed: started.elapsed(),
+    }
+}
+
+/// Returns a conflict if the transaction is a Replace that would not find the block it expects,
+/// or Unavailable if the block can not be read
+fn unmet_precondition(rewind: &Rewind, transaction: RawTransaction) -> Option<Rejection> {
+    let expected = match transaction.get_transaction_type() {
+        TransactionType::Replace { block_current, .. } => block_current.get(),
+        _ => return None,
+    };
+    let (x, y, z) = transaction.get_coords()?;
+    let world = rewind.world_in(&rewind.worlds.load(), transaction.get_world());
+    match world.get_block_defaulting(x, y, z) {
+        Ok(actual) if actual == expected => None,
+        Ok(actual) => Some(Rejection::new_conflict((x, y, z), expected, actual, None)),
+        Err(error) => Some(Rejection::from(error).or_coords(Some((x, y, z)))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    fn at_origin(transaction_type: TransactionType) -> RawTransaction {
+        RawTransactionBuilder::new(transaction_type)
+            .set_x_coord(0)
+            .set_y_coord(0)
+            .set_z_coord(0)
+            .build_transaction()
+            .unwrap()
+    }
+
+    #[test]
+    fn retries_until_the_precondition_holds() {
+        let rewind = Rewind::new(block(0));
+        rewind.apply_transaction(at_origin(TransactionType::new_set(block(1))));
+        let replace = at_origin(TransactionType::new_replace(block(0), block(2)));
+
+        // Nothing puts the block back
+        let policy = RetryPolicy::new(3).unwrap();
+        let report = rewind.apply_with_retry(replace.clone(), &policy);
+        assert!(!report.is_applied());
+        assert_eq!((report.get_attempts(), report.get_submitted()), (3, 1));
+        assert_eq!(report.get_rejections()[2].get_actual(), Some(block(1)));
+        assert_eq!(report.get_waited(), Duration::from_millis(3));
+
+        // Another writer puts the block back a little later
+        let writer = rewind.clone();
+        let put_back = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            writer.apply_transaction(at_origin(TransactionType::new_set(block(0))));
+        });
+        let mut policy = RetryPolicy::new(1000).unwrap();
+        policy
+            .set_backoff(Duration::from_millis(1), Duration::from_millis(5))
+            .unwrap();
+        let report = rewind.apply_with_retry(replace, &policy);
+        put_back.join().unwrap();
+        assert!(report.is_applied());
+        assert!(report.get_attempts() > 1);
+        assert_eq!(report.get_submitted(), 2);
+        assert!(
+            rewind
+                .get_world_state()
+                .get_block_defaulting(0, 0, 0)
+                .unwrap()
+                == block(2)
+        );
+
+        // Anything but a conflict is not retried
+        let undo = RawTransactionBuilder::new(TransactionType::new_undo(
+            TransactionID::new_from_parts(99, 0),
+        ))
+        .build_transaction()
+        .unwrap();
+        let report = rewind.apply_with_retry(undo, &policy);
+        assert_eq!(report.get_attempts(), 1);
+        assert_eq!(
+            report.get_result().unwrap_err().get_reason(),
+            RejectionReason::NotFound
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        let mut policy = RetryPolicy::default();
+        assert!(RetryPolicy::new(0).is_none());
+        assert!(policy
+            .set_backoff(Duration::from_millis(2), Duration::from_millis(1))
+            .is_none());
+        policy
+            .set_backoff(Duration::from_millis(2), Duration::from_millis(10))
+            .unwrap();
+        let backoffs: Vec<u128> = (0..5).map(|x| policy.get_backoff(x).as_millis()).collect();
+        assert!(backoffs == vec![2, 4, 8, 10, 10]);
+        assert_eq!(policy.get_backoff(200), Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,522 @@
+//! Provides a deterministic simulation harness, driving a Rewind with a random workload
+//!
+//! A simulation applies a stream of Sets, Replaces, Undos and rollbacks, picked by a random number
+//! generator seeded from the config, to a small region of a Rewind, and every so often checks
+//! that the invariants everything else relies on still hold:
+//!
+//! - Replaying the worldline from scratch gives the same world as the one applied live
+//! - Compacting the worldline does not change the world
+//! - Undoing a transaction twice leaves the world the same as undoing it once
+//! - Applying a rollback plan leaves every block it lists as the plan said it would
+//! - Every block the checks read can be read, so evicted chunks can be loaded back
+//!
+//! The same seed always produces the same workload, and the same worldline on Rewinds with the
+//! same node id, so a failure can be reproduced from the seed it reports. Integrators can point a simulation at a Rewind built with their own conflict
+//! policy, guards and validators, and run it for as long as they like as a soak test.
+
+use data::*;
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+use Rewind;
+
+/// A kind of step in a simulation
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SimAction {
+    Set,
+    /// Usually expecting the block that is there, sometimes a random one, so some conflict
+    Replace,
+    /// Of a random committed transaction, which may be an Undo itself, or already undone
+    Undo,
+    /// Of every transaction by a random owner
+    Rollback,
+}
+
+/// An invariant checked by a simulation, see the module documentation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Invariant {
+    Replay,
+    Compaction,
+    UndoIdempotence,
+    Rollback,
+    /// The expected and actual blocks of its failures are the default block at the coordinates
+    Readable,
+}
+
+/// An invariant that did not hold, with everything needed to reproduce it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimFailure {
+    seed: u64,
+    step: usize,
+    invariant: Invariant,
+    coords: (i32, i32, i32),
+    expected: MetaBlock,
+    actual: MetaBlock,
+}
+
+impl SimFailure {
+    /// Returns the seed of the simulation that failed
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the number of steps taken before the failure was found
+    pub fn get_step(&self) -> usize {
+        self.step
+    }
+
+    /// Returns the invariant that did not hold
+    pub fn get_invariant(&self) -> Invariant {
+        self.invariant
+    }
+
+    /// Returns the coordinates of the block the invariant did not hold for
+    pub fn get_coords(&self) -> (i32, i32, i32) {
+        self.coords
+    }
+
+    /// Returns the block that should have been there
+    pub fn get_expected(&self) -> MetaBlock {
+        self.expected.clone()
+    }
+
+    /// Returns the block that was there instead
+    pub fn get_actual(&self) -> MetaBlock {
+        self.actual.clone()
+    }
+}
+
+impl fmt::Display for SimFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x, y, z) = self.coords;
+        write!(
+            f,
+            "{:?} invariant broken after step {} of seed {}: expected {} at {} {} {}, found {}",
+            self.invariant, self.step, self.seed, self.expected, x, y, z, self.actual
+        )
+    }
+}
+
+/// What a simulation did
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimReport {
+    steps: usize,
+    checks: usize,
+    applied: HashMap<SimAction, usize>,
+    rejected: HashMap<SimAction, usize>,
+}
+
+impl SimReport {
+    /// Returns the number of steps taken
+    pub fn get_steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns the number of times the invariants were checked
+    pub fn get_checks(&self) -> usize {
+        self.checks
+    }
+
+    /// Returns the number of steps of a kind that were applied
+    pub fn get_applied(&self, action: SimAction) -> usize {
+        self.applied.get(&action).cloned().unwrap_or(0)
+    }
+
+    /// Returns the number of steps of a kind that were rejected
+    pub fn get_rejected(&self, action: SimAction) -> usize {
+        self.rejected.get(&action).cloned().unwrap_or(0)
+    }
+}
+
+/// Describes the workload of a simulation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SimConfig {
+    seed: u64,
+    steps: usize,
+    check_every: usize,
+    region: Region,
+    block_ids: u16,
+    owners: u8,
+}
+
+impl SimConfig {
+    /// Creates a config for a simulation with the given seed
+    ///
+    /// By default it takes 1000 steps, checking the invariants every 100 and after the last one,
+    /// with 8 different blocks set by 4 different owners in an 8 by 4 by 8 region at the origin.
+    pub fn new(seed: u64) -> SimConfig {
+        SimConfig {
+            seed,
+            steps: 1000,
+            check_every: 100,
+            region: Region::new((0, 0, 0), (7, 3, 7)),
+            block_ids: 8,
+            owners: 4,
+        }
+    }
+
+    /// Sets the number of steps to take
+    pub fn set_steps(&mut self, steps: usize) -> &mut Self {
+        self.steps = steps;
+        self
+    }
+
+    /// Sets how many steps apart the invariants are checked, they are always checked at the end
+    ///
+    /// Returns None, leaving the config untouched, if check_every is zero
+    pub fn set_check_every(&mut self, check_every: usize) -> Option<&mut Self> {
+        if check_every == 0 {
+            return None;
+        }
+        self.check_every = check_every;
+        Some(self)
+    }
+
+    /// Sets the region the workload is confined to
+    ///
+    /// Smaller regions make transactions land on the same blocks more often, and every block of
+    /// the region is compared when checking the invariants.
+    pub fn set_region(&mut self, region: Region) -> &mut Self {
+        self.region = region;
+        self
+    }
+
+    /// Sets the number of different blocks set, with ids counting up from 0 of provider 0
+    ///
+    /// Returns None, leaving the config untouched, if block_ids is zero
+    pub fn set_block_ids(&mut self, block_ids: u16) -> Option<&mut Self> {
+        if block_ids == 0 {
+            return None;
+        }
+        self.block_ids = block_ids;
+        Some(self)
+    }
+
+    /// Sets the number of different owners transactions are made by
+    ///
+    /// Returns None, leaving the config untouched, if owners is zero
+    pub fn set_owners(&mut self, owners: u8) -> Option<&mut Self> {
+        if owners == 0 {
+            return None;
+        }
+        self.owners = owners;
+        Some(self)
+    }
+}
+
+/// A simulation driving a Rewind, see the module documentation
+pub struct Simulation {
+    rewind: Rewind,
+    config: SimConfig,
+    rng: SplitMix,
+    committed: Vec<TransactionID>,
+    report: SimReport,
+}
+
+impl Simulation {
+    /// Creates a simulation driving the Rewind, which shares its state with the one given
+    pub fn new(rewind: &Rewind, config: SimConfig) -> Simulation {
+        Simulation {
+            rewind: rewind.clone(),
+            config,
+            rng: SplitMix(config.seed),
+            committed: Vec::new(),
+            report: SimReport::default(),
+        }
+    }
+
+    /// Takes every step of the workload, checking the invariants as configured
+    ///
+    /// Returns what was done, or the first invariant found not to hold.
+    pub fn run(&mut self) -> Result<SimReport, SimFailure> {
+        while self.report.steps < self.config.steps {
+            self.step()?;
+            if self.report.steps.is_multiple_of(self.config.check_every) {
+                self.check()?;
+            }
+        }
+        if !self.report.steps.is_multiple_of(self.config.check_every) {
+            self.check()?;
+        }
+        Ok(self.report.clone())
+    }
+
+    /// Takes a single random step of the workload, returning what kind of step it was and
+    /// whether it was applied
+    ///
+    /// Fails if the step was a rollback that did not do what its plan said it would.
+    pub fn step(&mut self) -> Result<(SimAction, bool), SimFailure> {
+        let roll = self.rng.below(100);
+        let action = match roll {
+            0..=44 => SimAction::Set,
+            45..=74 => SimAction::Replace,
+            75..=94 if !self.committed.is_empty() => SimAction::Undo,
+            75..=94 => SimAction::Set,
+            _ => SimAction::Rollback,
+        };
+        let applied = match action {
+            SimAction::Set => {
+                let (block, coords) = (self.block(), self.coords());
+                let transaction = self.at(TransactionType::new_set(block), coords);
+                self.apply(transaction)
+            }
+            SimAction::Replace => {
+                let coords = self.coords();
+                let current = if self.rng.below(4) == 0 {
+                    self.block()
+                } else {
+                    let world = self.rewind.get_world_state();
+                    self.read(&world, coords)?
+                };
+                let block = self.block();
+                let transaction = self.at(TransactionType::new_replace(current, block), coords);
+                self.apply(transaction)
+            }
+            SimAction::Undo => {
+                let target = self.committed[self.rng.below(self.committed.len() as u64) as usize];
+                let transaction = RawTransactionBuilder::new(TransactionType::new_undo(target))
+                    .set_owner(self.owner())
+                    .build_transaction()
+                    .unwrap();
+                self.apply(transaction)
+            }
+            SimAction::Rollback => self.rollback()?,
+        };
+        self.report.steps += 1;
+        let counts = if applied {
+            &mut self.report.applied
+        } else {
+            &mut self.report.rejected
+        };
+        *counts.entry(action).or_insert(0) += 1;
+        Ok((action, applied))
+    }
+
+    /// Checks every invariant but the one for rollbacks, which is checked as they are applied
+    pub fn check(&mut self) -> Result<(), SimFailure> {
+        self.report.checks += 1;
+        let live = self.rewind.get_world_state();
+
+        let replayed = self
+            .rewind
+            .builder()
+            .build_from_transactions(self.rewind.get_transactions())
+            .with_world(self.rewind.get_world_id());
+        self.compare(Invariant::Replay, &live, &replayed.get_world_state())?;
+        let compacted = self.rewind.compact().with_world(self.rewind.get_world_id());
+        self.compare(Invariant::Compaction, &live, &compacted.get_world_state())?;
+
+        if !self.committed.is_empty() {
+            let target = self.committed[self.rng.below(self.committed.len() as u64) as usize];
+            let sandbox = self.rewind.sandbox();
+            let undo = RawTransactionBuilder::new(TransactionType::new_undo(target))
+                .build_transaction()
+                .unwrap();
+            sandbox.apply_transaction(undo.clone());
+            let once = sandbox.get_world_state();
+            sandbox.apply_transaction(undo);
+            self.compare(
+                Invariant::UndoIdempotence,
+                &once,
+                &sandbox.get_world_state(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the Rewind being driven
+    pub fn get_rewind(&self) -> &Rewind {
+        &self.rewind
+    }
+
+    /// Returns what has been done so far
+    pub fn get_report(&self) -> &SimReport {
+        &self.report
+    }
+
+    /// Rolls back every transaction of a random owner, checking the plan was followed
+    ///
+    /// Returns whether the plan was applied.
+    fn rollback(&mut self) -> Result<bool, SimFailure> {
+        let mut filter = TransactionFilter::new();
+        filter
+            .set_owner(self.owner())
+            .set_region(self.config.region);
+        let plan = self.rewind.preview_rollback(&filter);
+        let committed = match self.rewind.apply_plan(&plan, Uuid::nil()) {
+            Some(committed) => committed,
+            None => return Ok(false),
+        };
+        self.committed.extend(committed.iter().map(|x| x.get_id()));
+        let world = self.rewind.get_world_state();
+        for change in plan.get_changes() {
+            let (x, y, z) = change.get_coords();
+            let actual = self.read(&world, (x, y, z))?;
+            if actual != change.get_after() {
+                return Err(self.failure(
+                    Invariant::Rollback,
+                    (x, y, z),
+                    change.get_after(),
+                    actual,
+                ));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Applies a transaction, remembering it if it was committed
+    fn apply(&mut self, transaction: RawTransaction) -> bool {
+        match self.rewind.apply_transaction(transaction) {
+            Some(committed) => {
+                self.committed.push(committed.get_id());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Compares two worlds block by block across the region
+    fn compare(
+        &self,
+        invariant: Invariant,
+        expected: &World,
+        actual: &World,
+    ) -> Result<(), SimFailure> {
+        let (min, max) = (self.config.region.get_min(), self.config.region.get_max());
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    let (expected, actual) = (
+                        self.read(expected, (x, y, z))?,
+                        self.read(actual, (x, y, z))?,
+                    );
+                    if expected != actual {
+                        return Err(self.failure(invariant, (x, y, z), expected, actual));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a block, failing the Readable invariant if its chunk can not be loaded back
+    fn read(&self, world: &World, coords: (i32, i32, i32)) -> Result<MetaBlock, SimFailure> {
+        let (x, y, z) = coords;
+        world.get_block_defaulting(x, y, z).map_err(|_| {
+            let block = world.get_default_block_at(x, y, z);
+            self.failure(Invariant::Readable, coords, block.clone(), block)
+        })
+    }
+
+    fn failure(
+        &self,
+        invariant: Invariant,
+        coords: (i32, i32, i32),
+        expected: MetaBlock,
+        actual: MetaBlock,
+    ) -> SimFailure {
+        SimFailure {
+            seed: self.config.seed,
+            step: self.report.steps,
+            invariant,
+            coords,
+            expected,
+            actual,
+        }
+    }
+
+    fn block(&mut self) -> MetaBlock {
+        let id = self.rng.below(u64::from(self.config.block_ids)) as u16;
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    fn owner(&mut self) -> Uuid {
+        let owner = self.rng.below(u64::from(self.config.owners)) as u8;
+        Uuid::from_uuid_bytes([owner; 16])
+    }
+
+    fn coords(&mut self) -> (i32, i32, i32) {
+        let (min, max) = (self.config.region.get_min(), self.config.region.get_max());
+        let mut between = |min: i32, max: i32| {
+            let span = (i64::from(max) - i64::from(min) + 1) as u64;
+            (i64::from(min) + self.rng.below(span) as i64) as i32
+        };
+        (
+            between(min.0, max.0),
+            between(min.1, max.1),
+            between(min.2, max.2),
+        )
+    }
+
+    fn at(&mut self, kind: TransactionType, (x, y, z): (i32, i32, i32)) -> RawTransaction {
+        RawTransactionBuilder::new(kind)
+            .set_owner(self.owner())
+            .set_x_coord(x)
+            .set_y_coord(y)
+            .set_z_coord(z)
+            .build_transaction()
+            .unwrap()
+    }
+}
+
+/// The splitmix64 generator, small and good enough to pick steps with, so the same seed gives the
+/// same workload on every platform
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number below n, which must not be zero
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u16) -> MetaBlock {
+        MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new())
+    }
+
+    #[test]
+    fn simulations_hold_and_repeat() {
+        let mut config = SimConfig::new(42);
+        config.set_steps(300).set_check_every(50).unwrap();
+
+        let rewind = Rewind::with_node_id(block(0), 0);
+        let report = Simulation::new(&rewind, config).run().unwrap();
+        assert_eq!(report.get_steps(), 300);
+        assert_eq!(report.get_checks(), 6);
+        for action in [SimAction::Set, SimAction::Replace, SimAction::Undo] {
+            assert!(report.get_applied(action) > 0);
+        }
+        assert!(report.get_rejected(SimAction::Replace) > 0);
+        assert!(report.get_applied(SimAction::Rollback) > 0);
+
+        // The same seed gives the same worldline, other than the times rollbacks were made at
+        let again = Rewind::with_node_id(block(0), 0);
+        assert!(Simulation::new(&again, config).run().unwrap() == report);
+        let history = |rewind: &Rewind| -> Vec<(TransactionID, TransactionType)> {
+            rewind
+                .get_transactions()
+                .iter()
+                .map(|x| {
+                    (
+                        x.get_id(),
+                        x.get_transaction().get_transaction_type().clone(),
+                    )
+                })
+                .collect()
+        };
+        assert!(history(&again) == history(&rewind));
+        assert!(config.set_check_every(0).is_none());
+    }
+}
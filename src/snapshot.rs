@@ -0,0 +1,98 @@
+//! Provides a cell holding an Arc that can be read without locking, and swapped atomically
+//!
+//! Readers never wait on writers, loading the value only clones the Arc. Writers are serialized
+//! with each other, so each one can build the new value from the current one without losing
+//! another's update.
+
+use arc_swap::ArcSwap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Holds an Arc, which can be loaded without locking and replaced atomically
+pub struct Snapshot<T> {
+    current: ArcSwap<T>,
+    writer: Mutex<()>,
+}
+
+/// Exclusive access to write a Snapshot, obtained with Snapshot::lock
+pub struct SnapshotWriter<'a, T: 'a> {
+    snapshot: &'a Snapshot<T>,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl<T> Snapshot<T> {
+    /// Creates a new cell holding the value
+    pub fn new(value: T) -> Snapshot<T> {
+        Snapshot {
+            current: ArcSwap::from_pointee(value),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Returns the current value, without waiting on writers
+    pub fn load(&self) -> Arc<T> {
+        self.current.load_full()
+    }
+
+    /// Locks the cell for writing, blocking until no other writer holds it
+    ///
+    /// Readers are not blocked, and keep seeing the current value until a new one is published
+    pub fn lock(&self) -> SnapshotWriter<'_, T> {
+        SnapshotWriter {
+            snapshot: self,
+            _guard: self.writer.lock().unwrap(),
+        }
+    }
+}
+
+impl<'a, T> SnapshotWriter<'a, T> {
+    /// Returns the current value
+    pub fn get(&self) -> Arc<T> {
+        self.snapshot.load()
+    }
+
+    /// Replaces the current value, every load from now on sees the new one
+    pub fn publish(&mut self, value: T) {
+        self.snapshot.current.store(Arc::new(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn readers_see_published_values() {
+        let snapshot = Arc::new(Snapshot::new(Arc::new(0)));
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (snapshot, done) = (snapshot.clone(), done.clone());
+                thread::spawn(move || {
+                    let mut last = 0;
+                    while !done.load(Ordering::SeqCst) {
+                        let value = **snapshot.load();
+                        assert!(value >= last);
+                        last = value;
+                    }
+                })
+            })
+            .collect();
+
+        // The inner Arcs count how many values are still alive
+        let values: Vec<Arc<usize>> = (1..=1000).map(Arc::new).collect();
+        for value in &values {
+            snapshot.lock().publish(value.clone());
+        }
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(**snapshot.load(), 1000);
+        assert!(values[..999].iter().all(|x| Arc::strong_count(x) == 1));
+        drop(snapshot);
+        assert_eq!(Arc::strong_count(&values[999]), 1);
+    }
+}
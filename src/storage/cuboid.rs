@@ -1,11 +1,19 @@
 //! Provides efficent, immutable storage of a 3D array
 
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::mem::size_of;
+use std::sync::Arc;
 use storage::purse::*;
 use storage::slice::*;
+use storage::voxel::*;
 
 /// Immutable 3D array with a default value
+///
+/// Nothing is allocated until the first value is set
 #[derive(Clone)]
 pub struct Cuboid<T> {
+    /// One slice per z, empty until the first value is set
     data: Purse<Slice<T>>,
     default: T,
     x_size: usize,
@@ -15,19 +23,17 @@ pub struct Cuboid<T> {
 
 impl<T: Clone> Cuboid<T> {
     pub fn new(x_size: usize, y_size: usize, z_size: usize, default: &T) -> Cuboid<T> {
-        let proto_slice = Slice::new(x_size, y_size, default.clone());
-        let purse = Purse::new_filled(z_size, proto_slice);
         Cuboid {
-            data: purse,
+            data: Purse::new(),
             default: default.clone(),
-            x_size: x_size,
-            y_size: y_size,
-            z_size: z_size,
+            x_size,
+            y_size,
+            z_size,
         }
     }
 
     pub fn get(&self, x: usize, y: usize, z: usize) -> &T {
-        if x >= self.x_size || y >= self.y_size || z >= self.z_size {
+        if x >= self.x_size || y >= self.y_size || z >= self.z_size || self.data.is_empty() {
             &self.default
         } else {
             let slice = &self.data[z];
@@ -39,9 +45,14 @@ impl<T: Clone> Cuboid<T> {
         if x >= self.x_size || y >= self.y_size || z >= self.z_size {
             None
         } else {
-            let old_slice = &self.data[z];
-            let new_slice = old_slice.set(x, y, value);
-            let new_purse = self.data.set(z, new_slice);
+            let data = if self.data.is_empty() {
+                let proto_slice = Slice::new(self.x_size, self.y_size, self.default.clone());
+                Purse::new_filled(self.z_size, proto_slice)
+            } else {
+                self.data.clone()
+            };
+            let new_slice = data[z].set(x, y, value);
+            let new_purse = data.set(z, new_slice);
             Some(Cuboid {
                 data: new_purse,
                 default: self.default.clone(),
@@ -51,4 +62,862 @@ impl<T: Clone> Cuboid<T> {
             })
         }
     }
+
+    /// Returns the x, y, and z size of the cuboid
+    pub fn get_size(&self) -> (usize, usize, usize) {
+        (self.x_size, self.y_size, self.z_size)
+    }
+
+    /// Returns every cell, along with its coordinates, in z, y, x order
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize, usize), &T)> {
+        cells(self.x_size, self.y_size, self.z_size)
+            .map(move |(x, y, z)| ((x, y, z), self.get(x, y, z)))
+    }
+
+    /// Returns a copy of the cuboid with a new size
+    ///
+    /// Cells keep their values, new cells hold the default, and cells past the new size are
+    /// dropped. Slices are reused as is if only the z size changes.
+    pub fn resize(&self, x_size: usize, y_size: usize, z_size: usize) -> Cuboid<T> {
+        let mut resized = Cuboid::new(x_size, y_size, z_size, &self.default);
+        if self.data.is_empty() {
+            return resized;
+        }
+        let same_slices = x_size == self.x_size && y_size == self.y_size;
+        let proto_slice = Slice::new(x_size, y_size, self.default.clone());
+        resized.data = (0..z_size)
+            .map(|z| {
+                if z >= self.z_size {
+                    proto_slice.clone()
+                } else if same_slices {
+                    self.data[z].clone()
+                } else {
+                    self.data[z].resize(x_size, y_size)
+                }
+            })
+            .collect();
+        resized
+    }
+
+    /// Sets every cell between the two corners, inclusive, to the same value
+    ///
+    /// Only the slices the region touches are rewritten, each of them once. Cells outside of the
+    /// cuboid are ignored.
+    pub fn fill_region(
+        &self,
+        min: (usize, usize, usize),
+        max: (usize, usize, usize),
+        value: T,
+    ) -> Cuboid<T> {
+        let (min, max) = match clamp_region(min, max, self.get_size()) {
+            Some(region) => region,
+            None => return self.clone(),
+        };
+        let mut data = if self.data.is_empty() {
+            let proto_slice = Slice::new(self.x_size, self.y_size, self.default.clone());
+            Purse::new_filled(self.z_size, proto_slice)
+        } else {
+            self.data.clone()
+        };
+        for z in min.2..=max.2 {
+            let slice = data[z].fill_region((min.0, min.1), (max.0, max.1), value.clone());
+            data = data.set(z, slice);
+        }
+        Cuboid {
+            data,
+            ..self.clone()
+        }
+    }
+}
+
+/// Coordinates of a cell
+type Cell = (usize, usize, usize);
+
+impl<T: Clone> Cuboid<T> {
+    /// Returns the part of the cuboid between the two corners, inclusive, as a new cuboid
+    ///
+    /// The corners are clamped to the cuboid, so the result may be smaller than asked for, or
+    /// empty if the region is entirely outside of it.
+    pub fn extract(&self, min: Cell, max: Cell) -> Cuboid<T> {
+        let (min, max) = match clamp_region(min, max, self.get_size()) {
+            Some(region) => region,
+            None => return Cuboid::new(0, 0, 0, &self.default),
+        };
+        let (x_size, y_size) = (max.0 - min.0 + 1, max.1 - min.1 + 1);
+        let mut extracted = Cuboid::new(x_size, y_size, max.2 - min.2 + 1, &self.default);
+        if !self.data.is_empty() {
+            extracted.data = (min.2..=max.2)
+                .map(|z| self.data[z].extract((min.0, min.1), (max.0, max.1)))
+                .collect();
+        }
+        extracted
+    }
+
+    /// Copies every cell of the other cuboid into this one, with its origin at the offset
+    ///
+    /// Cells landing outside of this cuboid are dropped. Only the slices the other cuboid
+    /// covers are rewritten, each of them once.
+    pub fn blit(&self, offset: Cell, other: &Cuboid<T>) -> Cuboid<T> {
+        let max = (
+            offset.0 + other.x_size.saturating_sub(1),
+            offset.1 + other.y_size.saturating_sub(1),
+            offset.2 + other.z_size.saturating_sub(1),
+        );
+        if other.x_size == 0 || other.y_size == 0 || other.z_size == 0 {
+            return self.clone();
+        }
+        let (min, max) = match clamp_region(offset, max, self.get_size()) {
+            Some(region) => region,
+            None => return self.clone(),
+        };
+        let mut data = if self.data.is_empty() {
+            let proto_slice = Slice::new(self.x_size, self.y_size, self.default.clone());
+            Purse::new_filled(self.z_size, proto_slice)
+        } else {
+            self.data.clone()
+        };
+        for z in min.2..=max.2 {
+            let cells = (min.0..=max.0)
+                .flat_map(|x| (min.1..=max.1).map(move |y| (x, y)))
+                .map(|(x, y)| {
+                    let value = other.get(x - offset.0, y - offset.1, z - offset.2);
+                    ((x, y), value.clone())
+                });
+            let slice = data[z].set_many(cells);
+            data = data.set(z, slice);
+        }
+        Cuboid {
+            data,
+            ..self.clone()
+        }
+    }
+}
+
+/// Clamps an inclusive region to a cuboid of the given size
+///
+/// Returns None if no cell of the region is inside of the cuboid
+fn clamp_region(min: Cell, max: Cell, size: Cell) -> Option<(Cell, Cell)> {
+    let clamp = |min: usize, max: usize, size: usize| {
+        if min > max || min >= size {
+            None
+        } else {
+            Some((min, max.min(size - 1)))
+        }
+    };
+    let x = clamp(min.0, max.0, size.0)?;
+    let y = clamp(min.1, max.1, size.1)?;
+    let z = clamp(min.2, max.2, size.2)?;
+    Some(((x.0, y.0, z.0), (x.1, y.1, z.1)))
+}
+
+/// Returns the coordinates of every cell of a cuboid, in z, y, x order
+fn cells(
+    x_size: usize,
+    y_size: usize,
+    z_size: usize,
+) -> impl Iterator<Item = (usize, usize, usize)> {
+    (0..z_size)
+        .flat_map(move |z| (0..y_size).flat_map(move |y| (0..x_size).map(move |x| (x, y, z))))
+}
+
+impl<T: Clone + PartialEq> Cuboid<T> {
+    /// Returns every cell holding something other than the default, along with its coordinates
+    ///
+    /// Only visits cells that have been set, rather than the entire cuboid
+    pub fn iter_non_default(&self) -> impl Iterator<Item = ((usize, usize, usize), &T)> {
+        self.data.into_iter().enumerate().flat_map(|(z, slice)| {
+            slice
+                .iter_non_default()
+                .map(move |((x, y), value)| ((x, y, z), value))
+        })
+    }
+
+    /// Returns the cells of this cuboid that differ in the other one, along with their values in
+    /// the other cuboid, in z, y, x order
+    ///
+    /// Only cells inside of this cuboid are compared. Slices both cuboids share, such as those
+    /// untouched since one was derived from the other, are skipped without being looked at.
+    pub fn diff(&self, other: &Cuboid<T>) -> Vec<(Cell, T)> {
+        if self.data.ptr_eq(&other.data) && self.default == other.default {
+            return Vec::new();
+        }
+        let empty =
+            |cuboid: &Cuboid<T>| Slice::new(cuboid.x_size, cuboid.y_size, cuboid.default.clone());
+        let (self_empty, other_empty) = (empty(self), empty(other));
+        let mut changes = Vec::new();
+        for z in 0..self.z_size {
+            let shared = z < other.z_size
+                && !self.data.is_empty()
+                && !other.data.is_empty()
+                && self.data.ptr_eq_at(&other.data, z);
+            if shared {
+                continue;
+            }
+            let ours = if self.data.is_empty() {
+                &self_empty
+            } else {
+                &self.data[z]
+            };
+            let theirs = if other.data.is_empty() || z >= other.z_size {
+                &other_empty
+            } else {
+                &other.data[z]
+            };
+            let mut slice: Vec<(Cell, T)> = ours
+                .diff(theirs)
+                .into_iter()
+                .map(|((x, y), value)| ((x, y, z), value))
+                .collect();
+            slice.sort_by_key(|&((x, y, _), _)| (y, x));
+            changes.extend(slice);
+        }
+        changes
+    }
+
+    /// Run length encodes the cuboid, in z, y, x order
+    ///
+    /// Mostly uniform cuboids come out as a handful of runs, which can then be serialized with
+    /// serde for persistence or sending over the network.
+    pub fn encode(&self) -> EncodedCuboid<T> {
+        let mut runs: Vec<(usize, T)> = Vec::new();
+        for (_, value) in self.iter() {
+            match runs.last_mut() {
+                Some(last) if last.1 == *value => last.0 += 1,
+                _ => runs.push((1, value.clone())),
+            }
+        }
+        EncodedCuboid {
+            size: self.get_size(),
+            default: self.default.clone(),
+            runs,
+        }
+    }
+
+    /// Rebuilds a cuboid from its run length encoding
+    ///
+    /// Returns None if the runs do not cover the cuboid exactly
+    pub fn decode(encoded: &EncodedCuboid<T>) -> Option<Cuboid<T>> {
+        let (x_size, y_size, z_size) = encoded.size;
+        let layer = x_size.checked_mul(y_size)?;
+        let mut total: usize = 0;
+        for &(length, _) in &encoded.runs {
+            total = total.checked_add(length)?;
+        }
+        if total != layer.checked_mul(z_size)? {
+            return None;
+        }
+
+        let mut cuboid = Cuboid::new(x_size, y_size, z_size, &encoded.default);
+        let mut layers: Vec<Vec<((usize, usize), T)>> = vec![Vec::new(); z_size];
+        let mut position = 0;
+        for &(length, ref value) in &encoded.runs {
+            if *value != encoded.default {
+                for cell in position..position + length {
+                    let (x, y, z) = (cell % x_size, (cell / x_size) % y_size, cell / layer);
+                    layers[z].push(((x, y), value.clone()));
+                }
+            }
+            position += length;
+        }
+        if layers.iter().any(|x| !x.is_empty()) {
+            let proto_slice = Slice::new(x_size, y_size, encoded.default.clone());
+            cuboid.data = layers
+                .into_iter()
+                .map(|cells| proto_slice.set_many(cells))
+                .collect();
+        }
+        Some(cuboid)
+    }
+}
+
+/// Mutable builder for a Cuboid
+///
+/// Setting a cell of a Cuboid copies the path to it, which adds up when filling in a whole
+/// cuboid cell by cell. The builder instead collects cells in place, and builds every slice in a
+/// single pass at the end.
+#[derive(Clone)]
+pub struct CuboidBuilder<T> {
+    /// Cells set so far, one map per z
+    layers: Vec<HashMap<(usize, usize), T>>,
+    default: T,
+    x_size: usize,
+    y_size: usize,
+}
+
+impl<T: Clone> CuboidBuilder<T> {
+    /// Creates a builder for a cuboid of the given size, with every cell holding the default
+    pub fn new(x_size: usize, y_size: usize, z_size: usize, default: &T) -> CuboidBuilder<T> {
+        CuboidBuilder {
+            layers: vec![HashMap::new(); z_size],
+            default: default.clone(),
+            x_size,
+            y_size,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> &T {
+        self.layers
+            .get(z)
+            .and_then(|layer| layer.get(&(x, y)))
+            .unwrap_or(&self.default)
+    }
+
+    /// Sets the value of a cell
+    ///
+    /// Returns None, leaving the builder untouched, if the cell is out of bounds
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: T) -> Option<&mut Self> {
+        if x >= self.x_size || y >= self.y_size || z >= self.layers.len() {
+            return None;
+        }
+        self.layers[z].insert((x, y), value);
+        Some(self)
+    }
+
+    /// Converts the builder into a cuboid
+    ///
+    /// Does not consume
+    pub fn build(&self) -> Cuboid<T> {
+        let z_size = self.layers.len();
+        let mut cuboid = Cuboid::new(self.x_size, self.y_size, z_size, &self.default);
+        if self.layers.iter().all(HashMap::is_empty) {
+            return cuboid;
+        }
+        let proto_slice = Slice::new(self.x_size, self.y_size, self.default.clone());
+        cuboid.data = self
+            .layers
+            .iter()
+            .map(|layer| {
+                let cells = layer.iter().map(|(&cell, value)| (cell, value.clone()));
+                proto_slice.set_many(cells)
+            })
+            .collect();
+        cuboid
+    }
+}
+
+/// A run length encoded Cuboid, produced by Cuboid::encode
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncodedCuboid<T> {
+    size: (usize, usize, usize),
+    default: T,
+    /// Lengths and values of the runs, in z, y, x order
+    runs: Vec<(usize, T)>,
+}
+
+impl<T> EncodedCuboid<T> {
+    /// Returns the runs, as pairs of lengths and values
+    pub fn get_runs(&self) -> &[(usize, T)] {
+        &self.runs
+    }
+}
+
+impl<T: Clone + PartialEq> VoxelStore<T> for Cuboid<T> {
+    fn new(x_size: usize, y_size: usize, z_size: usize, default: &T) -> Cuboid<T> {
+        Cuboid::new(x_size, y_size, z_size, default)
+    }
+
+    fn get_size(&self) -> (usize, usize, usize) {
+        Cuboid::get_size(self)
+    }
+
+    fn get(&self, x: usize, y: usize, z: usize) -> &T {
+        Cuboid::get(self, x, y, z)
+    }
+
+    fn set(&self, x: usize, y: usize, z: usize, value: T) -> Option<Cuboid<T>> {
+        Cuboid::set(self, x, y, z, value)
+    }
+
+    fn fill(&self, min: Cell, max: Cell, value: T) -> Cuboid<T> {
+        self.fill_region(min, max, value)
+    }
+
+    fn iter(&self) -> VoxelIter<'_, T> {
+        Box::new(Cuboid::iter(self))
+    }
+
+    fn iter_non_default(&self) -> VoxelIter<'_, T> {
+        Box::new(Cuboid::iter_non_default(self))
+    }
+
+    fn diff(&self, other: &Cuboid<T>) -> Vec<(Cell, T)> {
+        Cuboid::diff(self, other)
+    }
+
+    fn memory_estimate(&self) -> usize {
+        let slices: usize = self.data.into_iter().map(|x| x.memory_estimate()).sum();
+        size_of::<Cuboid<T>>() + slices
+    }
+}
+
+/// Immutable 3D array with a default value, storing each cell as an index into a palette
+///
+/// Suited to data with few distinct values, like the blocks of a chunk section. Every distinct
+/// value is stored once, and cells only take as many bits as needed to index the palette, which
+/// grows automatically as new values are set. Index 0 is always the default. Nothing is
+/// allocated until the first value other than the default is set.
+#[derive(Clone)]
+pub struct PackedCuboid<T> {
+    palette: Arc<Vec<T>>,
+    /// Position of each value in the palette
+    indices: Arc<HashMap<T, usize>>,
+    /// Bits taken by each cell, indices never span two words
+    bits: usize,
+    /// One layer of packed indices per z, empty until the first value is set
+    data: Purse<Vec<u64>>,
+    x_size: usize,
+    y_size: usize,
+    z_size: usize,
+}
+
+/// Returns the bits needed to index a palette of the given length
+fn bits_for(length: usize) -> usize {
+    let mut bits = 1;
+    while (1 << bits) < length {
+        bits += 1;
+    }
+    bits
+}
+
+impl<T: Clone + Eq + Hash> PackedCuboid<T> {
+    pub fn new(x_size: usize, y_size: usize, z_size: usize, default: &T) -> PackedCuboid<T> {
+        let mut indices = HashMap::new();
+        indices.insert(default.clone(), 0);
+        PackedCuboid {
+            palette: Arc::new(vec![default.clone()]),
+            indices: Arc::new(indices),
+            bits: 1,
+            data: Purse::new(),
+            x_size,
+            y_size,
+            z_size,
+        }
+    }
+
+    /// Returns the x, y, and z size of the cuboid
+    pub fn get_size(&self) -> (usize, usize, usize) {
+        (self.x_size, self.y_size, self.z_size)
+    }
+
+    /// Returns the number of distinct values that have been stored, including the default
+    pub fn get_palette_len(&self) -> usize {
+        self.palette.len()
+    }
+
+    /// Returns the number of bits each cell currently takes
+    pub fn get_bits(&self) -> usize {
+        self.bits
+    }
+
+    /// Returns the palette index stored at a position within a layer
+    fn get_index(layer: &[u64], bits: usize, position: usize) -> usize {
+        let per_word = 64 / bits;
+        let word = layer[position / per_word];
+        let shift = (position % per_word) * bits;
+        ((word >> shift) & ((1 << bits) - 1)) as usize
+    }
+
+    /// Stores a palette index at a position within a layer
+    fn set_index(layer: &mut [u64], bits: usize, position: usize, index: usize) {
+        let per_word = 64 / bits;
+        let word = &mut layer[position / per_word];
+        let shift = (position % per_word) * bits;
+        *word &= !(((1 << bits) - 1) << shift);
+        *word |= (index as u64) << shift;
+    }
+
+    /// Returns an empty layer using the given number of bits per cell
+    fn new_layer(&self, bits: usize) -> Vec<u64> {
+        let per_word = 64 / bits;
+        vec![0; (self.x_size * self.y_size).div_ceil(per_word)]
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> &T {
+        if x >= self.x_size || y >= self.y_size || z >= self.z_size || self.data.is_empty() {
+            &self.palette[0]
+        } else {
+            let index = Self::get_index(&self.data[z], self.bits, y * self.x_size + x);
+            &self.palette[index]
+        }
+    }
+
+    pub fn set(&self, x: usize, y: usize, z: usize, value: T) -> Option<PackedCuboid<T>> {
+        if x >= self.x_size || y >= self.y_size || z >= self.z_size {
+            return None;
+        }
+        let (mut cuboid, index) = self.with_value(value);
+        if cuboid.data.is_empty() {
+            return Some(cuboid);
+        }
+        let mut layer = cuboid.data[z].clone();
+        Self::set_index(&mut layer, cuboid.bits, y * self.x_size + x, index);
+        cuboid.data = cuboid.data.set(z, layer);
+        Some(cuboid)
+    }
+
+    /// Sets every cell between the two corners, inclusive, to the same value
+    ///
+    /// Only the layers the region touches are rewritten, each of them once. Cells outside of the
+    /// cuboid are ignored.
+    pub fn fill_region(
+        &self,
+        min: (usize, usize, usize),
+        max: (usize, usize, usize),
+        value: T,
+    ) -> PackedCuboid<T> {
+        let (min, max) = match clamp_region(min, max, self.get_size()) {
+            Some(region) => region,
+            None => return self.clone(),
+        };
+        let (mut cuboid, index) = self.with_value(value);
+        if cuboid.data.is_empty() {
+            return cuboid;
+        }
+        for z in min.2..=max.2 {
+            let mut layer = cuboid.data[z].clone();
+            for y in min.1..=max.1 {
+                for x in min.0..=max.0 {
+                    Self::set_index(&mut layer, cuboid.bits, y * self.x_size + x, index);
+                }
+            }
+            cuboid.data = cuboid.data.set(z, layer);
+        }
+        cuboid
+    }
+
+    /// Returns a copy of this cuboid able to store the given value, along with its index
+    ///
+    /// Adds the value to the palette, and allocates or widens the layers, as needed. Layers are
+    /// left unallocated if the value is the default.
+    fn with_value(&self, value: T) -> (PackedCuboid<T>, usize) {
+        let mut cuboid = self.clone();
+        let index = match self.indices.get(&value) {
+            Some(&index) => index,
+            None => {
+                let index = self.palette.len();
+                Arc::make_mut(&mut cuboid.palette).push(value.clone());
+                Arc::make_mut(&mut cuboid.indices).insert(value, index);
+                index
+            }
+        };
+        let bits = bits_for(cuboid.palette.len());
+        if cuboid.data.is_empty() {
+            if index != 0 {
+                cuboid.bits = bits;
+                cuboid.data = Purse::new_filled(self.z_size, self.new_layer(bits));
+            }
+        } else if bits > cuboid.bits {
+            cuboid = cuboid.repack(bits);
+        }
+        (cuboid, index)
+    }
+
+    /// Returns a copy of this cuboid using the given number of bits per cell
+    fn repack(&self, bits: usize) -> PackedCuboid<T> {
+        let mut data = Purse::new();
+        for layer in &self.data {
+            let mut packed = self.new_layer(bits);
+            for position in 0..self.x_size * self.y_size {
+                let index = Self::get_index(layer, self.bits, position);
+                Self::set_index(&mut packed, bits, position, index);
+            }
+            data = data.push(packed);
+        }
+        PackedCuboid {
+            bits,
+            data,
+            ..self.clone()
+        }
+    }
+
+    /// Returns every cell, along with its coordinates, in z, y, x order
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize, usize), &T)> {
+        cells(self.x_size, self.y_size, self.z_size)
+            .map(move |(x, y, z)| ((x, y, z), self.get(x, y, z)))
+    }
+
+    /// Returns the cells of this cuboid that differ in the other one, along with their values in
+    /// the other cuboid, in z, y, x order
+    ///
+    /// Only cells inside of this cuboid are compared. Layers both cuboids share are skipped,
+    /// and layers using the same palette are compared by their packed words.
+    pub fn diff(&self, other: &PackedCuboid<T>) -> Vec<(Cell, T)> {
+        let same_palette = Arc::ptr_eq(&self.palette, &other.palette);
+        let comparable =
+            same_palette && self.bits == other.bits && self.get_size() == other.get_size();
+        if comparable && self.data.ptr_eq(&other.data) {
+            return Vec::new();
+        }
+        let mut changes = Vec::new();
+        for z in 0..self.z_size {
+            let allocated = !self.data.is_empty() && !other.data.is_empty();
+            if comparable
+                && allocated
+                && (self.data.ptr_eq_at(&other.data, z) || self.data[z] == other.data[z])
+            {
+                continue;
+            }
+            for y in 0..self.y_size {
+                for x in 0..self.x_size {
+                    let value = other.get(x, y, z);
+                    if self.get(x, y, z) != value {
+                        changes.push(((x, y, z), value.clone()));
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    /// Returns every cell holding something other than the default, along with its coordinates
+    ///
+    /// Only visits the layers that have been allocated
+    pub fn iter_non_default(&self) -> impl Iterator<Item = ((usize, usize, usize), &T)> {
+        let (x_size, y_size, bits) = (self.x_size, self.y_size, self.bits);
+        self.data
+            .into_iter()
+            .enumerate()
+            .flat_map(move |(z, layer)| {
+                (0..x_size * y_size).filter_map(move |position| {
+                    let index = Self::get_index(layer, bits, position);
+                    if index == 0 {
+                        None
+                    } else {
+                        let location = (position % x_size, position / x_size, z);
+                        Some((location, &self.palette[index]))
+                    }
+                })
+            })
+    }
+}
+
+impl<T: Clone + Eq + Hash> VoxelStore<T> for PackedCuboid<T> {
+    fn new(x_size: usize, y_size: usize, z_size: usize, default: &T) -> PackedCuboid<T> {
+        PackedCuboid::new(x_size, y_size, z_size, default)
+    }
+
+    fn get_size(&self) -> (usize, usize, usize) {
+        PackedCuboid::get_size(self)
+    }
+
+    fn get(&self, x: usize, y: usize, z: usize) -> &T {
+        PackedCuboid::get(self, x, y, z)
+    }
+
+    fn set(&self, x: usize, y: usize, z: usize, value: T) -> Option<PackedCuboid<T>> {
+        PackedCuboid::set(self, x, y, z, value)
+    }
+
+    fn fill(&self, min: Cell, max: Cell, value: T) -> PackedCuboid<T> {
+        self.fill_region(min, max, value)
+    }
+
+    fn iter(&self) -> VoxelIter<'_, T> {
+        Box::new(PackedCuboid::iter(self))
+    }
+
+    fn iter_non_default(&self) -> VoxelIter<'_, T> {
+        Box::new(PackedCuboid::iter_non_default(self))
+    }
+
+    fn diff(&self, other: &PackedCuboid<T>) -> Vec<(Cell, T)> {
+        PackedCuboid::diff(self, other)
+    }
+
+    fn memory_estimate(&self) -> usize {
+        let palette = self.palette.len() * (size_of::<T>() * 2 + size_of::<usize>());
+        let layers: usize = self.data.into_iter().map(|x| x.len() * 8).sum();
+        size_of::<PackedCuboid<T>>() + palette + layers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iterate_cells() {
+        let cuboid = Cuboid::new(2, 3, 4, &0)
+            .set(1, 2, 3, 5)
+            .unwrap()
+            .set(0, 1, 0, 6)
+            .unwrap();
+        let cells: Vec<_> = cuboid.iter().collect();
+        assert_eq!(cells.len(), 24);
+        assert_eq!(cells[0], ((0, 0, 0), &0));
+        assert_eq!(cells[2], ((0, 1, 0), &6));
+        assert_eq!(cells[23], ((1, 2, 3), &5));
+        let mut set: Vec<_> = cuboid.iter_non_default().collect();
+        set.sort();
+        assert_eq!(set, vec![((0, 1, 0), &6), ((1, 2, 3), &5)]);
+
+        let packed = PackedCuboid::new(2, 3, 4, &0)
+            .set(1, 2, 3, 5)
+            .unwrap()
+            .set(0, 1, 0, 6)
+            .unwrap();
+        assert!(packed.iter().eq(cuboid.iter()));
+        assert!(packed.iter_non_default().eq(set.into_iter()));
+    }
+
+    #[test]
+    fn run_length_round_trip() {
+        // A small linear congruential generator stands in for a property testing crate
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |limit: u64| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            ((state >> 33) % limit) as usize
+        };
+        for _ in 0..50 {
+            let size = (next(6) + 1, next(6) + 1, next(6) + 1);
+            let mut cuboid = Cuboid::new(size.0, size.1, size.2, &0u8);
+            for _ in 0..next(40) {
+                let min = (next(6), next(6), next(6));
+                let max = (min.0 + next(3), min.1 + next(3), min.2 + next(3));
+                cuboid = cuboid.fill_region(min, max, next(3) as u8);
+            }
+            let encoded = cuboid.encode();
+            let decoded = Cuboid::decode(&encoded).unwrap();
+            assert!(decoded.iter().eq(cuboid.iter()));
+            assert_eq!(decoded.get_size(), size);
+            let json = serde_json::to_string(&encoded).unwrap();
+            assert!(serde_json::from_str::<EncodedCuboid<u8>>(&json).unwrap() == encoded);
+        }
+
+        let uniform = Cuboid::new(16, 16, 16, &3u8).fill_region((0, 0, 0), (15, 15, 15), 1);
+        assert_eq!(uniform.encode().get_runs(), &[(4096, 1)]);
+        let mut broken = uniform.encode();
+        broken.runs[0].0 -= 1;
+        assert!(Cuboid::decode(&broken).is_none());
+    }
+
+    #[test]
+    fn diff_skips_shared_slices() {
+        let base = Cuboid::new(4, 4, 4, &0).set(1, 1, 1, 5).unwrap();
+        let changed = base.set(2, 3, 1, 6).unwrap().set(0, 0, 3, 7).unwrap();
+        assert!(base.diff(&base.clone()).is_empty());
+        assert_eq!(base.diff(&changed), vec![((2, 3, 1), 6), ((0, 0, 3), 7)]);
+        assert_eq!(changed.diff(&base), vec![((2, 3, 1), 0), ((0, 0, 3), 0)]);
+        assert_eq!(Cuboid::new(4, 4, 4, &0).diff(&base), vec![((1, 1, 1), 5)]);
+
+        let packed = PackedCuboid::new(4, 4, 4, &0).set(1, 1, 1, 5).unwrap();
+        let packed_changed = packed.set(2, 3, 1, 6).unwrap().set(0, 0, 3, 7).unwrap();
+        assert!(packed.diff(&packed.clone()).is_empty());
+        assert_eq!(packed.diff(&packed_changed), base.diff(&changed));
+        assert_eq!(packed_changed.diff(&packed), changed.diff(&base));
+    }
+
+    #[test]
+    fn resize_keeps_contents() {
+        let cuboid = Cuboid::new(2, 2, 2, &0).fill_region((0, 0, 0), (1, 1, 1), 1);
+        let grown = cuboid.resize(3, 4, 5);
+        assert_eq!(grown.get_size(), (3, 4, 5));
+        assert_eq!(grown.iter_non_default().count(), 8);
+        assert_eq!(*grown.get(1, 1, 1), 1);
+        assert_eq!(*grown.get(2, 3, 4), 0);
+        assert!(grown.set(2, 3, 4, 1).is_some());
+
+        let shrunk = grown.resize(1, 2, 1);
+        assert_eq!(shrunk.iter_non_default().count(), 2);
+        assert!(shrunk.set(1, 0, 0, 1).is_none());
+
+        let deeper = cuboid.resize(2, 2, 3);
+        assert_eq!(deeper.iter_non_default().count(), 8);
+        assert_eq!(*deeper.get(1, 1, 2), 0);
+    }
+
+    #[test]
+    fn builder_matches_persistent_sets() {
+        let mut builder = CuboidBuilder::new(4, 5, 6, &0);
+        let mut cuboid = Cuboid::new(4, 5, 6, &0);
+        // Simple LCG, so the cells are spread out and sometimes overwritten
+        let mut seed: usize = 7;
+        for i in 0..200 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345) % (1 << 31);
+            let (x, y, z) = (seed % 4, (seed / 4) % 5, (seed / 20) % 6);
+            builder.set(x, y, z, i).unwrap();
+            cuboid = cuboid.set(x, y, z, i).unwrap();
+        }
+        assert!(builder.set(4, 0, 0, 1).is_none());
+        assert_eq!(*builder.get(4, 0, 0), 0);
+
+        let built = builder.build();
+        assert!(built.diff(&cuboid).is_empty());
+        assert!(built.iter().eq(cuboid.iter()));
+        assert!(CuboidBuilder::new(2, 2, 2, &0).build().data.is_empty());
+    }
+
+    #[test]
+    fn fill_regions() {
+        let cuboid = Cuboid::new(4, 4, 4, &0).set(3, 3, 3, 9).unwrap();
+        let filled = cuboid.fill_region((1, 0, 2), (2, 10, 10), 1);
+        let packed = PackedCuboid::new(4, 4, 4, &0)
+            .set(3, 3, 3, 9)
+            .unwrap()
+            .fill_region((1, 0, 2), (2, 10, 10), 1);
+        assert!(filled.iter().eq(packed.iter()));
+        assert_eq!(filled.iter_non_default().count(), 2 * 4 * 2 + 1);
+        assert_eq!(*filled.get(2, 3, 3), 1);
+        assert_eq!(*filled.get(3, 3, 3), 9);
+        assert_eq!(*filled.get(1, 0, 1), 0);
+        // Regions entirely outside of the cuboid, or inside out, leave it alone
+        assert!(filled
+            .fill_region((4, 0, 0), (8, 8, 8), 2)
+            .iter()
+            .eq(filled.iter()));
+        assert!(filled
+            .fill_region((2, 0, 0), (1, 3, 3), 2)
+            .iter()
+            .eq(filled.iter()));
+    }
+
+    #[test]
+    fn extract_and_blit() {
+        let cuboid = Cuboid::new(4, 4, 4, &0)
+            .fill_region((0, 0, 0), (1, 1, 1), 1)
+            .set(3, 3, 3, 2)
+            .unwrap();
+        let corner = cuboid.extract((1, 1, 1), (9, 9, 9));
+        assert_eq!(corner.get_size(), (3, 3, 3));
+        assert_eq!(*corner.get(0, 0, 0), 1);
+        assert_eq!(*corner.get(2, 2, 2), 2);
+        assert_eq!(corner.iter_non_default().count(), 2);
+        assert_eq!(cuboid.extract((5, 0, 0), (6, 1, 1)).get_size(), (0, 0, 0));
+
+        // Defaults are pasted too, and cells past the edge are dropped
+        let pasted = Cuboid::new(4, 4, 4, &7).blit((2, 2, 2), &corner);
+        assert_eq!(*pasted.get(2, 2, 2), 1);
+        assert_eq!(*pasted.get(3, 3, 3), 0);
+        assert_eq!(*pasted.get(1, 1, 1), 7);
+        assert!(pasted
+            .extract((2, 2, 2), (3, 3, 3))
+            .iter()
+            .eq(corner.extract((0, 0, 0), (1, 1, 1)).iter()));
+    }
+
+    #[test]
+    fn packed_widens_as_palette_grows() {
+        let cuboid = PackedCuboid::new(16, 16, 16, &0u32);
+        assert!(cuboid.set(0, 0, 0, 0).unwrap().data.is_empty());
+        assert!(cuboid.set(16, 0, 0, 1).is_none());
+
+        let mut set = cuboid;
+        for i in 1..20 {
+            set = set.set(i % 16, i / 16, 3, i as u32 * 7).unwrap();
+        }
+        assert_eq!(set.get_palette_len(), 20);
+        assert_eq!(set.get_bits(), 5);
+        for i in 1..20 {
+            assert_eq!(*set.get(i % 16, i / 16, 3), i as u32 * 7);
+        }
+        assert_eq!(*set.get(15, 15, 15), 0);
+
+        // Existing values are reused rather than added again
+        let reused = set.set(15, 15, 15, 7).unwrap();
+        assert_eq!(reused.get_palette_len(), 20);
+        assert_eq!(*reused.get(15, 15, 15), 7);
+        assert_eq!(reused.iter_non_default().count(), 20);
+    }
 }
@@ -3,4 +3,4 @@
 pub mod cuboid;
 pub mod purse;
 pub mod slice;
-
+pub mod voxel;
@@ -1,32 +1,133 @@
 //! Provides a persistent array with immutable elements
+//!
+//! Purses are bitmapped vector tries, like Clojure's vectors. Elements live in leaves of up to 32
+//! elements, below a tree of nodes with up to 32 children each. Setting or pushing an element
+//! copies only the nodes on the path to it, O(log n), and shares everything else with the
+//! original Purse.
 
-use std::sync::Arc;
+use std::iter::FromIterator;
 use std::ops::Index;
+use std::sync::Arc;
+
+/// Bits of an index consumed by each level of the trie
+const BITS: usize = 5;
+/// Number of children of a node, and elements of a leaf
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+enum Node<T> {
+    Branch(Vec<Arc<Node<T>>>),
+    /// Uses Arc for thread saftey
+    Leaf(Vec<Arc<T>>),
+}
+
+impl<T> Node<T> {
+    /// Creates the chain of nodes leading down to a single element
+    fn new_path(level: usize, element: Arc<T>) -> Node<T> {
+        if level == 0 {
+            Node::Leaf(vec![element])
+        } else {
+            Node::Branch(vec![Arc::new(Node::new_path(level - BITS, element))])
+        }
+    }
+
+    fn get(&self, level: usize, index: usize) -> &Arc<T> {
+        match *self {
+            Node::Branch(ref children) => {
+                children[(index >> level) & MASK].get(level - BITS, index)
+            }
+            Node::Leaf(ref elements) => &elements[index & MASK],
+        }
+    }
+
+    /// Returns a copy of this node with the element at index replaced
+    fn set(&self, level: usize, index: usize, element: Arc<T>) -> Node<T> {
+        match *self {
+            Node::Branch(ref children) => {
+                let mut children = children.clone();
+                let child = &mut children[(index >> level) & MASK];
+                *child = Arc::new(child.set(level - BITS, index, element));
+                Node::Branch(children)
+            }
+            Node::Leaf(ref elements) => {
+                let mut elements = elements.clone();
+                elements[index & MASK] = element;
+                Node::Leaf(elements)
+            }
+        }
+    }
+
+    /// Returns a copy of this node keeping only the elements up to and including last
+    fn truncate(&self, level: usize, last: usize) -> Node<T> {
+        match *self {
+            Node::Branch(ref children) => {
+                let slot = (last >> level) & MASK;
+                let last_child = Arc::new(children[slot].truncate(level - BITS, last));
+                let mut children = children[..slot].to_vec();
+                children.push(last_child);
+                Node::Branch(children)
+            }
+            Node::Leaf(ref elements) => Node::Leaf(elements[..=(last & MASK)].to_vec()),
+        }
+    }
+
+    /// Returns a copy of this node with the element added at index, which must have room
+    fn push(&self, level: usize, index: usize, element: Arc<T>) -> Node<T> {
+        match *self {
+            Node::Branch(ref children) => {
+                let mut children = children.clone();
+                let slot = (index >> level) & MASK;
+                if slot < children.len() {
+                    children[slot] = Arc::new(children[slot].push(level - BITS, index, element));
+                } else {
+                    children.push(Arc::new(Node::new_path(level - BITS, element)));
+                }
+                Node::Branch(children)
+            }
+            Node::Leaf(ref elements) => {
+                let mut elements = elements.clone();
+                elements.push(element);
+                Node::Leaf(elements)
+            }
+        }
+    }
+}
 
 /// Persistent array
 pub struct Purse<T> {
-    /// Uses Arc for thread saftey
-    contents: Vec<Arc<T>>,
+    root: Arc<Node<T>>,
+    /// Bits of an index consumed below the root
+    shift: usize,
+    length: usize,
 }
 
 impl<T> Purse<T> {
     /// Creates a new, empty Purse
     pub fn new() -> Purse<T> {
-        Purse { contents: Vec::new() }
+        Purse {
+            root: Arc::new(Node::Leaf(Vec::new())),
+            shift: 0,
+            length: 0,
+        }
     }
 
     pub fn new_filled(length: usize, element: T) -> Purse<T> {
         let element = Arc::new(element);
         let mut new_purse = Purse::new();
         for _ in 0..length {
-            new_purse.contents.push(element.clone());
+            new_purse = new_purse.push_arc(element.clone());
         }
         new_purse
     }
 
     /// Returns the length of the Purse
     pub fn len(&self) -> usize {
-        self.contents.len()
+        self.length
+    }
+
+    /// Returns true if the Purse contains no elements
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
     }
 
     /// "Sets" the value of the Purse at a given location
@@ -35,22 +136,162 @@ impl<T> Purse<T> {
     ///
     /// Panics if the given index is out of bounds
     pub fn set(&self, index: usize, element: T) -> Purse<T> {
-        let mut new_purse = self.clone();
-        new_purse.contents[index] = Arc::new(element);
-        new_purse
+        assert!(
+            index < self.length,
+            "index out of bounds: the len is {} but the index is {}",
+            self.length,
+            index
+        );
+        Purse {
+            root: Arc::new(self.root.set(self.shift, index, Arc::new(element))),
+            shift: self.shift,
+            length: self.length,
+        }
+    }
+
+    /// Returns true if both Purses share all of their structure, so are known to be equal
+    pub fn ptr_eq(&self, other: &Purse<T>) -> bool {
+        Arc::ptr_eq(&self.root, &other.root) && self.length == other.length
+    }
+
+    /// Returns true if both Purses share the element at the given index
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given index is out of bounds in either Purse
+    pub fn ptr_eq_at(&self, other: &Purse<T>, index: usize) -> bool {
+        assert!(index < self.length && index < other.length);
+        Arc::ptr_eq(
+            self.root.get(self.shift, index),
+            other.root.get(other.shift, index),
+        )
     }
 
     /// Adds a value to the end of the Purse
     pub fn push(&self, element: T) -> Purse<T> {
-        let mut new_purse = self.clone();
-        new_purse.contents.push(Arc::new(element));
-        new_purse
+        self.push_arc(Arc::new(element))
+    }
+
+    /// Adds every value of an iterator to the end of the Purse
+    ///
+    /// Costs O(log n) per value, like push
+    pub fn extend<I: IntoIterator<Item = T>>(&self, elements: I) -> Purse<T> {
+        elements
+            .into_iter()
+            .fold(self.clone(), |purse, element| purse.push(element))
+    }
+
+    /// Shortens the Purse to the given length, doing nothing if it is already shorter
+    ///
+    /// Costs O(log n), and everything before the new end stays shared with the original
+    pub fn truncate(&self, length: usize) -> Purse<T> {
+        if length >= self.length {
+            return self.clone();
+        }
+        if length == 0 {
+            return Purse::new();
+        }
+        let mut root = Arc::new(self.root.truncate(self.shift, length - 1));
+        let mut shift = self.shift;
+        // Drop levels the remaining elements no longer need
+        while shift > 0 && length <= WIDTH << (shift - BITS) {
+            root = match *root {
+                Node::Branch(ref children) => children[0].clone(),
+                Node::Leaf(_) => unreachable!("only the bottom level holds leaves"),
+            };
+            shift -= BITS;
+        }
+        Purse {
+            root,
+            shift,
+            length,
+        }
+    }
+
+    /// Inserts a value at the given index, shifting everything after it up by one
+    ///
+    /// Costs O(log n) for the elements before the index, which stay shared with the original,
+    /// plus O(log n) for each element after it, which are pushed again. Inserting near the end is
+    /// cheap, inserting near the front costs as much as rebuilding the Purse.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is greater than the length
+    pub fn insert_at(&self, index: usize, element: T) -> Purse<T> {
+        assert!(
+            index <= self.length,
+            "insertion index (is {}) should be <= len (is {})",
+            index,
+            self.length
+        );
+        let purse = self.truncate(index).push(element);
+        self.push_from(purse, index)
+    }
+
+    /// Removes the value at the given index, shifting everything after it down by one
+    ///
+    /// Costs the same as insert_at, so removing near the end is cheap, and removing near the
+    /// front costs as much as rebuilding the Purse.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given index is out of bounds
+    pub fn remove_at(&self, index: usize) -> Purse<T> {
+        assert!(
+            index < self.length,
+            "removal index (is {}) should be < len (is {})",
+            index,
+            self.length
+        );
+        self.push_from(self.truncate(index), index + 1)
+    }
+
+    /// Pushes the elements of this Purse from the given index on to another, sharing them
+    fn push_from(&self, purse: Purse<T>, start: usize) -> Purse<T> {
+        (start..self.length).fold(purse, |purse, i| {
+            purse.push_arc(self.root.get(self.shift, i).clone())
+        })
+    }
+
+    fn push_arc(&self, element: Arc<T>) -> Purse<T> {
+        if self.length == WIDTH << self.shift {
+            // The trie is full, so it grows a level
+            let path = Node::new_path(self.shift, element);
+            Purse {
+                root: Arc::new(Node::Branch(vec![self.root.clone(), Arc::new(path)])),
+                shift: self.shift + BITS,
+                length: self.length + 1,
+            }
+        } else {
+            Purse {
+                root: Arc::new(self.root.push(self.shift, self.length, element)),
+                shift: self.shift,
+                length: self.length + 1,
+            }
+        }
+    }
+}
+
+impl<T> Default for Purse<T> {
+    fn default() -> Purse<T> {
+        Purse::new()
     }
 }
 
 impl<T> Clone for Purse<T> {
     fn clone(&self) -> Purse<T> {
-        Purse { contents: self.contents.clone() }
+        Purse {
+            root: self.root.clone(),
+            shift: self.shift,
+            length: self.length,
+        }
+    }
+}
+
+impl<T> FromIterator<T> for Purse<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Purse<T> {
+        iter.into_iter()
+            .fold(Purse::new(), |purse, element| purse.push(element))
     }
 }
 
@@ -58,12 +299,16 @@ impl<T> Index<usize> for Purse<T> {
     type Output = T;
 
     fn index(&self, i: usize) -> &T {
-        &self.contents[i]
+        assert!(
+            i < self.length,
+            "index out of bounds: the len is {} but the index is {}",
+            self.length,
+            i
+        );
+        self.root.get(self.shift, i)
     }
 }
 
-
-
 pub struct PurseIter<'a, T: 'a> {
     purse: &'a Purse<T>,
     index: usize,
@@ -76,8 +321,8 @@ impl<'a, T> Iterator for PurseIter<'a, T> {
         if self.index >= self.purse.len() {
             None
         } else {
-            let next = &self.purse.contents[self.index];
-            self.index = self.index + 1;
+            let next = &self.purse[self.index];
+            self.index += 1;
             Some(next)
         }
     }
@@ -89,8 +334,85 @@ impl<'a, T> IntoIterator for &'a Purse<T> {
 
     fn into_iter(self) -> PurseIter<'a, T> {
         PurseIter {
-            purse: &self,
+            purse: self,
             index: 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_share_structure() {
+        let purse: Purse<usize> = (0..5000).collect();
+        assert_eq!(purse.len(), 5000);
+        assert!(purse.into_iter().cloned().eq(0..5000));
+
+        let set = purse.set(1234, 0).push(5000);
+        assert_eq!(set[1234], 0);
+        assert_eq!(set[5000], 5000);
+        assert_eq!(purse[1234], 1234);
+        assert_eq!(purse.len(), 5000);
+
+        // Only the path to the changed element was copied
+        if let (Node::Branch(ref a), Node::Branch(ref b)) = (&*purse.root, &*set.root) {
+            assert!(Arc::ptr_eq(&a[0], &b[0]));
+            assert!(!Arc::ptr_eq(&a[1], &b[1]));
+        } else {
+            panic!("expected a branch at the root");
+        }
+    }
+
+    #[test]
+    fn resizing_matches_vec() {
+        let mut purse: Purse<usize> = Purse::new();
+        let mut model: Vec<usize> = Vec::new();
+        // Simple LCG, so the Purse grows and shrinks across several levels of the trie
+        let mut seed: usize = 11;
+        for i in 0..400 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345) % (1 << 31);
+            match seed % 5 {
+                0 => {
+                    let index = seed % (model.len() + 1);
+                    purse = purse.insert_at(index, i);
+                    model.insert(index, i);
+                }
+                1 if !model.is_empty() => {
+                    let index = seed % model.len();
+                    purse = purse.remove_at(index);
+                    model.remove(index);
+                }
+                2 => {
+                    let length = model.len() - model.len() / 4;
+                    purse = purse.truncate(length);
+                    model.truncate(length);
+                }
+                _ => {
+                    let count = seed % 300;
+                    purse = purse.extend(i * 1000..i * 1000 + count);
+                    model.extend(i * 1000..i * 1000 + count);
+                }
+            }
+            assert_eq!(purse.len(), model.len());
+        }
+        assert!(purse.into_iter().eq(model.iter()));
+    }
+
+    #[test]
+    fn truncate_shares_structure() {
+        let purse: Purse<usize> = (0..5000).collect();
+        let truncated = purse.truncate(1000);
+        assert_eq!(truncated.len(), 1000);
+        assert_eq!(truncated.shift, BITS);
+        assert!((0..1000).all(|i| truncated.ptr_eq_at(&purse, i)));
+        assert!(truncated.push(1000).into_iter().cloned().eq(0..1001));
+
+        let removed = purse.remove_at(4990);
+        assert!(removed.ptr_eq_at(&purse, 4989));
+        assert_eq!(removed[4990], 4991);
+        assert!(purse.truncate(6000).ptr_eq(&purse));
+        assert!(purse.truncate(0).is_empty());
+    }
+}
@@ -1,9 +1,11 @@
 //! Provides efficent, immutable storage of a 2D Array/Matrix
 
-use storage::purse::*;
+use im::OrdMap;
 use std::mem::size_of;
+use storage::purse::*;
 
 /// Array matrix
+#[derive(Clone)]
 struct ArrayMatrix<T> {
     data: Purse<Option<T>>,
     x_size: usize,
@@ -14,29 +16,68 @@ impl<T> ArrayMatrix<T> {
     fn new(x_size: usize, y_size: usize) -> ArrayMatrix<T> {
         ArrayMatrix {
             data: Purse::new_filled(x_size * y_size, None),
-            x_size: x_size,
-            y_size: y_size,
+            x_size,
+            y_size,
         }
     }
 
+    /// Creates an array matrix holding the same cells as a sparse one
+    fn from_sparse(matrix: &SparseMatrix<T>) -> ArrayMatrix<T>
+    where
+        T: Clone,
+    {
+        let cells = matrix.iter().map(|(cell, data)| (cell, data.clone()));
+        ArrayMatrix::new(matrix.x_size, matrix.y_size).set_many(cells.collect())
+    }
+
+    fn get_index(&self, x: usize, y: usize) -> usize {
+        x * self.y_size + y
+    }
+
     fn get(&self, x: usize, y: usize) -> Option<&T> {
-        self.data[x * self.x_size + y].as_ref()
+        self.data[self.get_index(x, y)].as_ref()
     }
 
     fn set(&self, x: usize, y: usize, data: T) -> ArrayMatrix<T> {
         ArrayMatrix {
-            data: self.data.set(x * self.x_size + y, Some(data)),
+            data: self.data.set(self.get_index(x, y), Some(data)),
             x_size: self.x_size,
             y_size: self.y_size,
         }
     }
+
+    fn set_many(&self, cells: Vec<((usize, usize), T)>) -> ArrayMatrix<T>
+    where
+        T: Clone,
+    {
+        let mut new_data: Vec<Option<T>> = self.data.into_iter().cloned().collect();
+        for ((x, y), data) in cells {
+            new_data[self.get_index(x, y)] = Some(data);
+        }
+        ArrayMatrix {
+            data: new_data.into_iter().collect(),
+            x_size: self.x_size,
+            y_size: self.y_size,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = ((usize, usize), &T)> {
+        let y_size = self.y_size;
+        self.data
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(i, x)| x.as_ref().map(|x| ((i / y_size, i % y_size), x)))
+    }
 }
 
 /// Sparse matrix
 ///
-/// Currently implemented with COO for simplicity
+/// Values are stored in the order they were first set, and found through a persistent sorted
+/// index of their coordinates, so lookups are O(log n) and updates share the index's structure
+#[derive(Clone)]
 struct SparseMatrix<T> {
-    coords: Vec<(usize, usize)>,
+    /// Position of each set cell in data
+    index: OrdMap<(usize, usize), usize>,
     data: Purse<T>,
     x_size: usize,
     y_size: usize,
@@ -45,56 +86,81 @@ struct SparseMatrix<T> {
 impl<T> SparseMatrix<T> {
     fn new(x_size: usize, y_size: usize) -> SparseMatrix<T> {
         SparseMatrix {
-            coords: Vec::new(),
+            index: OrdMap::new(),
             data: Purse::new(),
-            x_size: x_size,
-            y_size: y_size,
+            x_size,
+            y_size,
         }
     }
 
     fn get_index(&self, x: usize, y: usize) -> Option<usize> {
-        self.coords.iter().position(|&r| r == (x, y))
+        self.index.get(&(x, y)).map(|x| *x)
     }
 
     fn get(&self, x: usize, y: usize) -> Option<&T> {
-        let index = self.get_index(x, y);
-        match index {
-            Some(x) => Some(&self.data[x]),
-            None => None,
-        }
+        self.get_index(x, y).map(|x| &self.data[x])
     }
 
     fn set(&self, x: usize, y: usize, data: T) -> SparseMatrix<T> {
-        let index = self.get_index(x, y);
-        let mut new_coords = self.coords.clone();
-        let new_data;
-        if let Some(index) = index {
-            new_data = self.data.set(index, data);
-        } else {
-            new_coords.push((x, y));
-            new_data = self.data.push(data);
+        let (index, data) = match self.get_index(x, y) {
+            Some(index) => (self.index.clone(), self.data.set(index, data)),
+            None => (
+                self.index.insert((x, y), self.data.len()),
+                self.data.push(data),
+            ),
+        };
+        SparseMatrix {
+            index,
+            data,
+            x_size: self.x_size,
+            y_size: self.y_size,
         }
+    }
 
+    fn set_many(&self, cells: Vec<((usize, usize), T)>) -> SparseMatrix<T>
+    where
+        T: Clone,
+    {
+        let mut new_index = self.index.clone();
+        let mut new_data: Vec<T> = self.data.into_iter().cloned().collect();
+        for (cell, data) in cells {
+            match new_index.get(&cell) {
+                Some(index) => new_data[*index] = data,
+                None => {
+                    new_index.insert_mut(cell, new_data.len());
+                    new_data.push(data);
+                }
+            }
+        }
         SparseMatrix {
-            coords: new_coords,
-            data: new_data,
+            index: new_index,
+            data: new_data.into_iter().collect(),
             x_size: self.x_size,
             y_size: self.y_size,
         }
     }
 
+    /// Returns every set cell, ordered by coordinates
+    fn iter(&self) -> impl Iterator<Item = ((usize, usize), &T)> {
+        self.index
+            .iter()
+            .map(move |(cell, index)| (*cell, &self.data[*index]))
+    }
+
     fn size(&self) -> usize {
-        let coords_size = self.coords.len() * size_of::<(usize, usize)>();
-        let data_size = self.coords.len() * size_of::<T>();
+        let coords_size = self.data.len() * size_of::<((usize, usize), usize)>();
+        let data_size = self.data.len() * size_of::<T>();
         coords_size + data_size
     }
 
+    /// Returns true once the sparse matrix takes as much space as an array matrix would
     fn packable(&self) -> bool {
         self.size() >= self.x_size * self.y_size * size_of::<T>()
     }
 }
 
 /// Provides a consistent interface for either type of matrix
+#[derive(Clone)]
 enum Matrix<T> {
     SMatrix(SparseMatrix<T>),
     AMatrix(ArrayMatrix<T>),
@@ -108,24 +174,57 @@ impl<T> Matrix<T> {
 
     fn get(&self, x: usize, y: usize) -> Option<&T> {
         match self {
-            &Matrix::SMatrix(ref i) => i.get(x, y),
-            &Matrix::AMatrix(ref i) => i.get(x, y),
+            Matrix::SMatrix(i) => i.get(x, y),
+            Matrix::AMatrix(i) => i.get(x, y),
         }
     }
 
-    fn set(&self, x: usize, y: usize, data: T) -> Matrix<T> {
+    /// Returns every cell that has been set, along with its coordinates
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = ((usize, usize), &'a T)> + 'a> {
         match self {
-            &Matrix::SMatrix(ref m) => {
-                let new_matrix = Matrix::SMatrix(m.set(x, y, data));
-                // TODO: Implement repacking here
-                new_matrix
-            }
-            &Matrix::AMatrix(ref m) => Matrix::AMatrix(m.set(x, y, data)),
+            Matrix::SMatrix(m) => Box::new(m.iter()),
+            Matrix::AMatrix(m) => Box::new(m.iter()),
+        }
+    }
+
+    fn set(&self, x: usize, y: usize, data: T) -> Matrix<T>
+    where
+        T: Clone,
+    {
+        match self {
+            Matrix::SMatrix(m) => Matrix::repack(m.set(x, y, data)),
+            Matrix::AMatrix(m) => Matrix::AMatrix(m.set(x, y, data)),
+        }
+    }
+
+    /// Switches a sparse matrix over to an array matrix once it is dense enough
+    ///
+    /// Cells are never unset, so a matrix never has to go back to being sparse
+    fn repack(matrix: SparseMatrix<T>) -> Matrix<T>
+    where
+        T: Clone,
+    {
+        if matrix.packable() {
+            Matrix::AMatrix(ArrayMatrix::from_sparse(&matrix))
+        } else {
+            Matrix::SMatrix(matrix)
+        }
+    }
+
+    /// Sets every given cell, copying the matrix only once
+    fn set_many(&self, cells: Vec<((usize, usize), T)>) -> Matrix<T>
+    where
+        T: Clone,
+    {
+        match self {
+            Matrix::SMatrix(m) => Matrix::repack(m.set_many(cells)),
+            Matrix::AMatrix(m) => Matrix::AMatrix(m.set_many(cells)),
         }
     }
 }
 
 /// Provides abstraction of an immutable, 2D array, with a default value
+#[derive(Clone)]
 pub struct Slice<T> {
     matrix: Matrix<T>,
     default: T,
@@ -137,9 +236,9 @@ impl<T: Clone> Slice<T> {
     pub fn new(x_size: usize, y_size: usize, default: T) -> Slice<T> {
         Slice {
             matrix: Matrix::new(x_size, y_size),
-            default: default,
-            x_size: x_size,
-            y_size: y_size,
+            default,
+            x_size,
+            y_size,
         }
     }
 
@@ -159,4 +258,149 @@ impl<T: Clone> Slice<T> {
             y_size: self.y_size,
         }
     }
+
+    /// Sets every cell between the two corners, inclusive, to the same value
+    ///
+    /// Cells outside of the slice are ignored
+    pub fn fill_region(&self, min: (usize, usize), max: (usize, usize), data: T) -> Slice<T> {
+        let x_max = max.0.min(self.x_size.saturating_sub(1));
+        let y_max = max.1.min(self.y_size.saturating_sub(1));
+        let cells = (min.0..=x_max)
+            .flat_map(|x| (min.1..=y_max).map(move |y| (x, y)))
+            .map(|cell| (cell, data.clone()));
+        self.set_many(cells)
+    }
+
+    /// Sets every given cell, copying the slice only once
+    ///
+    /// Cells outside of the slice are ignored
+    pub fn set_many<I: IntoIterator<Item = ((usize, usize), T)>>(&self, cells: I) -> Slice<T> {
+        let cells: Vec<((usize, usize), T)> = cells
+            .into_iter()
+            .filter(|&((x, y), _)| x < self.x_size && y < self.y_size)
+            .collect();
+        if cells.is_empty() {
+            return self.clone();
+        }
+        Slice {
+            matrix: self.matrix.set_many(cells),
+            default: self.default.clone(),
+            x_size: self.x_size,
+            y_size: self.y_size,
+        }
+    }
+
+    /// Returns the part of the slice between the two corners, inclusive, as a new slice
+    ///
+    /// Only cells that have been set are copied, everything else is left as the default
+    pub fn extract(&self, min: (usize, usize), max: (usize, usize)) -> Slice<T> {
+        let x_size = (max.0 + 1).saturating_sub(min.0);
+        let y_size = (max.1 + 1).saturating_sub(min.1);
+        let cells = self
+            .matrix
+            .iter()
+            .filter(|&((x, y), _)| x >= min.0 && x <= max.0 && y >= min.1 && y <= max.1)
+            .map(|((x, y), data)| ((x - min.0, y - min.1), data.clone()));
+        Slice::new(x_size, y_size, self.default.clone()).set_many(cells)
+    }
+
+    /// Returns a copy of the slice with a new size
+    ///
+    /// Cells keep their values, new cells hold the default, and cells past the new size are
+    /// dropped
+    pub fn resize(&self, x_size: usize, y_size: usize) -> Slice<T> {
+        let cells = self.matrix.iter().map(|(cell, data)| (cell, data.clone()));
+        Slice::new(x_size, y_size, self.default.clone()).set_many(cells)
+    }
+
+    /// Returns roughly how many bytes the slice takes up
+    pub fn memory_estimate(&self) -> usize {
+        let matrix = match self.matrix {
+            Matrix::SMatrix(ref m) => m.size(),
+            Matrix::AMatrix(ref m) => m.data.len() * size_of::<Option<T>>(),
+        };
+        size_of::<Slice<T>>() + matrix
+    }
+}
+
+impl<T: Clone + PartialEq> Slice<T> {
+    /// Returns every cell holding something other than the default, along with its coordinates
+    ///
+    /// Only visits cells that have been set, rather than the entire slice
+    pub fn iter_non_default(&self) -> impl Iterator<Item = ((usize, usize), &T)> {
+        self.matrix.iter().filter(move |(_, x)| **x != self.default)
+    }
+
+    /// Returns the cells of this slice that differ in the other one, along with their values in
+    /// the other slice, ordered by coordinates
+    ///
+    /// If both slices have the same default, only cells set in either of them are visited.
+    pub fn diff(&self, other: &Slice<T>) -> Vec<((usize, usize), T)> {
+        let mut cells: Vec<(usize, usize)> = if self.default == other.default {
+            let set = self.matrix.iter().chain(other.matrix.iter());
+            set.map(|x| x.0).collect()
+        } else {
+            (0..self.x_size)
+                .flat_map(|x| (0..self.y_size).map(move |y| (x, y)))
+                .collect()
+        };
+        cells.sort();
+        cells.dedup();
+        cells
+            .into_iter()
+            .filter(|&(x, y)| x < self.x_size && y < self.y_size)
+            .filter(|&(x, y)| self.get(x, y) != other.get(x, y))
+            .map(|(x, y)| ((x, y), other.get(x, y).clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_overwrites_in_place() {
+        let mut slice = Slice::new(256, 256, 0u32);
+        for i in 0..100 {
+            slice = slice.set(255 - i, i * 2, i as u32 + 1);
+        }
+        slice = slice.set(255, 0, 7).set(200, 1, 8);
+        assert!(matches!(slice.matrix, Matrix::SMatrix(_)));
+        assert_eq!(*slice.get(255, 0), 7);
+        assert_eq!(*slice.get(200, 1), 8);
+        assert_eq!(*slice.get(254, 2), 2);
+        assert_eq!(*slice.get(254, 3), 0);
+
+        // Cells come back ordered by coordinates, each of them once
+        let cells: Vec<_> = slice.iter_non_default().map(|x| x.0).collect();
+        assert_eq!(cells.len(), 101);
+        assert!(cells.windows(2).all(|x| x[0] < x[1]));
+    }
+
+    #[test]
+    fn repacks_once_dense() {
+        // Not square, so mixed up coordinates would show
+        let mut slice = Slice::new(3, 5, 0u64).set(2, 4, 24);
+        assert!(matches!(slice.matrix, Matrix::SMatrix(_)));
+        for x in 0..3 {
+            for y in 0..5 {
+                slice = slice.set(x, y, (x * 10 + y) as u64);
+            }
+        }
+        assert!(matches!(slice.matrix, Matrix::AMatrix(_)));
+        for x in 0..3 {
+            for y in 0..5 {
+                assert_eq!(*slice.get(x, y), (x * 10 + y) as u64);
+            }
+        }
+        let mut cells: Vec<_> = slice.iter_non_default().map(|x| x.0).collect();
+        cells.sort();
+        assert_eq!(cells.len(), 14);
+        assert_eq!(cells[0], (0, 1));
+
+        let filled = Slice::new(4, 2, 0u64).fill_region((0, 0), (3, 1), 1);
+        assert!(matches!(filled.matrix, Matrix::AMatrix(_)));
+        assert_eq!(*filled.set(3, 1, 2).get(3, 1), 2);
+    }
 }
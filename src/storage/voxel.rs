@@ -0,0 +1,42 @@
+//! Provides a common interface to the different ways of storing a 3D array
+//!
+//! Chunks are generic over their storage, so strategies can be benchmarked against each other
+//! and swapped without touching the rest of the crate.
+
+/// Iterator over cells of a VoxelStore, along with their coordinates
+pub type VoxelIter<'a, T> = Box<dyn Iterator<Item = ((usize, usize, usize), &'a T)> + 'a>;
+
+/// Immutable 3D array with a default value
+pub trait VoxelStore<T>: Clone {
+    /// Creates a store of the given size, filled with the default
+    fn new(x_size: usize, y_size: usize, z_size: usize, default: &T) -> Self;
+
+    /// Returns the x, y, and z size of the store
+    fn get_size(&self) -> (usize, usize, usize);
+
+    /// Returns the value of a cell, or the default if it is outside of the store
+    fn get(&self, x: usize, y: usize, z: usize) -> &T;
+
+    /// Returns a copy of the store with a cell set, or None if it is outside of the store
+    fn set(&self, x: usize, y: usize, z: usize, value: T) -> Option<Self>;
+
+    /// Returns a copy of the store with every cell between the two corners, inclusive, set
+    fn fill(&self, min: (usize, usize, usize), max: (usize, usize, usize), value: T) -> Self;
+
+    /// Returns every cell, along with its coordinates, in z, y, x order
+    fn iter(&self) -> VoxelIter<'_, T>;
+
+    /// Returns every cell holding something other than the default, along with its coordinates
+    fn iter_non_default(&self) -> VoxelIter<'_, T>;
+
+    /// Returns the cells of this store that differ in the other one, along with their values in
+    /// the other store
+    ///
+    /// Only cells inside of this store are compared. Parts the two stores share are skipped.
+    fn diff(&self, other: &Self) -> Vec<((usize, usize, usize), T)>;
+
+    /// Returns roughly how many bytes the store takes up
+    ///
+    /// Structure shared with other stores is counted in full
+    fn memory_estimate(&self) -> usize;
+}
@@ -0,0 +1,74 @@
+//! Provides live streams of committed transactions, for live maps and monitoring
+//!
+//! The same fan out also streams rejected transactions, for anything monitoring failed edits.
+//! Watchers only receive the committed transactions changing a region, such as a protected build.
+
+use data::*;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Fans events, such as committed transactions, out to every live subscriber
+pub struct Subscribers<T> {
+    senders: Vec<Sender<T>>,
+}
+
+impl<T: Clone> Subscribers<T> {
+    /// Creates a new set of subscribers with no one listening
+    pub fn new() -> Subscribers<T> {
+        Subscribers {
+            senders: Vec::new(),
+        }
+    }
+
+    /// Adds a new subscriber, returning the receiving end of its channel
+    pub fn subscribe(&mut self) -> Receiver<T> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Sends an event to every subscriber
+    ///
+    /// Subscribers whose receiver has been dropped are removed
+    pub fn publish(&mut self, event: T) {
+        self.senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+/// Fans committed transactions out to the subscribers watching the region they change
+pub struct Watchers {
+    watches: Vec<(WorldId, Region, Sender<Transaction>)>,
+}
+
+impl Watchers {
+    /// Creates a new set of watchers with no one watching
+    pub fn new() -> Watchers {
+        Watchers {
+            watches: Vec::new(),
+        }
+    }
+
+    /// Starts watching a region of a world, returning the receiving end of its channel
+    pub fn watch(&mut self, world: WorldId, region: Region) -> Receiver<Transaction> {
+        let (sender, receiver) = channel();
+        self.watches.push((world, region, sender));
+        receiver
+    }
+
+    /// Sends a transaction to everyone watching a block target changes
+    ///
+    /// The target of an Undo is the transaction it undoes. Watchers whose receiver has been
+    /// dropped are removed the next time a transaction is sent to them.
+    pub fn publish(&mut self, transaction: Transaction, target: Option<RawTransaction>) {
+        let target = match target {
+            Some(target) => target,
+            None => return,
+        };
+        let (world, blocks) = (target.get_world(), target.get_affected_coords());
+        self.watches.retain(|(watched, region, sender)| {
+            let watching =
+                *watched == world && blocks.iter().any(|&(x, y, z)| region.contains(x, y, z));
+            !watching || sender.send(transaction.clone()).is_ok()
+        });
+    }
+}
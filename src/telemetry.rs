@@ -0,0 +1,176 @@
+//! Records metrics through the metrics crate facade, with the metrics feature
+//!
+//! Without the feature every function here does nothing. With it, the following metrics are
+//! recorded, for whatever exporter the application installs to scrape:
+//!
+//! - `rewind_transactions_applied_total`: counter of committed transactions, labeled by `type`
+//! - `rewind_transactions_rejected_total`: counter of transactions that failed to apply, labeled
+//!   by `type`
+//! - `rewind_undo_seconds`: histogram of how long applying an Undo took
+//! - `rewind_history_query_seconds`: histogram of how long history queries took, labeled by
+//!   `query`
+//! - `rewind_chunks`: gauge of the number of chunks across every world, loaded or evicted
+//! - `rewind_chunks_evicted_total`: counter of chunks evicted to stay within a memory budget
+//! - `rewind_chunk_memory_bytes`: gauge of the estimated memory used by chunks in memory, as of
+//!   the last time a memory budget was enforced
+
+use data::*;
+use im::HashMap;
+#[cfg(feature = "metrics")]
+use metrics::{counter, gauge, histogram};
+use std::time::Instant;
+
+/// Returns the name a transaction type is labeled with
+#[cfg(feature = "metrics")]
+fn type_label(transaction_type: &TransactionType) -> &'static str {
+    match transaction_type {
+        TransactionType::Set { .. } => "set",
+        TransactionType::Replace { .. } => "replace",
+        TransactionType::Undo { .. } => "undo",
+        TransactionType::SetBiome { .. } => "set_biome",
+        TransactionType::SetDecoration { .. } => "set_decoration",
+        TransactionType::RemoveDecoration { .. } => "remove_decoration",
+        TransactionType::Explode { .. } => "explode",
+        TransactionType::PistonMove { .. } => "piston_move",
+    }
+}
+
+/// Records the outcome of applying a transaction
+#[cfg(feature = "metrics")]
+pub fn transaction_applied(transaction: &RawTransaction, committed: bool) {
+    let label = type_label(transaction.get_transaction_type());
+    if committed {
+        counter!("rewind_transactions_applied_total", "type" => label).increment(1);
+    } else {
+        counter!("rewind_transactions_rejected_total", "type" => label).increment(1);
+    }
+}
+
+/// Records how long an Undo that started at the given instant took
+#[cfg(feature = "metrics")]
+pub fn undo_finished(started: Instant) {
+    histogram!("rewind_undo_seconds").record(started.elapsed().as_secs_f64());
+}
+
+/// Records how long a history query that started at the given instant took
+#[cfg(feature = "metrics")]
+pub fn history_query_finished(query: &'static str, started: Instant) {
+    let elapsed = started.elapsed().as_secs_f64();
+    histogram!("rewind_history_query_seconds", "query" => query).record(elapsed);
+}
+
+/// Records the number of chunks across every world
+#[cfg(feature = "metrics")]
+pub fn chunk_count(worlds: &HashMap<WorldId, World>) {
+    let count: usize = worlds.values().map(|x| x.get_chunk_count()).sum();
+    gauge!("rewind_chunks").set(count as f64);
+}
+
+/// Records chunks evicted to stay within a memory budget, and the memory left in use after
+#[cfg(feature = "metrics")]
+pub fn chunks_evicted(count: usize, memory: usize) {
+    counter!("rewind_chunks_evicted_total").increment(count as u64);
+    gauge!("rewind_chunk_memory_bytes").set(memory as f64);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub fn transaction_applied(_: &RawTransaction, _: bool) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn undo_finished(_: Instant) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn history_query_finished(_: &'static str, _: Instant) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn chunk_count(_: &HashMap<WorldId, World>) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn chunks_evicted(_: usize, _: usize) {}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+    use metrics::{
+        Counter, CounterFn, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use Rewind;
+
+    /// Records the counters, by name and labels, and nothing else
+    #[derive(Default)]
+    struct Counters(Mutex<Vec<(String, Arc<AtomicU64>)>>);
+
+    struct Count(Arc<AtomicU64>);
+
+    impl CounterFn for Count {
+        fn increment(&self, value: u64) {
+            self.0.fetch_add(value, Ordering::SeqCst);
+        }
+
+        fn absolute(&self, value: u64) {
+            self.0.store(value, Ordering::SeqCst);
+        }
+    }
+
+    impl Counters {
+        fn get(&self, key: &str) -> u64 {
+            let counters = self.0.lock().unwrap();
+            counters
+                .iter()
+                .filter(|x| x.0 == key)
+                .map(|x| x.1.load(Ordering::SeqCst))
+                .sum()
+        }
+    }
+
+    impl Recorder for Counters {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            let labels: Vec<String> = key.labels().map(|x| x.value().to_string()).collect();
+            let name = format!("{}{{{}}}", key.name(), labels.join(","));
+            let count = Arc::new(AtomicU64::new(0));
+            self.0.lock().unwrap().push((name, count.clone()));
+            Counter::from_arc(Arc::new(Count(count)))
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    #[test]
+    fn counts_transactions() {
+        let counters = Counters::default();
+        let block = |id| MetaBlock::fuse(Block::new_from_ids(0, id), MetaData::new());
+        metrics::with_local_recorder(&counters, || {
+            let rewind = Rewind::new(block(0));
+            let set = RawTransactionBuilder::new(TransactionType::new_set(block(1)))
+                .set_x_coord(0)
+                .set_y_coord(0)
+                .set_z_coord(0)
+                .build_transaction()
+                .unwrap();
+            let committed = rewind.apply_transaction(set).unwrap();
+            let undo = |id| {
+                RawTransactionBuilder::new(TransactionType::new_undo(id))
+                    .build_transaction()
+                    .unwrap()
+            };
+            rewind.apply_transaction(undo(committed.get_id())).unwrap();
+            let missing = TransactionID::new_from_parts(99, 0);
+            assert!(rewind.apply_transaction(undo(missing)).is_none());
+        });
+        assert_eq!(counters.get("rewind_transactions_applied_total{set}"), 1);
+        assert_eq!(counters.get("rewind_transactions_applied_total{undo}"), 1);
+        assert_eq!(counters.get("rewind_transactions_rejected_total{undo}"), 1);
+    }
+}
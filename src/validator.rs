@@ -0,0 +1,117 @@
+//! Provides validators that check, and can rewrite, transactions before they are applied
+//!
+//! Validators form a chain. Every transaction applied to a Rewind is passed through each
+//! validator in the order they were added, each one seeing the output of the last, and the
+//! result is what gets applied. Any validator can reject the transaction instead, which then
+//! shows up on the stream of rejections with the reason Invalid.
+//!
+//! Validators run before guards, so guards see transactions as they will be committed.
+//! Transactions merged in from other nodes have already been validated where they were
+//! committed, and are not validated again.
+
+use chrono::{Local, Utc};
+use data::*;
+
+/// Checks, and possibly rewrites, transactions before they are applied
+pub trait TransactionValidator: Send + Sync {
+    /// Returns the transaction to apply in place of this one, or None to reject it
+    fn validate(&self, transaction: RawTransaction) -> Option<RawTransaction>;
+}
+
+/// Custom validators can be written as closures
+impl<F> TransactionValidator for F
+where
+    F: Fn(RawTransaction) -> Option<RawTransaction> + Send + Sync,
+{
+    fn validate(&self, transaction: RawTransaction) -> Option<RawTransaction> {
+        self(transaction)
+    }
+}
+
+/// Stamps transactions without a time with the current time, and discards the offset of ones
+/// with a time, so every transaction reads back in UTC
+///
+/// Times are always compared in UTC, this only changes what get_local_time and exports show.
+pub struct NormalizeTime;
+
+impl TransactionValidator for NormalizeTime {
+    fn validate(&self, transaction: RawTransaction) -> Option<RawTransaction> {
+        let time = match transaction.get_time() {
+            Some(time) => time,
+            None => Local::now().with_timezone(&Utc),
+        };
+        Some(transaction.with_time(time))
+    }
+}
+
+/// Clamps the height of transactions into the build limits of the world
+///
+/// Transactions without coordinates, like Undos, are left alone.
+pub struct ClampHeight {
+    min_y: i32,
+    max_y: i32,
+}
+
+impl ClampHeight {
+    /// Creates a validator clamping heights to between min_y and max_y, inclusive
+    ///
+    /// Returns None if min_y is above max_y
+    pub fn new(min_y: i32, max_y: i32) -> Option<ClampHeight> {
+        if min_y > max_y {
+            return None;
+        }
+        Some(ClampHeight { min_y, max_y })
+    }
+}
+
+impl TransactionValidator for ClampHeight {
+    fn validate(&self, transaction: RawTransaction) -> Option<RawTransaction> {
+        match transaction.get_coords() {
+            Some((x, y, z)) => transaction.with_coords((x, y.clamp(self.min_y, self.max_y), z)),
+            None => Some(transaction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone};
+
+    #[test]
+    fn normalizes_and_clamps() {
+        let set = RawTransactionBuilder::new(TransactionType::new_set(MetaBlock::fuse(
+            Block::new_from_ids(0, 1),
+            MetaData::new(),
+        )))
+        .set_time(
+            FixedOffset::east_opt(3600)
+                .unwrap()
+                .with_ymd_and_hms(2020, 1, 1, 1, 0, 0)
+                .unwrap(),
+        )
+        .set_x_coord(1)
+        .set_y_coord(400)
+        .set_z_coord(2)
+        .build_transaction()
+        .unwrap();
+
+        let normalized = NormalizeTime.validate(set.clone()).unwrap();
+        assert_eq!(normalized.get_time(), set.get_time());
+        assert_eq!(set.get_offset().unwrap().local_minus_utc(), 3600);
+        assert_eq!(normalized.get_offset().unwrap().local_minus_utc(), 0);
+
+        assert!(ClampHeight::new(1, 0).is_none());
+        let clamp = ClampHeight::new(-64, 319).unwrap();
+        assert_eq!(
+            clamp.validate(set.clone()).unwrap().get_coords(),
+            Some((1, 319, 2))
+        );
+        let undo = RawTransactionBuilder::new(TransactionType::new_undo(
+            TransactionID::new_from_parts(0, 0),
+        ))
+        .build_transaction()
+        .unwrap();
+        assert!(clamp.validate(undo.clone()) == Some(undo));
+    }
+}
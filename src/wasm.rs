@@ -0,0 +1,106 @@
+//! wasm-bindgen bindings for read-only history viewers
+//!
+//! Enabled with the `wasm` feature. A `WorldLineViewer` is loaded from the text of an exported
+//! worldline, and answers queries with JSON strings, so a browser based inspector can run
+//! entirely client-side.
+
+use data::*;
+use serde_json;
+use wasm_bindgen::prelude::*;
+use Rewind;
+
+/// A read-only view of an exported worldline
+#[wasm_bindgen]
+pub struct WorldLineViewer {
+    rewind: Rewind,
+}
+
+/// One entry of a block history, as handed to javascript
+#[derive(Serialize)]
+struct HistoryEntry {
+    block: MetaBlock,
+    transaction: Transaction,
+}
+
+/// One changed block of a region diff, as handed to javascript
+#[derive(Serialize)]
+struct DiffEntry {
+    coords: (i32, i32, i32),
+    before: MetaBlock,
+    after: MetaBlock,
+}
+
+#[wasm_bindgen]
+impl WorldLineViewer {
+    /// Loads a worldline from the contents of a worldline export
+    #[wasm_bindgen(constructor)]
+    pub fn new(export: &str) -> Result<WorldLineViewer, JsValue> {
+        let rewind = Rewind::import_worldline(export.as_bytes()).map_err(|e| e.to_string())?;
+        Ok(WorldLineViewer { rewind })
+    }
+
+    /// Returns the number of transactions in the worldline
+    pub fn transaction_count(&self) -> usize {
+        self.rewind.get_transactions().len()
+    }
+
+    /// Returns every transaction by the given owner as a JSON array, oldest first
+    pub fn owner_transactions(&self, owner: &str) -> Result<String, JsValue> {
+        let owner = owner.parse::<::uuid::Uuid>().map_err(|e| e.to_string())?;
+        let transactions: Vec<Transaction> = self
+            .rewind
+            .get_transactions()
+            .into_iter()
+            .filter(|x| x.get_transaction().get_owner() == owner)
+            .collect();
+        to_json(&transactions)
+    }
+
+    /// Returns the history of a block as a JSON array of `{block, transaction}`, oldest first
+    pub fn block_history(&self, x: i32, y: i32, z: i32) -> Result<String, JsValue> {
+        let history: Vec<HistoryEntry> = self
+            .rewind
+            .get_block_history(x, y, z)
+            .into_iter()
+            .map(|(block, transaction)| HistoryEntry { block, transaction })
+            .collect();
+        to_json(&history)
+    }
+
+    /// Returns the blocks in a region that changed between two transactions
+    ///
+    /// Transactions are given by major id. The result is a JSON array of
+    /// `{coords, before, after}`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn diff_region(
+        &self,
+        min_x: i32,
+        min_y: i32,
+        min_z: i32,
+        max_x: i32,
+        max_y: i32,
+        max_z: i32,
+        from: u32,
+        to: u32,
+    ) -> Result<String, JsValue> {
+        let diff: Vec<DiffEntry> = self
+            .rewind
+            .diff_region(
+                Region::new((min_x, min_y, min_z), (max_x, max_y, max_z)),
+                TransactionID::new_from_parts(from, u32::MAX),
+                TransactionID::new_from_parts(to, u32::MAX),
+            )
+            .into_iter()
+            .map(|(coords, before, after)| DiffEntry {
+                coords,
+                before,
+                after,
+            })
+            .collect();
+        to_json(&diff)
+    }
+}
+
+fn to_json<T: ::serde::Serialize>(value: &T) -> Result<String, JsValue> {
+    serde_json::to_string(value).map_err(|e| JsValue::from(e.to_string()))
+}